@@ -0,0 +1,108 @@
+//! Transition throughput of the counter machine from `examples/csm.rs`.
+//!
+//! Runs it once with a `Vec` actions container and once with `SmallActions`, since
+//! every transition emits exactly one action.
+//!
+//! Run with `cargo bench --bench counter`.
+
+use std::{future, hint::black_box, marker::PhantomData};
+
+use phasm::{
+    Input, StateMachine,
+    actions::{Action, ActionsContainer, SmallActions, TrackedActionTypes},
+    bench::{CountingAllocator, bench},
+};
+
+#[global_allocator]
+static ALLOC: CountingAllocator = CountingAllocator;
+
+struct Counter<C> {
+    counter: u64,
+    container: PhantomData<C>,
+}
+
+impl<C> Counter<C> {
+    fn new() -> Self {
+        Self {
+            counter: 0,
+            container: PhantomData,
+        }
+    }
+}
+
+impl<C> Clone for Counter<C> {
+    fn clone(&self) -> Self {
+        Self {
+            counter: self.counter,
+            container: PhantomData,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum CounterAction {
+    #[allow(dead_code)]
+    Incremented { from: u64, to: u64 },
+}
+
+#[derive(Debug)]
+struct CounterTracked;
+
+impl TrackedActionTypes for CounterTracked {
+    type Id = ();
+    type Action = ();
+    type Result = ();
+}
+
+impl<C: ActionsContainer<CounterAction, CounterTracked>> StateMachine for Counter<C> {
+    type UntrackedAction = CounterAction;
+    type TrackedAction = CounterTracked;
+    type Actions = C;
+    type State = Self;
+    type Input = u64;
+    type TransitionError = ();
+    type RestoreError = ();
+    type StfFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+    type RestoreFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+
+    fn stf<'state, 'actions>(
+        state: &'state mut Self::State,
+        input: Input<Self::TrackedAction, Self::Input>,
+        actions: &'actions mut Self::Actions,
+    ) -> Self::StfFuture<'state, 'actions> {
+        let Input::Normal(by) = input else {
+            return future::ready(Ok(()));
+        };
+        let from = state.counter;
+        let Some(to) = from.checked_add(by) else {
+            return future::ready(Err(()));
+        };
+        state.counter = to;
+        let res = actions.add(Action::Untracked(CounterAction::Incremented { from, to }));
+        future::ready(res.map_err(|_| ()))
+    }
+
+    fn restore<'state, 'actions>(
+        _state: &'state Self::State,
+        _actions: &'actions mut Self::Actions,
+    ) -> Self::RestoreFuture<'state, 'actions> {
+        future::ready(Ok(()))
+    }
+}
+
+#[monoio::main]
+async fn main() {
+    let inputs: Vec<_> = (1..=1000).map(Input::Normal).collect();
+
+    type VecCounter = Counter<Vec<Action<CounterAction, CounterTracked>>>;
+    type SmallCounter = Counter<SmallActions<CounterAction, CounterTracked, 1>>;
+
+    // Warm up, then measure
+    bench::<VecCounter>(&Counter::new(), &inputs, 100).await;
+    let report = bench::<VecCounter>(black_box(&Counter::new()), &inputs, 10_000).await;
+    println!("counter/increment/vec       {}", report);
+
+    bench::<SmallCounter>(&Counter::new(), &inputs, 100).await;
+    let report = bench::<SmallCounter>(black_box(&Counter::new()), &inputs, 10_000).await;
+    println!("counter/increment/smallvec  {}", report);
+}
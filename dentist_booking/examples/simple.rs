@@ -19,12 +19,15 @@ async fn main() {
             day: Day::Monday,
             time: Time::new(9, 0),
             apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
         }),
         &mut actions,
     )
     .await
     .unwrap();
-    
+
     let req_id = system.pending.keys().next().copied().unwrap();
     actions.clear();
 
@@ -32,20 +35,27 @@ async fn main() {
         &mut system,
         Input::TrackedActionCompleted {
             id: req_id,
-            res: PaymentResult::Success { amount: 75.0 },
+            res: PaymentResult::Success {
+                amount: Cents(7_500),
+            },
         },
         &mut actions,
     )
     .await
     .unwrap();
-    
+
     println!("✓ Alice booked\n");
     actions.clear();
 
     // Show final bookings
     println!("Final bookings:");
     for (slot, booking) in &system.bookings {
-        println!("  {} - {} ({})", slot, booking.name, booking.apt_type.name());
+        println!(
+            "  {} - {} ({})",
+            slot,
+            booking.name,
+            booking.apt_type.name()
+        );
     }
 
     // Check invariants
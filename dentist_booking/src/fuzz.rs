@@ -0,0 +1,75 @@
+//! `Arbitrary` impls for fuzzing.
+//!
+//! Generation is constrained to values the constructors accept (`Time` in range,
+//! `TimeRange` with `start < end`), so fuzzing explores valid-but-adversarial inputs
+//! instead of tripping constructor asserts.
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::{AptType, BookingInput, Day, Slot, Time, TimeRange};
+
+impl<'a> Arbitrary<'a> for Day {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        u.choose(Day::all()).copied()
+    }
+}
+
+impl<'a> Arbitrary<'a> for Time {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Time::new(u.int_in_range(0..=23)?, u.int_in_range(0..=59)?))
+    }
+}
+
+impl<'a> Arbitrary<'a> for TimeRange {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        const LAST_MIN: u16 = 24 * 60 - 1;
+        let start = u.int_in_range(0..=LAST_MIN - 1)?;
+        let end = u.int_in_range(start + 1..=LAST_MIN)?;
+        Ok(TimeRange::new(Time::from_mins(start), Time::from_mins(end)))
+    }
+}
+
+impl<'a> Arbitrary<'a> for AptType {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        u.choose(AptType::all()).copied()
+    }
+}
+
+impl<'a> Arbitrary<'a> for Slot {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        Ok(Slot {
+            day: u.arbitrary()?,
+            time: u.arbitrary()?,
+        })
+    }
+}
+
+impl<'a> Arbitrary<'a> for BookingInput {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        if u.arbitrary()? {
+            Ok(BookingInput::RequestSlot {
+                user_id: u.arbitrary()?,
+                name: u.arbitrary()?,
+                email: u.arbitrary()?,
+                day: u.arbitrary()?,
+                time: u.arbitrary()?,
+                apt_type: u.arbitrary()?,
+                now: u.arbitrary()?,
+                token: u.arbitrary()?,
+                expected_version: None,
+            })
+        } else {
+            Ok(BookingInput::RequestAuto {
+                user_id: u.arbitrary()?,
+                name: u.arbitrary()?,
+                email: u.arbitrary()?,
+                days: u.arbitrary()?,
+                times: u.arbitrary()?,
+                apt_type: u.arbitrary()?,
+                now: u.arbitrary()?,
+                token: u.arbitrary()?,
+                expected_version: None,
+            })
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Construction of inputs from JSON by name, for admin tools.
+//!
+//! Values use the same text forms as `Display`: days as "Mon" (or "Monday"), times as
+//! "09:30", ranges as "09:00-12:00", apt types as "checkup", and `now` as "Mon 08:00".
+
+use std::{fmt::Display, str::FromStr};
+
+use phasm::InputParseError;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{BookingInput, Day, Slot, Time, TimeRange};
+
+#[derive(Deserialize)]
+struct RequestSlotArgs {
+    user_id: u64,
+    name: String,
+    email: String,
+    day: String,
+    time: String,
+    apt_type: String,
+    now: String,
+    #[serde(default)]
+    token: Option<u64>,
+    #[serde(default)]
+    expected_version: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct RequestAutoArgs {
+    user_id: u64,
+    name: String,
+    email: String,
+    days: Vec<String>,
+    times: Vec<String>,
+    apt_type: String,
+    now: String,
+    #[serde(default)]
+    token: Option<u64>,
+    #[serde(default)]
+    expected_version: Option<u64>,
+}
+
+pub(crate) fn parse_input(
+    name: &str,
+    args: &serde_json::Value,
+) -> Result<BookingInput, InputParseError> {
+    let invalid = |reason: String| InputParseError::InvalidArgs {
+        variant: name.to_string(),
+        reason,
+    };
+    match name {
+        "request_slot" => {
+            let args: RequestSlotArgs = from_value(args).map_err(invalid)?;
+            Ok(BookingInput::RequestSlot {
+                user_id: args.user_id,
+                name: args.name,
+                email: args.email,
+                day: day(&args.day).map_err(invalid)?,
+                time: parse("time", &args.time).map_err(invalid)?,
+                apt_type: parse("apt_type", &args.apt_type).map_err(invalid)?,
+                now: slot(&args.now).map_err(invalid)?,
+                token: args.token,
+                expected_version: args.expected_version,
+            })
+        }
+        "request_auto" => {
+            let args: RequestAutoArgs = from_value(args).map_err(invalid)?;
+            Ok(BookingInput::RequestAuto {
+                user_id: args.user_id,
+                name: args.name,
+                email: args.email,
+                days: args
+                    .days
+                    .iter()
+                    .map(|d| day(d))
+                    .collect::<Result<_, _>>()
+                    .map_err(invalid)?,
+                times: args
+                    .times
+                    .iter()
+                    .map(|r| range(r))
+                    .collect::<Result<_, _>>()
+                    .map_err(invalid)?,
+                apt_type: parse("apt_type", &args.apt_type).map_err(invalid)?,
+                now: slot(&args.now).map_err(invalid)?,
+                token: args.token,
+                expected_version: args.expected_version,
+            })
+        }
+        _ => Err(InputParseError::UnknownVariant(name.to_string())),
+    }
+}
+
+fn from_value<T: DeserializeOwned>(args: &serde_json::Value) -> Result<T, String> {
+    T::deserialize(args).map_err(|e| e.to_string())
+}
+
+fn parse<T: FromStr>(field: &str, s: &str) -> Result<T, String>
+where
+    T::Err: Display,
+{
+    s.parse().map_err(|e| format!("{}: {}", field, e))
+}
+
+fn day(s: &str) -> Result<Day, String> {
+    Day::from_name(s).ok_or_else(|| format!("day: unknown day {:?}", s))
+}
+
+/// Parses "Mon 08:00", the `Display` form of [`Slot`].
+fn slot(s: &str) -> Result<Slot, String> {
+    let (d, t) = s
+        .split_once(' ')
+        .ok_or_else(|| format!("now: expected \"DAY HH:MM\", got {:?}", s))?;
+    Ok(Slot {
+        day: day(d)?,
+        time: parse("now", t)?,
+    })
+}
+
+/// Parses "09:00-12:00", the `Display` form of [`TimeRange`].
+fn range(s: &str) -> Result<TimeRange, String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| format!("times: expected \"HH:MM-HH:MM\", got {:?}", s))?;
+    let (start, end): (Time, Time) = (parse("times", start)?, parse("times", end)?);
+    TimeRange::try_new(start, end).map_err(|_| format!("times: {:?} ends before it starts", s))
+}
@@ -1,16 +1,24 @@
 pub mod types;
 
+#[cfg(feature = "arbitrary")]
+mod fuzz;
+#[cfg(feature = "serde")]
+mod json;
+
 use std::{
-    future,
+    future, iter,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 
 use ahash::{HashMap, HashMapExt};
 
 use phasm::{
-    Input, StateMachine,
+    HealthReport, Input, StateMachine, StateSize, Versioned,
     actions::{Action, ActionsContainer, TrackedAction, TrackedActionTypes},
+    collections::OrderedMap,
+    diff::{FieldChange, StateDiff},
 };
 
 pub use types::*;
@@ -19,20 +27,53 @@ pub use types::*;
 // State Machine
 // ============================================================================
 
+#[derive(Clone)]
 pub struct BookingSystem {
-    pub schedule: HashMap<Day, Vec<TimeRange>>,
-    pub bookings: HashMap<Slot, ConfirmedBooking>,
-    pub pending: HashMap<u64, PendingReq>,
+    pub schedule: HashMap<Day, DaySchedule>,
+    /// Ordered so reports and emitted actions don't depend on hash order.
+    pub bookings: OrderedMap<Slot, ConfirmedBooking>,
+    /// Ordered by id so `restore` re-emits status checks deterministically.
+    pub pending: OrderedMap<u64, PendingReq>,
     pub next_id: u64,
+    /// Bumped by every successful transition, see [`Versioned`].
+    pub version: u64,
+    /// Minimum minutes between the request's `now` and the appointment start.
+    pub min_lead_mins: u32,
+    /// Minimum free minutes required between consecutive bookings on the same day.
+    ///
+    /// A floor: the buffer between two appointments is the largest of this and their
+    /// [`AptType::buffer_mins`], see [`pair_buffer`](Self::pair_buffer).
+    pub buffer_mins: u16,
+    /// Reserve the slot when a request is accepted, not when its payment succeeds.
+    pub optimistic_holds: bool,
+    /// Slots reserved by requests still awaiting preauth (see `optimistic_holds`), and
+    /// by tentative holds.
+    pub holds: HashMap<Slot, ReqId>,
+    /// Maintenance blocks by start slot, with their end time. Occupied like bookings.
+    pub maintenance: OrderedMap<Slot, Time>,
+    /// If set, patients must acknowledge a booking with `PatientConfirm` within this many
+    /// minutes of requesting it, or `ExpireUnconfirmed` cancels it.
+    pub confirm_window_mins: Option<u32>,
+    /// If set, a confirmed booking captures only this percentage of its price as a
+    /// deposit, and the rest on `MarkAttended`.
+    pub deposit_percent: Option<u8>,
 }
 
 impl BookingSystem {
     pub fn new() -> Self {
         Self {
             schedule: HashMap::new(),
-            bookings: HashMap::new(),
-            pending: HashMap::new(),
+            bookings: OrderedMap::new(),
+            pending: OrderedMap::new(),
             next_id: 1,
+            version: 0,
+            min_lead_mins: 0,
+            buffer_mins: 0,
+            optimistic_holds: false,
+            holds: HashMap::new(),
+            maintenance: OrderedMap::new(),
+            confirm_window_mins: None,
+            deposit_percent: None,
         }
     }
 
@@ -85,70 +126,400 @@ impl BookingSystem {
             TimeRange::new(Time::new(9, 0), Time::new(15, 0)),
         );
 
+        system.close_day(Day::Saturday);
+        system.close_day(Day::Sunday);
+
         system
     }
 
+    /// Adds an opening range to `day`, opening it if it was closed or unconfigured.
     pub fn add_schedule(&mut self, day: Day, range: TimeRange) {
-        self.schedule
-            .entry(day)
-            .or_insert_with(Vec::new)
-            .push(range);
+        match self.schedule.entry(day).or_insert(DaySchedule::Closed) {
+            DaySchedule::Open(ranges) => ranges.push(range),
+            closed => *closed = DaySchedule::Open(vec![range]),
+        }
+    }
+
+    /// Marks `day` as closed, dropping any opening ranges.
+    pub fn close_day(&mut self, day: Day) {
+        self.schedule.insert(day, DaySchedule::Closed);
+    }
+
+    /// Opening ranges of `day`, empty if closed or unconfigured.
+    pub fn day_ranges(&self, day: Day) -> &[TimeRange] {
+        self.schedule.get(&day).map_or(&[], DaySchedule::ranges)
+    }
+
+    pub fn is_available(&self, slot: Slot, apt_type: AptType) -> bool {
+        self.unavailable_reason(slot, apt_type).is_none()
+    }
+
+    /// Free minutes required between appointments of types `a` and `b` on the same day.
+    pub fn pair_buffer(&self, a: AptType, b: AptType) -> u16 {
+        self.buffer_mins.max(a.buffer_mins()).max(b.buffer_mins())
+    }
+
+    /// Returns why `slot` can't be booked for `apt_type`, or `None` if it can.
+    ///
+    /// Held slots conflict like bookings. When several conflict, the earliest one
+    /// is reported so the result doesn't depend on `HashMap` iteration order.
+    pub fn unavailable_reason(&self, slot: Slot, apt_type: AptType) -> Option<UnavailableReason> {
+        self.unavailable_for(slot, apt_type, apt_type.dur(), None, None)
     }
 
-    pub fn is_available(&self, slot: Slot, dur: u16) -> bool {
+    /// [`unavailable_reason`](Self::unavailable_reason) for an appointment of `dur`
+    /// minutes, ignoring the booking at `skip` (the one being resized) and the holds of
+    /// `holder` (the request being checked).
+    fn unavailable_for(
+        &self,
+        slot: Slot,
+        apt_type: AptType,
+        dur: u16,
+        skip: Option<Slot>,
+        holder: Option<ReqId>,
+    ) -> Option<UnavailableReason> {
         // Check schedule
-        let Some(ranges) = self.schedule.get(&slot.day) else {
-            return false;
+        let ranges = match self.schedule.get(&slot.day) {
+            None => return Some(UnavailableReason::Unconfigured),
+            Some(DaySchedule::Closed) => return Some(UnavailableReason::ClinicClosed),
+            Some(DaySchedule::Open(ranges)) => ranges,
         };
         if !ranges.iter().any(|r| r.can_fit(slot.time, dur)) {
-            return false;
+            return Some(UnavailableReason::OutsideHours);
+        }
+        if let Some(start) = self.maintenance_overlap(slot, dur) {
+            return Some(UnavailableReason::Maintenance { start });
         }
 
-        // Check conflicts
-        let end = slot.time.add(dur);
-        for (booked, booking) in &self.bookings {
-            if booked.day != slot.day {
-                continue;
+        // Check conflicts, keeping the pair's buffer free on either side
+        let booked = self
+            .bookings
+            .iter()
+            .filter(|(booked, _)| Some(**booked) != skip)
+            .map(|(booked, booking)| (*booked, booking.apt_type, booking.dur_mins));
+        let held = self.holds.iter().filter_map(|(held, req_id)| {
+            if Some(*req_id) == holder {
+                return None;
             }
-            let booked_end = booked.time.add(booking.apt_type.dur());
-            if slot.time < booked_end && end > booked.time {
-                return false;
+            let apt_type = self.pending.get(req_id)?.apt_type;
+            Some((*held, apt_type, apt_type.dur()))
+        });
+        booked
+            .chain(held)
+            .filter(|(taken, _, _)| taken.day == slot.day)
+            .filter(|(taken, taken_type, taken_dur)| {
+                let buffer = self.pair_buffer(apt_type, *taken_type);
+                TimeRange::starting_at(slot.time, dur + buffer)
+                    .overlaps(&TimeRange::starting_at(taken.time, taken_dur + buffer))
+            })
+            .map(|(taken, _, _)| taken)
+            .min_by_key(|taken| taken.time)
+            .map(|slot| UnavailableReason::Conflict { slot })
+    }
+
+    /// Start of the earliest maintenance block overlapping `dur` minutes from `slot`.
+    pub fn maintenance_overlap(&self, slot: Slot, dur: u16) -> Option<Slot> {
+        let wanted = TimeRange::starting_at(slot.time, dur);
+        self.maintenance
+            .iter()
+            .find(|(start, block_end)| {
+                start.day == slot.day && wanted.overlaps(&TimeRange(start.time, **block_end))
+            })
+            .map(|(start, _)| *start)
+    }
+
+    /// Read-only availability query for UIs.
+    ///
+    /// If `slot` is unavailable, the report carries the reason and up to 3 free
+    /// slots on the same day, ordered by distance from the requested time (earlier
+    /// slot first on ties). Candidates are on the same 15-minute grid as
+    /// [`find_slot`](Self::find_slot), so the result is deterministic.
+    pub fn availability_report(&self, slot: Slot, apt_type: AptType) -> AvailabilityReport {
+        let dur = apt_type.dur();
+        let Some(reason) = self.unavailable_reason(slot, apt_type) else {
+            return AvailabilityReport {
+                available: true,
+                reason: None,
+                alternatives: Vec::new(),
+            };
+        };
+
+        let mut candidates = Vec::new();
+        for range in self.day_ranges(slot.day) {
+            let mut t = range.0;
+            while range.can_fit(t, dur) {
+                let candidate = Slot {
+                    day: slot.day,
+                    time: t,
+                };
+                if self.is_available(candidate, apt_type) {
+                    candidates.push(candidate);
+                }
+                let Some(next) = t.add(15) else { break };
+                t = next;
             }
         }
-        true
+
+        let requested = slot.time.to_mins();
+        candidates.sort_by_key(|c| (c.time.to_mins().abs_diff(requested), c.time));
+        candidates.truncate(3);
+
+        AvailabilityReport {
+            available: false,
+            reason: Some(reason),
+            alternatives: candidates,
+        }
     }
 
-    pub fn find_slot(&self, days: &[Day], ranges: &[TimeRange], dur: u16) -> Option<Slot> {
-        for &day in days {
-            let Some(sched_ranges) = self.schedule.get(&day) else {
-                continue;
+    /// Whether `slot` starts at least `min_lead_mins` after `now`.
+    ///
+    /// Slots earlier in the week than `now` are in the past and never meet the lead time.
+    pub fn meets_lead_time(&self, slot: Slot, now: Slot) -> bool {
+        slot.week_mins() >= now.week_mins().saturating_add(self.min_lead_mins)
+    }
+
+    /// A `Capture` for the booking of `req_id`, against its group's preauth if it has one.
+    fn capture(&self, req_id: ReqId, amount_cents: u32) -> PaymentReq {
+        let preauth = self.pending.get(&req_id).and_then(|p| p.group_id);
+        PaymentReq::Capture {
+            req_id: preauth.unwrap_or(req_id),
+            amount_cents,
+        }
+    }
+
+    /// The `confirm_by` deadline for a request made at `now`, if confirmation is required.
+    pub fn confirm_deadline(&self, now: Slot) -> Option<u32> {
+        self.confirm_window_mins
+            .map(|window| now.week_mins().saturating_add(window))
+    }
+
+    pub fn find_slot(&self, days: &[Day], ranges: &[TimeRange], apt_type: AptType) -> Option<Slot> {
+        self.find_slot_where(days, ranges, apt_type, |_| true)
+    }
+
+    /// Like [`find_slot`](Self::find_slot), but skips slots that don't meet the lead time from `now`.
+    pub fn find_slot_from(
+        &self,
+        now: Slot,
+        days: &[Day],
+        ranges: &[TimeRange],
+        apt_type: AptType,
+    ) -> Option<Slot> {
+        self.find_slot_where(days, ranges, apt_type, |slot| {
+            self.meets_lead_time(slot, now)
+        })
+    }
+
+    fn find_slot_where(
+        &self,
+        days: &[Day],
+        ranges: &[TimeRange],
+        apt_type: AptType,
+        accept: impl Fn(Slot) -> bool,
+    ) -> Option<Slot> {
+        self.candidate_slots(days, ranges, apt_type)
+            .find(|slot| accept(*slot))
+    }
+
+    /// Available slots for `apt_type` within `ranges` on `days`, in preference order.
+    ///
+    /// Days in the order given, then schedule ranges, then preferred ranges, stepping in
+    /// 15-minute increments within each.
+    fn candidate_slots<'a>(
+        &'a self,
+        days: &'a [Day],
+        ranges: &'a [TimeRange],
+        apt_type: AptType,
+    ) -> impl Iterator<Item = Slot> + 'a {
+        let dur = apt_type.dur();
+        days.iter()
+            .flat_map(move |&day| {
+                self.day_ranges(day).iter().flat_map(move |sched_range| {
+                    ranges
+                        .iter()
+                        .filter_map(|pref_range| sched_range.intersect(pref_range))
+                        .flat_map(move |range| {
+                            iter::successors(Some(range.0), |t| t.add(15))
+                                .take_while(move |t| range.can_fit(*t, dur))
+                                .map(move |time| Slot { day, time })
+                        })
+                })
+            })
+            .filter(move |slot| self.is_available(*slot, apt_type))
+    }
+
+    /// The earliest available slot for `apt_type` anywhere in the schedule that meets the
+    /// lead time from `now`.
+    pub fn soonest_slot(&self, now: Slot, apt_type: AptType) -> Option<Slot> {
+        let whole_day = [TimeRange(Time(0, 0), Time(24, 0))];
+        self.candidate_slots(Day::all(), &whole_day, apt_type)
+            .filter(|slot| self.meets_lead_time(*slot, now))
+            .min_by_key(Slot::week_mins)
+    }
+
+    /// Back-to-back slots on `day` for `members` in order, starting at `start`.
+    ///
+    /// Each member starts as soon as the previous one ends plus the pair's buffer.
+    /// `None` if any member's slot is unavailable.
+    pub fn group_slots(&self, day: Day, start: Time, members: &[MemberReq]) -> Option<Vec<Slot>> {
+        let mut slots: Vec<Slot> = Vec::with_capacity(members.len());
+        let mut prev: Option<(Slot, AptType)> = None;
+        for member in members {
+            let time = match prev {
+                None => start,
+                Some((slot, apt_type)) => slot
+                    .time
+                    .add(apt_type.dur() + self.pair_buffer(apt_type, member.apt_type))?,
             };
+            let slot = Slot { day, time };
+            if !self.is_available(slot, member.apt_type) {
+                return None;
+            }
+            slots.push(slot);
+            prev = Some((slot, member.apt_type));
+        }
+        Some(slots)
+    }
 
-            for sched_range in sched_ranges {
-                for pref_range in ranges {
-                    let start = sched_range.0.max(pref_range.0);
-                    let end = sched_range.1.min(pref_range.1);
-                    if start >= end {
-                        continue;
-                    }
+    /// Assigns slots to a batch of auto-selection requests at once, trying to satisfy as
+    /// many as possible.
+    ///
+    /// Sequential [`find_slot`](Self::find_slot) gives each request its first choice,
+    /// which can take the only slot a later, pickier request could use. This instead
+    /// serves the most constrained request first (fewest available slots, earlier in the
+    /// batch on ties) and gives it the slot overlapping the fewest candidates of the
+    /// requests still waiting (earliest on ties), repeating until every request is
+    /// placed or has no slot left. It is a best-effort heuristic, not an optimal
+    /// matching: some batches can satisfy more requests than it finds.
+    ///
+    /// Read-only: the result is a plan, in batch order, for the caller to submit as
+    /// `RequestSlot` inputs. Deterministic for the same state and batch.
+    pub fn assign_batch(&self, requests: &[AutoRequest]) -> Vec<(ReqId, Option<Slot>)> {
+        let mut scratch = self.clone();
+        let mut assigned = vec![None; requests.len()];
+        let mut remaining: Vec<usize> = (0..requests.len()).collect();
+
+        while !remaining.is_empty() {
+            let candidates: Vec<Vec<Slot>> = remaining
+                .iter()
+                .map(|&i| {
+                    let req = &requests[i];
+                    scratch
+                        .candidate_slots(&req.days, &req.times, req.apt_type)
+                        .filter(|slot| scratch.meets_lead_time(*slot, req.now))
+                        .collect()
+                })
+                .collect();
+            let (pos, _) = candidates
+                .iter()
+                .enumerate()
+                .min_by_key(|(pos, slots)| (slots.len(), remaining[*pos]))
+                .unwrap();
+            let apt_type = requests[remaining[pos]].apt_type;
 
-                    let mut t = start;
-                    while t.add(dur) <= end {
-                        let slot = Slot { day, time: t };
-                        if self.is_available(slot, dur) {
-                            return Some(slot);
-                        }
-                        t = t.add(15); // Try 15-min increments
-                    }
-                }
+            let competing: Vec<(Slot, AptType)> = candidates
+                .iter()
+                .enumerate()
+                .filter(|(other, _)| *other != pos)
+                .flat_map(|(other, slots)| {
+                    let other_type = requests[remaining[other]].apt_type;
+                    slots.iter().map(move |slot| (*slot, other_type))
+                })
+                .collect();
+            let best = candidates[pos].iter().copied().min_by_key(|slot| {
+                let blocked = competing
+                    .iter()
+                    .filter(|(other, other_type)| {
+                        other.day == slot.day
+                            && TimeRange::starting_at(slot.time, apt_type.dur())
+                                .overlaps(&TimeRange::starting_at(other.time, other_type.dur()))
+                    })
+                    .count();
+                (blocked, *slot)
+            });
+
+            let i = remaining.remove(pos);
+            if let Some(slot) = best {
+                // Placeholder booking, only its slot and type matter for availability
+                scratch.bookings.insert(
+                    slot,
+                    ConfirmedBooking {
+                        user_id: 0,
+                        name: String::new(),
+                        email: String::new(),
+                        apt_type,
+                        dur_mins: apt_type.dur(),
+                        amount_paid: Cents::ZERO,
+                        confirm_by: None,
+                        deposit: None,
+                    },
+                );
+                assigned[i] = Some(slot);
             }
         }
-        None
+
+        requests
+            .iter()
+            .zip(assigned)
+            .map(|(req, slot)| (req.req_id, slot))
+            .collect()
+    }
+
+    /// Read-only startup diagnostic for state that restore can't reconcile.
+    ///
+    /// Reports confirmed bookings whose pending record was lost and pending
+    /// `AwaitingPreauth` requests whose slot another user has since booked. Bookings
+    /// come first ordered by slot, then requests ordered by id.
+    pub fn detect_orphans(&self) -> Vec<OrphanReport> {
+        let orphan_bookings: Vec<_> = self
+            .bookings
+            .iter()
+            .filter(|(slot, booking)| {
+                !self.pending.values().any(|p| {
+                    p.status == ReqStatus::SlotConfirmed
+                        && p.slot == Some(**slot)
+                        && p.user_id == booking.user_id
+                })
+            })
+            .map(|(slot, booking)| (*slot, booking.user_id))
+            .collect();
+
+        let taken_requests: Vec<_> = self
+            .pending
+            .iter()
+            .filter(|(_, p)| p.status == ReqStatus::AwaitingPreauth)
+            .filter_map(|(req_id, p)| {
+                let slot = p.slot?;
+                let Some(UnavailableReason::Conflict { slot: booked }) =
+                    self.unavailable_for(slot, p.apt_type, p.apt_type.dur(), None, Some(*req_id))
+                else {
+                    return None;
+                };
+                // Another request's hold isn't a booking
+                let booked_by = self.bookings.get(&booked)?.user_id;
+                (booked_by != p.user_id).then_some((*req_id, slot, booked_by))
+            })
+            .collect();
+
+        orphan_bookings
+            .into_iter()
+            .map(|(slot, user_id)| OrphanReport::BookingWithoutRequest { slot, user_id })
+            .chain(taken_requests.into_iter().map(|(req_id, slot, booked_by)| {
+                OrphanReport::RequestSlotTaken {
+                    req_id,
+                    slot,
+                    booked_by,
+                }
+            }))
+            .collect()
     }
 
     /// Check system invariants for testing
     pub fn check_invariants(&self) -> Result<(), String> {
-        // 1. No overlapping bookings
+        // Each check reports its earliest violation, so the message is stable for a
+        // given state even where it is read from a `HashMap`.
+
+        // 1. No overlapping bookings; in slot order, so the earliest pair is reported
         let bookings_vec: Vec<_> = self.bookings.iter().collect();
         for i in 0..bookings_vec.len() {
             for j in (i + 1)..bookings_vec.len() {
@@ -156,33 +527,51 @@ impl BookingSystem {
                 let (slot2, booking2) = bookings_vec[j];
 
                 if slot1.day == slot2.day {
-                    let end1 = slot1.time.add(booking1.apt_type.dur());
-                    let end2 = slot2.time.add(booking2.apt_type.dur());
+                    let (start1, start2) = (slot1.time.to_mins(), slot2.time.to_mins());
+                    let end1 = start1 + booking1.dur_mins;
+                    let end2 = start2 + booking2.dur_mins;
 
-                    if slot1.time < end2 && end1 > slot2.time {
+                    if start1 < end2 && end1 > start2 {
                         return Err(format!(
                             "Overlapping bookings: {} ({:?}) and {} ({:?})",
                             slot1, booking1.apt_type, slot2, booking2.apt_type
                         ));
                     }
+
+                    // Same policy as `is_available`, so the two can't diverge
+                    let gap = if end1 <= start2 {
+                        start2 - end1
+                    } else {
+                        start1 - end2
+                    };
+                    let buffer = self.pair_buffer(booking1.apt_type, booking2.apt_type);
+                    if gap < buffer {
+                        return Err(format!(
+                            "Bookings too close: {} ({:?}) and {} ({:?}) are {} min apart, buffer is {} min",
+                            slot1, booking1.apt_type, slot2, booking2.apt_type, gap, buffer
+                        ));
+                    }
                 }
             }
         }
 
         // 2. All bookings fit within schedule
         for (slot, booking) in &self.bookings {
-            let Some(ranges) = self.schedule.get(&slot.day) else {
-                return Err(format!("Booking {} on day without schedule", slot));
+            let ranges = match self.schedule.get(&slot.day) {
+                None => return Err(format!("Booking {} on day without schedule", slot)),
+                Some(DaySchedule::Closed) => {
+                    return Err(format!("Booking {} on closed day", slot));
+                }
+                Some(DaySchedule::Open(ranges)) => ranges,
             };
 
             let fits = ranges
                 .iter()
-                .any(|r| r.can_fit(slot.time, booking.apt_type.dur()));
+                .any(|r| r.can_fit(slot.time, booking.dur_mins));
             if !fits {
                 return Err(format!(
                     "Booking {} doesn't fit in schedule (dur: {})",
-                    slot,
-                    booking.apt_type.dur()
+                    slot, booking.dur_mins
                 ));
             }
         }
@@ -203,6 +592,83 @@ impl BookingSystem {
             }
         }
 
+        // 4. No booking overlaps a maintenance block
+        for (slot, booking) in &self.bookings {
+            if let Some(start) = self.maintenance_overlap(*slot, booking.dur_mins) {
+                return Err(format!(
+                    "Booking {} overlaps maintenance block starting {}",
+                    slot, start
+                ));
+            }
+        }
+
+        // 5. Exactly the cancelled requests record why
+        for (req_id, pending) in &self.pending {
+            let cancelled = pending.status == ReqStatus::Cancelled;
+            if cancelled != pending.cancel_reason.is_some() {
+                return Err(format!(
+                    "Request {} is {:?} with cancel reason {:?}",
+                    req_id, pending.status, pending.cancel_reason
+                ));
+            }
+        }
+
+        // 6. Holds belong to requests still awaiting preauth or tentative for that slot
+        let mut holds: Vec<_> = self.holds.iter().collect();
+        holds.sort_unstable();
+        for (slot, req_id) in holds {
+            let held_by_waiting = self.pending.get(req_id).is_some_and(|p| {
+                matches!(
+                    p.status,
+                    ReqStatus::AwaitingPreauth | ReqStatus::Tentative { .. }
+                ) && p.slot == Some(*slot)
+            });
+            if !held_by_waiting {
+                return Err(format!(
+                    "Hold on {} by request {} is not awaiting preauth",
+                    slot, req_id
+                ));
+            }
+        }
+
+        // 7. Tentative requests hold their slot
+        for (req_id, pending) in &self.pending {
+            if matches!(pending.status, ReqStatus::Tentative { .. })
+                && pending
+                    .slot
+                    .is_none_or(|slot| self.holds.get(&slot) != Some(req_id))
+            {
+                return Err(format!("Tentative request {} holds no slot", req_id));
+            }
+        }
+
+        // 8. Only cancelled requests wait on a preauth to release
+        for (req_id, pending) in &self.pending {
+            if pending.preauth_in_flight && pending.status != ReqStatus::Cancelled {
+                return Err(format!(
+                    "Request {} is {:?} with a preauth in flight",
+                    req_id, pending.status
+                ));
+            }
+        }
+
+        // 9. Deposit bookings account for every cent of their price
+        for (slot, booking) in &self.bookings {
+            let Some(deposit) = &booking.deposit else {
+                continue;
+            };
+            let price_cents = to_cents(booking.amount_paid);
+            let accounted = deposit.captured_cents
+                + deposit.capturing_cents.unwrap_or(0)
+                + deposit.outstanding_cents;
+            if accounted != price_cents {
+                return Err(format!(
+                    "Booking {} accounts for {} of {} cents",
+                    slot, accounted, price_cents
+                ));
+            }
+        }
+
         Ok(())
     }
 }
@@ -213,6 +679,46 @@ impl Default for BookingSystem {
     }
 }
 
+impl Versioned for BookingSystem {
+    fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+impl StateDiff for BookingSystem {
+    fn diff(before: &Self, after: &Self) -> Vec<FieldChange> {
+        [
+            FieldChange::entries("schedule", &before.schedule, &after.schedule),
+            FieldChange::entries("bookings", &before.bookings, &after.bookings),
+            FieldChange::entries("pending", &before.pending, &after.pending),
+            FieldChange::value("next_id", &before.next_id, &after.next_id),
+            FieldChange::value("version", &before.version, &after.version),
+            FieldChange::value("min_lead_mins", &before.min_lead_mins, &after.min_lead_mins),
+            FieldChange::value("buffer_mins", &before.buffer_mins, &after.buffer_mins),
+            FieldChange::value(
+                "optimistic_holds",
+                &before.optimistic_holds,
+                &after.optimistic_holds,
+            ),
+            FieldChange::entries("holds", &before.holds, &after.holds),
+            FieldChange::entries("maintenance", &before.maintenance, &after.maintenance),
+            FieldChange::value(
+                "confirm_window_mins",
+                &before.confirm_window_mins,
+                &after.confirm_window_mins,
+            ),
+            FieldChange::value(
+                "deposit_percent",
+                &before.deposit_percent,
+                &after.deposit_percent,
+            ),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
 #[derive(Debug)]
 pub enum BookingInput {
     RequestSlot {
@@ -222,6 +728,12 @@ pub enum BookingInput {
         day: Day,
         time: Time,
         apt_type: AptType,
+        /// Current point in the week, used for the lead-time check.
+        now: Slot,
+        /// Client-supplied idempotency token, so retries of the same request apply once.
+        token: Option<u64>,
+        /// State version the client last saw; rejected with `Conflict` if stale.
+        expected_version: Option<u64>,
     },
     RequestAuto {
         user_id: u64,
@@ -230,21 +742,126 @@ pub enum BookingInput {
         days: Vec<Day>,
         times: Vec<TimeRange>,
         apt_type: AptType,
+        /// Current point in the week, used for the lead-time check.
+        now: Slot,
+        /// Client-supplied idempotency token, so retries of the same request apply once.
+        token: Option<u64>,
+        /// State version the client last saw; rejected with `Conflict` if stale.
+        expected_version: Option<u64>,
+    },
+    /// Books the earliest available slot for `apt_type` from `now`, in any scheduled day.
+    ///
+    /// The slot is held while the preauth is pending even without `optimistic_holds`, so
+    /// concurrent requests get different slots. Fails with `NoSlotFound` if none is free.
+    RequestSoonest {
+        user_id: u64,
+        name: String,
+        email: String,
+        apt_type: AptType,
+        /// Current point in the week; only slots meeting the lead time from it qualify.
+        now: Slot,
+    },
+    /// Books `members` into back-to-back slots on `day` from `start_time`, in order, with
+    /// one preauth of the combined price charged to `payer_id`.
+    ///
+    /// All or nothing: fails with `SlotNotAvailable` if any member's slot is taken, and
+    /// the members are confirmed or released together when the payment resolves.
+    RequestGroup {
+        payer_id: u64,
+        members: Vec<MemberReq>,
+        day: Day,
+        start_time: Time,
+        /// Current point in the week, used for the lead-time check.
+        now: Slot,
+        /// Client-supplied idempotency token, so retries of the same request apply once.
+        token: Option<u64>,
+        /// State version the client last saw; rejected with `Conflict` if stale.
+        expected_version: Option<u64>,
+    },
+    /// Blocks `start..end` on `day` for equipment maintenance.
+    ///
+    /// Fails with `SlotNotAvailable` if a booking overlaps the window, unless `force` is
+    /// set, in which case those bookings are cancelled and their payments released.
+    BlockMaintenance {
+        day: Day,
+        start: Time,
+        end: Time,
+        force: bool,
+    },
+    /// Removes the maintenance block starting at `start` on `day`.
+    ClearMaintenance { day: Day, start: Time },
+    /// Cancels `drop`, a duplicate of the same patient's request `keep`.
+    ///
+    /// Both must be active requests of the same user and not part of a group booking, or
+    /// this fails with `InvalidRequest` and changes nothing. A confirmed `drop` frees its
+    /// slot and has its payment released; one still awaiting preauth is released when
+    /// the preauth succeeds.
+    MergeRequests { keep: ReqId, drop: ReqId },
+    /// Cancels the active request `req_id`, recording `reason`.
+    ///
+    /// A confirmed booking frees its slot and has its payment released; a request still
+    /// awaiting preauth is released when the preauth succeeds. Group members can't be
+    /// cancelled individually, so they fail with `InvalidRequest`.
+    CancelBooking { req_id: ReqId, reason: CancelReason },
+    /// Extends the confirmed booking `req_id` by `extra_mins`, e.g. for an appointment
+    /// running long.
+    ///
+    /// Fails with `SlotNotAvailable` and changes nothing if the longer appointment would
+    /// overlap the next booking (including the buffer between them), a hold or a
+    /// maintenance block, or run past the end of its schedule range.
+    Extend { req_id: ReqId, extra_mins: u16 },
+    /// The patient acknowledges their confirmed booking, clearing its `confirm_by`.
+    PatientConfirm { req_id: ReqId },
+    /// The patient attended the confirmed booking `req_id`, so the rest of its price is
+    /// captured. Fails with `InvalidRequest` if nothing is outstanding or the deposit
+    /// capture hasn't gone through yet.
+    MarkAttended { req_id: ReqId },
+    /// Cancels every booking whose `confirm_by` deadline is before `now`, releasing its
+    /// payment and freeing the slot.
+    ExpireUnconfirmed { now: Slot },
+    /// Holds `slot` for `hold_mins` from `now` without taking payment, e.g. while the
+    /// patient decides. The slot is unavailable to others until the hold is confirmed
+    /// with `ConfirmHold` or expires.
+    ///
+    /// Fails with `SlotNotAvailable` or `TooSoon` like `RequestSlot`, and with
+    /// `InvalidRequest` for a zero-minute hold or one ending past `u32::MAX` minutes.
+    TentativeHold {
+        user_id: u64,
+        name: String,
+        email: String,
+        slot: Slot,
+        apt_type: AptType,
+        now: Slot,
+        hold_mins: u32,
     },
+    /// Turns the tentative hold `req_id` into a request awaiting preauth and emits the
+    /// preauth; the slot stays held. Fails with `InvalidRequest` if `req_id` isn't a
+    /// tentative hold or it expired before `now`.
+    ConfirmHold { req_id: ReqId, now: Slot },
+    /// Releases every tentative hold that expired before `now`.
+    ExpireTentative { now: Slot },
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum BookingError {
     SlotNotAvailable,
     NoSlotFound,
+    /// The slot starts sooner than `min_lead_mins` after `now`.
+    TooSoon,
     InvalidRequest,
     ActionQueueFailed,
+    /// The input's `expected_version` doesn't match the current state version.
+    Conflict,
 }
 
 // Tracked actions
 pub type ReqId = u64;
 
-#[derive(Debug, PartialEq, Eq)]
+/// How long a preauth or status check may take before its request counts as failed,
+/// see [`TrackedAction::with_deadline`].
+pub const PAYMENT_DEADLINE: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PaymentReq {
     Preauth {
         user_id: u64,
@@ -254,6 +871,14 @@ pub enum PaymentReq {
     Release {
         req_id: ReqId,
     },
+    /// Captures part of the preauthorized amount, answered with `Captured`.
+    ///
+    /// `req_id` names the preauth, so for a group booking it's the group's first request.
+    /// The action itself is tracked under the booking's own request.
+    Capture {
+        req_id: ReqId,
+        amount_cents: u32,
+    },
     CheckStatus {
         req_id: ReqId,
     },
@@ -261,9 +886,17 @@ pub enum PaymentReq {
 
 #[derive(Debug)]
 pub enum PaymentResult {
-    Success { amount: f32 },
-    Failed { reason: String },
+    Success {
+        amount: Cents,
+    },
+    Failed {
+        reason: String,
+    },
     Released,
+    /// A `Capture` went through.
+    Captured {
+        amount_cents: u32,
+    },
     Pending,
 }
 
@@ -274,13 +907,37 @@ impl TrackedActionTypes for BookingTracked {
     type Id = ReqId;
     type Action = PaymentReq;
     type Result = PaymentResult;
+
+    /// A preauth or status check that can't get through counts as a failed payment, so
+    /// the request doesn't stay pending forever. A stuck release is only dead-lettered.
+    fn exhausted_result(action: &PaymentReq, last_error: &str) -> Option<PaymentResult> {
+        match action {
+            PaymentReq::Preauth { .. } | PaymentReq::CheckStatus { .. } => {
+                Some(PaymentResult::Failed {
+                    reason: last_error.to_string(),
+                })
+            }
+            PaymentReq::Release { .. } | PaymentReq::Capture { .. } => None,
+        }
+    }
 }
 
 // Untracked actions
 #[derive(Debug, PartialEq, Eq)]
 pub enum UntrackedAction {
-    Notify { user_id: u64, msg: String },
-    Log { event: String },
+    Notify {
+        user_id: u64,
+        msg: String,
+    },
+    Log {
+        event: String,
+    },
+    /// Analytics event for a request moving to `Cancelled`.
+    Cancelled {
+        req_id: ReqId,
+        user_id: u64,
+        reason: CancelReason,
+    },
 }
 
 impl StateMachine for BookingSystem {
@@ -313,17 +970,118 @@ impl StateMachine for BookingSystem {
         state: &'state Self::State,
         actions: &'actions mut Self::Actions,
     ) -> Self::RestoreFuture<'state, 'actions> {
-        let _ = actions.clear();
-        for (id, pending) in &state.pending {
-            if pending.status == ReqStatus::AwaitingPreauth {
-                let _ = actions.add(Action::Tracked(TrackedAction::new(
+        actions.clear();
+        let restored = state.pending.iter().flat_map(|(id, pending)| {
+            // A group's preauth is tracked under its first request only
+            let awaiting = pending.status == ReqStatus::AwaitingPreauth
+                && pending.group_id.is_none_or(|group_id| group_id == *id);
+            // Still to be released if it succeeds, so keep asking, with a fresh deadline
+            let preauth = (awaiting || pending.preauth_in_flight).then(|| {
+                TrackedAction::with_deadline(
                     *id,
                     PaymentReq::CheckStatus { req_id: *id },
-                )));
-            }
-        }
+                    PAYMENT_DEADLINE,
+                )
+            });
+            // Captures still in flight
+            let capture = pending
+                .slot
+                .filter(|_| pending.status == ReqStatus::SlotConfirmed)
+                .and_then(|slot| state.bookings.get(&slot)?.deposit.as_ref()?.capturing_cents)
+                .map(|amount_cents| TrackedAction::new(*id, state.capture(*id, amount_cents)));
+            preauth.into_iter().chain(capture).map(Action::Tracked)
+        });
+        let _ = actions.add_all(restored);
         future::ready(Ok(()))
     }
+
+    fn is_known_tracked_id(state: &Self::State, id: &ReqId) -> bool {
+        state.pending.contains_key(id)
+    }
+
+    fn approx_state_size(state: &Self::State) -> StateSize {
+        StateSize::new([
+            ("pending", state.pending.len()),
+            ("bookings", state.bookings.len()),
+            ("holds", state.holds.len()),
+            ("maintenance", state.maintenance.len()),
+            ("schedule", state.schedule.len()),
+        ])
+    }
+
+    fn input_variants() -> &'static [&'static str] {
+        &["request_slot", "request_auto"]
+    }
+
+    #[cfg(feature = "serde")]
+    fn parse_input(
+        name: &str,
+        args: &serde_json::Value,
+    ) -> Result<Self::Input, phasm::InputParseError> {
+        json::parse_input(name, args)
+    }
+
+    /// A stale version or a slot someone else got first may succeed after a refresh.
+    fn is_conflict(error: &BookingError) -> bool {
+        matches!(
+            error,
+            BookingError::Conflict | BookingError::SlotNotAvailable
+        )
+    }
+
+    fn is_read_only(input: &BookingInput) -> bool {
+        // Every input books, cancels or expires something; query-only inputs go here
+        match input {
+            BookingInput::RequestSlot { .. }
+            | BookingInput::RequestAuto { .. }
+            | BookingInput::RequestSoonest { .. }
+            | BookingInput::RequestGroup { .. }
+            | BookingInput::BlockMaintenance { .. }
+            | BookingInput::ClearMaintenance { .. }
+            | BookingInput::MergeRequests { .. }
+            | BookingInput::CancelBooking { .. }
+            | BookingInput::Extend { .. }
+            | BookingInput::PatientConfirm { .. }
+            | BookingInput::MarkAttended { .. }
+            | BookingInput::ExpireUnconfirmed { .. }
+            | BookingInput::TentativeHold { .. }
+            | BookingInput::ConfirmHold { .. }
+            | BookingInput::ExpireTentative { .. } => false,
+        }
+    }
+
+    fn check_invariants(state: &Self) -> Result<(), String> {
+        state.check_invariants()
+    }
+
+    /// Orphans are reported as issues without failing the invariants.
+    fn health(state: &Self) -> HealthReport {
+        let mut report = HealthReport::from_invariants(state.check_invariants());
+        report
+            .issues
+            .extend(state.detect_orphans().iter().map(OrphanReport::to_string));
+        report
+    }
+
+    fn input_key(input: &Self::Input) -> Option<u64> {
+        match input {
+            BookingInput::RequestSlot { token, .. }
+            | BookingInput::RequestAuto { token, .. }
+            | BookingInput::RequestGroup { token, .. } => *token,
+            BookingInput::RequestSoonest { .. }
+            | BookingInput::BlockMaintenance { .. }
+            | BookingInput::ClearMaintenance { .. }
+            | BookingInput::MergeRequests { .. }
+            | BookingInput::CancelBooking { .. }
+            | BookingInput::Extend { .. }
+            | BookingInput::PatientConfirm { .. }
+            | BookingInput::MarkAttended { .. }
+            | BookingInput::ExpireUnconfirmed { .. }
+            | BookingInput::TentativeHold { .. }
+            | BookingInput::ConfirmHold { .. }
+            | BookingInput::ExpireTentative { .. } => None,
+        }
+    }
 }
 
 pub struct BookingFuture<'s, 'a> {
@@ -346,6 +1104,7 @@ impl<'s, 'a> future::Future for BookingFuture<'s, 'a> {
                 email: String,
                 slot: Slot,
                 apt_type: AptType,
+                now: Slot,
             },
             Auto {
                 user_id: u64,
@@ -354,16 +1113,116 @@ impl<'s, 'a> future::Future for BookingFuture<'s, 'a> {
                 days: Vec<Day>,
                 times: Vec<TimeRange>,
                 apt_type: AptType,
+                now: Slot,
+            },
+            Soonest {
+                user_id: u64,
+                name: String,
+                email: String,
+                apt_type: AptType,
+                now: Slot,
+            },
+            Group {
+                payer_id: u64,
+                members: Vec<MemberReq>,
+                start: Slot,
+                now: Slot,
             },
             Success {
                 req_id: ReqId,
-                amount: f32,
+                amount: Cents,
             },
             Failed {
                 req_id: ReqId,
                 reason: String,
             },
-            Other,
+            Released {
+                req_id: ReqId,
+            },
+            Captured {
+                req_id: ReqId,
+                amount_cents: u32,
+            },
+            Pending {
+                req_id: ReqId,
+            },
+            Block {
+                start: Slot,
+                end: Time,
+                force: bool,
+            },
+            Clear {
+                start: Slot,
+            },
+            Merge {
+                keep: ReqId,
+                drop: ReqId,
+            },
+            Cancel {
+                req_id: ReqId,
+                reason: CancelReason,
+            },
+            Extend {
+                req_id: ReqId,
+                extra_mins: u16,
+            },
+            Acknowledge {
+                req_id: ReqId,
+            },
+            Attended {
+                req_id: ReqId,
+            },
+            Expire {
+                now: Slot,
+            },
+            Tentative {
+                user_id: u64,
+                name: String,
+                email: String,
+                slot: Slot,
+                apt_type: AptType,
+                now: Slot,
+                hold_mins: u32,
+            },
+            ConfirmHold {
+                req_id: ReqId,
+                now: Slot,
+            },
+            ExpireTentative {
+                now: Slot,
+            },
+        }
+
+        let expected_version = match &self.input {
+            Input::Normal(
+                BookingInput::RequestSlot {
+                    expected_version, ..
+                }
+                | BookingInput::RequestAuto {
+                    expected_version, ..
+                }
+                | BookingInput::RequestGroup {
+                    expected_version, ..
+                },
+            ) => *expected_version,
+            Input::Normal(
+                BookingInput::RequestSoonest { .. }
+                | BookingInput::BlockMaintenance { .. }
+                | BookingInput::ClearMaintenance { .. }
+                | BookingInput::MergeRequests { .. }
+                | BookingInput::CancelBooking { .. }
+                | BookingInput::Extend { .. }
+                | BookingInput::PatientConfirm { .. }
+                | BookingInput::MarkAttended { .. }
+                | BookingInput::ExpireUnconfirmed { .. }
+                | BookingInput::TentativeHold { .. }
+                | BookingInput::ConfirmHold { .. }
+                | BookingInput::ExpireTentative { .. },
+            ) => None,
+            Input::TrackedActionCompleted { .. } | Input::TrackedActionExpired { .. } => None,
+        };
+        if expected_version.is_some_and(|v| v != self.state.version) {
+            return Poll::Ready(Err(BookingError::Conflict));
         }
 
         let action = match &self.input {
@@ -374,6 +1233,8 @@ impl<'s, 'a> future::Future for BookingFuture<'s, 'a> {
                 day,
                 time,
                 apt_type,
+                now,
+                ..
             }) => Action::Slot {
                 user_id: *user_id,
                 name: name.clone(),
@@ -383,6 +1244,7 @@ impl<'s, 'a> future::Future for BookingFuture<'s, 'a> {
                     time: *time,
                 },
                 apt_type: *apt_type,
+                now: *now,
             },
             Input::Normal(BookingInput::RequestAuto {
                 user_id,
@@ -391,6 +1253,8 @@ impl<'s, 'a> future::Future for BookingFuture<'s, 'a> {
                 days,
                 times,
                 apt_type,
+                now,
+                ..
             }) => Action::Auto {
                 user_id: *user_id,
                 name: name.clone(),
@@ -398,7 +1262,99 @@ impl<'s, 'a> future::Future for BookingFuture<'s, 'a> {
                 days: days.clone(),
                 times: times.clone(),
                 apt_type: *apt_type,
+                now: *now,
+            },
+            Input::Normal(BookingInput::RequestSoonest {
+                user_id,
+                name,
+                email,
+                apt_type,
+                now,
+            }) => Action::Soonest {
+                user_id: *user_id,
+                name: name.clone(),
+                email: email.clone(),
+                apt_type: *apt_type,
+                now: *now,
+            },
+            Input::Normal(BookingInput::RequestGroup {
+                payer_id,
+                members,
+                day,
+                start_time,
+                now,
+                ..
+            }) => Action::Group {
+                payer_id: *payer_id,
+                members: members.clone(),
+                start: Slot {
+                    day: *day,
+                    time: *start_time,
+                },
+                now: *now,
+            },
+            Input::Normal(BookingInput::BlockMaintenance {
+                day,
+                start,
+                end,
+                force,
+            }) => Action::Block {
+                start: Slot {
+                    day: *day,
+                    time: *start,
+                },
+                end: *end,
+                force: *force,
+            },
+            Input::Normal(BookingInput::ClearMaintenance { day, start }) => Action::Clear {
+                start: Slot {
+                    day: *day,
+                    time: *start,
+                },
+            },
+            Input::Normal(BookingInput::MergeRequests { keep, drop }) => Action::Merge {
+                keep: *keep,
+                drop: *drop,
+            },
+            Input::Normal(BookingInput::CancelBooking { req_id, reason }) => Action::Cancel {
+                req_id: *req_id,
+                reason: *reason,
+            },
+            Input::Normal(BookingInput::Extend { req_id, extra_mins }) => Action::Extend {
+                req_id: *req_id,
+                extra_mins: *extra_mins,
+            },
+            Input::Normal(BookingInput::PatientConfirm { req_id }) => {
+                Action::Acknowledge { req_id: *req_id }
+            }
+            Input::Normal(BookingInput::MarkAttended { req_id }) => {
+                Action::Attended { req_id: *req_id }
+            }
+            Input::Normal(BookingInput::ExpireUnconfirmed { now }) => Action::Expire { now: *now },
+            Input::Normal(BookingInput::TentativeHold {
+                user_id,
+                name,
+                email,
+                slot,
+                apt_type,
+                now,
+                hold_mins,
+            }) => Action::Tentative {
+                user_id: *user_id,
+                name: name.clone(),
+                email: email.clone(),
+                slot: *slot,
+                apt_type: *apt_type,
+                now: *now,
+                hold_mins: *hold_mins,
             },
+            Input::Normal(BookingInput::ConfirmHold { req_id, now }) => Action::ConfirmHold {
+                req_id: *req_id,
+                now: *now,
+            },
+            Input::Normal(BookingInput::ExpireTentative { now }) => {
+                Action::ExpireTentative { now: *now }
+            }
             Input::TrackedActionCompleted { id, res } => match res {
                 PaymentResult::Success { amount } => Action::Success {
                     req_id: *id,
@@ -408,7 +1364,16 @@ impl<'s, 'a> future::Future for BookingFuture<'s, 'a> {
                     req_id: *id,
                     reason: reason.clone(),
                 },
-                _ => Action::Other,
+                PaymentResult::Released => Action::Released { req_id: *id },
+                PaymentResult::Captured { amount_cents } => Action::Captured {
+                    req_id: *id,
+                    amount_cents: *amount_cents,
+                },
+                PaymentResult::Pending => Action::Pending { req_id: *id },
+            },
+            Input::TrackedActionExpired { id } => Action::Failed {
+                req_id: *id,
+                reason: "payment timed out".to_string(),
             },
         };
 
@@ -419,7 +1384,8 @@ impl<'s, 'a> future::Future for BookingFuture<'s, 'a> {
                 email,
                 slot,
                 apt_type,
-            } => self.handle_slot(user_id, name, email, slot, apt_type),
+                now,
+            } => self.handle_slot(user_id, name, email, slot, apt_type, now),
             Action::Auto {
                 user_id,
                 name,
@@ -427,11 +1393,52 @@ impl<'s, 'a> future::Future for BookingFuture<'s, 'a> {
                 days,
                 times,
                 apt_type,
-            } => self.handle_auto(user_id, name, email, days, times, apt_type),
+                now,
+            } => self.handle_auto(user_id, name, email, days, times, apt_type, now),
+            Action::Soonest {
+                user_id,
+                name,
+                email,
+                apt_type,
+                now,
+            } => self.handle_soonest(user_id, name, email, apt_type, now),
+            Action::Group {
+                payer_id,
+                members,
+                start,
+                now,
+            } => self.handle_group(payer_id, members, start, now),
             Action::Success { req_id, amount } => self.handle_success(req_id, amount),
             Action::Failed { req_id, reason } => self.handle_failed(req_id, reason),
-            Action::Other => Ok(()),
-        };
+            Action::Released { req_id } => self.handle_released(req_id),
+            Action::Captured {
+                req_id,
+                amount_cents,
+            } => self.handle_captured(req_id, amount_cents),
+            Action::Pending { req_id } => self.handle_pending(req_id),
+            Action::Block { start, end, force } => self.handle_block(start, end, force),
+            Action::Clear { start } => self.handle_clear(start),
+            Action::Merge { keep, drop } => self.handle_merge(keep, drop),
+            Action::Cancel { req_id, reason } => self.handle_cancel(req_id, reason),
+            Action::Extend { req_id, extra_mins } => self.handle_extend(req_id, extra_mins),
+            Action::Acknowledge { req_id } => self.handle_acknowledge(req_id),
+            Action::Attended { req_id } => self.handle_attended(req_id),
+            Action::Expire { now } => self.handle_expire(now),
+            Action::Tentative {
+                user_id,
+                name,
+                email,
+                slot,
+                apt_type,
+                now,
+                hold_mins,
+            } => self.handle_tentative(user_id, name, email, slot, apt_type, now, hold_mins),
+            Action::ConfirmHold { req_id, now } => self.handle_confirm_hold(req_id, now),
+            Action::ExpireTentative { now } => self.handle_expire_tentative(now),
+        };
+        if result.is_ok() {
+            self.state.version += 1;
+        }
         Poll::Ready(result)
     }
 }
@@ -444,40 +1451,20 @@ impl<'s, 'a> BookingFuture<'s, 'a> {
         email: String,
         slot: Slot,
         apt_type: AptType,
+        now: Slot,
     ) -> Result<(), BookingError> {
-        if !self.state.is_available(slot, apt_type.dur()) {
+        if !self.state.meets_lead_time(slot, now) {
+            return Err(BookingError::TooSoon);
+        }
+        if !self.state.is_available(slot, apt_type) {
             return Err(BookingError::SlotNotAvailable);
         }
 
-        let id = self.state.next_id;
-        self.state.next_id += 1;
-
-        self.state.pending.insert(
-            id,
-            PendingReq {
-                user_id,
-                name,
-                email,
-                slot: Some(slot),
-                apt_type,
-                status: ReqStatus::AwaitingPreauth,
-            },
-        );
-
-        self.actions
-            .add(Action::Tracked(TrackedAction::new(
-                id,
-                PaymentReq::Preauth {
-                    user_id,
-                    amount_cents: (apt_type.price() * 100.0) as u32,
-                    req_id: id,
-                },
-            )))
-            .map_err(|_| BookingError::ActionQueueFailed)?;
-
-        Ok(())
+        let hold = self.state.optimistic_holds;
+        self.reserve(user_id, name, email, slot, apt_type, now, hold)
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn handle_auto(
         &mut self,
         user_id: u64,
@@ -486,15 +1473,52 @@ impl<'s, 'a> BookingFuture<'s, 'a> {
         days: Vec<Day>,
         times: Vec<TimeRange>,
         apt_type: AptType,
+        now: Slot,
+    ) -> Result<(), BookingError> {
+        let Some(slot) = self.state.find_slot_from(now, &days, &times, apt_type) else {
+            // Distinguish "everything free is too soon" from "nothing free at all"
+            return Err(match self.state.find_slot(&days, &times, apt_type) {
+                Some(_) => BookingError::TooSoon,
+                None => BookingError::NoSlotFound,
+            });
+        };
+
+        let hold = self.state.optimistic_holds;
+        self.reserve(user_id, name, email, slot, apt_type, now, hold)
+    }
+
+    fn handle_soonest(
+        &mut self,
+        user_id: u64,
+        name: String,
+        email: String,
+        apt_type: AptType,
+        now: Slot,
     ) -> Result<(), BookingError> {
         let slot = self
             .state
-            .find_slot(&days, &times, apt_type.dur())
+            .soonest_slot(now, apt_type)
             .ok_or(BookingError::NoSlotFound)?;
+        self.reserve(user_id, name, email, slot, apt_type, now, true)
+    }
 
+    /// Records a request for `slot` awaiting preauth and emits the preauth, holding the
+    /// slot if `hold`.
+    #[allow(clippy::too_many_arguments)]
+    fn reserve(
+        &mut self,
+        user_id: u64,
+        name: String,
+        email: String,
+        slot: Slot,
+        apt_type: AptType,
+        now: Slot,
+        hold: bool,
+    ) -> Result<(), BookingError> {
         let id = self.state.next_id;
         self.state.next_id += 1;
 
+        let confirm_by = self.state.confirm_deadline(now);
         self.state.pending.insert(
             id,
             PendingReq {
@@ -504,25 +1528,169 @@ impl<'s, 'a> BookingFuture<'s, 'a> {
                 slot: Some(slot),
                 apt_type,
                 status: ReqStatus::AwaitingPreauth,
+                confirm_by,
+                group_id: None,
+                cancel_reason: None,
+                preauth_in_flight: false,
             },
         );
+        if hold {
+            self.state.holds.insert(slot, id);
+        }
 
         self.actions
-            .add(Action::Tracked(TrackedAction::new(
+            .add(Action::Tracked(TrackedAction::with_deadline(
                 id,
                 PaymentReq::Preauth {
                     user_id,
-                    amount_cents: (apt_type.price() * 100.0) as u32,
+                    amount_cents: apt_type.price_cents(),
                     req_id: id,
                 },
+                PAYMENT_DEADLINE,
+            )))
+            .map_err(|_| BookingError::ActionQueueFailed)?;
+
+        Ok(())
+    }
+
+    fn handle_group(
+        &mut self,
+        payer_id: u64,
+        members: Vec<MemberReq>,
+        start: Slot,
+        now: Slot,
+    ) -> Result<(), BookingError> {
+        if members.is_empty() {
+            return Err(BookingError::InvalidRequest);
+        }
+        if !self.state.meets_lead_time(start, now) {
+            return Err(BookingError::TooSoon);
+        }
+        // Place everyone before reserving anything, so a failure leaves no trace
+        let slots = self
+            .state
+            .group_slots(start.day, start.time, &members)
+            .ok_or(BookingError::SlotNotAvailable)?;
+
+        let group_id = self.state.next_id;
+        let confirm_by = self.state.confirm_deadline(now);
+        let amount_cents = members.iter().map(|m| m.apt_type.price_cents()).sum();
+        for (member, slot) in members.into_iter().zip(slots) {
+            let id = self.state.next_id;
+            self.state.next_id += 1;
+            self.state.pending.insert(
+                id,
+                PendingReq {
+                    user_id: member.user_id,
+                    name: member.name,
+                    email: member.email,
+                    slot: Some(slot),
+                    apt_type: member.apt_type,
+                    status: ReqStatus::AwaitingPreauth,
+                    confirm_by,
+                    group_id: Some(group_id),
+                    cancel_reason: None,
+                    preauth_in_flight: false,
+                },
+            );
+            if self.state.optimistic_holds {
+                self.state.holds.insert(slot, id);
+            }
+        }
+
+        self.actions
+            .add(Action::Tracked(TrackedAction::with_deadline(
+                group_id,
+                PaymentReq::Preauth {
+                    user_id: payer_id,
+                    amount_cents,
+                    req_id: group_id,
+                },
+                PAYMENT_DEADLINE,
             )))
             .map_err(|_| BookingError::ActionQueueFailed)?;
 
         Ok(())
     }
 
-    fn handle_success(&mut self, req_id: ReqId, amount: f32) -> Result<(), BookingError> {
-        let (slot, apt_type, user_id, name, email) = {
+    /// `req_id` and, if it leads a group booking, the other members' requests.
+    fn group_members(&self, req_id: ReqId) -> Vec<ReqId> {
+        let leads_group = self
+            .state
+            .pending
+            .get(&req_id)
+            .is_some_and(|p| p.group_id == Some(req_id));
+        if !leads_group {
+            return vec![req_id];
+        }
+        self.state
+            .pending
+            .iter()
+            .filter(|(_, p)| p.group_id == Some(req_id))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Confirms every member of the group led by `group_id`, or none if any slot is taken.
+    fn handle_group_success(&mut self, group_id: ReqId) -> Result<(), BookingError> {
+        let members = self.group_members(group_id);
+        // Our own holds mustn't count against us
+        for id in &members {
+            self.release_hold(*id);
+        }
+
+        // Members never conflict with each other, only with what's booked since
+        let all_free = members.iter().all(|id| {
+            let pending = &self.state.pending[id];
+            pending
+                .slot
+                .is_some_and(|slot| self.state.is_available(slot, pending.apt_type))
+        });
+        if !all_free {
+            for id in &members {
+                self.state.pending.get_mut(id).unwrap().status = ReqStatus::SlotTaken;
+            }
+            self.actions
+                .add(Action::Tracked(TrackedAction::new(
+                    group_id,
+                    PaymentReq::Release { req_id: group_id },
+                )))
+                .ok();
+            return Ok(());
+        }
+
+        // Each member pays its own deposit out of the group's preauth
+        let mut deposits = Vec::with_capacity(members.len());
+        for id in &members {
+            let price_cents = self.state.pending[id].apt_type.price_cents();
+            deposits.push(self.take_deposit(*id, price_cents)?);
+        }
+
+        for (id, deposit) in members.into_iter().zip(deposits) {
+            let pending = self.state.pending.get_mut(&id).unwrap();
+            pending.status = ReqStatus::SlotConfirmed;
+            self.state.bookings.insert(
+                pending.slot.unwrap(),
+                ConfirmedBooking {
+                    user_id: pending.user_id,
+                    name: pending.name.clone(),
+                    email: pending.email.clone(),
+                    apt_type: pending.apt_type,
+                    dur_mins: pending.apt_type.dur(),
+                    amount_paid: pending.apt_type.price(),
+                    confirm_by: pending.confirm_by,
+                    deposit,
+                },
+            );
+        }
+        Ok(())
+    }
+
+    fn handle_success(&mut self, req_id: ReqId, amount: Cents) -> Result<(), BookingError> {
+        if self.group_members(req_id).len() > 1 {
+            return self.handle_group_success(req_id);
+        }
+        let (slot, apt_type, user_id, name, email, confirm_by) = {
             let pending = self
                 .state
                 .pending
@@ -532,6 +1700,16 @@ impl<'s, 'a> BookingFuture<'s, 'a> {
             let Some(slot) = pending.slot else {
                 return Err(BookingError::InvalidRequest);
             };
+            // Cancelled while awaiting this preauth, so refund it
+            if pending.status == ReqStatus::Cancelled {
+                self.actions
+                    .add(Action::Tracked(TrackedAction::new(
+                        req_id,
+                        PaymentReq::Release { req_id },
+                    )))
+                    .map_err(|_| BookingError::ActionQueueFailed)?;
+                return Ok(());
+            }
 
             (
                 slot,
@@ -539,11 +1717,15 @@ impl<'s, 'a> BookingFuture<'s, 'a> {
                 pending.user_id,
                 pending.name.clone(),
                 pending.email.clone(),
+                pending.confirm_by,
             )
         };
 
+        // Our own hold mustn't count against us
+        self.release_hold(req_id);
+
         // Race condition check
-        if !self.state.is_available(slot, apt_type.dur()) {
+        if !self.state.is_available(slot, apt_type) {
             let pending = self.state.pending.get_mut(&req_id).unwrap();
             pending.status = ReqStatus::SlotTaken;
             self.actions
@@ -555,6 +1737,8 @@ impl<'s, 'a> BookingFuture<'s, 'a> {
             return Ok(());
         }
 
+        let deposit = self.take_deposit(req_id, to_cents(amount))?;
+
         // Confirm booking
         let pending = self.state.pending.get_mut(&req_id).unwrap();
         pending.status = ReqStatus::SlotConfirmed;
@@ -565,16 +1749,485 @@ impl<'s, 'a> BookingFuture<'s, 'a> {
                 name,
                 email,
                 apt_type,
+                dur_mins: apt_type.dur(),
                 amount_paid: amount,
+                confirm_by,
+                deposit,
             },
         );
 
         Ok(())
     }
 
+    /// Captures the deposit on `price_cents` for the booking of `req_id` now, leaving the
+    /// rest for when the patient attends. `None` if the clinic doesn't take deposits.
+    fn take_deposit(
+        &mut self,
+        req_id: ReqId,
+        price_cents: u32,
+    ) -> Result<Option<Deposit>, BookingError> {
+        let Some(percent) = self.state.deposit_percent else {
+            return Ok(None);
+        };
+        // Widened so large prices can't overflow
+        let deposit_cents = (u64::from(price_cents) * u64::from(percent.min(100)) / 100) as u32;
+        self.actions
+            .add(Action::Tracked(TrackedAction::new(
+                req_id,
+                self.state.capture(req_id, deposit_cents),
+            )))
+            .map_err(|_| BookingError::ActionQueueFailed)?;
+        Ok(Some(Deposit {
+            captured_cents: 0,
+            capturing_cents: Some(deposit_cents),
+            outstanding_cents: price_cents - deposit_cents,
+        }))
+    }
+
+    /// The deposit of the confirmed booking `req_id`, if it has one.
+    fn deposit_mut(&mut self, req_id: ReqId) -> Option<&mut Deposit> {
+        let pending = self.state.pending.get(&req_id)?;
+        if pending.status != ReqStatus::SlotConfirmed {
+            return None;
+        }
+        self.state
+            .bookings
+            .get_mut(&pending.slot?)?
+            .deposit
+            .as_mut()
+    }
+
+    fn handle_captured(&mut self, req_id: ReqId, amount_cents: u32) -> Result<(), BookingError> {
+        let deposit = self
+            .deposit_mut(req_id)
+            .ok_or(BookingError::InvalidRequest)?;
+        if deposit.capturing_cents != Some(amount_cents) {
+            return Err(BookingError::InvalidRequest);
+        }
+        deposit.capturing_cents = None;
+        deposit.captured_cents += amount_cents;
+        Ok(())
+    }
+
+    fn handle_attended(&mut self, req_id: ReqId) -> Result<(), BookingError> {
+        let deposit = self
+            .deposit_mut(req_id)
+            .ok_or(BookingError::InvalidRequest)?;
+        if deposit.capturing_cents.is_some() || deposit.outstanding_cents == 0 {
+            return Err(BookingError::InvalidRequest);
+        }
+        let amount_cents = deposit.outstanding_cents;
+        self.actions
+            .add(Action::Tracked(TrackedAction::new(
+                req_id,
+                self.state.capture(req_id, amount_cents),
+            )))
+            .map_err(|_| BookingError::ActionQueueFailed)?;
+        let deposit = self.deposit_mut(req_id).unwrap();
+        deposit.outstanding_cents = 0;
+        deposit.capturing_cents = Some(amount_cents);
+        Ok(())
+    }
+
     fn handle_failed(&mut self, req_id: ReqId, _reason: String) -> Result<(), BookingError> {
-        if let Some(pending) = self.state.pending.get_mut(&req_id) {
-            pending.status = ReqStatus::NoSlot;
+        for id in self.group_members(req_id) {
+            self.release_hold(id);
+            if let Some(pending) = self.state.pending.get_mut(&id) {
+                pending.preauth_in_flight = false;
+                // A cancelled request stays cancelled, whatever became of its preauth
+                if pending.status != ReqStatus::Cancelled {
+                    pending.status = ReqStatus::NoSlot;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_released(&mut self, req_id: ReqId) -> Result<(), BookingError> {
+        let members = self.group_members(req_id);
+        for id in &members {
+            let pending = self
+                .state
+                .pending
+                .get(id)
+                .ok_or(BookingError::InvalidRequest)?;
+            // A confirmed booking was paid for, its hold can't have been released
+            if pending.status == ReqStatus::SlotConfirmed {
+                return Err(BookingError::InvalidRequest);
+            }
+        }
+
+        for id in members {
+            let pending = self.state.pending.get_mut(&id).unwrap();
+            pending.preauth_in_flight = false;
+            if pending.status != ReqStatus::Cancelled {
+                pending.status = ReqStatus::Released;
+            }
+            self.release_hold(id);
+        }
+        Ok(())
+    }
+
+    fn release_hold(&mut self, req_id: ReqId) {
+        self.state.holds.retain(|_, held_by| *held_by != req_id);
+    }
+
+    fn handle_pending(&mut self, req_id: ReqId) -> Result<(), BookingError> {
+        if !self.state.pending.contains_key(&req_id) {
+            return Err(BookingError::InvalidRequest);
+        }
+
+        // Processor hasn't decided yet, ask again
+        self.actions
+            .add(Action::Tracked(TrackedAction::with_deadline(
+                req_id,
+                PaymentReq::CheckStatus { req_id },
+                PAYMENT_DEADLINE,
+            )))
+            .map_err(|_| BookingError::ActionQueueFailed)?;
+        Ok(())
+    }
+
+    fn handle_block(&mut self, start: Slot, end: Time, force: bool) -> Result<(), BookingError> {
+        if start.time >= end {
+            return Err(BookingError::InvalidRequest);
+        }
+        let window = TimeRange::new(start.time, end);
+        let overlapping: Vec<Slot> = self
+            .state
+            .bookings
+            .iter()
+            .filter(|(slot, booking)| {
+                slot.day == start.day
+                    && TimeRange::starting_at(slot.time, booking.dur_mins).overlaps(&window)
+            })
+            .map(|(slot, _)| *slot)
+            .collect();
+        if !overlapping.is_empty() && !force {
+            return Err(BookingError::SlotNotAvailable);
+        }
+
+        let reason = format!("for maintenance ({})", window);
+        for slot in overlapping {
+            let req_id = self.cancel_booking(slot, ReqStatus::Cancelled, &reason)?;
+            if let Some(req_id) = req_id {
+                self.record_cancel(req_id, CancelReason::Maintenance)?;
+            }
+        }
+        self.state.maintenance.insert(start, end);
+        Ok(())
+    }
+
+    /// Cancels the booking at `slot`, releasing its payment and notifying the patient.
+    ///
+    /// The request moves to `status`, and the notification ends with `reason`. Returns
+    /// the request, if the booking had one.
+    fn cancel_booking(
+        &mut self,
+        slot: Slot,
+        status: ReqStatus,
+        reason: &str,
+    ) -> Result<Option<ReqId>, BookingError> {
+        let booking = self.state.bookings.remove(&slot).unwrap();
+        let req_id = self
+            .state
+            .pending
+            .iter_mut()
+            .find(|(_, p)| p.status == ReqStatus::SlotConfirmed && p.slot == Some(slot))
+            .map(|(req_id, pending)| {
+                pending.status = status;
+                *req_id
+            });
+
+        if let Some(req_id) = req_id {
+            self.actions
+                .add(Action::Tracked(TrackedAction::new(
+                    req_id,
+                    PaymentReq::Release { req_id },
+                )))
+                .map_err(|_| BookingError::ActionQueueFailed)?;
+        }
+        self.actions
+            .add(Action::Untracked(UntrackedAction::Notify {
+                user_id: booking.user_id,
+                msg: format!(
+                    "Your {} on {} was cancelled {}",
+                    booking.apt_type.name(),
+                    slot,
+                    reason
+                ),
+            }))
+            .map_err(|_| BookingError::ActionQueueFailed)?;
+        Ok(req_id)
+    }
+
+    /// Marks `req_id` cancelled for `reason` and reports it to analytics.
+    fn record_cancel(&mut self, req_id: ReqId, reason: CancelReason) -> Result<(), BookingError> {
+        let pending = self.state.pending.get_mut(&req_id).unwrap();
+        pending.status = ReqStatus::Cancelled;
+        pending.cancel_reason = Some(reason);
+        let user_id = pending.user_id;
+        self.actions
+            .add(Action::Untracked(UntrackedAction::Cancelled {
+                req_id,
+                user_id,
+                reason,
+            }))
+            .map_err(|_| BookingError::ActionQueueFailed)
+    }
+
+    fn handle_cancel(&mut self, req_id: ReqId, reason: CancelReason) -> Result<(), BookingError> {
+        let pending = self
+            .state
+            .pending
+            .get(&req_id)
+            .ok_or(BookingError::InvalidRequest)?;
+        if !pending.status.is_active() || pending.group_id.is_some() {
+            return Err(BookingError::InvalidRequest);
+        }
+
+        if pending.status == ReqStatus::SlotConfirmed {
+            let slot = pending.slot.ok_or(BookingError::InvalidRequest)?;
+            self.cancel_booking(slot, ReqStatus::Cancelled, &reason.to_string())?;
+        } else {
+            // Released when the preauth succeeds
+            let awaiting = pending.status == ReqStatus::AwaitingPreauth;
+            self.state
+                .pending
+                .get_mut(&req_id)
+                .unwrap()
+                .preauth_in_flight = awaiting;
+            self.release_hold(req_id);
+        }
+        self.record_cancel(req_id, reason)
+    }
+
+    fn handle_clear(&mut self, start: Slot) -> Result<(), BookingError> {
+        self.state
+            .maintenance
+            .remove(&start)
+            .map(|_| ())
+            .ok_or(BookingError::InvalidRequest)
+    }
+
+    fn handle_merge(&mut self, keep: ReqId, drop: ReqId) -> Result<(), BookingError> {
+        let (Some(kept), Some(dropped)) =
+            (self.state.pending.get(&keep), self.state.pending.get(&drop))
+        else {
+            return Err(BookingError::InvalidRequest);
+        };
+        if keep == drop
+            || kept.user_id != dropped.user_id
+            || !kept.status.is_active()
+            || !dropped.status.is_active()
+            || kept.group_id.is_some()
+            || dropped.group_id.is_some()
+        {
+            return Err(BookingError::InvalidRequest);
+        }
+        let was_confirmed = dropped.status == ReqStatus::SlotConfirmed;
+        let preauth_in_flight = dropped.status == ReqStatus::AwaitingPreauth;
+        let slot = dropped.slot;
+
+        self.release_hold(drop);
+        if was_confirmed {
+            if let Some(slot) = slot {
+                self.state.bookings.remove(&slot);
+            }
+            self.actions
+                .add(Action::Tracked(TrackedAction::new(
+                    drop,
+                    PaymentReq::Release { req_id: drop },
+                )))
+                .map_err(|_| BookingError::ActionQueueFailed)?;
+        }
+        self.state.pending.get_mut(&drop).unwrap().preauth_in_flight = preauth_in_flight;
+        self.record_cancel(drop, CancelReason::Duplicate)
+    }
+
+    fn handle_extend(&mut self, req_id: ReqId, extra_mins: u16) -> Result<(), BookingError> {
+        let pending = self
+            .state
+            .pending
+            .get(&req_id)
+            .ok_or(BookingError::InvalidRequest)?;
+        if pending.status != ReqStatus::SlotConfirmed || extra_mins == 0 {
+            return Err(BookingError::InvalidRequest);
+        }
+        let slot = pending.slot.ok_or(BookingError::InvalidRequest)?;
+        let booking = self
+            .state
+            .bookings
+            .get(&slot)
+            .ok_or(BookingError::InvalidRequest)?;
+
+        let dur = booking.dur_mins.saturating_add(extra_mins);
+        // Past midnight can't fit any range
+        if slot.time.add(dur).is_none()
+            || self
+                .state
+                .unavailable_for(slot, booking.apt_type, dur, Some(slot), None)
+                .is_some()
+        {
+            return Err(BookingError::SlotNotAvailable);
+        }
+
+        self.actions
+            .add(Action::Untracked(UntrackedAction::Notify {
+                user_id: booking.user_id,
+                msg: format!(
+                    "Your {} on {} was extended to {} minutes",
+                    booking.apt_type.name(),
+                    slot,
+                    dur
+                ),
+            }))
+            .map_err(|_| BookingError::ActionQueueFailed)?;
+        self.state.bookings.get_mut(&slot).unwrap().dur_mins = dur;
+        Ok(())
+    }
+
+    fn handle_acknowledge(&mut self, req_id: ReqId) -> Result<(), BookingError> {
+        let pending = self
+            .state
+            .pending
+            .get(&req_id)
+            .ok_or(BookingError::InvalidRequest)?;
+        if pending.status != ReqStatus::SlotConfirmed {
+            return Err(BookingError::InvalidRequest);
+        }
+        let slot = pending.slot.ok_or(BookingError::InvalidRequest)?;
+        let booking = self
+            .state
+            .bookings
+            .get_mut(&slot)
+            .ok_or(BookingError::InvalidRequest)?;
+        booking.confirm_by = None;
+        Ok(())
+    }
+
+    fn handle_expire(&mut self, now: Slot) -> Result<(), BookingError> {
+        let now = now.week_mins();
+        // Bookings iterate in slot order, so expiries are emitted deterministically
+        let expired: Vec<Slot> = self
+            .state
+            .bookings
+            .iter()
+            .filter(|(_, booking)| booking.confirm_by.is_some_and(|by| by < now))
+            .map(|(slot, _)| *slot)
+            .collect();
+
+        for slot in expired {
+            self.cancel_booking(
+                slot,
+                ReqStatus::Expired,
+                "because it wasn't confirmed in time",
+            )?;
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn handle_tentative(
+        &mut self,
+        user_id: u64,
+        name: String,
+        email: String,
+        slot: Slot,
+        apt_type: AptType,
+        now: Slot,
+        hold_mins: u32,
+    ) -> Result<(), BookingError> {
+        if hold_mins == 0 {
+            return Err(BookingError::InvalidRequest);
+        }
+        let until = now
+            .week_mins()
+            .checked_add(hold_mins)
+            .ok_or(BookingError::InvalidRequest)?;
+        if !self.state.meets_lead_time(slot, now) {
+            return Err(BookingError::TooSoon);
+        }
+        if !self.state.is_available(slot, apt_type) {
+            return Err(BookingError::SlotNotAvailable);
+        }
+
+        let id = self.state.next_id;
+        self.state.next_id += 1;
+        self.state.pending.insert(
+            id,
+            PendingReq {
+                user_id,
+                name,
+                email,
+                slot: Some(slot),
+                apt_type,
+                status: ReqStatus::Tentative { until },
+                confirm_by: None,
+                group_id: None,
+                cancel_reason: None,
+                preauth_in_flight: false,
+            },
+        );
+        self.state.holds.insert(slot, id);
+        Ok(())
+    }
+
+    fn handle_confirm_hold(&mut self, req_id: ReqId, now: Slot) -> Result<(), BookingError> {
+        let pending = self
+            .state
+            .pending
+            .get(&req_id)
+            .ok_or(BookingError::InvalidRequest)?;
+        let ReqStatus::Tentative { until } = pending.status else {
+            return Err(BookingError::InvalidRequest);
+        };
+        if until < now.week_mins() {
+            return Err(BookingError::InvalidRequest);
+        }
+
+        let (user_id, amount_cents) = (pending.user_id, pending.apt_type.price_cents());
+        self.actions
+            .add(Action::Tracked(TrackedAction::with_deadline(
+                req_id,
+                PaymentReq::Preauth {
+                    user_id,
+                    amount_cents,
+                    req_id,
+                },
+                PAYMENT_DEADLINE,
+            )))
+            .map_err(|_| BookingError::ActionQueueFailed)?;
+
+        let confirm_by = self.state.confirm_deadline(now);
+        let pending = self.state.pending.get_mut(&req_id).unwrap();
+        pending.status = ReqStatus::AwaitingPreauth;
+        pending.confirm_by = confirm_by;
+        Ok(())
+    }
+
+    fn handle_expire_tentative(&mut self, now: Slot) -> Result<(), BookingError> {
+        let now = now.week_mins();
+        // Pending iterates in id order, so releases are emitted deterministically
+        let expired: Vec<ReqId> = self
+            .state
+            .pending
+            .iter()
+            .filter(|(_, p)| matches!(p.status, ReqStatus::Tentative { until } if until < now))
+            .map(|(id, _)| *id)
+            .collect();
+
+        for req_id in expired {
+            self.release_hold(req_id);
+            let pending = self.state.pending.get_mut(&req_id).unwrap();
+            pending.status = ReqStatus::HoldExpired;
+            let (user_id, slot) = (pending.user_id, pending.slot.unwrap());
+            self.actions
+                .add(Action::Untracked(UntrackedAction::Notify {
+                    user_id,
+                    msg: format!("Your hold on {} has expired", slot),
+                }))
+                .map_err(|_| BookingError::ActionQueueFailed)?;
         }
         Ok(())
     }
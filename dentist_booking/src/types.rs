@@ -1,4 +1,6 @@
-use std::fmt;
+use std::{fmt, str::FromStr};
+
+pub use phasm::money::Cents;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Day {
@@ -24,6 +26,19 @@ impl Day {
         }
     }
 
+    /// Parses a short name ("Mon"), the inverse of [`name`](Self::name), or a full name
+    /// ("Monday" or "monday").
+    pub fn from_name(s: &str) -> Option<Day> {
+        Day::all().iter().copied().find(|day| {
+            s == day.name() || s == format!("{:?}", day) || s == format!("{:?}", day).to_lowercase()
+        })
+    }
+
+    /// Position in the week, Monday = 0.
+    pub fn index(&self) -> u8 {
+        *self as u8
+    }
+
     pub fn all() -> &'static [Day] {
         &[
             Day::Monday,
@@ -54,8 +69,20 @@ impl Time {
         Time((m / 60) as u8, (m % 60) as u8)
     }
 
-    pub fn add(&self, mins: u16) -> Self {
-        Self::from_mins(self.to_mins() + mins)
+    /// `mins` later on the same day, or `None` at or past midnight.
+    pub fn add(&self, mins: u16) -> Option<Self> {
+        self.checked_add(mins)
+    }
+
+    /// `mins` later on the same day, or `None` at or past midnight.
+    pub fn checked_add(&self, mins: u16) -> Option<Self> {
+        let end = self.to_mins().checked_add(mins)?;
+        (end < 24 * 60).then(|| Self::from_mins(end))
+    }
+
+    /// `mins` later, clamped to 23:59.
+    pub fn saturating_add(&self, mins: u16) -> Self {
+        self.checked_add(mins).unwrap_or(Time(23, 59))
     }
 }
 
@@ -65,13 +92,99 @@ impl fmt::Display for Time {
     }
 }
 
+/// Error from parsing a [`Time`] from an "HH:MM" string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimeParseError {
+    /// Not of the form "HH:MM" with numeric parts.
+    Malformed(String),
+    /// Well-formed, but the hour is over 23 or the minute over 59.
+    OutOfRange { hour: u8, minute: u8 },
+}
+
+impl fmt::Display for TimeParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeParseError::Malformed(s) => write!(f, "expected HH:MM, got {:?}", s),
+            TimeParseError::OutOfRange { hour, minute } => {
+                write!(f, "time {:02}:{:02} is out of range", hour, minute)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TimeParseError {}
+
+impl FromStr for Time {
+    type Err = TimeParseError;
+
+    /// Parses "HH:MM" (the hour may be a single digit), the inverse of `Display`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || TimeParseError::Malformed(s.to_string());
+        let (hour, minute) = s.split_once(':').ok_or_else(malformed)?;
+        let numeric = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if !(1..=2).contains(&hour.len()) || minute.len() != 2 || !numeric(hour) || !numeric(minute)
+        {
+            return Err(malformed());
+        }
+        let (hour, minute): (u8, u8) = (hour.parse().unwrap(), minute.parse().unwrap());
+        if hour >= 24 || minute >= 60 {
+            return Err(TimeParseError::OutOfRange { hour, minute });
+        }
+        Ok(Time(hour, minute))
+    }
+}
+
+/// Error from [`TimeRange::try_new`]: the range doesn't end after it starts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRangeError {
+    pub start: Time,
+    pub end: Time,
+}
+
+impl fmt::Display for TimeRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "time range {}-{} ends before it starts",
+            self.start, self.end
+        )
+    }
+}
+
+impl std::error::Error for TimeRangeError {}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct TimeRange(pub Time, pub Time);
 
 impl TimeRange {
+    /// # Panics
+    ///
+    /// If `start` isn't before `end`; use [`try_new`](Self::try_new) for untrusted times.
     pub fn new(start: Time, end: Time) -> Self {
-        assert!(start < end);
-        TimeRange(start, end)
+        match Self::try_new(start, end) {
+            Ok(range) => range,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    pub fn try_new(start: Time, end: Time) -> Result<Self, TimeRangeError> {
+        if start < end {
+            Ok(TimeRange(start, end))
+        } else {
+            Err(TimeRangeError { start, end })
+        }
+    }
+
+    /// The `mins` minutes from `start`, cut off at the end of the day (`Time(24, 0)`).
+    ///
+    /// Cutting off doesn't change which same-day ranges it overlaps, since they all start
+    /// before midnight.
+    ///
+    /// # Panics
+    ///
+    /// If `mins` is 0.
+    pub fn starting_at(start: Time, mins: u16) -> Self {
+        TimeRange::new(start, start.add(mins).unwrap_or(Time(24, 0)))
     }
 
     pub fn contains(&self, t: Time) -> bool {
@@ -79,7 +192,40 @@ impl TimeRange {
     }
 
     pub fn can_fit(&self, start: Time, dur: u16) -> bool {
-        self.contains(start) && start.add(dur) <= self.1
+        // In minutes, so a range ending at midnight can fit an appointment up to it
+        self.contains(start) && start.to_mins() + dur <= self.1.to_mins()
+    }
+
+    /// Whether the ranges share any time. Ranges that only touch, one ending where the
+    /// other starts, don't.
+    pub fn overlaps(&self, other: &TimeRange) -> bool {
+        self.0 < other.1 && other.0 < self.1
+    }
+
+    /// The time both ranges cover, `None` if they don't [overlap](Self::overlaps).
+    pub fn intersect(&self, other: &TimeRange) -> Option<TimeRange> {
+        let (start, end) = (self.0.max(other.0), self.1.min(other.1));
+        (start < end).then_some(TimeRange(start, end))
+    }
+
+    /// Splits the range at `t` into the parts before and after it.
+    ///
+    /// A side is `None` if it would be empty, i.e. when `t` is at or outside that end.
+    pub fn split_at(&self, t: Time) -> (Option<TimeRange>, Option<TimeRange>) {
+        if t <= self.0 {
+            (None, Some(*self))
+        } else if t >= self.1 {
+            (Some(*self), None)
+        } else {
+            (Some(TimeRange(self.0, t)), Some(TimeRange(t, self.1)))
+        }
+    }
+
+    /// The parts of this range not covered by `booked`, in order (0, 1, or 2 ranges).
+    pub fn subtract(&self, booked: &TimeRange) -> Vec<TimeRange> {
+        let (before, _) = self.split_at(booked.0);
+        let (_, after) = self.split_at(booked.1);
+        before.into_iter().chain(after).collect()
     }
 }
 
@@ -107,15 +253,29 @@ impl AptType {
         }
     }
 
-    pub fn price(&self) -> f32 {
+    /// Cleanup minutes needed next to this appointment, on top of its duration.
+    pub fn buffer_mins(&self) -> u16 {
+        match self {
+            AptType::Cleaning => 0,
+            AptType::Checkup => 0,
+            AptType::Filling => 5,
+            AptType::RootCanal => 15,
+        }
+    }
+
+    pub fn price(&self) -> Cents {
         match self {
-            AptType::Cleaning => 50.0,
-            AptType::Checkup => 75.0,
-            AptType::Filling => 150.0,
-            AptType::RootCanal => 200.0,
+            AptType::Cleaning => Cents(5_000),
+            AptType::Checkup => Cents(7_500),
+            AptType::Filling => Cents(15_000),
+            AptType::RootCanal => Cents(20_000),
         }
     }
 
+    pub fn price_cents(&self) -> u32 {
+        to_cents(self.price())
+    }
+
     pub fn name(&self) -> &str {
         match self {
             AptType::Cleaning => "Cleaning",
@@ -133,27 +293,185 @@ impl AptType {
             AptType::RootCanal,
         ]
     }
+
+    /// Parses a display name ("Root Canal") or its snake_case form ("root_canal").
+    pub fn from_name(s: &str) -> Option<AptType> {
+        match s {
+            "Cleaning" | "cleaning" => Some(AptType::Cleaning),
+            "Checkup" | "checkup" => Some(AptType::Checkup),
+            "Filling" | "filling" => Some(AptType::Filling),
+            "Root Canal" | "root_canal" => Some(AptType::RootCanal),
+            _ => None,
+        }
+    }
+}
+
+/// Error from parsing an [`AptType`] that matches no known name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownAptType(pub String);
+
+impl fmt::Display for UnknownAptType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown appointment type {:?}", self.0)
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+impl std::error::Error for UnknownAptType {}
+
+impl FromStr for AptType {
+    type Err = UnknownAptType;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AptType::from_name(s).ok_or_else(|| UnknownAptType(s.to_string()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct Slot {
     pub day: Day,
     pub time: Time,
 }
 
+impl Slot {
+    /// Monday 00:00, the earliest point in the week.
+    pub const WEEK_START: Slot = Slot {
+        day: Day::Monday,
+        time: Time(0, 0),
+    };
+
+    /// Minutes since [`Slot::WEEK_START`].
+    pub fn week_mins(&self) -> u32 {
+        self.day.index() as u32 * 24 * 60 + self.time.to_mins() as u32
+    }
+}
+
 impl fmt::Display for Slot {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{} {}", self.day.name(), self.time)
     }
 }
 
+/// Opening hours for one day of the week.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DaySchedule {
+    Open(Vec<TimeRange>),
+    /// Closed by policy, as opposed to a day with no schedule configured.
+    Closed,
+}
+
+impl DaySchedule {
+    /// The opening ranges, empty if closed.
+    pub fn ranges(&self) -> &[TimeRange] {
+        match self {
+            DaySchedule::Open(ranges) => ranges,
+            DaySchedule::Closed => &[],
+        }
+    }
+}
+
+/// Why a slot can't be booked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnavailableReason {
+    /// The day is explicitly [`DaySchedule::Closed`].
+    ClinicClosed,
+    /// No schedule is configured for the day. A setup bug rather than clinic policy.
+    Unconfigured,
+    /// The appointment doesn't fit inside any scheduled range.
+    OutsideHours,
+    /// The appointment overlaps the maintenance block starting at `start`.
+    Maintenance { start: Slot },
+    /// The appointment overlaps the booking starting at `slot`.
+    Conflict { slot: Slot },
+}
+
+/// Result of [`BookingSystem::availability_report`](crate::BookingSystem::availability_report).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AvailabilityReport {
+    pub available: bool,
+    pub reason: Option<UnavailableReason>,
+    /// Nearby free slots on the same day, closest first. Empty when available.
+    pub alternatives: Vec<Slot>,
+}
+
+/// An auto-selection request for [`BookingSystem::assign_batch`](crate::BookingSystem::assign_batch).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AutoRequest {
+    pub req_id: u64,
+    pub days: Vec<Day>,
+    pub times: Vec<TimeRange>,
+    pub apt_type: AptType,
+    /// Current point in the week, used for the lead-time check.
+    pub now: Slot,
+}
+
+/// Inconsistency found by [`BookingSystem::detect_orphans`](crate::BookingSystem::detect_orphans).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrphanReport {
+    /// A confirmed booking with no `SlotConfirmed` pending record for its slot, so
+    /// restore has nothing to reconcile it against.
+    BookingWithoutRequest { slot: Slot, user_id: u64 },
+    /// An `AwaitingPreauth` request whose slot is already booked by another user.
+    RequestSlotTaken {
+        req_id: u64,
+        slot: Slot,
+        booked_by: u64,
+    },
+}
+
+impl fmt::Display for OrphanReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrphanReport::BookingWithoutRequest { slot, user_id } => write!(
+                f,
+                "booking {} of user {} has no confirmed request",
+                slot, user_id
+            ),
+            OrphanReport::RequestSlotTaken {
+                req_id,
+                slot,
+                booked_by,
+            } => write!(
+                f,
+                "request {} awaits preauth for {}, already booked by user {}",
+                req_id, slot, booked_by
+            ),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ConfirmedBooking {
     pub user_id: u64,
     pub name: String,
     pub email: String,
     pub apt_type: AptType,
-    pub amount_paid: f32,
+    /// Minutes the booking occupies. Starts as `apt_type`'s duration and grows with
+    /// `Extend`.
+    pub dur_mins: u16,
+    pub amount_paid: Cents,
+    /// Deadline for the patient to acknowledge the booking with `PatientConfirm`, in
+    /// [`Slot::week_mins`]. `None` once acknowledged, or if confirmation isn't required.
+    pub confirm_by: Option<u32>,
+    /// Capture progress if only a deposit was captured on confirmation (see
+    /// `deposit_percent`), `None` otherwise.
+    pub deposit: Option<Deposit>,
+}
+
+/// How much of a deposit booking's price has been captured, in cents.
+///
+/// The three amounts always add up to the price.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Deposit {
+    pub captured_cents: u32,
+    /// A `Capture` in flight, re-emitted by restore.
+    pub capturing_cents: Option<u32>,
+    /// Authorized but not captured yet, captured on `MarkAttended`.
+    pub outstanding_cents: u32,
+}
+
+/// Narrows an amount to the `u32` cents payment requests carry, saturating.
+pub fn to_cents(amount: Cents) -> u32 {
+    u32::try_from(amount.0).unwrap_or(u32::MAX)
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -163,6 +481,61 @@ pub enum ReqStatus {
     SlotConfirmed,
     SlotTaken,
     NoSlot,
+    /// The preauth hold was released. Terminal.
+    Released,
+    /// The request was cancelled, for the `cancel_reason` on its record, and any payment
+    /// is being released.
+    Cancelled,
+    /// The patient didn't acknowledge the booking before its `confirm_by` deadline, so
+    /// it was cancelled and its payment is being released.
+    Expired,
+    /// Holds its slot without payment until minute `until` of the week; `ConfirmHold`
+    /// starts the preauth.
+    Tentative {
+        until: u32,
+    },
+    /// The tentative hold ran out before it was confirmed. Terminal.
+    HoldExpired,
+}
+
+impl ReqStatus {
+    /// Whether the request is still heading for, or holding, a booking.
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self,
+            ReqStatus::AwaitingPreauth
+                | ReqStatus::PreauthSuccess
+                | ReqStatus::SlotConfirmed
+                | ReqStatus::Tentative { .. }
+        )
+    }
+}
+
+/// Why a request was cancelled, recorded on it and reported to analytics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CancelReason {
+    PatientRequest,
+    NoShow,
+    ClinicClosure,
+    PaymentFailure,
+    /// The slot was blocked for maintenance.
+    Maintenance,
+    /// Merged into another request of the same patient.
+    Duplicate,
+}
+
+impl fmt::Display for CancelReason {
+    /// Completes "Your booking was cancelled ...".
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            CancelReason::PatientRequest => "at your request",
+            CancelReason::NoShow => "because you didn't attend",
+            CancelReason::ClinicClosure => "because the clinic is closed",
+            CancelReason::PaymentFailure => "because the payment failed",
+            CancelReason::Maintenance => "for maintenance",
+            CancelReason::Duplicate => "as a duplicate of another booking",
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -173,4 +546,26 @@ pub struct PendingReq {
     pub slot: Option<Slot>,
     pub apt_type: AptType,
     pub status: ReqStatus,
+    /// Carried into the [`ConfirmedBooking`] if the preauth succeeds.
+    pub confirm_by: Option<u32>,
+    /// Id of the group's first request, shared by every member of a group booking.
+    ///
+    /// The group's single preauth is tracked under this id, so only that request gets
+    /// payment results; they apply to every member.
+    pub group_id: Option<u64>,
+    /// Set when the request is `Cancelled`.
+    pub cancel_reason: Option<CancelReason>,
+    /// Set when the request is cancelled before its preauth is answered, until the
+    /// preauth fails or is released. Restore re-checks it, so a preauth that succeeds
+    /// is still released after a restart.
+    pub preauth_in_flight: bool,
+}
+
+/// One patient of a group booking.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemberReq {
+    pub user_id: u64,
+    pub name: String,
+    pub email: String,
+    pub apt_type: AptType,
 }
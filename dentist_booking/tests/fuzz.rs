@@ -0,0 +1,70 @@
+use arbitrary::{Arbitrary, Unstructured};
+use dentist_booking::*;
+use phasm::{Input, StateMachine};
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Feeds one byte buffer to the machine as a sequence of inputs.
+async fn run_fuzz_case(data: &[u8]) {
+    let mut u = Unstructured::new(data);
+    let mut system = BookingSystem::with_default_schedule();
+    let mut actions = Vec::new();
+
+    while !u.is_empty() {
+        let input = if u.ratio(1, 3).unwrap_or(false) && system.next_id > 1 {
+            // Result for a request that may or may not exist
+            let Ok(id) = u.int_in_range(0..=system.next_id) else {
+                break;
+            };
+            let res = if u.arbitrary().unwrap_or(true) {
+                PaymentResult::Success {
+                    amount: Cents(u.arbitrary().unwrap_or(0)),
+                }
+            } else {
+                PaymentResult::Failed {
+                    reason: String::arbitrary(&mut u).unwrap_or_default(),
+                }
+            };
+            Input::TrackedActionCompleted { id, res }
+        } else {
+            let Ok(input) = BookingInput::arbitrary(&mut u) else {
+                break;
+            };
+            Input::Normal(input)
+        };
+
+        let _ = BookingSystem::stf(&mut system, input, &mut actions).await;
+        actions.clear();
+
+        if let Err(e) = system.check_invariants() {
+            panic!("Invariant violated for input bytes {:?}: {}", data, e);
+        }
+    }
+}
+
+#[monoio::test]
+async fn test_fuzz_smoke() {
+    let mut rng = ChaCha8Rng::seed_from_u64(8080);
+    let mut data = vec![0u8; 4096];
+
+    for _ in 0..200 {
+        rng.fill_bytes(&mut data);
+        run_fuzz_case(&data).await;
+    }
+}
+
+#[test]
+fn test_arbitrary_values_are_valid() {
+    let mut rng = ChaCha8Rng::seed_from_u64(4242);
+    let mut data = vec![0u8; 4096];
+    rng.fill_bytes(&mut data);
+    let mut u = Unstructured::new(&data);
+
+    for _ in 0..100 {
+        let time = Time::arbitrary(&mut u).unwrap();
+        assert!(time.0 < 24 && time.1 < 60, "Time out of range: {:?}", time);
+
+        let range = TimeRange::arbitrary(&mut u).unwrap();
+        assert!(range.0 < range.1, "Inverted range: {}", range);
+    }
+}
@@ -1,5 +1,8 @@
 use dentist_booking::*;
-use phasm::{Input, StateMachine};
+use phasm::{
+    actions::{Action, ActionsContainer},
+    Input, StateMachine,
+};
 
 #[monoio::test]
 async fn test_basic_booking_flow() {
@@ -16,6 +19,9 @@ async fn test_basic_booking_flow() {
             day: Day::Monday,
             time: Time::new(9, 0),
             apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
         }),
         &mut actions,
     )
@@ -31,7 +37,9 @@ async fn test_basic_booking_flow() {
         &mut system,
         Input::TrackedActionCompleted {
             id: req_id,
-            res: PaymentResult::Success { amount: 75.0 },
+            res: PaymentResult::Success {
+                amount: Cents(7_500),
+            },
         },
         &mut actions,
     )
@@ -78,6 +86,9 @@ async fn test_slot_conflict() {
             day: Day::Monday,
             time: Time::new(9, 0),
             apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
         }),
         &mut actions,
     )
@@ -92,7 +103,9 @@ async fn test_slot_conflict() {
         &mut system,
         Input::TrackedActionCompleted {
             id: alice_req,
-            res: PaymentResult::Success { amount: 75.0 },
+            res: PaymentResult::Success {
+                amount: Cents(7_500),
+            },
         },
         &mut actions,
     )
@@ -111,6 +124,9 @@ async fn test_slot_conflict() {
             day: Day::Monday,
             time: Time::new(9, 0),
             apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
         }),
         &mut actions,
     )
@@ -135,6 +151,9 @@ async fn test_auto_selection() {
             days: vec![Day::Monday, Day::Tuesday],
             times: vec![TimeRange::new(Time::new(9, 0), Time::new(12, 0))],
             apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
         }),
         &mut actions,
     )
@@ -149,7 +168,7 @@ async fn test_auto_selection() {
 
     let slot = pending.slot.unwrap();
     assert!(
-        system.is_available(slot, AptType::Checkup.dur()),
+        system.is_available(slot, AptType::Checkup),
         "Selected slot should be available"
     );
 
@@ -184,7 +203,9 @@ async fn test_auto_selection() {
         &mut system,
         Input::TrackedActionCompleted {
             id: req_id,
-            res: PaymentResult::Success { amount: 75.0 },
+            res: PaymentResult::Success {
+                amount: Cents(7_500),
+            },
         },
         &mut actions,
     )
@@ -221,8 +242,11 @@ async fn test_invariants_after_operations() {
                 name: format!("User{}", i + 1),
                 email: format!("user{}@example.com", i + 1),
                 day: Day::Monday,
-                time: Time::new(9, 0).add((i * 30) as u16),
+                time: Time::new(9, 0).add((i * 30) as u16).unwrap(),
                 apt_type: AptType::Checkup,
+                now: Slot::WEEK_START,
+                token: None,
+                expected_version: None,
             }),
             &mut actions,
         )
@@ -236,7 +260,9 @@ async fn test_invariants_after_operations() {
                 &mut system,
                 Input::TrackedActionCompleted {
                     id: req_id,
-                    res: PaymentResult::Success { amount: 75.0 },
+                    res: PaymentResult::Success {
+                        amount: Cents(7_500),
+                    },
                 },
                 &mut actions,
             )
@@ -248,7 +274,7 @@ async fn test_invariants_after_operations() {
             // Verify the booking matches what was requested
             let expected_slot = Slot {
                 day: Day::Monday,
-                time: Time::new(9, 0).add((i * 30) as u16),
+                time: Time::new(9, 0).add((i * 30) as u16).unwrap(),
             };
             if let Some(booking) = system.bookings.get(&expected_slot) {
                 assert_eq!(booking.user_id, i + 1, "Booking should be for correct user");
@@ -266,7 +292,7 @@ async fn test_invariants_after_operations() {
             .expect("Invariants should hold after each operation");
     }
 
-    assert!(system.bookings.len() > 0, "Should have some bookings");
+    assert!(!system.bookings.is_empty(), "Should have some bookings");
 
     // Verify all bookings match their original requests
     for (slot, booking) in &system.bookings {
@@ -298,6 +324,9 @@ async fn test_booking_preferences_honored() {
             day: Day::Wednesday,
             time: Time::new(14, 30),
             apt_type: AptType::Filling,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
         }),
         &mut actions,
     )
@@ -370,6 +399,9 @@ async fn test_booking_preferences_honored() {
                 TimeRange::new(Time::new(14, 0), Time::new(16, 0)),
             ],
             apt_type: AptType::RootCanal,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
         }),
         &mut actions,
     )
@@ -382,13 +414,13 @@ async fn test_booking_preferences_honored() {
 
     // Verify day preference
     assert!(
-        vec![Day::Tuesday, Day::Thursday].contains(&selected_slot.day),
+        [Day::Tuesday, Day::Thursday].contains(&selected_slot.day),
         "Selected day {:?} should be in preferred days [Tuesday, Thursday]",
         selected_slot.day
     );
 
     // Verify time preference
-    let time_ranges = vec![
+    let time_ranges = [
         TimeRange::new(Time::new(10, 0), Time::new(13, 0)),
         TimeRange::new(Time::new(14, 0), Time::new(16, 0)),
     ];
@@ -408,7 +440,7 @@ async fn test_booking_preferences_honored() {
         "Appointment type should match"
     );
     assert!(
-        system.is_available(selected_slot, AptType::RootCanal.dur()),
+        system.is_available(selected_slot, AptType::RootCanal),
         "Selected slot should fit the 60-minute root canal appointment"
     );
 
@@ -443,10 +475,7 @@ async fn test_booking_preferences_honored() {
     );
 
     // Test 3: Different appointment durations work correctly
-    for (user_id, apt_type) in [
-        (3, AptType::Cleaning),
-        (4, AptType::Checkup),
-    ] {
+    for (user_id, apt_type) in [(3, AptType::Cleaning), (4, AptType::Checkup)] {
         actions.clear();
 
         BookingSystem::stf(
@@ -456,8 +485,11 @@ async fn test_booking_preferences_honored() {
                 name: format!("User{}", user_id),
                 email: format!("user{}@example.com", user_id),
                 day: Day::Friday,
-                time: Time::new(9, 0).add(((user_id - 3) * 60) as u16),
+                time: Time::new(9, 0).add(((user_id - 3) * 60) as u16).unwrap(),
                 apt_type,
+                now: Slot::WEEK_START,
+                token: None,
+                expected_version: None,
             }),
             &mut actions,
         )
@@ -500,8 +532,8 @@ async fn test_booking_preferences_honored() {
         // Verify no overlaps (this is also checked by invariants, but let's be explicit)
         for (other_slot, other_booking) in &system.bookings {
             if slot != other_slot && slot.day == other_slot.day {
-                let booking_end = slot.time.add(booking.apt_type.dur());
-                let other_end = other_slot.time.add(other_booking.apt_type.dur());
+                let booking_end = slot.time.add(booking.apt_type.dur()).unwrap();
+                let other_end = other_slot.time.add(other_booking.apt_type.dur()).unwrap();
 
                 let no_overlap = booking_end <= other_slot.time || slot.time >= other_end;
                 assert!(
@@ -520,3 +552,3066 @@ async fn test_booking_preferences_honored() {
         .check_invariants()
         .expect("All invariants should be satisfied");
 }
+
+#[monoio::test]
+async fn test_availability_report_suggests_alternatives() {
+    let mut system = BookingSystem::with_default_schedule();
+    let mut actions = Vec::new();
+
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(9, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await
+    .expect("Alice's request should succeed");
+
+    let req_id = system.next_id - 1;
+    actions.clear();
+
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: req_id,
+            res: PaymentResult::Success {
+                amount: Cents(7_500),
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .expect("Alice's confirmation should succeed");
+
+    let taken = Slot {
+        day: Day::Monday,
+        time: Time::new(9, 0),
+    };
+    let report = system.availability_report(taken, AptType::Checkup);
+
+    assert!(!report.available, "Taken slot should be unavailable");
+    assert_eq!(
+        report.reason,
+        Some(UnavailableReason::Conflict { slot: taken }),
+        "Should report the conflicting booking"
+    );
+    assert_eq!(
+        report.alternatives,
+        vec![
+            Slot {
+                day: Day::Monday,
+                time: Time::new(9, 30)
+            },
+            Slot {
+                day: Day::Monday,
+                time: Time::new(9, 45)
+            },
+            Slot {
+                day: Day::Monday,
+                time: Time::new(10, 0)
+            },
+        ],
+        "Should suggest the nearest free slots"
+    );
+    for alt in &report.alternatives {
+        assert_eq!(alt.day, taken.day, "Alternatives should be on the same day");
+        assert!(
+            system.is_available(*alt, AptType::Checkup),
+            "Alternative {} should be bookable",
+            alt
+        );
+    }
+
+    // Free slot and closed day
+    let free = system.availability_report(
+        Slot {
+            day: Day::Monday,
+            time: Time::new(14, 0),
+        },
+        AptType::Checkup,
+    );
+    assert!(free.available && free.reason.is_none() && free.alternatives.is_empty());
+
+    let closed = system.availability_report(
+        Slot {
+            day: Day::Sunday,
+            time: Time::new(10, 0),
+        },
+        AptType::Checkup,
+    );
+    assert_eq!(closed.reason, Some(UnavailableReason::ClinicClosed));
+    assert!(closed.alternatives.is_empty());
+}
+
+#[monoio::test]
+async fn test_lead_time_threshold() {
+    let mut system = BookingSystem::with_default_schedule();
+    system.min_lead_mins = 120;
+    let mut actions = Vec::new();
+    let now = Slot {
+        day: Day::Monday,
+        time: Time::new(9, 0),
+    };
+
+    // Below threshold: 105 minutes ahead
+    let result = BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(10, 45),
+            apt_type: AptType::Checkup,
+            now,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await;
+
+    assert!(
+        matches!(result, Err(BookingError::TooSoon)),
+        "Booking inside the lead time should be rejected"
+    );
+    assert!(
+        system.pending.is_empty(),
+        "Rejected request must not be stored"
+    );
+    assert_eq!(system.next_id, 1, "ID counter should not change on error");
+    assert!(actions.is_empty(), "No actions should be emitted on error");
+
+    // Exactly at threshold: 120 minutes ahead
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(11, 0),
+            apt_type: AptType::Cleaning,
+            now,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await
+    .expect("Booking exactly at the lead time should succeed");
+
+    // Above threshold: later in the week
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 2,
+            name: "Bob".into(),
+            email: "bob@example.com".into(),
+            day: Day::Tuesday,
+            time: Time::new(9, 0),
+            apt_type: AptType::Checkup,
+            now,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await
+    .expect("Booking beyond the lead time should succeed");
+
+    assert_eq!(system.pending.len(), 2, "Should have 2 pending requests");
+
+    // Auto-selection skips slots inside the lead time
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::RequestAuto {
+            user_id: 3,
+            name: "Carol".into(),
+            email: "carol@example.com".into(),
+            days: vec![Day::Monday],
+            times: vec![TimeRange::new(Time::new(9, 0), Time::new(12, 0))],
+            apt_type: AptType::Cleaning,
+            now,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await
+    .expect("Auto-selection should find a slot past the lead time");
+
+    let selected = system.pending[&(system.next_id - 1)].slot.unwrap();
+    assert_eq!(
+        selected,
+        Slot {
+            day: Day::Monday,
+            time: Time::new(11, 0)
+        },
+        "Earliest slot meeting the lead time should be selected"
+    );
+}
+
+#[test]
+fn test_lead_time_saturates() {
+    let mut system = BookingSystem::with_default_schedule();
+    system.min_lead_mins = u32::MAX;
+    let slot = Slot {
+        day: Day::Friday,
+        time: Time::new(9, 0),
+    };
+    assert!(!system.meets_lead_time(slot, Slot::WEEK_START));
+    assert!(!system.meets_lead_time(slot, slot));
+}
+
+#[monoio::test]
+async fn test_detect_orphans() {
+    let mut system = BookingSystem::with_default_schedule();
+    let mut actions = Vec::new();
+
+    // Bob requests Tuesday 10:00 but hasn't completed preauth yet
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 2,
+            name: "Bob".into(),
+            email: "bob@example.com".into(),
+            day: Day::Tuesday,
+            time: Time::new(10, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await
+    .expect("Bob's request should succeed");
+    let bob_req = system.next_id - 1;
+
+    assert!(
+        system.detect_orphans().is_empty(),
+        "Consistent state should have no orphans"
+    );
+
+    // Simulate a recovered state where Alice's booking survived but her
+    // pending record was lost
+    let slot = Slot {
+        day: Day::Tuesday,
+        time: Time::new(10, 0),
+    };
+    system.bookings.insert(
+        slot,
+        ConfirmedBooking {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            apt_type: AptType::Checkup,
+            dur_mins: AptType::Checkup.dur(),
+            amount_paid: Cents(7_500),
+            confirm_by: None,
+            deposit: None,
+        },
+    );
+
+    assert_eq!(
+        system.detect_orphans(),
+        vec![
+            OrphanReport::BookingWithoutRequest { slot, user_id: 1 },
+            OrphanReport::RequestSlotTaken {
+                req_id: bob_req,
+                slot,
+                booked_by: 1,
+            },
+        ],
+        "Should report the orphaned booking and the request whose slot it took"
+    );
+}
+
+#[monoio::test]
+async fn test_buffer_between_bookings() {
+    let mut system = BookingSystem::with_default_schedule();
+    system.buffer_mins = 15;
+    let mut actions = Vec::new();
+
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(9, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await
+    .expect("Alice's request should succeed");
+    let req_id = system.next_id - 1;
+
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: req_id,
+            res: PaymentResult::Success {
+                amount: Cents(7_500),
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .expect("Alice's confirmation should succeed");
+
+    // Checkup ends 9:30, so 9:30 is inside the buffer and 9:45 isn't
+    let tight = Slot {
+        day: Day::Monday,
+        time: Time::new(9, 30),
+    };
+    let clear = Slot {
+        day: Day::Monday,
+        time: Time::new(9, 45),
+    };
+    assert!(!system.is_available(tight, AptType::Cleaning));
+    assert!(system.is_available(clear, AptType::Cleaning));
+    system
+        .check_invariants()
+        .expect("Buffered state should be valid");
+}
+
+#[test]
+fn test_invariants_flag_too_tight_pair() {
+    let mut system = BookingSystem::with_default_schedule();
+    system.buffer_mins = 15;
+
+    // Bypass the STF to plant a pair only 5 minutes apart
+    for (time, apt_type) in [
+        (Time::new(9, 0), AptType::Checkup),
+        (Time::new(9, 35), AptType::Cleaning),
+    ] {
+        system.bookings.insert(
+            Slot {
+                day: Day::Monday,
+                time,
+            },
+            ConfirmedBooking {
+                user_id: 1,
+                name: "Alice".into(),
+                email: "alice@example.com".into(),
+                apt_type,
+                dur_mins: apt_type.dur(),
+                amount_paid: apt_type.price(),
+                confirm_by: None,
+                deposit: None,
+            },
+        );
+    }
+
+    let err = system
+        .check_invariants()
+        .expect_err("Pair closer than the buffer should violate invariants");
+    assert!(
+        err.contains("Mon 09:00") && err.contains("Mon 09:35") && err.contains("5 min apart"),
+        "Error should name both slots and the gap: {}",
+        err
+    );
+
+    system.buffer_mins = 5;
+    system
+        .check_invariants()
+        .expect("Gap equal to the buffer is allowed");
+}
+
+#[monoio::test]
+async fn test_state_diff_after_request() {
+    use phasm::diff::StateDiff;
+
+    let mut system = BookingSystem::with_default_schedule();
+    let before = system.clone();
+    let mut actions = Vec::new();
+
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(9, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await
+    .expect("Request should succeed");
+
+    let diff: Vec<String> = BookingSystem::diff(&before, &system)
+        .iter()
+        .map(|change| change.to_string())
+        .collect();
+    assert_eq!(
+        diff,
+        vec!["pending: +1 entry", "next_id: 1 -> 2", "version: 0 -> 1"]
+    );
+
+    assert!(
+        BookingSystem::diff(&system, &system).is_empty(),
+        "Identical states should have no diff"
+    );
+}
+
+#[monoio::test]
+async fn test_engine_reports_diff_to_sink() {
+    use std::{cell::RefCell, rc::Rc};
+
+    use phasm::engine::Engine;
+
+    let changes = Rc::new(RefCell::new(Vec::new()));
+    let sink = changes.clone();
+    let mut engine = Engine::<BookingSystem>::new(BookingSystem::with_default_schedule())
+        .unwrap()
+        .debug_diff(move |transition, change| {
+            sink.borrow_mut()
+                .push(format!("{}: {}", transition, change))
+        });
+    engine
+        .step(Input::Normal(BookingInput::RequestSlot {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(9, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }))
+        .await
+        .unwrap();
+    assert_eq!(
+        *changes.borrow(),
+        [
+            "1: pending: +1 entry",
+            "1: next_id: 1 -> 2",
+            "1: version: 0 -> 1"
+        ]
+    );
+}
+
+#[test]
+fn test_state_diff_reports_settings() {
+    use phasm::diff::StateDiff;
+
+    let before = BookingSystem::with_default_schedule();
+    let mut after = before.clone();
+    after.confirm_window_mins = Some(60);
+    after.deposit_percent = Some(20);
+    let diff: Vec<String> = BookingSystem::diff(&before, &after)
+        .iter()
+        .map(|change| change.to_string())
+        .collect();
+    assert_eq!(
+        diff,
+        vec![
+            "confirm_window_mins: None -> Some(60)",
+            "deposit_percent: None -> Some(20)",
+        ]
+    );
+}
+
+#[monoio::test]
+async fn test_duplicate_token_books_once() {
+    use phasm::engine::Engine;
+
+    let mut engine = Engine::<BookingSystem>::new(BookingSystem::with_default_schedule())
+        .unwrap()
+        .idempotency_keys(16);
+    let request = || {
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(9, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: Some(42),
+            expected_version: None,
+        })
+    };
+
+    engine
+        .step(request())
+        .await
+        .expect("First request should succeed");
+    let req_id = engine.state().next_id - 1;
+    assert!(
+        engine
+            .actions()
+            .iter()
+            .any(|a| matches!(a, Action::Tracked(_))),
+        "First request should emit a preauth"
+    );
+
+    // Network retry of the same request
+    engine
+        .step(request())
+        .await
+        .expect("Retry should replay success");
+    assert_eq!(
+        engine.state().pending.len(),
+        1,
+        "Retry must not create a second request"
+    );
+    assert!(engine.actions().is_empty(), "Replay should emit no actions");
+
+    engine
+        .step(Input::TrackedActionCompleted {
+            id: req_id,
+            res: PaymentResult::Success {
+                amount: Cents(7_500),
+            },
+        })
+        .await
+        .expect("Confirmation should succeed");
+    engine
+        .step(request())
+        .await
+        .expect("Late retry should replay success");
+
+    assert_eq!(engine.state().bookings.len(), 1, "Should book exactly once");
+    assert_eq!(engine.transitions(), 2, "Duplicates never reach STF");
+    engine.state().check_invariants().unwrap();
+}
+
+#[test]
+fn test_time_range_try_new_rejects_inverted_ranges() {
+    let (nine, noon) = (Time::new(9, 0), Time::new(12, 0));
+    assert_eq!(TimeRange::try_new(nine, noon), Ok(TimeRange(nine, noon)));
+
+    let err = TimeRange::try_new(noon, nine).unwrap_err();
+    assert_eq!(
+        err,
+        TimeRangeError {
+            start: noon,
+            end: nine
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "time range 12:00-09:00 ends before it starts"
+    );
+    assert!(
+        TimeRange::try_new(nine, nine).is_err(),
+        "Empty ranges are invalid"
+    );
+}
+
+#[test]
+fn test_time_range_split_at() {
+    let range = TimeRange::new(Time::new(9, 0), Time::new(12, 0));
+
+    assert_eq!(
+        range.split_at(Time::new(10, 30)),
+        (
+            Some(TimeRange::new(Time::new(9, 0), Time::new(10, 30))),
+            Some(TimeRange::new(Time::new(10, 30), Time::new(12, 0)))
+        )
+    );
+    assert_eq!(range.split_at(Time::new(9, 0)), (None, Some(range)));
+    assert_eq!(range.split_at(Time::new(13, 0)), (Some(range), None));
+}
+
+#[test]
+fn test_time_range_overlaps_and_intersect() {
+    let range = TimeRange::new(Time::new(9, 0), Time::new(12, 0));
+    let late = TimeRange::new(Time::new(11, 0), Time::new(14, 0));
+    assert!(range.overlaps(&late) && late.overlaps(&range));
+    assert_eq!(
+        range.intersect(&late),
+        Some(TimeRange::new(Time::new(11, 0), Time::new(12, 0)))
+    );
+
+    // Touching ranges share no time
+    let after = TimeRange::new(Time::new(12, 0), Time::new(13, 0));
+    assert!(!range.overlaps(&after) && !after.overlaps(&range));
+    assert_eq!(range.intersect(&after), None);
+
+    let inner = TimeRange::new(Time::new(10, 0), Time::new(10, 15));
+    assert!(range.overlaps(&inner));
+    assert_eq!(range.intersect(&inner), Some(inner));
+
+    // Cut off at the end of the day
+    assert_eq!(
+        TimeRange::starting_at(Time::new(23, 30), 60),
+        TimeRange(Time::new(23, 30), Time(24, 0))
+    );
+}
+
+#[test]
+fn test_time_range_subtract() {
+    let range = TimeRange::new(Time::new(9, 0), Time::new(12, 0));
+
+    // Middle interval leaves a gap on each side
+    let middle = TimeRange::new(Time::new(10, 0), Time::new(10, 30));
+    assert_eq!(
+        range.subtract(&middle),
+        vec![
+            TimeRange::new(Time::new(9, 0), Time::new(10, 0)),
+            TimeRange::new(Time::new(10, 30), Time::new(12, 0)),
+        ]
+    );
+
+    // Edge interval leaves one side
+    let edge = TimeRange::new(Time::new(8, 0), Time::new(9, 45));
+    assert_eq!(
+        range.subtract(&edge),
+        vec![TimeRange::new(Time::new(9, 45), Time::new(12, 0))]
+    );
+
+    // Covering interval leaves nothing
+    let covering = TimeRange::new(Time::new(8, 0), Time::new(13, 0));
+    assert!(range.subtract(&covering).is_empty());
+
+    // Disjoint interval leaves the range untouched
+    let disjoint = TimeRange::new(Time::new(13, 0), Time::new(14, 0));
+    assert_eq!(range.subtract(&disjoint), vec![range]);
+}
+
+#[monoio::test]
+async fn test_request_slot_emit_matches_restore() {
+    use phasm::testing::assert_emit_matches_restore;
+
+    let mut system = BookingSystem::with_default_schedule();
+    let mut actions = Vec::new();
+
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(9, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await
+    .expect("Request should succeed");
+
+    // The Preauth id must be the one restore emits a CheckStatus for
+    assert_emit_matches_restore::<BookingSystem>(&system, &actions).await;
+}
+async fn request_alice(system: &mut BookingSystem) -> ReqId {
+    let mut actions = Vec::new();
+    BookingSystem::stf(
+        system,
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(9, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await
+    .expect("Request should succeed");
+    system.next_id - 1
+}
+
+#[monoio::test]
+async fn test_released_result_sets_terminal_status() {
+    let mut system = BookingSystem::with_default_schedule();
+    let req_id = request_alice(&mut system).await;
+    let mut actions = Vec::new();
+
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: req_id,
+            res: PaymentResult::Released,
+        },
+        &mut actions,
+    )
+    .await
+    .expect("Released should be applied");
+
+    assert_eq!(system.pending[&req_id].status, ReqStatus::Released);
+    assert!(actions.is_empty(), "Released is terminal, nothing to emit");
+
+    // Restore must not re-check a released request
+    BookingSystem::restore(&system, &mut actions).await.unwrap();
+    assert!(
+        actions.is_empty(),
+        "Released request should not be restored"
+    );
+}
+
+#[monoio::test]
+async fn test_released_result_rejected_for_confirmed_booking() {
+    let mut system = BookingSystem::with_default_schedule();
+    let req_id = request_alice(&mut system).await;
+    let mut actions = Vec::new();
+
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: req_id,
+            res: PaymentResult::Success {
+                amount: Cents(7_500),
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .expect("Confirmation should succeed");
+
+    let result = BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: req_id,
+            res: PaymentResult::Released,
+        },
+        &mut actions,
+    )
+    .await;
+    assert!(matches!(result, Err(BookingError::InvalidRequest)));
+    assert_eq!(system.pending[&req_id].status, ReqStatus::SlotConfirmed);
+    system.check_invariants().unwrap();
+}
+
+#[monoio::test]
+async fn test_pending_result_reemits_check_status() {
+    let mut system = BookingSystem::with_default_schedule();
+    let req_id = request_alice(&mut system).await;
+    let mut actions = Vec::new();
+
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: req_id,
+            res: PaymentResult::Pending,
+        },
+        &mut actions,
+    )
+    .await
+    .expect("Pending should be applied");
+
+    assert_eq!(
+        system.pending[&req_id].status,
+        ReqStatus::AwaitingPreauth,
+        "Still waiting on the processor"
+    );
+    assert_eq!(actions.len(), 1);
+    assert!(
+        matches!(
+            &actions[0],
+            Action::Tracked(t)
+                if *t.id() == req_id && *t.action() == PaymentReq::CheckStatus { req_id }
+        ),
+        "Pending should re-emit a CheckStatus under the same id"
+    );
+}
+#[monoio::test]
+async fn test_optimistic_hold_rejects_at_request_time() {
+    let mut system = BookingSystem::with_default_schedule();
+    system.optimistic_holds = true;
+    let alice_req = request_alice(&mut system).await;
+    let mut actions = Vec::new();
+
+    assert_eq!(
+        system.holds.get(&Slot {
+            day: Day::Monday,
+            time: Time::new(9, 0),
+        }),
+        Some(&alice_req),
+        "Alice's request should hold the slot"
+    );
+
+    // Bob asks for an overlapping slot before Alice's payment completes
+    let bob_request = || {
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 2,
+            name: "Bob".into(),
+            email: "bob@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(9, 15),
+            apt_type: AptType::Cleaning,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        })
+    };
+    let result = BookingSystem::stf(&mut system, bob_request(), &mut actions).await;
+    assert!(
+        matches!(result, Err(BookingError::SlotNotAvailable)),
+        "Held slot should be rejected at request time"
+    );
+    assert_eq!(system.pending.len(), 1, "Bob's request must not be stored");
+    system.check_invariants().unwrap();
+
+    // Alice's payment fails, releasing the hold
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: alice_req,
+            res: PaymentResult::Failed {
+                reason: "Card declined".into(),
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .expect("Failure should be applied");
+    assert!(
+        system.holds.is_empty(),
+        "Failed payment should clear the hold"
+    );
+
+    BookingSystem::stf(&mut system, bob_request(), &mut actions)
+        .await
+        .expect("Bob's request should succeed once the hold is gone");
+    let bob_req = system.next_id - 1;
+
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: bob_req,
+            res: PaymentResult::Success {
+                amount: Cents(5_000),
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .expect("Bob's confirmation should succeed");
+
+    assert!(
+        system.holds.is_empty(),
+        "Confirmation should clear the hold"
+    );
+    assert_eq!(system.bookings.len(), 1);
+    system.check_invariants().unwrap();
+}
+#[test]
+fn test_closed_day_distinct_from_unconfigured() {
+    let mut system = BookingSystem::new();
+    system.add_schedule(
+        Day::Monday,
+        TimeRange::new(Time::new(9, 0), Time::new(17, 0)),
+    );
+    system.close_day(Day::Wednesday);
+
+    let at_ten = |day| Slot {
+        day,
+        time: Time::new(10, 0),
+    };
+    let checkup = AptType::Checkup;
+
+    assert_eq!(
+        system.unavailable_reason(at_ten(Day::Wednesday), checkup),
+        Some(UnavailableReason::ClinicClosed),
+        "Wednesday is closed by policy"
+    );
+    assert_eq!(
+        system.unavailable_reason(at_ten(Day::Saturday), checkup),
+        Some(UnavailableReason::Unconfigured),
+        "Saturday was never configured"
+    );
+    assert_eq!(
+        system.unavailable_reason(at_ten(Day::Monday), checkup),
+        None
+    );
+    assert!(system.day_ranges(Day::Wednesday).is_empty());
+
+    // Reopening a closed day replaces the closure
+    system.add_schedule(
+        Day::Wednesday,
+        TimeRange::new(Time::new(9, 0), Time::new(12, 0)),
+    );
+    assert!(system.is_available(at_ten(Day::Wednesday), checkup));
+}
+#[monoio::test]
+async fn test_stale_expected_version_is_rejected() {
+    use phasm::Versioned;
+
+    let mut system = BookingSystem::with_default_schedule();
+    let mut actions = Vec::new();
+    let request = |user_id, hour, expected_version| {
+        Input::Normal(BookingInput::RequestSlot {
+            user_id,
+            name: "Patient".into(),
+            email: "patient@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(hour, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version,
+        })
+    };
+
+    // Two clients both saw version 0
+    BookingSystem::stf(&mut system, request(1, 9, Some(0)), &mut actions)
+        .await
+        .expect("Request at the current version should succeed");
+    assert_eq!(system.version(), 1);
+
+    actions.clear();
+    let result = BookingSystem::stf(&mut system, request(2, 10, Some(0)), &mut actions).await;
+    assert!(
+        matches!(result, Err(BookingError::Conflict)),
+        "Stale version should be rejected"
+    );
+    assert_eq!(
+        system.pending.len(),
+        1,
+        "Rejected request must not be stored"
+    );
+    assert_eq!(
+        system.version(),
+        1,
+        "Failed transition must not bump the version"
+    );
+    assert!(actions.is_empty());
+
+    // Client refreshes and retries at the current version
+    BookingSystem::stf(&mut system, request(2, 10, Some(1)), &mut actions)
+        .await
+        .expect("Request at the current version should succeed");
+    assert_eq!(system.version(), 2);
+    assert_eq!(system.pending.len(), 2);
+}
+/// Requests 12 slots in an order unrelated to their ids, then restores.
+async fn restored_ids() -> Vec<ReqId> {
+    let mut system = BookingSystem::with_default_schedule();
+    let mut actions = Vec::new();
+    let days = [Day::Friday, Day::Monday, Day::Wednesday, Day::Tuesday];
+    for (i, day) in days.into_iter().enumerate() {
+        for hour in [11, 9, 10] {
+            BookingSystem::stf(
+                &mut system,
+                Input::Normal(BookingInput::RequestSlot {
+                    user_id: i as u64 * 10 + hour as u64,
+                    name: "Patient".into(),
+                    email: "patient@example.com".into(),
+                    day,
+                    time: Time::new(hour, 0),
+                    apt_type: AptType::Checkup,
+                    now: Slot::WEEK_START,
+                    token: None,
+                    expected_version: None,
+                }),
+                &mut actions,
+            )
+            .await
+            .expect("Request should succeed");
+        }
+    }
+
+    let mut restored = Vec::new();
+    BookingSystem::restore(&system, &mut restored)
+        .await
+        .unwrap();
+    restored
+        .iter()
+        .map(|a| match a {
+            Action::Tracked(t) => *t.id(),
+            Action::Untracked(_) => panic!("Restore only emits tracked actions"),
+        })
+        .collect()
+}
+
+#[monoio::test]
+async fn test_restore_order_is_stable() {
+    // No sort: pending is an OrderedMap, so restore emits in request id order
+    let ids = restored_ids().await;
+    assert_eq!(ids, (1..=12).collect::<Vec<_>>());
+
+    // Each map gets its own hasher seed, so a HashMap would likely reorder here
+    assert_eq!(restored_ids().await, ids, "Restore order must be stable");
+}
+#[test]
+fn test_apt_type_from_name() {
+    let spellings = [
+        (AptType::Cleaning, "Cleaning", "cleaning"),
+        (AptType::Checkup, "Checkup", "checkup"),
+        (AptType::Filling, "Filling", "filling"),
+        (AptType::RootCanal, "Root Canal", "root_canal"),
+    ];
+    for (apt_type, display, snake) in spellings {
+        assert_eq!(display, apt_type.name());
+        assert_eq!(AptType::from_name(display), Some(apt_type));
+        assert_eq!(AptType::from_name(snake), Some(apt_type));
+        assert_eq!(snake.parse::<AptType>(), Ok(apt_type));
+    }
+
+    assert_eq!(AptType::from_name("root canal"), None);
+    assert_eq!(AptType::from_name("whitening"), None);
+    let err = "whitening".parse::<AptType>().unwrap_err();
+    assert_eq!(err, UnknownAptType("whitening".into()));
+    assert_eq!(err.to_string(), "unknown appointment type \"whitening\"");
+}
+fn block_maintenance(
+    day: Day,
+    start: Time,
+    end: Time,
+    force: bool,
+) -> Input<BookingTracked, BookingInput> {
+    Input::Normal(BookingInput::BlockMaintenance {
+        day,
+        start,
+        end,
+        force,
+    })
+}
+
+#[monoio::test]
+async fn test_maintenance_blocks_free_window() {
+    let mut system = BookingSystem::with_default_schedule();
+    let mut actions = Vec::new();
+    let block_start = Slot {
+        day: Day::Monday,
+        time: Time::new(9, 0),
+    };
+
+    BookingSystem::stf(
+        &mut system,
+        block_maintenance(Day::Monday, Time::new(9, 0), Time::new(10, 0), false),
+        &mut actions,
+    )
+    .await
+    .expect("Blocking a free window should succeed");
+    assert!(actions.is_empty());
+
+    let slot = Slot {
+        day: Day::Monday,
+        time: Time::new(9, 30),
+    };
+    assert_eq!(
+        system.unavailable_reason(slot, AptType::Checkup),
+        Some(UnavailableReason::Maintenance { start: block_start })
+    );
+    let morning = [TimeRange::new(Time::new(9, 0), Time::new(12, 0))];
+    assert_eq!(
+        system.find_slot(&[Day::Monday], &morning, AptType::Checkup),
+        Some(Slot {
+            day: Day::Monday,
+            time: Time::new(10, 0),
+        }),
+        "Auto-selection must skip the maintenance window"
+    );
+    system.check_invariants().unwrap();
+
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::ClearMaintenance {
+            day: Day::Monday,
+            start: Time::new(9, 0),
+        }),
+        &mut actions,
+    )
+    .await
+    .expect("Clearing the block should succeed");
+    assert!(system.is_available(slot, AptType::Checkup));
+    assert!(matches!(
+        BookingSystem::stf(
+            &mut system,
+            Input::Normal(BookingInput::ClearMaintenance {
+                day: Day::Monday,
+                start: Time::new(9, 0),
+            }),
+            &mut actions,
+        )
+        .await,
+        Err(BookingError::InvalidRequest)
+    ));
+}
+
+#[monoio::test]
+async fn test_maintenance_over_booking_requires_force() {
+    let mut system = BookingSystem::with_default_schedule();
+    let req_id = request_alice(&mut system).await;
+    let mut actions = Vec::new();
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: req_id,
+            res: PaymentResult::Success {
+                amount: Cents(7_500),
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .unwrap();
+
+    // Alice's 9:00 checkup runs until 9:30
+    let result = BookingSystem::stf(
+        &mut system,
+        block_maintenance(Day::Monday, Time::new(9, 15), Time::new(10, 0), false),
+        &mut actions,
+    )
+    .await;
+    assert!(matches!(result, Err(BookingError::SlotNotAvailable)));
+    assert!(system.maintenance.is_empty());
+    assert_eq!(system.bookings.len(), 1);
+
+    actions.clear();
+    BookingSystem::stf(
+        &mut system,
+        block_maintenance(Day::Monday, Time::new(9, 15), Time::new(10, 0), true),
+        &mut actions,
+    )
+    .await
+    .expect("Forced block should cancel the booking");
+    assert!(system.bookings.is_empty());
+    assert_eq!(system.pending[&req_id].status, ReqStatus::Cancelled);
+    assert_eq!(actions.len(), 3);
+    assert!(matches!(
+        &actions[0],
+        Action::Tracked(t) if *t.action() == PaymentReq::Release { req_id }
+    ));
+    assert!(matches!(
+        &actions[1],
+        Action::Untracked(UntrackedAction::Notify { user_id: 1, .. })
+    ));
+    assert_eq!(
+        actions[2].as_untracked(),
+        Some(&UntrackedAction::Cancelled {
+            req_id,
+            user_id: 1,
+            reason: CancelReason::Maintenance,
+        })
+    );
+    system.check_invariants().unwrap();
+}
+#[monoio::test]
+async fn test_unknown_tracked_result_rejected_uniformly() {
+    use phasm::engine::{Engine, EngineError};
+
+    let mut engine = Engine::<BookingSystem>::new(BookingSystem::with_default_schedule())
+        .unwrap()
+        .reject_unknown_results();
+
+    // Unchecked, these would hit InvalidRequest, a silent no-op, and InvalidRequest
+    let results = [
+        PaymentResult::Success {
+            amount: Cents(7_500),
+        },
+        PaymentResult::Failed {
+            reason: "declined".into(),
+        },
+        PaymentResult::Pending,
+    ];
+    for res in results {
+        let result = engine
+            .step(Input::TrackedActionCompleted { id: 999, res })
+            .await;
+        assert!(
+            matches!(result, Err(EngineError::StaleTrackedResult)),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+    assert_eq!(engine.transitions(), 0);
+    assert_eq!(engine.state().version, 0, "State must be untouched");
+
+    // Results for requests the system knows still go through
+    engine
+        .step(Input::Normal(BookingInput::RequestSlot {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(9, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }))
+        .await
+        .unwrap();
+    let req_id = engine.state().next_id - 1;
+    engine
+        .step(Input::TrackedActionCompleted {
+            id: req_id,
+            res: PaymentResult::Success {
+                amount: Cents(7_500),
+            },
+        })
+        .await
+        .expect("Known id should reach STF");
+    assert_eq!(engine.state().bookings.len(), 1);
+}
+#[monoio::test]
+async fn test_apt_type_buffer_uses_larger_of_pair() {
+    let monday = |hour, minute| Slot {
+        day: Day::Monday,
+        time: Time::new(hour, minute),
+    };
+    let mut actions = Vec::new();
+
+    for (apt_type, end) in [
+        (AptType::Cleaning, monday(9, 15)),
+        (AptType::RootCanal, monday(10, 0)),
+    ] {
+        let mut system = BookingSystem::with_default_schedule();
+        BookingSystem::stf(
+            &mut system,
+            Input::Normal(BookingInput::RequestSlot {
+                user_id: 1,
+                name: "Alice".into(),
+                email: "alice@example.com".into(),
+                day: Day::Monday,
+                time: Time::new(9, 0),
+                apt_type,
+                now: Slot::WEEK_START,
+                token: None,
+                expected_version: None,
+            }),
+            &mut actions,
+        )
+        .await
+        .unwrap();
+        let req_id = system.next_id - 1;
+        BookingSystem::stf(
+            &mut system,
+            Input::TrackedActionCompleted {
+                id: req_id,
+                res: PaymentResult::Success {
+                    amount: apt_type.price(),
+                },
+            },
+            &mut actions,
+        )
+        .await
+        .unwrap();
+
+        // A cleaning right after the first appointment ends
+        let allowed = system.is_available(end, AptType::Cleaning);
+        match apt_type {
+            AptType::Cleaning => assert!(allowed, "Cleanings need no buffer"),
+            _ => {
+                assert!(!allowed, "Root canal cleanup must block {}", end);
+                assert!(system.is_available(monday(10, 15), AptType::Cleaning));
+            }
+        }
+        system.check_invariants().unwrap();
+    }
+
+    // The invariant applies the same pair buffer
+    let mut system = BookingSystem::with_default_schedule();
+    for (slot, apt_type) in [
+        (monday(9, 0), AptType::RootCanal),
+        (monday(10, 5), AptType::Cleaning),
+    ] {
+        system.bookings.insert(
+            slot,
+            ConfirmedBooking {
+                user_id: 1,
+                name: "Alice".into(),
+                email: "alice@example.com".into(),
+                apt_type,
+                dur_mins: apt_type.dur(),
+                amount_paid: apt_type.price(),
+                confirm_by: None,
+                deposit: None,
+            },
+        );
+    }
+    let err = system
+        .check_invariants()
+        .expect_err("5 min after a root canal is too tight");
+    assert!(
+        err.contains("buffer is 15 min"),
+        "Unexpected error: {}",
+        err
+    );
+}
+#[test]
+fn test_time_add_stops_at_midnight() {
+    assert_eq!(Time::new(9, 45).add(30), Some(Time::new(10, 15)));
+    assert_eq!(Time::new(23, 30).add(29), Some(Time::new(23, 59)));
+    assert_eq!(Time::new(23, 30).add(30), None, "Midnight is the next day");
+    assert_eq!(Time::new(23, 30).checked_add(u16::MAX), None);
+    assert_eq!(Time::new(23, 30).saturating_add(90), Time::new(23, 59));
+}
+#[test]
+fn test_time_from_str() {
+    for (s, time) in [
+        ("00:00", Time::new(0, 0)),
+        ("09:05", Time::new(9, 5)),
+        ("9:05", Time::new(9, 5)),
+        ("14:30", Time::new(14, 30)),
+        ("23:59", Time::new(23, 59)),
+    ] {
+        assert_eq!(s.parse::<Time>(), Ok(time), "Parsing {:?}", s);
+        // Round-trips through Display
+        assert_eq!(time.to_string().parse::<Time>(), Ok(time));
+    }
+
+    assert_eq!(
+        "24:00".parse::<Time>(),
+        Err(TimeParseError::OutOfRange {
+            hour: 24,
+            minute: 0
+        })
+    );
+    assert_eq!(
+        "12:60".parse::<Time>(),
+        Err(TimeParseError::OutOfRange {
+            hour: 12,
+            minute: 60
+        })
+    );
+    for malformed in [
+        "ab:cd",
+        "12",
+        "12:3",
+        "-1:00",
+        "12:30:00",
+        "",
+        " 9:00",
+        "１２:00",
+    ] {
+        assert_eq!(
+            malformed.parse::<Time>(),
+            Err(TimeParseError::Malformed(malformed.into())),
+            "Parsing {:?}",
+            malformed
+        );
+    }
+}
+#[monoio::test]
+async fn test_exhausted_preauth_is_dead_lettered_and_fails_request() {
+    use phasm::engine::{DeadLetter, Engine, EngineError};
+    use std::{cell::RefCell, rc::Rc};
+
+    let letters: Rc<RefCell<Vec<DeadLetter<BookingTracked>>>> = Rc::default();
+    let sink = letters.clone();
+    let mut engine = Engine::<BookingSystem>::new(BookingSystem::with_default_schedule())
+        .unwrap()
+        .dead_letters(move |letter| sink.borrow_mut().push(letter));
+
+    engine
+        .step(Input::Normal(BookingInput::RequestSlot {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(9, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }))
+        .await
+        .unwrap();
+    let req_id = engine.state().next_id - 1;
+    assert!(engine.is_in_flight(&req_id));
+
+    engine
+        .tracked_exhausted(&req_id, "processor unreachable after 5 attempts")
+        .await
+        .unwrap();
+
+    let letter = letters
+        .borrow_mut()
+        .pop()
+        .expect("Preauth should be dead-lettered");
+    assert!(letters.borrow().is_empty());
+    assert_eq!(letter.id, req_id);
+    assert!(matches!(letter.action, PaymentReq::Preauth { .. }));
+    assert_eq!(letter.last_error, "processor unreachable after 5 attempts");
+
+    assert!(!engine.is_in_flight(&req_id));
+    assert_eq!(engine.state().pending[&req_id].status, ReqStatus::NoSlot);
+    engine.state().check_invariants().unwrap();
+
+    // Already resolved, so a second report is stale
+    assert!(matches!(
+        engine.tracked_exhausted(&req_id, "again").await,
+        Err(EngineError::StaleTrackedResult)
+    ));
+}
+
+/// Requests a 9:00 checkup on `day` and completes its preauth.
+async fn book_and_pay(system: &mut BookingSystem, user_id: u64, day: Day) -> ReqId {
+    let mut actions = Vec::new();
+    BookingSystem::stf(
+        system,
+        Input::Normal(BookingInput::RequestSlot {
+            user_id,
+            name: "Patient".into(),
+            email: "patient@example.com".into(),
+            day,
+            time: Time::new(9, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await
+    .expect("Request should succeed");
+    let req_id = system.next_id - 1;
+    BookingSystem::stf(
+        system,
+        Input::TrackedActionCompleted {
+            id: req_id,
+            res: PaymentResult::Success {
+                amount: Cents(7_500),
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .expect("Preauth should succeed");
+    req_id
+}
+
+async fn expire_unconfirmed(
+    system: &mut BookingSystem,
+    now: Slot,
+) -> Vec<Action<UntrackedAction, BookingTracked>> {
+    let mut actions = Vec::new();
+    BookingSystem::stf(
+        system,
+        Input::Normal(BookingInput::ExpireUnconfirmed { now }),
+        &mut actions,
+    )
+    .await
+    .expect("Expiry should succeed");
+    actions
+}
+
+#[test]
+fn test_confirm_deadline_saturates() {
+    let mut system = BookingSystem::with_default_schedule();
+    system.confirm_window_mins = Some(u32::MAX);
+    let now = Slot {
+        day: Day::Friday,
+        time: Time::new(9, 0),
+    };
+    assert_eq!(system.confirm_deadline(now), Some(u32::MAX));
+}
+
+#[monoio::test]
+async fn test_unconfirmed_booking_expires_and_frees_slot() {
+    let mut system = BookingSystem::with_default_schedule();
+    system.confirm_window_mins = Some(60);
+    let monday = |hour, minute| Slot {
+        day: Day::Monday,
+        time: Time::new(hour, minute),
+    };
+
+    let alice = book_and_pay(&mut system, 1, Day::Monday).await;
+    let bob = book_and_pay(&mut system, 2, Day::Tuesday).await;
+    assert_eq!(system.bookings[&monday(9, 0)].confirm_by, Some(60));
+
+    // Only Bob acknowledges his booking
+    let mut actions = Vec::new();
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::PatientConfirm { req_id: bob }),
+        &mut actions,
+    )
+    .await
+    .unwrap();
+
+    // The deadline itself isn't past it yet
+    assert!(expire_unconfirmed(&mut system, monday(1, 0))
+        .await
+        .is_empty());
+    assert_eq!(system.bookings.len(), 2);
+
+    let actions = expire_unconfirmed(&mut system, monday(1, 1)).await;
+    assert_eq!(actions.len(), 2);
+    assert!(matches!(
+        &actions[0],
+        Action::Tracked(t) if *t.id() == alice && *t.action() == PaymentReq::Release { req_id: alice }
+    ));
+    assert!(matches!(
+        &actions[1],
+        Action::Untracked(UntrackedAction::Notify { user_id: 1, .. })
+    ));
+    assert_eq!(system.pending[&alice].status, ReqStatus::Expired);
+    assert_eq!(system.pending[&bob].status, ReqStatus::SlotConfirmed);
+    assert_eq!(system.bookings.len(), 1, "Acknowledged booking is kept");
+    system.check_invariants().unwrap();
+
+    // The slot is bookable again
+    assert!(system.is_available(monday(9, 0), AptType::Checkup));
+    let carol = book_and_pay(&mut system, 3, Day::Monday).await;
+    assert_eq!(system.pending[&carol].status, ReqStatus::SlotConfirmed);
+    assert_eq!(system.bookings[&monday(9, 0)].user_id, 3);
+}
+#[monoio::test]
+async fn test_parse_input_builds_request_slot_from_json() {
+    assert_eq!(
+        BookingSystem::input_variants(),
+        ["request_slot", "request_auto"]
+    );
+
+    let args = serde_json::json!({
+        "user_id": 1,
+        "name": "Alice",
+        "email": "alice@example.com",
+        "day": "Mon",
+        "time": "09:00",
+        "apt_type": "checkup",
+        "now": "Mon 08:00",
+        "token": 7
+    });
+    let input = BookingSystem::parse_input("request_slot", &args).unwrap();
+    assert!(matches!(
+        &input,
+        BookingInput::RequestSlot {
+            user_id: 1,
+            day: Day::Monday,
+            time: Time(9, 0),
+            apt_type: AptType::Checkup,
+            now: Slot {
+                day: Day::Monday,
+                time: Time(8, 0)
+            },
+            token: Some(7),
+            expected_version: None,
+            ..
+        }
+    ));
+
+    // The parsed input goes through STF like any other
+    let mut system = BookingSystem::with_default_schedule();
+    let mut actions = Vec::new();
+    BookingSystem::stf(&mut system, Input::Normal(input), &mut actions)
+        .await
+        .unwrap();
+    assert_eq!(system.pending.len(), 1);
+
+    let bad_time = serde_json::json!({
+        "user_id": 1,
+        "name": "Alice",
+        "email": "alice@example.com",
+        "day": "Mon",
+        "time": "25:00",
+        "apt_type": "checkup",
+        "now": "Mon 08:00"
+    });
+    assert!(matches!(
+        BookingSystem::parse_input("request_slot", &bad_time),
+        Err(phasm::InputParseError::InvalidArgs { variant, reason })
+            if variant == "request_slot" && reason.starts_with("time:")
+    ));
+    assert!(matches!(
+        BookingSystem::parse_input("cancel_everything", &args),
+        Err(phasm::InputParseError::UnknownVariant(name)) if name == "cancel_everything"
+    ));
+}
+#[monoio::test]
+async fn test_assign_batch_satisfies_more_than_sequential() {
+    let nine_to_half_past = TimeRange::new(Time::new(9, 0), Time::new(9, 30));
+    // A flexible request first, then one that only fits Monday 9:00
+    let requests = [
+        AutoRequest {
+            req_id: 1,
+            days: vec![Day::Monday, Day::Tuesday],
+            times: vec![nine_to_half_past],
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+        },
+        AutoRequest {
+            req_id: 2,
+            days: vec![Day::Monday],
+            times: vec![nine_to_half_past],
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+        },
+    ];
+
+    // Sequential greedy: the flexible request takes Monday and starves the second
+    let mut system = BookingSystem::with_default_schedule();
+    let mut sequential = 0;
+    for req in &requests {
+        let mut actions = Vec::new();
+        let result = BookingSystem::stf(
+            &mut system,
+            Input::Normal(BookingInput::RequestAuto {
+                user_id: req.req_id,
+                name: "Patient".into(),
+                email: "patient@example.com".into(),
+                days: req.days.clone(),
+                times: req.times.clone(),
+                apt_type: req.apt_type,
+                now: req.now,
+                token: None,
+                expected_version: None,
+            }),
+            &mut actions,
+        )
+        .await;
+        if result.is_ok() {
+            let req_id = system.next_id - 1;
+            BookingSystem::stf(
+                &mut system,
+                Input::TrackedActionCompleted {
+                    id: req_id,
+                    res: PaymentResult::Success {
+                        amount: Cents(7_500),
+                    },
+                },
+                &mut actions,
+            )
+            .await
+            .unwrap();
+            sequential += 1;
+        }
+    }
+    assert_eq!(sequential, 1);
+
+    let system = BookingSystem::with_default_schedule();
+    let at_nine = |day| Slot {
+        day,
+        time: Time::new(9, 0),
+    };
+    let plan = system.assign_batch(&requests);
+    assert_eq!(
+        plan,
+        vec![
+            (1, Some(at_nine(Day::Tuesday))),
+            (2, Some(at_nine(Day::Monday)))
+        ]
+    );
+    assert_eq!(system.assign_batch(&requests), plan, "Deterministic");
+    assert!(system.bookings.is_empty(), "Planning is read-only");
+}
+#[monoio::test]
+async fn test_group_booking_is_atomic() {
+    let mut system = BookingSystem::with_default_schedule();
+    let mut actions = Vec::new();
+    // Monday 10:00 is taken, so only 9:00 and 9:30 are free before it
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::BlockMaintenance {
+            day: Day::Monday,
+            start: Time::new(10, 0),
+            end: Time::new(10, 30),
+            force: false,
+        }),
+        &mut actions,
+    )
+    .await
+    .unwrap();
+
+    let family: Vec<MemberReq> = ["Ann", "Ben", "Cat"]
+        .iter()
+        .enumerate()
+        .map(|(i, name)| MemberReq {
+            user_id: i as u64 + 1,
+            name: name.to_string(),
+            email: format!("{}@example.com", name.to_lowercase()),
+            apt_type: AptType::Checkup,
+        })
+        .collect();
+    let group_at = |hour, minute| {
+        Input::Normal(BookingInput::RequestGroup {
+            payer_id: 1,
+            members: family.clone(),
+            day: Day::Monday,
+            start_time: Time::new(hour, minute),
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        })
+    };
+
+    // 9:00, 9:30 are free but 10:00 isn't: nothing is reserved
+    let pending_before = system.pending.len();
+    let next_id_before = system.next_id;
+    let result = BookingSystem::stf(&mut system, group_at(9, 0), &mut actions).await;
+    assert!(matches!(result, Err(BookingError::SlotNotAvailable)));
+    assert!(actions.is_empty());
+    assert_eq!(system.pending.len(), pending_before);
+    assert_eq!(system.next_id, next_id_before);
+
+    // 10:30, 11:00, 11:30 are all free
+    BookingSystem::stf(&mut system, group_at(10, 30), &mut actions)
+        .await
+        .unwrap();
+    let group_id = next_id_before;
+    assert_eq!(actions.len(), 1, "One preauth for the whole group");
+    assert!(matches!(
+        &actions[0],
+        Action::Tracked(t) if *t.id() == group_id
+            && *t.action() == PaymentReq::Preauth { user_id: 1, amount_cents: 22500, req_id: group_id }
+    ));
+    let members: Vec<ReqId> = system
+        .pending
+        .iter()
+        .filter(|(_, p)| p.group_id == Some(group_id))
+        .map(|(id, _)| *id)
+        .collect();
+    assert_eq!(members, [group_id, group_id + 1, group_id + 2]);
+
+    let mut restored = Vec::new();
+    BookingSystem::restore(&system, &mut restored)
+        .await
+        .unwrap();
+    assert_eq!(
+        restored.iter_tracked().map(|t| *t.id()).collect::<Vec<_>>(),
+        [group_id],
+        "Restore re-checks the group's payment once"
+    );
+
+    actions.clear();
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: group_id,
+            res: PaymentResult::Success {
+                amount: Cents(22_500),
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    for (id, time) in members.iter().zip([(10, 30), (11, 0), (11, 30)]) {
+        assert_eq!(system.pending[id].status, ReqStatus::SlotConfirmed);
+        let slot = Slot {
+            day: Day::Monday,
+            time: Time::new(time.0, time.1),
+        };
+        assert_eq!(system.bookings[&slot].user_id, system.pending[id].user_id);
+    }
+    system.check_invariants().unwrap();
+}
+#[monoio::test]
+async fn test_approx_state_size_counts_collections() {
+    let mut system = BookingSystem::with_default_schedule();
+    assert_eq!(
+        BookingSystem::approx_state_size(&system),
+        phasm::StateSize::new([
+            ("pending", 0),
+            ("bookings", 0),
+            ("holds", 0),
+            ("maintenance", 0),
+            ("schedule", 7),
+        ])
+    );
+
+    system.optimistic_holds = true;
+    book_and_pay(&mut system, 1, Day::Monday).await;
+    book_and_pay(&mut system, 2, Day::Tuesday).await;
+    let mut actions = Vec::new();
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 3,
+            name: "Carol".into(),
+            email: "carol@example.com".into(),
+            day: Day::Wednesday,
+            time: Time::new(9, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await
+    .unwrap();
+
+    let size = BookingSystem::approx_state_size(&system);
+    assert_eq!(size.get("pending"), Some(system.pending.len()));
+    assert_eq!(size.get("pending"), Some(3));
+    assert_eq!(size.get("bookings"), Some(2));
+    assert_eq!(size.get("holds"), Some(1), "Only Carol's request is unpaid");
+    assert_eq!(size.entries, 3 + 2 + 1 + 7);
+    assert_eq!(
+        size.to_string(),
+        "13 entries (pending: 3, bookings: 2, holds: 1, maintenance: 0, schedule: 7)"
+    );
+}
+
+#[monoio::test]
+async fn test_engine_observes_state_size_periodically() {
+    use std::{cell::RefCell, rc::Rc};
+
+    use phasm::engine::Engine;
+
+    let sizes = Rc::new(RefCell::new(Vec::new()));
+    let observer = sizes.clone();
+    let mut engine = Engine::<BookingSystem>::new(BookingSystem::with_default_schedule())
+        .unwrap()
+        .observe_state_size(3, move |transition, size| {
+            observer
+                .borrow_mut()
+                .push((transition, size.collections[0]))
+        });
+    for day in [Day::Monday, Day::Tuesday, Day::Wednesday] {
+        for hour in [9, 10] {
+            engine
+                .step(Input::Normal(BookingInput::RequestSlot {
+                    user_id: 1,
+                    name: "Alice".into(),
+                    email: "alice@example.com".into(),
+                    day,
+                    time: Time::new(hour, 0),
+                    apt_type: AptType::Checkup,
+                    now: Slot::WEEK_START,
+                    token: None,
+                    expected_version: None,
+                }))
+                .await
+                .unwrap();
+        }
+    }
+    assert_eq!(*sizes.borrow(), [(3, ("pending", 3)), (6, ("pending", 6))]);
+}
+
+#[monoio::test]
+async fn test_merge_requests_releases_dropped_duplicate() {
+    let mut system = BookingSystem::with_default_schedule();
+    let keep = book_and_pay(&mut system, 1, Day::Monday).await;
+    let drop = book_and_pay(&mut system, 1, Day::Tuesday).await;
+    let other = book_and_pay(&mut system, 2, Day::Wednesday).await;
+
+    // Another patient's request can't be merged
+    let version = system.version;
+    let mut actions = Vec::new();
+    let result = BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::MergeRequests { keep, drop: other }),
+        &mut actions,
+    )
+    .await;
+    assert!(matches!(result, Err(BookingError::InvalidRequest)));
+    assert!(actions.is_empty());
+    assert_eq!(system.version, version);
+    assert_eq!(system.pending[&other].status, ReqStatus::SlotConfirmed);
+    assert_eq!(system.bookings.len(), 3);
+
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::MergeRequests { keep, drop }),
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    assert_eq!(actions.len(), 2);
+    assert!(matches!(
+        &actions[0],
+        Action::Tracked(t) if *t.id() == drop && *t.action() == PaymentReq::Release { req_id: drop }
+    ));
+    assert!(matches!(
+        actions[1],
+        Action::Untracked(UntrackedAction::Cancelled {
+            reason: CancelReason::Duplicate,
+            ..
+        })
+    ));
+    let active: Vec<ReqId> = system
+        .pending
+        .iter()
+        .filter(|(_, p)| p.user_id == 1 && p.status.is_active())
+        .map(|(id, _)| *id)
+        .collect();
+    assert_eq!(active, [keep]);
+    assert_eq!(system.pending[&drop].status, ReqStatus::Cancelled);
+    assert!(!system.bookings.contains_key(&Slot {
+        day: Day::Tuesday,
+        time: Time::new(9, 0),
+    }));
+    system.check_invariants().unwrap();
+}
+
+#[monoio::test]
+async fn test_merge_releases_awaiting_preauth_once_it_succeeds() {
+    let mut system = BookingSystem::with_default_schedule();
+    let keep = book_and_pay(&mut system, 1, Day::Tuesday).await;
+    let drop = request_alice(&mut system).await;
+
+    let mut actions = Vec::new();
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::MergeRequests { keep, drop }),
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    assert!(
+        matches!(
+            actions.as_slice(),
+            [Action::Untracked(UntrackedAction::Cancelled { .. })]
+        ),
+        "Nothing to release before the preauth"
+    );
+    actions.clear();
+
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: drop,
+            res: PaymentResult::Success {
+                amount: Cents(7_500),
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    assert!(matches!(
+        actions.as_slice(),
+        [Action::Tracked(t)] if *t.action() == PaymentReq::Release { req_id: drop }
+    ));
+    assert_eq!(system.bookings.len(), 1, "The dropped request isn't booked");
+    system.check_invariants().unwrap();
+}
+
+/// The requests whose payment status `restore` re-checks.
+async fn restored_checks(system: &BookingSystem) -> Vec<ReqId> {
+    let mut restored = Vec::new();
+    BookingSystem::restore(system, &mut restored).await.unwrap();
+    restored
+        .iter()
+        .filter_map(|a| match a.as_tracked()?.action() {
+            PaymentReq::CheckStatus { req_id } => Some(*req_id),
+            _ => None,
+        })
+        .collect()
+}
+
+#[monoio::test]
+async fn test_merged_preauth_is_rechecked_after_restart() {
+    let mut system = BookingSystem::with_default_schedule();
+    let keep = book_and_pay(&mut system, 1, Day::Tuesday).await;
+    let drop = request_alice(&mut system).await;
+
+    let mut actions = Vec::new();
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::MergeRequests { keep, drop }),
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    assert!(system.pending[&drop].preauth_in_flight);
+    assert_eq!(
+        restored_checks(&system).await,
+        [drop],
+        "A successful preauth must still be released"
+    );
+
+    // Releasing it may be lost too, so keep checking until it's confirmed
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: drop,
+            res: PaymentResult::Success {
+                amount: Cents(7_500),
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    assert_eq!(restored_checks(&system).await, [drop]);
+
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: drop,
+            res: PaymentResult::Released,
+        },
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    assert!(!system.pending[&drop].preauth_in_flight);
+    assert_eq!(system.pending[&drop].status, ReqStatus::Cancelled);
+    assert!(restored_checks(&system).await.is_empty());
+    system.check_invariants().unwrap();
+}
+
+async fn request_soonest(
+    system: &mut BookingSystem,
+    user_id: u64,
+    now: Slot,
+) -> Result<ReqId, BookingError> {
+    let mut actions = Vec::new();
+    BookingSystem::stf(
+        system,
+        Input::Normal(BookingInput::RequestSoonest {
+            user_id,
+            name: format!("User {}", user_id),
+            email: format!("user{}@example.com", user_id),
+            apt_type: AptType::Checkup,
+            now,
+        }),
+        &mut actions,
+    )
+    .await?;
+    let id = *actions.iter_tracked().next().unwrap().id();
+    Ok(id)
+}
+
+#[monoio::test]
+async fn test_concurrent_request_soonest_get_different_slots() {
+    let mut system = BookingSystem::with_default_schedule();
+    assert!(!system.optimistic_holds);
+    let now = Slot {
+        day: Day::Monday,
+        time: Time::new(10, 0),
+    };
+
+    // Both arrive before either preauth completes
+    let first = request_soonest(&mut system, 1, now).await.unwrap();
+    let second = request_soonest(&mut system, 2, now).await.unwrap();
+    let first_slot = system.pending[&first].slot.unwrap();
+    let second_slot = system.pending[&second].slot.unwrap();
+    assert_eq!(first_slot, now, "Earliest slot from now");
+    assert!(second_slot.week_mins() > first_slot.week_mins());
+    assert_eq!(system.holds.len(), 2);
+
+    let mut actions = Vec::new();
+    for id in [first, second] {
+        BookingSystem::stf(
+            &mut system,
+            Input::TrackedActionCompleted {
+                id,
+                res: PaymentResult::Success {
+                    amount: Cents(7_500),
+                },
+            },
+            &mut actions,
+        )
+        .await
+        .unwrap();
+        assert_eq!(system.pending[&id].status, ReqStatus::SlotConfirmed);
+    }
+    assert_eq!(system.bookings.len(), 2);
+    assert!(system.holds.is_empty());
+    system.check_invariants().unwrap();
+
+    // Nothing left in the week after Sunday evening
+    let version = system.version;
+    let late = Slot {
+        day: Day::Sunday,
+        time: Time::new(23, 0),
+    };
+    let result = request_soonest(&mut system, 3, late).await;
+    assert!(matches!(result, Err(BookingError::NoSlotFound)));
+    assert_eq!(system.version, version);
+    assert_eq!(system.pending.len(), 2);
+}
+#[monoio::test]
+async fn test_cancel_reason_is_preserved_for_each_variant() {
+    let reasons = [
+        CancelReason::PatientRequest,
+        CancelReason::NoShow,
+        CancelReason::ClinicClosure,
+        CancelReason::PaymentFailure,
+        CancelReason::Maintenance,
+        CancelReason::Duplicate,
+    ];
+    for reason in reasons {
+        let mut system = BookingSystem::with_default_schedule();
+        let req_id = book_and_pay(&mut system, 1, Day::Monday).await;
+
+        let mut actions = Vec::new();
+        BookingSystem::stf(
+            &mut system,
+            Input::Normal(BookingInput::CancelBooking { req_id, reason }),
+            &mut actions,
+        )
+        .await
+        .unwrap();
+        let pending = &system.pending[&req_id];
+        assert_eq!(pending.status, ReqStatus::Cancelled);
+        assert_eq!(pending.cancel_reason, Some(reason));
+        assert!(system.bookings.is_empty());
+        assert!(matches!(
+            actions.as_slice(),
+            [
+                Action::Tracked(t),
+                Action::Untracked(UntrackedAction::Notify { msg, .. }),
+                Action::Untracked(UntrackedAction::Cancelled { req_id: id, user_id: 1, reason: r }),
+            ] if *t.action() == PaymentReq::Release { req_id }
+                && msg.ends_with(&reason.to_string())
+                && *id == req_id
+                && *r == reason
+        ));
+        system.check_invariants().unwrap();
+
+        // Cancelled is terminal
+        let result = BookingSystem::stf(
+            &mut system,
+            Input::Normal(BookingInput::CancelBooking {
+                req_id,
+                reason: CancelReason::PatientRequest,
+            }),
+            &mut actions,
+        )
+        .await;
+        assert!(matches!(result, Err(BookingError::InvalidRequest)));
+        assert_eq!(system.pending[&req_id].cancel_reason, Some(reason));
+    }
+}
+
+#[monoio::test]
+async fn test_cancelled_request_stays_cancelled_when_preauth_resolves() {
+    let results = [
+        || PaymentResult::Released,
+        || PaymentResult::Failed {
+            reason: "Card declined".into(),
+        },
+    ];
+    for result in results {
+        let mut system = BookingSystem::with_default_schedule();
+        let req_id = request_alice(&mut system).await;
+        let mut actions = Vec::new();
+        BookingSystem::stf(
+            &mut system,
+            Input::Normal(BookingInput::CancelBooking {
+                req_id,
+                reason: CancelReason::PatientRequest,
+            }),
+            &mut actions,
+        )
+        .await
+        .unwrap();
+        system.check_invariants().unwrap();
+        assert_eq!(
+            restored_checks(&system).await,
+            [req_id],
+            "Restarting mustn't forget the preauth"
+        );
+
+        // The preauth still goes through, and is released
+        actions.clear();
+        BookingSystem::stf(
+            &mut system,
+            Input::TrackedActionCompleted {
+                id: req_id,
+                res: PaymentResult::Success {
+                    amount: Cents(7_500),
+                },
+            },
+            &mut actions,
+        )
+        .await
+        .unwrap();
+        assert!(matches!(
+            &actions[..],
+            [Action::Tracked(t)] if *t.action() == PaymentReq::Release { req_id }
+        ));
+        system.check_invariants().unwrap();
+
+        BookingSystem::stf(
+            &mut system,
+            Input::TrackedActionCompleted {
+                id: req_id,
+                res: result(),
+            },
+            &mut actions,
+        )
+        .await
+        .unwrap();
+        let pending = &system.pending[&req_id];
+        assert_eq!(pending.status, ReqStatus::Cancelled);
+        assert_eq!(pending.cancel_reason, Some(CancelReason::PatientRequest));
+        assert!(system.bookings.is_empty());
+        assert!(restored_checks(&system).await.is_empty());
+        system.check_invariants().unwrap();
+    }
+}
+
+#[test]
+fn test_invariants_require_cancel_reason() {
+    let mut system = BookingSystem::with_default_schedule();
+    system.pending.insert(
+        0,
+        PendingReq {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            slot: None,
+            apt_type: AptType::Checkup,
+            status: ReqStatus::Cancelled,
+            confirm_by: None,
+            group_id: None,
+            cancel_reason: None,
+            preauth_in_flight: false,
+        },
+    );
+    let err = system.check_invariants().unwrap_err();
+    assert!(
+        err.contains("Request 0 is Cancelled with cancel reason None"),
+        "{}",
+        err
+    );
+}
+#[monoio::test]
+async fn test_step_outcome_classifies_transitions() {
+    use phasm::engine::{Engine, TransitionOutcome};
+
+    let mut engine = Engine::<BookingSystem>::new(BookingSystem::with_default_schedule())
+        .unwrap()
+        .idempotency_keys(16);
+    let request = |token: u64, time: Time, expected_version: Option<u64>| {
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            day: Day::Monday,
+            time,
+            apt_type: AptType::Checkup,
+            now: Slot {
+                day: Day::Monday,
+                time: Time::new(9, 30),
+            },
+            token: Some(token),
+            expected_version,
+        })
+    };
+
+    assert!(matches!(
+        engine
+            .step_outcome(request(1, Time::new(10, 0), None))
+            .await,
+        TransitionOutcome::Applied { actions: 1 }
+    ));
+    assert!(
+        matches!(
+            engine
+                .step_outcome(request(1, Time::new(10, 0), None))
+                .await,
+            TransitionOutcome::AppliedNoOp
+        ),
+        "Idempotent duplicate"
+    );
+    assert!(engine.actions().is_empty());
+
+    assert!(matches!(
+        engine.step_outcome(request(2, Time::new(9, 0), None)).await,
+        TransitionOutcome::RejectedValidation(BookingError::TooSoon)
+    ));
+    assert!(matches!(
+        engine
+            .step_outcome(request(3, Time::new(11, 0), Some(0)))
+            .await,
+        TransitionOutcome::RejectedConflict(Some(BookingError::Conflict))
+    ));
+    assert_eq!(engine.state().pending.len(), 1);
+}
+#[monoio::test]
+async fn test_extend_booking() {
+    let mut system = BookingSystem::with_default_schedule();
+    let first = book_and_pay(&mut system, 1, Day::Monday).await;
+    let mut actions = Vec::new();
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 2,
+            name: "Bob".into(),
+            email: "bob@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(10, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    let second = system.next_id - 1;
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: second,
+            res: PaymentResult::Success {
+                amount: Cents(7_500),
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    let first_slot = Slot {
+        day: Day::Monday,
+        time: Time::new(9, 0),
+    };
+
+    // 09:00-09:30 extended up to the 10:00 booking
+    actions.clear();
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::Extend {
+            req_id: first,
+            extra_mins: 30,
+        }),
+        &mut actions,
+    )
+    .await
+    .expect("Free time after the booking");
+    assert_eq!(system.bookings[&first_slot].dur_mins, 60);
+    assert_eq!(
+        actions[0].as_untracked(),
+        Some(&UntrackedAction::Notify {
+            user_id: 1,
+            msg: "Your Checkup on Mon 09:00 was extended to 60 minutes".into(),
+        })
+    );
+    system.check_invariants().unwrap();
+
+    // Into the next booking, or past the end of the 09:00-12:00 range
+    let version = system.version;
+    for (req_id, extra_mins) in [(first, 15), (second, 150)] {
+        actions.clear();
+        let result = BookingSystem::stf(
+            &mut system,
+            Input::Normal(BookingInput::Extend { req_id, extra_mins }),
+            &mut actions,
+        )
+        .await;
+        assert!(matches!(result, Err(BookingError::SlotNotAvailable)));
+        assert!(actions.is_empty());
+    }
+    assert_eq!(system.bookings[&first_slot].dur_mins, 60);
+    assert_eq!(system.version, version, "Failed extensions change nothing");
+    system.check_invariants().unwrap();
+}
+#[monoio::test]
+async fn test_apply_all_atomic_rolls_back_batch() {
+    use phasm::{apply_all_atomic, diff::StateDiff};
+
+    let mut system = BookingSystem::with_default_schedule();
+    book_and_pay(&mut system, 1, Day::Monday).await;
+    let request = |user_id, time| {
+        Input::Normal(BookingInput::RequestSlot {
+            user_id,
+            name: "Patient".into(),
+            email: "patient@example.com".into(),
+            day: Day::Monday,
+            time,
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        })
+    };
+    let before = system.clone();
+
+    // 09:00 is already booked by user 1
+    let mut actions = Vec::new();
+    let result = apply_all_atomic::<BookingSystem>(
+        &mut system,
+        vec![
+            request(2, Time::new(10, 0)),
+            request(3, Time::new(9, 0)),
+            request(4, Time::new(11, 0)),
+        ],
+        &mut actions,
+    )
+    .await;
+    assert!(matches!(result, Err((1, BookingError::SlotNotAvailable))));
+    assert!(actions.is_empty(), "Actions of the batch are discarded");
+    assert_eq!(BookingSystem::diff(&before, &system), []);
+
+    apply_all_atomic::<BookingSystem>(
+        &mut system,
+        vec![request(2, Time::new(10, 0)), request(4, Time::new(11, 0))],
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    assert_eq!(system.pending.len(), 3);
+    assert_eq!(actions.iter_tracked().count(), 2);
+}
+#[monoio::test]
+async fn test_process_all_stops_at_first_error() {
+    use phasm::process_all;
+
+    let mut system = BookingSystem::with_default_schedule();
+    book_and_pay(&mut system, 1, Day::Monday).await;
+    let request = |user_id, time| {
+        Input::Normal(BookingInput::RequestSlot {
+            user_id,
+            name: "Patient".into(),
+            email: "patient@example.com".into(),
+            day: Day::Monday,
+            time,
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        })
+    };
+
+    // 09:00 is already booked by user 1
+    let mut actions = Vec::new();
+    let result = process_all::<BookingSystem>(
+        &mut system,
+        vec![
+            request(2, Time::new(10, 0)),
+            request(3, Time::new(9, 0)),
+            request(4, Time::new(11, 0)),
+        ],
+        &mut actions,
+    )
+    .await;
+    assert!(matches!(result, Err((1, BookingError::SlotNotAvailable))));
+    assert_eq!(
+        system.pending.len(),
+        2,
+        "Inputs before the failure stay applied"
+    );
+    assert_eq!(actions.iter_tracked().count(), 1);
+
+    actions.clear();
+    let applied = process_all::<BookingSystem>(
+        &mut system,
+        vec![request(3, Time::new(11, 0)), request(4, Time::new(14, 0))],
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    assert_eq!(applied, 2);
+    assert_eq!(system.pending.len(), 4);
+    system.check_invariants().unwrap();
+}
+#[monoio::test]
+async fn test_health_reports_invariants_and_orphans() {
+    let mut system = BookingSystem::with_default_schedule();
+    let req_id = book_and_pay(&mut system, 1, Day::Monday).await;
+    let health = BookingSystem::health(&system);
+    assert!(health.invariants_ok && health.is_healthy(), "{:?}", health);
+
+    // The confirmed request's record was lost, so its booking drifted
+    system.pending.remove(&req_id);
+    let health = BookingSystem::health(&system);
+    assert!(health.invariants_ok, "Orphans alone don't fail invariants");
+    assert_eq!(
+        health.issues,
+        ["booking Mon 09:00 of user 1 has no confirmed request"]
+    );
+
+    // A booking on a closed day breaks an invariant too
+    let booking = system.bookings.values().next().unwrap().clone();
+    let saturday = Slot {
+        day: Day::Saturday,
+        time: Time::new(9, 0),
+    };
+    system.bookings.insert(saturday, booking);
+    let health = BookingSystem::health(&system);
+    assert!(!health.invariants_ok);
+    assert_eq!(
+        health.issues,
+        [
+            "Booking Sat 09:00 on closed day",
+            "booking Mon 09:00 of user 1 has no confirmed request",
+            "booking Sat 09:00 of user 1 has no confirmed request",
+        ]
+    );
+}
+
+#[monoio::test]
+async fn test_health_ignores_requests_own_holds() {
+    let mut system = BookingSystem::with_default_schedule();
+    system.optimistic_holds = true;
+    let healthy = |system: &BookingSystem| {
+        assert!(system.detect_orphans().is_empty());
+        let health = BookingSystem::health(system);
+        assert!(health.is_healthy(), "{:?}", health);
+    };
+
+    request_alice(&mut system).await;
+    healthy(&system);
+
+    let now = Slot {
+        day: Day::Monday,
+        time: Time::new(10, 0),
+    };
+    request_soonest(&mut system, 3, now).await.unwrap();
+    healthy(&system);
+
+    let bob = hold_for_bob(&mut system, Day::Tuesday, 60).await;
+    let mut actions = Vec::new();
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::ConfirmHold {
+            req_id: bob,
+            now: Slot::WEEK_START,
+        }),
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    assert_eq!(system.pending[&bob].status, ReqStatus::AwaitingPreauth);
+    assert_eq!(system.holds.len(), 3, "Every request holds its own slot");
+    healthy(&system);
+}
+
+/// The `Capture` actions in `actions`, as (req_id, amount_cents).
+fn captures(actions: &[Action<UntrackedAction, BookingTracked>]) -> Vec<(ReqId, u32)> {
+    actions
+        .iter()
+        .filter_map(|a| match a {
+            Action::Tracked(t) => match t.action() {
+                PaymentReq::Capture {
+                    req_id,
+                    amount_cents,
+                } => Some((*req_id, *amount_cents)),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
+}
+
+#[monoio::test]
+async fn test_deposit_and_final_capture_equal_price() {
+    let mut system = BookingSystem::with_default_schedule();
+    system.deposit_percent = Some(33);
+    let mut actions = Vec::new();
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(9, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    let req_id = system.next_id - 1;
+    let price_cents = AptType::Checkup.price_cents();
+
+    // Confirmation captures only the deposit
+    actions.clear();
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: req_id,
+            res: PaymentResult::Success {
+                amount: AptType::Checkup.price(),
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    let deposit = captures(&actions);
+    assert_eq!(deposit, vec![(req_id, price_cents * 33 / 100)]);
+    system.check_invariants().unwrap();
+
+    // An in-flight capture is re-emitted on restore, and blocks MarkAttended
+    let mut restored = Vec::new();
+    BookingSystem::restore(&system, &mut restored)
+        .await
+        .unwrap();
+    assert_eq!(captures(&restored), deposit);
+    let attended = || Input::Normal(BookingInput::MarkAttended { req_id });
+    let result = BookingSystem::stf(&mut system, attended(), &mut actions).await;
+    assert!(matches!(result, Err(BookingError::InvalidRequest)));
+
+    actions.clear();
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: req_id,
+            res: PaymentResult::Captured {
+                amount_cents: deposit[0].1,
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .unwrap();
+
+    // Attending captures the rest
+    BookingSystem::stf(&mut system, attended(), &mut actions)
+        .await
+        .unwrap();
+    let rest = captures(&actions);
+    assert_eq!(rest.len(), 1);
+    assert_eq!(deposit[0].1 + rest[0].1, price_cents);
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: req_id,
+            res: PaymentResult::Captured {
+                amount_cents: rest[0].1,
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .unwrap();
+
+    let booking = system.bookings.values().next().unwrap();
+    assert_eq!(
+        booking.deposit,
+        Some(Deposit {
+            captured_cents: price_cents,
+            capturing_cents: None,
+            outstanding_cents: 0,
+        })
+    );
+    system.check_invariants().unwrap();
+
+    // Nothing left to capture
+    let result = BookingSystem::stf(&mut system, attended(), &mut actions).await;
+    assert!(matches!(result, Err(BookingError::InvalidRequest)));
+}
+#[monoio::test]
+async fn test_group_booking_takes_deposit_per_member() {
+    let mut system = BookingSystem::with_default_schedule();
+    system.deposit_percent = Some(20);
+    let members = [AptType::Checkup, AptType::Cleaning]
+        .into_iter()
+        .zip(["Ann", "Ben"])
+        .enumerate()
+        .map(|(i, (apt_type, name))| MemberReq {
+            user_id: i as u64 + 1,
+            name: name.to_string(),
+            email: format!("{}@example.com", name.to_lowercase()),
+            apt_type,
+        })
+        .collect();
+    let mut actions = Vec::new();
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::RequestGroup {
+            payer_id: 1,
+            members,
+            day: Day::Monday,
+            start_time: Time::new(9, 0),
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    let group_id = system.next_id - 2;
+    let ben = group_id + 1;
+
+    actions.clear();
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: group_id,
+            res: PaymentResult::Success {
+                amount: Cents(12_500),
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    let tracked = |actions: &[Action<UntrackedAction, BookingTracked>]| {
+        actions
+            .iter()
+            .filter_map(|a| Some(*a.as_tracked()?.id()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        captures(&actions),
+        [(group_id, 1_500), (group_id, 1_000)],
+        "Each deposit is captured from the group's preauth"
+    );
+    assert_eq!(tracked(&actions), [group_id, ben]);
+    system.check_invariants().unwrap();
+
+    let mut restored = Vec::new();
+    BookingSystem::restore(&system, &mut restored)
+        .await
+        .unwrap();
+    assert_eq!(captures(&restored), captures(&actions));
+    assert_eq!(tracked(&restored), [group_id, ben]);
+
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: ben,
+            res: PaymentResult::Captured {
+                amount_cents: 1_000,
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    actions.clear();
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::MarkAttended { req_id: ben }),
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    assert_eq!(captures(&actions), [(group_id, 4_000)]);
+    assert_eq!(tracked(&actions), [ben]);
+    system.check_invariants().unwrap();
+}
+
+#[test]
+fn test_no_input_is_read_only() {
+    let inputs = [
+        BookingInput::RequestSoonest {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+        },
+        BookingInput::ClearMaintenance {
+            day: Day::Monday,
+            start: Time::new(9, 0),
+        },
+        BookingInput::CancelBooking {
+            req_id: 0,
+            reason: CancelReason::PatientRequest,
+        },
+        BookingInput::PatientConfirm { req_id: 0 },
+        BookingInput::MarkAttended { req_id: 0 },
+        BookingInput::ExpireUnconfirmed {
+            now: Slot::WEEK_START,
+        },
+    ];
+    for input in &inputs {
+        assert!(
+            !BookingSystem::is_read_only(input),
+            "{:?} changes state and must go to the leader",
+            input
+        );
+    }
+}
+/// A state whose invariants fail twice over: overlapping bookings, and holds by
+/// requests that don't exist.
+fn inconsistent_state() -> BookingSystem {
+    let mut system = BookingSystem::with_default_schedule();
+    for (mins, apt_type) in [
+        (45, AptType::Checkup),
+        (0, AptType::RootCanal),
+        (15, AptType::Checkup),
+    ] {
+        system.bookings.insert(
+            Slot {
+                day: Day::Monday,
+                time: Time::new(9, 0).add(mins).unwrap(),
+            },
+            ConfirmedBooking {
+                user_id: 1,
+                name: "Alice".into(),
+                email: "alice@example.com".into(),
+                apt_type,
+                dur_mins: apt_type.dur(),
+                amount_paid: apt_type.price(),
+                confirm_by: None,
+                deposit: None,
+            },
+        );
+    }
+    for (i, day) in [Day::Friday, Day::Tuesday, Day::Thursday, Day::Wednesday]
+        .into_iter()
+        .enumerate()
+    {
+        system.holds.insert(
+            Slot {
+                day,
+                time: Time::new(14, 0),
+            },
+            100 + i as ReqId,
+        );
+    }
+    system
+}
+
+#[test]
+fn test_invariant_violations_are_reported_deterministically() {
+    // Each map gets its own hash seed, so a hash-ordered scan would vary between them
+    for _ in 0..20 {
+        let mut system = inconsistent_state();
+        assert_eq!(
+            system.check_invariants(),
+            Err("Overlapping bookings: Mon 09:00 (RootCanal) and Mon 09:15 (Checkup)".into())
+        );
+
+        system.bookings.clear();
+        assert_eq!(
+            system.check_invariants(),
+            Err("Hold on Tue 14:00 by request 101 is not awaiting preauth".into())
+        );
+    }
+}
+/// Tentatively holds 09:00 on `day` for a Checkup for user 2, for `hold_mins` from
+/// the week start.
+async fn hold_for_bob(system: &mut BookingSystem, day: Day, hold_mins: u32) -> ReqId {
+    let mut actions = Vec::new();
+    BookingSystem::stf(
+        system,
+        Input::Normal(BookingInput::TentativeHold {
+            user_id: 2,
+            name: "Bob".into(),
+            email: "bob@example.com".into(),
+            slot: Slot {
+                day,
+                time: Time::new(9, 0),
+            },
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            hold_mins,
+        }),
+        &mut actions,
+    )
+    .await
+    .expect("Hold should succeed");
+    assert!(
+        actions.is_empty(),
+        "No payment is taken for a tentative hold"
+    );
+    system.next_id - 1
+}
+
+#[monoio::test]
+async fn test_tentative_hold_blocks_slot_until_expiry() {
+    let mut system = BookingSystem::with_default_schedule();
+    let bob = hold_for_bob(&mut system, Day::Monday, 60).await;
+    assert_eq!(
+        system.pending[&bob].status,
+        ReqStatus::Tentative { until: 60 }
+    );
+    system.check_invariants().unwrap();
+
+    let mut actions = Vec::new();
+    let result = BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(9, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await;
+    assert!(matches!(result, Err(BookingError::SlotNotAvailable)));
+
+    // Not expired at its deadline, only after it
+    let expire = |mins| {
+        Input::Normal(BookingInput::ExpireTentative {
+            now: Slot {
+                day: Day::Monday,
+                time: Time::new(0, 0).add(mins).unwrap(),
+            },
+        })
+    };
+    BookingSystem::stf(&mut system, expire(60), &mut actions)
+        .await
+        .unwrap();
+    assert!(matches!(
+        system.pending[&bob].status,
+        ReqStatus::Tentative { .. }
+    ));
+
+    actions.clear();
+    BookingSystem::stf(&mut system, expire(61), &mut actions)
+        .await
+        .unwrap();
+    assert_eq!(system.pending[&bob].status, ReqStatus::HoldExpired);
+    assert!(system.holds.is_empty());
+    assert_eq!(actions.len(), 1);
+    assert_eq!(
+        actions[0].as_untracked(),
+        Some(&UntrackedAction::Notify {
+            user_id: 2,
+            msg: "Your hold on Mon 09:00 has expired".into(),
+        })
+    );
+    system.check_invariants().unwrap();
+
+    // Too late to confirm, and the slot is free again
+    let result = BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::ConfirmHold {
+            req_id: bob,
+            now: Slot::WEEK_START,
+        }),
+        &mut actions,
+    )
+    .await;
+    assert!(matches!(result, Err(BookingError::InvalidRequest)));
+    request_alice(&mut system).await;
+}
+
+#[monoio::test]
+async fn test_tentative_hold_rejects_overflowing_duration() {
+    let mut system = BookingSystem::with_default_schedule();
+    let mut actions = Vec::new();
+    let result = BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::TentativeHold {
+            user_id: 2,
+            name: "Bob".into(),
+            email: "bob@example.com".into(),
+            slot: Slot {
+                day: Day::Monday,
+                time: Time::new(9, 0),
+            },
+            apt_type: AptType::Checkup,
+            now: Slot {
+                day: Day::Monday,
+                time: Time::new(8, 0),
+            },
+            hold_mins: u32::MAX,
+        }),
+        &mut actions,
+    )
+    .await;
+    assert!(matches!(result, Err(BookingError::InvalidRequest)));
+    assert!(system.pending.is_empty());
+    assert!(system.holds.is_empty());
+}
+
+#[monoio::test]
+async fn test_confirm_hold_emits_preauth() {
+    let mut system = BookingSystem::with_default_schedule();
+    let bob = hold_for_bob(&mut system, Day::Tuesday, 60).await;
+
+    let mut actions = Vec::new();
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::ConfirmHold {
+            req_id: bob,
+            now: Slot::WEEK_START,
+        }),
+        &mut actions,
+    )
+    .await
+    .expect("Hold hasn't expired");
+    assert_eq!(system.pending[&bob].status, ReqStatus::AwaitingPreauth);
+    assert!(matches!(
+        &actions[..],
+        [Action::Tracked(t)] if *t.action() == PaymentReq::Preauth {
+            user_id: 2,
+            amount_cents: 7500,
+            req_id: bob,
+        }
+    ));
+    assert_eq!(
+        system.holds.len(),
+        1,
+        "Still held while the preauth is pending"
+    );
+
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionCompleted {
+            id: bob,
+            res: PaymentResult::Success {
+                amount: Cents(7_500),
+            },
+        },
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    assert_eq!(system.pending[&bob].status, ReqStatus::SlotConfirmed);
+    assert!(system.holds.is_empty());
+    system.check_invariants().unwrap();
+}
+
+#[monoio::test]
+async fn test_expired_preauth_fails_request() {
+    let mut system = BookingSystem::with_default_schedule();
+    let mut actions = Vec::new();
+    BookingSystem::stf(
+        &mut system,
+        Input::Normal(BookingInput::RequestSlot {
+            user_id: 1,
+            name: "Alice".into(),
+            email: "alice@example.com".into(),
+            day: Day::Monday,
+            time: Time::new(9, 0),
+            apt_type: AptType::Checkup,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }),
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    let req_id = system.next_id - 1;
+    assert!(matches!(
+        &actions[..],
+        [Action::Tracked(t)] if t.deadline() == Some(PAYMENT_DEADLINE)
+    ));
+
+    // A restart re-checks the preauth with a fresh deadline
+    let mut restored = Vec::new();
+    BookingSystem::restore(&system, &mut restored)
+        .await
+        .unwrap();
+    assert!(matches!(
+        &restored[..],
+        [Action::Tracked(t)] if *t.action() == PaymentReq::CheckStatus { req_id }
+            && t.deadline() == Some(PAYMENT_DEADLINE)
+    ));
+
+    actions.clear();
+    BookingSystem::stf(
+        &mut system,
+        Input::TrackedActionExpired { id: req_id },
+        &mut actions,
+    )
+    .await
+    .unwrap();
+    assert_eq!(system.pending[&req_id].status, ReqStatus::NoSlot);
+    assert!(system.bookings.is_empty());
+    system.check_invariants().unwrap();
+}
@@ -1,8 +1,11 @@
 use dentist_booking::*;
-use phasm::{Input, StateMachine};
+use phasm::{actions::TrackedAction, sim::Simulator, testing::EffectsLedger, Input, StateMachine};
 use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha8Rng;
-use std::time::{Duration, Instant};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
 
 #[derive(Debug, Default)]
 struct TestStats {
@@ -349,6 +352,9 @@ async fn request_slot(
             day,
             time,
             apt_type,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
         }),
         &mut actions,
     )
@@ -375,6 +381,9 @@ async fn request_auto(
             days,
             times,
             apt_type,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
         }),
         &mut actions,
     )
@@ -392,7 +401,7 @@ async fn complete_preauth(
 
     let result = if success {
         let apt_type = system.pending.get(&req_id).map(|p| p.apt_type);
-        let amount = apt_type.map(|t| t.price()).unwrap_or(50.0);
+        let amount = apt_type.map(|t| t.price()).unwrap_or(Cents(5_000));
         PaymentResult::Success { amount }
     } else {
         PaymentResult::Failed {
@@ -453,9 +462,9 @@ fn random_time_ranges(rng: &mut ChaCha8Rng, count: usize) -> Vec<TimeRange> {
     let mut ranges = Vec::new();
     for _ in 0..count {
         let start = random_time(rng);
-        let end = start.add(rng.gen_range(60..240));
-        if end.0 < 18 {
-            ranges.push(TimeRange::new(start, end));
+        let end = start.saturating_add(rng.gen_range(60..240));
+        if let Ok(range) = TimeRange::try_new(start, end) {
+            ranges.push(range);
         }
     }
     if ranges.is_empty() {
@@ -503,9 +512,10 @@ fn verify_booking_matches_request(
     // If confirmed, verify the booking also matches
     if pending.status == ReqStatus::SlotConfirmed {
         if let Some(slot) = pending.slot {
-            let booking = system.bookings.get(&slot).ok_or_else(|| {
-                format!("Confirmed booking not found at slot {:?}", slot)
-            })?;
+            let booking = system
+                .bookings
+                .get(&slot)
+                .ok_or_else(|| format!("Confirmed booking not found at slot {:?}", slot))?;
 
             if booking.user_id != expected_user_id {
                 return Err(format!(
@@ -541,7 +551,7 @@ fn verify_auto_selection_preferences(
 
     let slot = pending
         .slot
-        .ok_or_else(|| format!("Auto-selection did not assign a slot"))?;
+        .ok_or_else(|| "Auto-selection did not assign a slot".to_string())?;
 
     // Verify day preference
     if !preferred_days.contains(&slot.day) {
@@ -552,7 +562,9 @@ fn verify_auto_selection_preferences(
     }
 
     // Verify time preference
-    let time_matches = preferred_times.iter().any(|range| range.contains(slot.time));
+    let time_matches = preferred_times
+        .iter()
+        .any(|range| range.contains(slot.time));
     if !time_matches {
         return Err(format!(
             "Auto-selected time {} not in any preferred time range",
@@ -654,7 +666,9 @@ async fn run_booking_preferences_test(seed: u64) -> Result<TestStats, String> {
         let times = random_time_ranges(&mut rng, time_count);
         let apt_type = random_apt_type(&mut rng);
 
-        if let Ok(req_id) = request_auto(&mut system, user_id, days.clone(), times.clone(), apt_type).await {
+        if let Ok(req_id) =
+            request_auto(&mut system, user_id, days.clone(), times.clone(), apt_type).await
+        {
             stats.total_operations += 1;
 
             // Verify auto-selection respected preferences
@@ -689,3 +703,193 @@ async fn run_booking_preferences_test(seed: u64) -> Result<TestStats, String> {
 
     Ok(stats)
 }
+
+// ============================================================================
+// Payment Effects
+// ============================================================================
+
+/// Totals folded from the payment actions of a run, in cents.
+#[derive(Debug, Default)]
+struct PaymentTotals {
+    /// Preauth amount per request, since a release only carries the request id.
+    holds: BTreeMap<ReqId, u64>,
+    preauthorized: u64,
+    released: u64,
+}
+
+fn fold_payment(totals: &mut PaymentTotals, tracked: &TrackedAction<BookingTracked>) {
+    match tracked.action() {
+        PaymentReq::Preauth {
+            amount_cents,
+            req_id,
+            ..
+        } => {
+            totals.holds.insert(*req_id, *amount_cents as u64);
+            totals.preauthorized += *amount_cents as u64;
+        }
+        PaymentReq::Release { req_id } => totals.released += totals.holds[req_id],
+        PaymentReq::Capture { .. } | PaymentReq::CheckStatus { .. } => {}
+    }
+}
+
+fn confirmed_cents(system: &BookingSystem) -> u64 {
+    system.bookings.values().map(|b| b.amount_paid.0).sum()
+}
+
+#[monoio::test]
+async fn test_payment_effects_balance() {
+    let mut rng = ChaCha8Rng::seed_from_u64(31337);
+    let mut system = BookingSystem::with_default_schedule();
+    let mut ledger = EffectsLedger::<BookingSystem, _>::new(PaymentTotals::default(), fold_payment);
+    let mut actions = Vec::new();
+    // Declined preauths never held any money
+    let mut declined = 0u64;
+    let mut in_flight: Vec<ReqId> = Vec::new();
+
+    for step in 0..2000 {
+        actions.clear();
+        if step % 5 < 2 && !in_flight.is_empty() {
+            let req_id = in_flight.swap_remove(rng.gen_range(0..in_flight.len()));
+            let res = if rng.gen_bool(0.8) {
+                PaymentResult::Success {
+                    amount: system.pending[&req_id].apt_type.price(),
+                }
+            } else {
+                declined += ledger.total().holds[&req_id];
+                PaymentResult::Failed {
+                    reason: "Card declined".into(),
+                }
+            };
+            let input = Input::TrackedActionCompleted { id: req_id, res };
+            BookingSystem::stf(&mut system, input, &mut actions)
+                .await
+                .unwrap();
+        } else {
+            let user_id = step as u64;
+            let input = Input::Normal(BookingInput::RequestSlot {
+                user_id,
+                name: format!("User{}", user_id),
+                email: format!("user{}@example.com", user_id),
+                day: random_day(&mut rng),
+                time: random_time(&mut rng),
+                apt_type: random_apt_type(&mut rng),
+                now: Slot::WEEK_START,
+                token: None,
+                expected_version: None,
+            });
+            if BookingSystem::stf(&mut system, input, &mut actions)
+                .await
+                .is_ok()
+            {
+                in_flight.push(system.next_id - 1);
+            }
+        }
+        ledger.record(&actions);
+
+        // Money still held covers every confirmed booking
+        let totals = ledger.total();
+        assert!(
+            totals.preauthorized - totals.released - declined >= confirmed_cents(&system),
+            "Step {}: confirmed bookings exceed held preauths",
+            step
+        );
+    }
+
+    // Settle everything still in flight so the books close
+    for req_id in in_flight {
+        actions.clear();
+        let res = PaymentResult::Success {
+            amount: system.pending[&req_id].apt_type.price(),
+        };
+        let input = Input::TrackedActionCompleted { id: req_id, res };
+        BookingSystem::stf(&mut system, input, &mut actions)
+            .await
+            .unwrap();
+        ledger.record(&actions);
+    }
+
+    let totals = ledger.into_total();
+    let confirmed = confirmed_cents(&system);
+    assert!(confirmed > 0 && totals.released > 0 && declined > 0);
+    assert_eq!(
+        totals.preauthorized,
+        confirmed + totals.released + declined,
+        "Every preauthorized cent is booked, released or declined"
+    );
+}
+// ============================================================================
+// phasm::sim
+// ============================================================================
+
+/// Same mix as `generate_operation`, but derived from the state alone so it can drive a
+/// [`Simulator`].
+fn generate_input(
+    rng: &mut ChaCha8Rng,
+    system: &BookingSystem,
+) -> Input<BookingTracked, BookingInput> {
+    let awaiting: Vec<ReqId> = system
+        .pending
+        .iter()
+        .filter(|(_, p)| p.status == ReqStatus::AwaitingPreauth)
+        .map(|(req_id, _)| *req_id)
+        .collect();
+    if rng.gen_range(0..100) < 40 && !awaiting.is_empty() {
+        let req_id = awaiting[rng.gen_range(0..awaiting.len())];
+        let res = if rng.gen_bool(0.85) {
+            PaymentResult::Success {
+                amount: system.pending[&req_id].apt_type.price(),
+            }
+        } else {
+            PaymentResult::Failed {
+                reason: "Insufficient funds".into(),
+            }
+        };
+        return Input::TrackedActionCompleted { id: req_id, res };
+    }
+
+    let user_id = system.next_id;
+    let (name, email) = (
+        format!("User{}", user_id),
+        format!("user{}@example.com", user_id),
+    );
+    let apt_type = random_apt_type(rng);
+    Input::Normal(if rng.gen_bool(0.6) {
+        BookingInput::RequestSlot {
+            user_id,
+            name,
+            email,
+            day: random_day(rng),
+            time: random_time(rng),
+            apt_type,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }
+    } else {
+        let (day_count, time_count) = (rng.gen_range(1..=3), rng.gen_range(1..=2));
+        BookingInput::RequestAuto {
+            user_id,
+            name,
+            email,
+            days: random_days(rng, day_count),
+            times: random_time_ranges(rng, time_count),
+            apt_type,
+            now: Slot::WEEK_START,
+            token: None,
+            expected_version: None,
+        }
+    })
+}
+
+#[monoio::test]
+async fn test_simulator_mixed_operations() {
+    let sim = Simulator::<BookingSystem, _>::new(generate_input).steps(2000);
+    for seed in 0..4 {
+        let mut system = BookingSystem::with_default_schedule();
+        let report = sim.run(seed, &mut system).await;
+        report.assert_ok();
+        assert_eq!(report.steps, 2000);
+        assert!(report.rejected > 0, "Slots run out: {}", report);
+        assert!(!system.bookings.is_empty());
+    }
+}
@@ -5,8 +5,12 @@ use std::{
 };
 
 use phasm::{
-    Input, StateMachine,
+    Completion, Input, Snapshot, StateMachine,
     actions::{Action, ActionsContainer, TrackedAction, TrackedActionTypes},
+    driver::{ActionExecutor, Driver},
+    load_and_restore,
+    money::Cents,
+    testing::assert_atomic,
 };
 
 /// Simulates a coffee shop loyalty app state machine.
@@ -20,19 +24,17 @@ async fn main() {
     println!("=== Coffee Shop Loyalty App Demo ===\n");
 
     // Initialize state with user having 150 points
-    let mut app = CoffeeShopApp {
-        user_id: 12345,
-        points_balance: 150,
-        pending_redemption: None,
-        order_total: 5.50,
-        next_redemption_id: 1,
-    };
+    let mut app = CoffeeShopApp::builder(12345)
+        .points_balance(150)
+        .order_total(Cents(550))
+        .build()
+        .unwrap();
 
     let mut actions = Vec::new();
 
     println!("Initial state:");
     println!("  Points: {}", app.points_balance);
-    println!("  Order total: ${:.2}", app.order_total);
+    println!("  Order total: {}", app.order_total);
     println!("  Pending redemption: {:?}\n", app.pending_redemption);
 
     // Scenario 1: User redeems 100 points for a free coffee ($5 off)
@@ -54,19 +56,14 @@ async fn main() {
     println!("  Pending redemption: {:?}", app.pending_redemption);
     println!("\nActions produced:");
 
-    for (i, action) in actions.iter().enumerate() {
-        match action {
-            Action::Tracked(ta) => {
-                println!("  {}. [TRACKED] {:?}", i + 1, ta);
-                println!("     → Will wait for backend confirmation");
-            }
-            Action::Untracked(ua) => {
-                println!("  {}. [UNTRACKED] {:?}", i + 1, ua);
-            }
-        }
+    let (untracked, tracked) = actions.drain_partition().unwrap();
+    for ta in &tracked {
+        println!("  [TRACKED] {:?}", ta);
+        println!("     → Will wait for backend confirmation");
+    }
+    for ua in &untracked {
+        println!("  [UNTRACKED] {:?}", ua);
     }
-
-    actions.clear();
 
     // Simulate backend confirming the redemption
     println!("\n>>> Backend confirms: Redemption successful!\n");
@@ -89,32 +86,23 @@ async fn main() {
 
     println!("After redemption confirmed:");
     println!("  Points: {}", app.points_balance);
-    println!("  Order total: ${:.2}", app.order_total);
+    println!("  Order total: {}", app.order_total);
     println!("  Pending redemption: {:?}", app.pending_redemption);
     println!("\nActions produced:");
 
-    for (i, action) in actions.iter().enumerate() {
-        match action {
-            Action::Tracked(_) => unreachable!(),
-            Action::Untracked(ua) => {
-                println!("  {}. [UNTRACKED] {:?}", i + 1, ua);
-            }
-        }
+    let (untracked, tracked) = actions.drain_partition().unwrap();
+    assert!(tracked.is_empty());
+    for (i, ua) in untracked.iter().enumerate() {
+        println!("  {}. [UNTRACKED] {:?}", i + 1, ua);
     }
 
-    actions.clear();
-
     // Scenario 2: Demonstrate error handling - user tries to redeem more points than available
     println!("\n>>> User tries to redeem 200 points (only has 50 remaining)...\n");
 
-    let points_before = app.points_balance;
-    let pending_before = app.pending_redemption.clone();
-    let next_id_before = app.next_redemption_id;
-
-    let result = CoffeeShopApp::stf(
+    // Panics if the failed transition changed any part of the state
+    let result = assert_atomic::<CoffeeShopApp>(
         &mut app,
         Input::Normal(UserAction::RedeemPoints { points: 200 }),
-        &mut actions,
     )
     .await;
 
@@ -129,26 +117,11 @@ async fn main() {
         "  Next redemption ID: {} (same as before)",
         app.next_redemption_id
     );
-    println!("  Actions produced: {} (empty)", actions.len());
 
-    // Verify atomicity - state should be completely unchanged
     assert!(
         result.is_err(),
         "Should return error for insufficient points"
     );
-    assert_eq!(
-        app.points_balance, points_before,
-        "Points should not change on error"
-    );
-    assert_eq!(
-        app.pending_redemption, pending_before,
-        "Pending should not change on error"
-    );
-    assert_eq!(
-        app.next_redemption_id, next_id_before,
-        "ID counter should not increment on error"
-    );
-    assert_eq!(actions.len(), 0, "No actions should be emitted on error");
 
     println!("\n✓ STF Atomicity verified: State unchanged after error\n");
 
@@ -157,28 +130,28 @@ async fn main() {
     // Demonstrate restore functionality
     println!(">>> Simulating app crash and restore...\n");
 
-    // Create new app state with a pending redemption (simulating crash during redemption)
-    let crashed_app = CoffeeShopApp {
-        user_id: 12345,
-        points_balance: 150,
-        pending_redemption: Some(PendingRedemption {
+    // State persisted with a pending redemption (simulating crash during redemption)
+    let snapshot = CoffeeShopApp::builder(12345)
+        .points_balance(150)
+        .pending_redemption(PendingRedemption {
             id: RedemptionId(2),
             points: 100,
-        }),
-        order_total: 5.50,
-        next_redemption_id: 3,
-    };
+        })
+        .order_total(Cents(550))
+        .next_redemption_id(3)
+        .build()
+        .unwrap()
+        .encode();
+    println!("Persisted state: {}", String::from_utf8_lossy(&snapshot));
 
-    println!("Crashed state recovered from disk:");
+    let (mut crashed_app, restored) = load_and_restore::<CoffeeShopApp>(&snapshot).await.unwrap();
+
+    println!("\nCrashed state recovered from disk:");
     println!("  Points: {}", crashed_app.points_balance);
     println!("  Pending redemption: {:?}", crashed_app.pending_redemption);
 
-    CoffeeShopApp::restore(&crashed_app, &mut actions)
-        .await
-        .unwrap();
-
-    println!("\nRestore produced {} action(s) to retry:", actions.len());
-    for (i, action) in actions.iter().enumerate() {
+    println!("\nRestore produced {} action(s) to retry:", restored.len());
+    for (i, action) in restored.iter().enumerate() {
         match action {
             Action::Tracked(ta) => {
                 println!("  {}. [TRACKED] {:?}", i + 1, ta);
@@ -188,24 +161,191 @@ async fn main() {
         }
     }
 
+    // The backend's answer is persisted as an {id, res} record before it is applied,
+    // so it can be replayed if the app crashes again
+    let record = serde_json::to_string(&Completion::<CoffeeTrackedAction> {
+        id: RedemptionId(2),
+        res: RedemptionResult::Success {
+            points_deducted: 100,
+        },
+    })
+    .unwrap();
+    println!("\nPersisted completion: {}", record);
+
+    let completion: Completion<CoffeeTrackedAction> = serde_json::from_str(&record).unwrap();
+    actions.clear();
+    CoffeeShopApp::stf(&mut crashed_app, completion.into(), &mut actions)
+        .await
+        .unwrap();
+    println!("Points after replaying it: {}", crashed_app.points_balance);
+
+    // Scenario 3: Let a Driver execute the actions instead of doing it by hand
+    println!("\n>>> Running a redemption through a Driver...\n");
+
+    let app = CoffeeShopApp::builder(12345)
+        .points_balance(250)
+        .order_total(Cents(800))
+        .build()
+        .unwrap();
+    let mut driver = Driver::<CoffeeShopApp, _>::new(app, Backend::default()).unwrap();
+    driver
+        .submit(UserAction::RedeemPoints { points: 100 })
+        .await
+        .unwrap();
+
+    println!("\nAfter the driver settled:");
+    println!("  Points: {}", driver.state().points_balance);
+    println!("  Order total: {}", driver.state().order_total);
+    println!(
+        "  Pending redemption: {:?}",
+        driver.state().pending_redemption
+    );
+    println!("  Backend calls: {}", driver.executor().calls);
+
     println!("\n=== Demo Complete ===");
 }
 
+// ============================================================================
+// Action Executor - Where the real side effects go
+// ============================================================================
+
+/// Stands in for the loyalty backend and the app's UI layer.
+#[derive(Default)]
+struct Backend {
+    calls: u32,
+}
+
+impl ActionExecutor<UntrackedAction, CoffeeTrackedAction> for Backend {
+    async fn execute_untracked(&mut self, action: &UntrackedAction) {
+        // A real app would update the UI or send the notification here
+        println!("  [UI] {:?}", action);
+    }
+
+    async fn execute_tracked(
+        &mut self,
+        id: &RedemptionId,
+        action: &RedemptionRequest,
+    ) -> RedemptionResult {
+        // A real app would call the backend here, retrying until it gets an answer
+        self.calls += 1;
+        println!("  [BACKEND] {:?} {:?}", id, action);
+        match action {
+            RedemptionRequest::Redeem { points, .. } => RedemptionResult::Success {
+                points_deducted: *points,
+            },
+            RedemptionRequest::CheckStatus { .. } => RedemptionResult::Pending,
+        }
+    }
+}
+
 // ============================================================================
 // State Machine Definition
 // ============================================================================
 
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 struct CoffeeShopApp {
     user_id: u64,
     points_balance: u32,
     pending_redemption: Option<PendingRedemption>,
-    order_total: f32,
+    order_total: Cents,
     // INVARIANT: Deterministic ID generation (Invariant #4)
     // Counter must be stored in state, NOT generated from SystemTime or random
     next_redemption_id: u64,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl CoffeeShopApp {
+    /// Starts building the state for `user_id`: no points, nothing pending, an empty
+    /// order, and redemption ids starting at 1.
+    fn builder(user_id: u64) -> CoffeeShopAppBuilder {
+        CoffeeShopAppBuilder {
+            app: CoffeeShopApp {
+                user_id,
+                points_balance: 0,
+                pending_redemption: None,
+                order_total: Cents::ZERO,
+                next_redemption_id: 1,
+            },
+        }
+    }
+}
+
+/// Builds a [`CoffeeShopApp`], e.g. from state recovered from disk, rejecting states the
+/// STF could never have produced.
+struct CoffeeShopAppBuilder {
+    app: CoffeeShopApp,
+}
+
+impl CoffeeShopAppBuilder {
+    fn points_balance(mut self, points: u32) -> Self {
+        self.app.points_balance = points;
+        self
+    }
+
+    fn pending_redemption(mut self, pending: PendingRedemption) -> Self {
+        self.app.pending_redemption = Some(pending);
+        self
+    }
+
+    fn order_total(mut self, total: Cents) -> Self {
+        self.app.order_total = total;
+        self
+    }
+
+    fn next_redemption_id(mut self, id: u64) -> Self {
+        self.app.next_redemption_id = id;
+        self
+    }
+
+    fn build(self) -> Result<CoffeeShopApp, InvalidState> {
+        let app = self.app;
+        // The next redemption would reuse the pending one's id, and its result would be
+        // applied to the wrong redemption
+        if let Some(pending) = &app.pending_redemption
+            && app.next_redemption_id <= pending.id.0
+        {
+            return Err(InvalidState::RedemptionIdReused {
+                pending: pending.id.0,
+                next: app.next_redemption_id,
+            });
+        }
+        Ok(app)
+    }
+}
+
+// Fields are only read through `Debug`
+#[allow(dead_code)]
+#[derive(Debug, PartialEq)]
+enum InvalidState {
+    /// `next_redemption_id` isn't past the pending redemption's id.
+    RedemptionIdReused { pending: u64, next: u64 },
+}
+
+// Fields are only read through `Debug`
+#[allow(dead_code)]
+#[derive(Debug)]
+enum LoadStateError {
+    Malformed(serde_json::Error),
+    Invalid(InvalidState),
+}
+
+/// Persists the state as JSON. Loading goes through the builder's checks, since the
+/// bytes may not have been written by this app.
+impl Snapshot for CoffeeShopApp {
+    type Error = LoadStateError;
+
+    fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("state is always serializable")
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, Self::Error> {
+        let app = serde_json::from_slice(bytes).map_err(LoadStateError::Malformed)?;
+        CoffeeShopAppBuilder { app }
+            .build()
+            .map_err(LoadStateError::Invalid)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 struct PendingRedemption {
     id: RedemptionId,
     #[allow(dead_code)]
@@ -235,16 +375,16 @@ enum CoffeeShopError {
 // Tracked Actions - Need backend confirmation
 // ============================================================================
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, serde::Serialize, serde::Deserialize)]
 struct RedemptionId(u64);
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum RedemptionRequest {
     Redeem { user_id: u64, points: u32 },
     CheckStatus { redemption_id: RedemptionId },
 }
 
-#[derive(Debug)]
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
 enum RedemptionResult {
     Success {
         points_deducted: u32,
@@ -270,11 +410,11 @@ impl TrackedActionTypes for CoffeeTrackedAction {
 // Untracked Actions - Fire and forget (UI, notifications, logs)
 // ============================================================================
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 enum UntrackedAction {
     ShowStampAnimation,
     UpdatePointsDisplay { new_balance: u32 },
-    UpdateOrderTotal { new_total_cents: u32 },
+    UpdateOrderTotal { new_total: Cents },
     ShowSuccessMessage { message: String },
     ShowErrorMessage { message: String },
     PlaySuccessSound,
@@ -317,7 +457,7 @@ impl StateMachine for CoffeeShopApp {
         actions: &'actions mut Self::Actions,
     ) -> Self::RestoreFuture<'state, 'actions> {
         // Clear the actions container first to reuse allocation
-        let _ = actions.clear();
+        actions.clear();
 
         // If there's a pending redemption, we need to check its status with the backend
         if let Some(pending) = &state.pending_redemption {
@@ -386,6 +526,10 @@ impl<'state, 'actions> Future for CoffeeStfFuture<'state, 'actions> {
                 },
                 RedemptionResult::Pending => InputAction::RedemptionPending { id: id.clone() },
             },
+            Input::TrackedActionExpired { id } => InputAction::RedemptionFailed {
+                id: id.clone(),
+                reason: "timed out".to_string(),
+            },
         };
 
         let result = match action {
@@ -476,8 +620,8 @@ impl<'state, 'actions> CoffeeStfFuture<'state, 'actions> {
 
         // Backend confirmed! Update our state
         self.state.points_balance -= points_deducted;
-        let discount = (points_deducted as f32) * 0.05; // 100 points = $5
-        self.state.order_total = (self.state.order_total - discount).max(0.0);
+        let discount = Cents(u64::from(points_deducted) * 5); // 100 points = $5
+        self.state.order_total = self.state.order_total.saturating_sub(discount);
         self.state.pending_redemption = None;
 
         // Emit untracked actions for UI updates
@@ -489,16 +633,13 @@ impl<'state, 'actions> CoffeeStfFuture<'state, 'actions> {
 
         self.actions
             .add(Action::Untracked(UntrackedAction::UpdateOrderTotal {
-                new_total_cents: (self.state.order_total * 100.0) as u32,
+                new_total: self.state.order_total,
             }))
             .map_err(|_| CoffeeShopError::FailedToQueueAction)?;
 
         self.actions
             .add(Action::Untracked(UntrackedAction::ShowSuccessMessage {
-                message: format!(
-                    "Redeemed {} points! Saved ${:.2}",
-                    points_deducted, discount
-                ),
+                message: format!("Redeemed {} points! Saved {}", points_deducted, discount),
             }))
             .map_err(|_| CoffeeShopError::FailedToQueueAction)?;
 
@@ -559,3 +700,99 @@ impl<'state, 'actions> CoffeeStfFuture<'state, 'actions> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use phasm::LoadError;
+
+    use super::*;
+
+    #[test]
+    fn test_builder_rejects_redemption_id_reuse() {
+        let pending = || PendingRedemption {
+            id: RedemptionId(2),
+            points: 100,
+        };
+        for next in [1, 2] {
+            let result = CoffeeShopApp::builder(1)
+                .points_balance(150)
+                .pending_redemption(pending())
+                .next_redemption_id(next)
+                .build();
+            assert_eq!(
+                result.err(),
+                Some(InvalidState::RedemptionIdReused { pending: 2, next })
+            );
+        }
+
+        let app = CoffeeShopApp::builder(1)
+            .pending_redemption(pending())
+            .next_redemption_id(3)
+            .build()
+            .unwrap();
+        assert_eq!(app.next_redemption_id, 3);
+    }
+
+    #[monoio::test]
+    async fn test_snapshot_reloads_and_restores_pending_redemption() {
+        let app = CoffeeShopApp::builder(1)
+            .points_balance(150)
+            .pending_redemption(PendingRedemption {
+                id: RedemptionId(2),
+                points: 100,
+            })
+            .order_total(Cents(550))
+            .next_redemption_id(3)
+            .build()
+            .unwrap();
+
+        let (loaded, actions) = load_and_restore::<CoffeeShopApp>(&app.encode())
+            .await
+            .unwrap();
+        assert_eq!(loaded.points_balance, 150);
+        assert_eq!(loaded.order_total, Cents(550));
+        assert_eq!(loaded.pending_redemption, app.pending_redemption);
+        let requests: Vec<_> = actions.iter_tracked().map(TrackedAction::action).collect();
+        assert_eq!(
+            requests,
+            [&RedemptionRequest::CheckStatus {
+                redemption_id: RedemptionId(2)
+            }]
+        );
+
+        // Snapshots are checked like any other recovered state
+        let corrupt = br#"{"user_id":1,"points_balance":0,"pending_redemption":{"id":2,"points":100},"order_total":0,"next_redemption_id":2}"#;
+        let result = load_and_restore::<CoffeeShopApp>(corrupt).await;
+        assert!(matches!(
+            result,
+            Err(LoadError::Decode(LoadStateError::Invalid(
+                InvalidState::RedemptionIdReused {
+                    pending: 2,
+                    next: 2
+                }
+            )))
+        ));
+    }
+
+    #[monoio::test]
+    async fn test_persisted_completion_is_applied() {
+        let mut app = CoffeeShopApp::builder(1)
+            .points_balance(150)
+            .pending_redemption(PendingRedemption {
+                id: RedemptionId(2),
+                points: 100,
+            })
+            .next_redemption_id(3)
+            .build()
+            .unwrap();
+        let record = r#"{"id":2,"res":{"Success":{"points_deducted":100}}}"#;
+        let completion: Completion<CoffeeTrackedAction> = serde_json::from_str(record).unwrap();
+
+        let mut actions = Vec::new();
+        CoffeeShopApp::stf(&mut app, completion.into(), &mut actions)
+            .await
+            .unwrap();
+        assert_eq!(app.points_balance, 50);
+        assert_eq!(app.pending_redemption, None);
+    }
+}
@@ -1,9 +1,3 @@
-use std::{
-    future,
-    pin::Pin,
-    task::{Context, Poll},
-};
-
 use phasm::{
     Input, StateMachine,
     actions::{Action, ActionsContainer, TrackedActionTypes},
@@ -23,18 +17,11 @@ async fn main() {
         vec![Action::Untracked(CsmAction::Incremented { from: 0, to: 1 })]
     );
 
-    for action in actions.iter() {
-        match action {
-            Action::Tracked(_) => unreachable!(),
-            Action::Untracked(act) => match act {
-                CsmAction::Incremented { from, to } => {
-                    println!("Incremented from {} to {}", from, to);
-                }
-            },
-        }
+    let (untracked, tracked) = actions.drain_partition().unwrap();
+    assert!(tracked.is_empty());
+    for CsmAction::Incremented { from, to } in untracked {
+        println!("Incremented from {} to {}", from, to);
     }
-
-    actions.clear();
 }
 
 struct CounterStateMachine {
@@ -47,7 +34,7 @@ enum CsmStfError {
     FailedToQueueAction,
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 enum CsmAction {
     Incremented { from: u64, to: u64 },
 }
@@ -61,6 +48,7 @@ impl TrackedActionTypes for CsmTrackedAction {
     type Result = ();
 }
 
+#[phasm::state_machine]
 impl StateMachine for CounterStateMachine {
     type UntrackedAction = CsmAction;
     type TrackedAction = CsmTrackedAction;
@@ -72,50 +60,30 @@ impl StateMachine for CounterStateMachine {
     type TransitionError = CsmStfError;
     type RestoreError = ();
 
-    type StfFuture<'state, 'actions> = CsmStfFuture<'state, 'actions>;
-    type RestoreFuture<'state, 'actions> = future::Ready<Result<(), Self::RestoreError>>;
-
-    fn stf<'state, 'actions>(
-        state: &'state mut Self::State,
+    async fn stf(
+        state: &mut Self::State,
         _input: Input<Self::TrackedAction, Self::Input>,
-        actions: &'actions mut Self::Actions,
-    ) -> Self::StfFuture<'state, 'actions> {
-        CsmStfFuture { state, actions }
+        actions: &mut Self::Actions,
+    ) -> Result<(), Self::TransitionError> {
+        let prev = state.counter;
+        let new = state
+            .counter
+            .checked_add(1)
+            .ok_or(CsmStfError::Overflowed)?;
+        state.counter = new;
+        actions
+            .add(Action::Untracked(CsmAction::Incremented {
+                from: prev,
+                to: new,
+            }))
+            .map_err(|_| CsmStfError::FailedToQueueAction)?;
+        Ok(())
     }
 
-    fn restore<'state, 'actions>(
-        _state: &'state Self::State,
-        _actions: &'actions mut Self::Actions,
-    ) -> Self::RestoreFuture<'state, 'actions> {
-        future::ready(Ok(()))
-    }
-}
-
-struct CsmStfFuture<'state, 'actions> {
-    state: &'state mut CounterStateMachine,
-    actions: &'actions mut <CounterStateMachine as StateMachine>::Actions,
-}
-
-impl<'state, 'actions> Future for CsmStfFuture<'state, 'actions> {
-    type Output = Result<(), <CounterStateMachine as StateMachine>::TransitionError>;
-
-    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let result = (|| {
-            let prev = self.state.counter;
-            let new = self
-                .state
-                .counter
-                .checked_add(1)
-                .ok_or(CsmStfError::Overflowed)?;
-            self.state.counter = new;
-            self.actions
-                .add(Action::Untracked(CsmAction::Incremented {
-                    from: prev,
-                    to: new,
-                }))
-                .map_err(|_| CsmStfError::FailedToQueueAction)?;
-            Ok(())
-        })();
-        Poll::Ready(result)
+    async fn restore(
+        _state: &Self::State,
+        _actions: &mut Self::Actions,
+    ) -> Result<(), Self::RestoreError> {
+        Ok(())
     }
 }
@@ -0,0 +1,215 @@
+//! Procedural macros for phasm. Use them through the `phasm` crate with the `derive`
+//! feature rather than depending on this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenTree};
+use quote::quote;
+use syn::{
+    Error, FnArg, ImplItem, ImplItemFn, ItemImpl, Lifetime, ReturnType, Type, parse_macro_input,
+    parse_quote,
+};
+
+/// Lets a `StateMachine` impl write `stf` and `restore` as plain `async fn`s.
+///
+/// Generates the `StfFuture` and `RestoreFuture` types and the signatures the trait
+/// expects, so the body can `.await` state access and use `?` directly:
+///
+/// ```ignore
+/// #[phasm::state_machine]
+/// impl StateMachine for Counter {
+///     type TrackedAction = NoTracked;
+///     type UntrackedAction = ();
+///     type Actions = Vec<Action<(), NoTracked>>;
+///     type State = Self;
+///     type Input = u64;
+///     type TransitionError = CounterError;
+///     type RestoreError = ();
+///
+///     async fn stf(
+///         state: &mut Self::State,
+///         input: Input<Self::TrackedAction, Self::Input>,
+///         actions: &mut Self::Actions,
+///     ) -> Result<(), Self::TransitionError> {
+///         let Input::Normal(n) = input else {
+///             return Err(CounterError::Unexpected);
+///         };
+///         state.count = state.count.checked_add(n).ok_or(CounterError::Overflow)?;
+///         Ok(())
+///     }
+///
+///     async fn restore(
+///         _state: &Self::State,
+///         _actions: &mut Self::Actions,
+///     ) -> Result<(), Self::RestoreError> {
+///         Ok(())
+///     }
+/// }
+/// ```
+///
+/// Either function can instead be written by hand, with its future type, and is left
+/// alone. The generated futures are `phasm::BoxFuture`s, which
+/// costs an allocation per call; write the future by hand where that matters.
+///
+/// Generic impls and types with lifetimes other than `'static` are rejected, since the
+/// future must not borrow anything but the state and actions.
+#[proc_macro_attribute]
+pub fn state_machine(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return Error::new(
+            Span::call_site(),
+            "`#[state_machine]` does not take arguments",
+        )
+        .into_compile_error()
+        .into();
+    }
+    let mut item = parse_macro_input!(item as ItemImpl);
+    match expand(&mut item) {
+        Ok(()) => quote!(#item).into(),
+        Err(err) => err.into_compile_error().into(),
+    }
+}
+
+fn expand(item: &mut ItemImpl) -> syn::Result<()> {
+    // The generated future may only borrow through `state` and `actions`, which holds
+    // when nothing else in the impl can carry a lifetime
+    if !item.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &item.generics,
+            "`#[state_machine]` does not support generic impls",
+        ));
+    }
+    let self_ty = &item.self_ty;
+    if names_lifetime(quote!(#self_ty)) {
+        return Err(Error::new_spanned(
+            &item.self_ty,
+            "`#[state_machine]` does not support types with lifetimes other than `'static`",
+        ));
+    }
+
+    let mut futures = Vec::new();
+    for impl_item in &mut item.items {
+        let ImplItem::Fn(func) = impl_item else {
+            continue;
+        };
+        if func.sig.asyncness.is_none() {
+            continue;
+        }
+        let (future, state_mut) = match func.sig.ident.to_string().as_str() {
+            "stf" => ("StfFuture", true),
+            "restore" => ("RestoreFuture", false),
+            _ => continue,
+        };
+        let output = desugar(func, future, state_mut)?;
+        futures.push((future, output));
+    }
+
+    for (future, output) in futures {
+        let ident = syn::Ident::new(future, Span::call_site());
+        if item
+            .items
+            .iter()
+            .any(|impl_item| matches!(impl_item, ImplItem::Type(ty) if ty.ident == ident))
+        {
+            return Err(Error::new_spanned(
+                &ident,
+                format!("`{}` is generated by `#[state_machine]`, remove it", future),
+            ));
+        }
+        item.items.push(parse_quote! {
+            type #ident<'state, 'actions> = ::phasm::BoxFuture<'state, 'actions, #output>;
+        });
+    }
+    Ok(())
+}
+
+/// Whether `tokens` name a lifetime other than `'static`.
+fn names_lifetime(tokens: proc_macro2::TokenStream) -> bool {
+    let mut tokens = tokens.into_iter().peekable();
+    while let Some(token) = tokens.next() {
+        let named = match token {
+            TokenTree::Group(group) => names_lifetime(group.stream()),
+            TokenTree::Punct(punct) => {
+                punct.as_char() == '\''
+                    && !matches!(tokens.peek(), Some(TokenTree::Ident(ident)) if ident == "static")
+            }
+            _ => false,
+        };
+        if named {
+            return true;
+        }
+    }
+    false
+}
+
+/// Rewrites `async fn` into a plain `fn` returning a `BoxFuture`, and returns the
+/// future's output type.
+fn desugar(func: &mut ImplItemFn, future: &str, state_mut: bool) -> syn::Result<Type> {
+    let sig = &mut func.sig;
+    let params = if state_mut { 3 } else { 2 };
+    if sig.inputs.len() != params {
+        return Err(Error::new_spanned(
+            &sig.inputs,
+            format!("expected {} parameters", params),
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &sig.generics,
+            "lifetimes are added by `#[state_machine]`, remove them",
+        ));
+    }
+
+    let state_lifetime = Lifetime::new("'state", Span::call_site());
+    let actions_lifetime = Lifetime::new("'actions", Span::call_site());
+    let last = params - 1;
+    for (i, arg) in sig.inputs.iter_mut().enumerate() {
+        let lifetime = match i {
+            0 => &state_lifetime,
+            i if i == last => &actions_lifetime,
+            _ => continue,
+        };
+        let FnArg::Typed(arg) = arg else {
+            return Err(Error::new_spanned(arg, "expected a typed parameter"));
+        };
+        let Type::Reference(reference) = &mut *arg.ty else {
+            return Err(Error::new_spanned(&arg.ty, "expected a reference"));
+        };
+        if reference.lifetime.is_some() {
+            return Err(Error::new_spanned(
+                &reference.lifetime,
+                "lifetimes are added by `#[state_machine]`, remove them",
+            ));
+        }
+        if reference.mutability.is_some() != (i == last || state_mut) {
+            return Err(Error::new_spanned(
+                &*reference,
+                "reference mutability doesn't match `StateMachine`",
+            ));
+        }
+        reference.lifetime = Some(lifetime.clone());
+    }
+
+    let output = match &sig.output {
+        ReturnType::Type(_, ty) => (**ty).clone(),
+        ReturnType::Default => {
+            return Err(Error::new_spanned(
+                &sig.ident,
+                "expected a `Result` return type",
+            ));
+        }
+    };
+    let ident = syn::Ident::new(future, Span::call_site());
+    sig.asyncness = None;
+    sig.generics = parse_quote!(<'state, 'actions>);
+    sig.output = parse_quote!(-> Self::#ident<'state, 'actions>);
+
+    let body = &func.block;
+    func.block = parse_quote!({
+        // Outside the `unsafe` block so the body isn't unsafe code too
+        let future = ::phasm::BoxFuture::<'state, 'actions, #output>::typed(async move #body);
+        // SAFETY: the impl has no generics, so the parameters are the only borrows the
+        // future can hold, and they live for both `'state` and `'actions`
+        unsafe { ::phasm::BoxFuture::new(future) }
+    });
+    Ok(output)
+}
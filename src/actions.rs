@@ -1,4 +1,7 @@
-use std::fmt::Debug;
+use std::{fmt::Debug, time::Duration};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 pub trait TrackedActionTypes {
     /// A type used to identify a tracked action within a given state machine.
@@ -7,26 +10,429 @@ pub trait TrackedActionTypes {
     type Action: Debug + PartialEq + Eq;
     /// A type used to represent the result of the action.
     type Result: Debug;
+
+    /// Whether `res` is a failure that aborts the transaction the action belongs to.
+    ///
+    /// Only consulted for actions emitted with [`TrackedAction::new_in_txn`]. Return `true`
+    /// for failures that can't be fixed by retrying the same action (e.g. a declined capture),
+    /// so the [`Engine`](crate::engine::Engine) compensates the rest of the transaction.
+    fn aborts_txn(_res: &Self::Result) -> bool {
+        false
+    }
+
+    /// The action that undoes `action` when its transaction is aborted, if any.
+    ///
+    /// The compensating action is emitted under the same `id`, so its result is delivered
+    /// to the state machine like any other tracked action result.
+    fn compensate(_id: &Self::Id, _action: &Self::Action) -> Option<Self::Action> {
+        None
+    }
+
+    /// The failure result to deliver when `action` has permanently failed, if any.
+    ///
+    /// Called by [`Engine::tracked_exhausted`](crate::engine::Engine::tracked_exhausted)
+    /// once the executor has given up retrying. Returning `Some` feeds the result back to
+    /// the state machine so it can mark the operation failed; with `None` the action is
+    /// only dead-lettered.
+    fn exhausted_result(_action: &Self::Action, _last_error: &str) -> Option<Self::Result> {
+        None
+    }
 }
 
+/// Identifies a group of tracked actions that form one business transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct TxnId(pub u64);
+
 #[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "Types::Id: Serialize, Types::Action: Serialize",
+        deserialize = "Types::Id: Deserialize<'de>, Types::Action: Deserialize<'de>"
+    ))
+)]
 pub struct TrackedAction<Types: TrackedActionTypes> {
     action_id: Types::Id,
     action: Types::Action,
+    txn_id: Option<TxnId>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    deadline: Option<Duration>,
 }
 
 impl<Types: TrackedActionTypes> TrackedAction<Types> {
     pub fn new(action_id: Types::Id, action: Types::Action) -> Self {
-        Self { action_id, action }
+        Self::from_parts(action_id, action, None, None)
+    }
+
+    /// Creates a tracked action that must resolve within `deadline` of starting to
+    /// execute it.
+    ///
+    /// The deadline is data carried with the action, never compared with a clock in STF.
+    /// The [`Driver`](crate::driver::Driver) enforces it while executing, retries
+    /// included, and once it passes applies
+    /// [`Input::TrackedActionExpired`](crate::Input::TrackedActionExpired) instead of a
+    /// result, so the machine times the operation out from an ordinary input.
+    pub fn with_deadline(action_id: Types::Id, action: Types::Action, deadline: Duration) -> Self {
+        Self::from_parts(action_id, action, None, Some(deadline))
+    }
+
+    pub(crate) fn from_parts(
+        action_id: Types::Id,
+        action: Types::Action,
+        txn_id: Option<TxnId>,
+        deadline: Option<Duration>,
+    ) -> Self {
+        Self {
+            action_id,
+            action,
+            txn_id,
+            deadline,
+        }
+    }
+
+    /// Creates a tracked action that belongs to transaction `txn_id`.
+    ///
+    /// All actions sharing a `TxnId` succeed or fail as a group: when the result of one
+    /// of them is a failure according to [`TrackedActionTypes::aborts_txn`], the
+    /// [`Engine`](crate::engine::Engine) emits [`TrackedActionTypes::compensate`] actions
+    /// for every sibling that is still in flight. Siblings that already completed are
+    /// the state machine's responsibility - it sees their results and can undo them in STF.
+    ///
+    /// Like the id, the `TxnId` must be generated deterministically from state.
+    pub fn new_in_txn(action_id: Types::Id, action: Types::Action, txn_id: TxnId) -> Self {
+        Self::from_parts(action_id, action, Some(txn_id), None)
+    }
+
+    pub fn id(&self) -> &Types::Id {
+        &self.action_id
+    }
+
+    pub fn action(&self) -> &Types::Action {
+        &self.action
+    }
+
+    pub fn txn_id(&self) -> Option<TxnId> {
+        self.txn_id
+    }
+
+    /// How long the action may take to resolve, see [`with_deadline`](Self::with_deadline).
+    ///
+    /// The deadline is relative to when execution starts, so time spent before a restart
+    /// doesn't count: an action re-emitted by
+    /// [`StateMachine::restore`](crate::StateMachine::restore) gets the full deadline
+    /// again, and only if restore re-emits it with one. Machines that need an absolute
+    /// cut-off across restarts should keep it in state and compare it with the time
+    /// carried by their inputs.
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline
+    }
+}
+
+// Manual impl: a derive would require `Types: Clone`, but only the id and action are stored.
+impl<Types: TrackedActionTypes> Clone for TrackedAction<Types>
+where
+    Types::Id: Clone,
+    Types::Action: Clone,
+{
+    fn clone(&self) -> Self {
+        Self::from_parts(
+            self.action_id.clone(),
+            self.action.clone(),
+            self.txn_id,
+            self.deadline,
+        )
     }
 }
 
 #[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "UA: Serialize, TATypes::Id: Serialize, TATypes::Action: Serialize",
+        deserialize = "UA: Deserialize<'de>, TATypes::Id: Deserialize<'de>, TATypes::Action: Deserialize<'de>"
+    ))
+)]
 pub enum Action<UA, TATypes: TrackedActionTypes> {
     Tracked(TrackedAction<TATypes>),
     Untracked(UA),
 }
 
+impl<UA, TATypes: TrackedActionTypes> Action<UA, TATypes> {
+    pub fn as_tracked(&self) -> Option<&TrackedAction<TATypes>> {
+        match self {
+            Action::Tracked(tracked) => Some(tracked),
+            Action::Untracked(_) => None,
+        }
+    }
+
+    pub fn as_untracked(&self) -> Option<&UA> {
+        match self {
+            Action::Tracked(_) => None,
+            Action::Untracked(untracked) => Some(untracked),
+        }
+    }
+}
+
+impl<UA, I, TATypes: TrackedActionTypes> Action<SelfScheduled<UA, I>, TATypes> {
+    /// Schedules `input` as a follow-up transition of the machine itself, see
+    /// [`SelfScheduled`].
+    pub fn self_input(input: I) -> Self {
+        Action::Untracked(SelfScheduled::SelfInput(input))
+    }
+}
+
+/// Untracked actions of a machine whose STF schedules its own follow-up inputs.
+///
+/// Use `SelfScheduled<UA, Self::Input>` as the machine's
+/// [`UntrackedAction`](crate::StateMachine::UntrackedAction), and emit follow-ups with
+/// [`Action::self_input`] (e.g. after confirming a booking, check whether a reminder is
+/// due) instead of relying on the caller to re-invoke the machine. An
+/// [`Engine`](crate::engine::Engine) with [`self_inputs`](crate::engine::Engine::self_inputs)
+/// enabled applies them within the same step and takes them out of the container, so
+/// executors only ever see [`SelfScheduled::Untracked`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SelfScheduled<UA, I> {
+    /// An ordinary untracked action, for the executor.
+    Untracked(UA),
+    /// An input for the machine to apply to itself.
+    SelfInput(I),
+}
+
+impl<UA, I> SelfScheduled<UA, I> {
+    pub fn as_untracked(&self) -> Option<&UA> {
+        match self {
+            SelfScheduled::Untracked(untracked) => Some(untracked),
+            SelfScheduled::SelfInput(_) => None,
+        }
+    }
+
+    pub fn as_self_input(&self) -> Option<&I> {
+        match self {
+            SelfScheduled::Untracked(_) => None,
+            SelfScheduled::SelfInput(input) => Some(input),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+pub use envelope::{ACTION_ENVELOPE_VERSION, ActionEnvelope};
+
+#[cfg(feature = "serde")]
+mod envelope {
+    use std::{fmt, marker::PhantomData};
+
+    use serde::{
+        Deserialize, Deserializer, Serialize,
+        de::{self, MapAccess, SeqAccess, Visitor},
+    };
+
+    use super::{Action, TrackedAction, TrackedActionTypes, TxnId};
+
+    /// The envelope version written by this binary: `major << 8 | minor` (currently 1.2).
+    pub const ACTION_ENVELOPE_VERSION: u16 = 0x0102;
+
+    const fn major(version: u16) -> u8 {
+        (version >> 8) as u8
+    }
+
+    const fn minor(version: u16) -> u8 {
+        version as u8
+    }
+
+    /// A versioned wire wrapper for persisting an [`Action`], e.g. in an outbox.
+    ///
+    /// # Compatibility policy
+    ///
+    /// `version` is `major << 8 | minor`.
+    ///
+    /// - **Major** bumps change the wire shape incompatibly. Envelopes with a major other
+    ///   than this binary's are rejected before the action is decoded.
+    /// - **Minor** bumps only add fields. Older minors of the same major are migrated on
+    ///   decode (1.0 predates [`TrackedAction::txn_id`] and 1.1 predates
+    ///   [`TrackedAction::deadline`], which decode as `None`). Newer
+    ///   minors are rejected, since this binary would silently drop what they added - roll
+    ///   readers forward before writers.
+    ///
+    /// Decoding happens in `Deserialize`, so any serde format works. `version` must come
+    /// before `action` on the wire, which is how `Serialize` writes it.
+    #[derive(Debug, PartialEq, Eq, Serialize)]
+    #[serde(bound(serialize = "UA: Serialize, TA::Id: Serialize, TA::Action: Serialize"))]
+    pub struct ActionEnvelope<UA, TA: TrackedActionTypes> {
+        pub version: u16,
+        pub action: Action<UA, TA>,
+    }
+
+    impl<UA, TA: TrackedActionTypes> ActionEnvelope<UA, TA> {
+        /// Wraps `action` at [`ACTION_ENVELOPE_VERSION`].
+        pub fn new(action: Action<UA, TA>) -> Self {
+            Self {
+                version: ACTION_ENVELOPE_VERSION,
+                action,
+            }
+        }
+
+        pub fn into_action(self) -> Action<UA, TA> {
+            self.action
+        }
+    }
+
+    fn check_version<E: de::Error>(version: u16) -> Result<(), E> {
+        let current = ACTION_ENVELOPE_VERSION;
+        if major(version) != major(current) || minor(version) > minor(current) {
+            return Err(E::custom(format_args!(
+                "unsupported action envelope version {}.{} (this binary reads {}.0 to {}.{})",
+                major(version),
+                minor(version),
+                major(current),
+                major(current),
+                minor(current),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Wire shape of 1.0 actions, before tracked actions had a `txn_id`.
+    #[derive(Deserialize)]
+    #[serde(
+        rename = "Action",
+        bound(
+            deserialize = "UA: Deserialize<'de>, TA::Id: Deserialize<'de>, TA::Action: Deserialize<'de>"
+        )
+    )]
+    enum ActionV1_0<UA, TA: TrackedActionTypes> {
+        Tracked(TrackedActionV1_0<TA>),
+        Untracked(UA),
+    }
+
+    #[derive(Deserialize)]
+    #[serde(
+        rename = "TrackedAction",
+        bound(deserialize = "TA::Id: Deserialize<'de>, TA::Action: Deserialize<'de>")
+    )]
+    struct TrackedActionV1_0<TA: TrackedActionTypes> {
+        action_id: TA::Id,
+        action: TA::Action,
+    }
+
+    impl<UA, TA: TrackedActionTypes> From<ActionV1_0<UA, TA>> for Action<UA, TA> {
+        fn from(old: ActionV1_0<UA, TA>) -> Self {
+            match old {
+                ActionV1_0::Tracked(t) => {
+                    Action::Tracked(TrackedAction::new(t.action_id, t.action))
+                }
+                ActionV1_0::Untracked(ua) => Action::Untracked(ua),
+            }
+        }
+    }
+
+    /// Wire shape of 1.1 actions, before tracked actions had a `deadline`.
+    #[derive(Deserialize)]
+    #[serde(
+        rename = "Action",
+        bound(
+            deserialize = "UA: Deserialize<'de>, TA::Id: Deserialize<'de>, TA::Action: Deserialize<'de>"
+        )
+    )]
+    enum ActionV1_1<UA, TA: TrackedActionTypes> {
+        Tracked(TrackedActionV1_1<TA>),
+        Untracked(UA),
+    }
+
+    #[derive(Deserialize)]
+    #[serde(
+        rename = "TrackedAction",
+        bound(deserialize = "TA::Id: Deserialize<'de>, TA::Action: Deserialize<'de>")
+    )]
+    struct TrackedActionV1_1<TA: TrackedActionTypes> {
+        action_id: TA::Id,
+        action: TA::Action,
+        txn_id: Option<TxnId>,
+    }
+
+    impl<UA, TA: TrackedActionTypes> From<ActionV1_1<UA, TA>> for Action<UA, TA> {
+        fn from(old: ActionV1_1<UA, TA>) -> Self {
+            match old {
+                ActionV1_1::Tracked(t) => Action::Tracked(TrackedAction::from_parts(
+                    t.action_id,
+                    t.action,
+                    t.txn_id,
+                    None,
+                )),
+                ActionV1_1::Untracked(ua) => Action::Untracked(ua),
+            }
+        }
+    }
+
+    impl<'de, UA, TA> Deserialize<'de> for ActionEnvelope<UA, TA>
+    where
+        UA: Deserialize<'de>,
+        TA: TrackedActionTypes,
+        TA::Id: Deserialize<'de>,
+        TA::Action: Deserialize<'de>,
+    {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_struct(
+                "ActionEnvelope",
+                &["version", "action"],
+                EnvelopeVisitor(PhantomData),
+            )
+        }
+    }
+
+    struct EnvelopeVisitor<UA, TA>(PhantomData<(UA, TA)>);
+
+    impl<'de, UA, TA> Visitor<'de> for EnvelopeVisitor<UA, TA>
+    where
+        UA: Deserialize<'de>,
+        TA: TrackedActionTypes,
+        TA::Id: Deserialize<'de>,
+        TA::Action: Deserialize<'de>,
+    {
+        type Value = ActionEnvelope<UA, TA>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("an action envelope")
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let version: u16 = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+            check_version(version)?;
+            let action = match minor(version) {
+                0 => seq.next_element::<ActionV1_0<UA, TA>>()?.map(Action::from),
+                1 => seq.next_element::<ActionV1_1<UA, TA>>()?.map(Action::from),
+                _ => seq.next_element()?,
+            }
+            .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+            Ok(ActionEnvelope { version, action })
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            if map.next_key::<String>()?.as_deref() != Some("version") {
+                return Err(de::Error::custom(
+                    "action envelope must start with `version`",
+                ));
+            }
+            let version: u16 = map.next_value()?;
+            check_version(version)?;
+            if map.next_key::<String>()?.as_deref() != Some("action") {
+                return Err(de::Error::missing_field("action"));
+            }
+            let action = match minor(version) {
+                0 => map.next_value::<ActionV1_0<UA, TA>>()?.into(),
+                1 => map.next_value::<ActionV1_1<UA, TA>>()?.into(),
+                _ => map.next_value()?,
+            };
+            Ok(ActionEnvelope { version, action })
+        }
+    }
+}
+
 /// A trait for describing a fallible container for a set of [`Action`]s.
 pub trait ActionsContainer<UA, TA: TrackedActionTypes> {
     type Error;
@@ -45,6 +451,105 @@ pub trait ActionsContainer<UA, TA: TrackedActionTypes> {
 
     /// Adds an action to the container. May fail if the container cannot be modified.
     fn add(&mut self, action: Action<UA, TA>) -> Result<(), Self::Error>;
+
+    /// Adds `actions` in order, stopping at the first that fails to be added.
+    ///
+    /// Not named `extend`, which would be ambiguous with `Extend::extend` on `Vec`.
+    fn add_all(
+        &mut self,
+        actions: impl IntoIterator<Item = Action<UA, TA>>,
+    ) -> Result<(), Self::Error> {
+        for action in actions {
+            self.add(action)?;
+        }
+        Ok(())
+    }
+
+    /// Number of actions in the container.
+    fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether any tracked action was added, i.e. results are still to come.
+    ///
+    /// Scans by default; containers that know without scanning should override it.
+    fn has_tracked(&self) -> bool {
+        self.iter_tracked().next().is_some()
+    }
+
+    /// Whether any untracked action was added.
+    ///
+    /// Scans by default; containers that know without scanning should override it.
+    fn has_untracked(&self) -> bool {
+        self.iter_untracked().next().is_some()
+    }
+
+    /// Iterates over the actions in insertion order, unless the container documents
+    /// another order (see [`PriorityActions`]). Either way, each kind of action keeps its
+    /// insertion order, which the [`Engine`](crate::engine::Engine) relies on.
+    ///
+    /// Executors must dispatch actions in this order: a machine may rely on a tracked
+    /// action being started before the untracked actions it emitted after it.
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a Action<UA, TA>>
+    where
+        UA: 'a,
+        TA: 'a;
+
+    /// Iterates over the tracked actions only, in insertion order.
+    fn iter_tracked<'a>(&'a self) -> impl Iterator<Item = &'a TrackedAction<TA>>
+    where
+        UA: 'a,
+        TA: 'a,
+    {
+        self.iter().filter_map(Action::as_tracked)
+    }
+
+    /// Iterates over the untracked actions only, in insertion order.
+    fn iter_untracked<'a>(&'a self) -> impl Iterator<Item = &'a UA>
+    where
+        UA: 'a,
+        TA: 'a,
+    {
+        self.iter().filter_map(Action::as_untracked)
+    }
+
+    /// Takes the actions out, untracked apart from tracked, leaving the container empty.
+    ///
+    /// For executors that fire the untracked actions and await the tracked ones. Each
+    /// kind keeps its order. The default clones the actions out and clears the
+    /// container; containers that own their actions override it to move them instead.
+    fn drain_partition(&mut self) -> Result<Partitioned<UA, TA>, Self::Error>
+    where
+        UA: Clone,
+        TrackedAction<TA>: Clone,
+    {
+        let untracked = self.iter_untracked().cloned().collect();
+        let tracked = self.iter_tracked().cloned().collect();
+        self.clear()?;
+        Ok((untracked, tracked))
+    }
+}
+
+/// Untracked and tracked actions taken out by [`ActionsContainer::drain_partition`].
+pub type Partitioned<UA, TA> = (Vec<UA>, Vec<TrackedAction<TA>>);
+
+/// Splits owned actions into untracked and tracked, each in order.
+fn partition<UA, TA: TrackedActionTypes>(
+    actions: impl IntoIterator<Item = Action<UA, TA>>,
+) -> Partitioned<UA, TA> {
+    let mut untracked = Vec::new();
+    let mut tracked = Vec::new();
+    for action in actions {
+        match action {
+            Action::Tracked(action) => tracked.push(action),
+            Action::Untracked(action) => untracked.push(action),
+        }
+    }
+    (untracked, tracked)
 }
 
 impl<UA, TA: TrackedActionTypes> ActionsContainer<UA, TA> for Vec<Action<UA, TA>> {
@@ -73,4 +578,531 @@ impl<UA, TA: TrackedActionTypes> ActionsContainer<UA, TA> for Vec<Action<UA, TA>
         self.push(action);
         Ok(())
     }
+
+    fn add_all(
+        &mut self,
+        actions: impl IntoIterator<Item = Action<UA, TA>>,
+    ) -> Result<(), Self::Error> {
+        self.extend(actions);
+        Ok(())
+    }
+
+    fn drain_partition(&mut self) -> Result<Partitioned<UA, TA>, Self::Error>
+    where
+        UA: Clone,
+        TrackedAction<TA>: Clone,
+    {
+        Ok(partition(self.drain(..)))
+    }
+
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a Action<UA, TA>>
+    where
+        UA: 'a,
+        TA: 'a,
+    {
+        self.as_slice().iter()
+    }
+}
+
+/// Default number of actions an [`ArenaActions`] created with `new` has room for.
+pub const DEFAULT_ARENA_CAPACITY: usize = 64;
+
+/// The [`ArenaActions`] arena is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArenaFull {
+    pub capacity: usize,
+}
+
+impl std::fmt::Display for ArenaFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "actions arena is full ({} actions)", self.capacity)
+    }
+}
+
+impl std::error::Error for ArenaFull {}
+
+/// An actions container that recycles its actions instead of reallocating them.
+///
+/// Room for a fixed number of actions is allocated up front, `add` bumps into it, and
+/// `clear` resets it, so the container itself never allocates after construction -
+/// even on the first transition, unlike a reused `Vec` that grows on demand. Running out
+/// of room is an [`ArenaFull`] error rather than a reallocation, so size the arena for
+/// the most actions one transition can emit.
+///
+/// # Owned payloads
+///
+/// `clear` doesn't drop the actions it removes but keeps them, up to the arena's
+/// capacity, for [`recycle`](ArenaActions::recycle) to hand back. A transition that
+/// emits payloads owning heap data, such as `String` or `Vec`, can take a cleared action
+/// and reuse its buffers, so once every slot has been used a steady-state transition
+/// does no heap allocation:
+///
+/// ```ignore
+/// let mut label = match actions.recycle() {
+///     Some(Action::Untracked(label)) => label,
+///     _ => String::new(),
+/// };
+/// label.clear();
+/// write!(label, "charged {}", amount).unwrap();
+/// actions.add(Action::Untracked(label))?;
+/// ```
+///
+/// Cleared actions live until they are recycled, dropped to make room for newer ones,
+/// or the arena is dropped, so payloads that release external resources on drop (file
+/// handles, connections) release them late.
+#[derive(Debug)]
+pub struct ArenaActions<UA, TA: TrackedActionTypes> {
+    // Neither is grown past its initial capacity, so pushes never reallocate
+    slots: Vec<Action<UA, TA>>,
+    cleared: Vec<Action<UA, TA>>,
+}
+
+impl<UA, TA: TrackedActionTypes> ArenaActions<UA, TA> {
+    /// Number of actions the arena has room for.
+    pub fn capacity(&self) -> usize {
+        self.slots.capacity()
+    }
+
+    /// Takes back an action removed by an earlier `clear`, to reuse its payload's
+    /// allocations, or `None` if there are none left.
+    ///
+    /// Actions come back in the order they were added before the clear.
+    pub fn recycle(&mut self) -> Option<Action<UA, TA>> {
+        self.cleared.pop()
+    }
+}
+
+impl<UA, TA: TrackedActionTypes> ActionsContainer<UA, TA> for ArenaActions<UA, TA> {
+    type Error = ArenaFull;
+
+    fn new() -> Result<Self, Self::Error>
+    where
+        Self: Sized,
+    {
+        Self::with_capacity(DEFAULT_ARENA_CAPACITY)
+    }
+
+    fn with_capacity(capacity: usize) -> Result<Self, Self::Error>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            slots: Vec::with_capacity(capacity),
+            cleared: Vec::with_capacity(capacity),
+        })
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        // Make room by dropping the actions cleared longest ago, then stack these in
+        // reverse so `recycle` pops them in the order they were added
+        let stale = (self.cleared.len() + self.slots.len()).saturating_sub(self.cleared.capacity());
+        self.cleared.drain(..stale.min(self.cleared.len()));
+        self.cleared.extend(self.slots.drain(..).rev());
+        Ok(())
+    }
+
+    fn add(&mut self, action: Action<UA, TA>) -> Result<(), Self::Error> {
+        if self.slots.len() == self.slots.capacity() {
+            return Err(ArenaFull {
+                capacity: self.slots.capacity(),
+            });
+        }
+        self.slots.push(action);
+        Ok(())
+    }
+
+    fn drain_partition(&mut self) -> Result<Partitioned<UA, TA>, Self::Error>
+    where
+        UA: Clone,
+        TrackedAction<TA>: Clone,
+    {
+        Ok(partition(self.slots.drain(..)))
+    }
+
+    fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a Action<UA, TA>>
+    where
+        UA: 'a,
+        TA: 'a,
+    {
+        self.slots.iter()
+    }
+}
+
+/// An action rejected by a full [`BoundedActions`], returned so the STF can decide what to
+/// do with it, e.g. fail the transition or drop a low-priority untracked action.
+#[derive(Debug, PartialEq, Eq)]
+pub struct BoundedOverflow<UA, TA: TrackedActionTypes> {
+    pub action: Action<UA, TA>,
+    pub capacity: usize,
+}
+
+impl<UA, TA: TrackedActionTypes> BoundedOverflow<UA, TA> {
+    pub fn into_action(self) -> Action<UA, TA> {
+        self.action
+    }
+}
+
+impl<UA, TA: TrackedActionTypes> std::fmt::Display for BoundedOverflow<UA, TA> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "actions container is full ({} actions per transition)",
+            self.capacity
+        )
+    }
+}
+
+impl<UA: Debug, TA: TrackedActionTypes + Debug> std::error::Error for BoundedOverflow<UA, TA> {}
+
+/// An actions container that holds at most `N` actions, enforcing a per-transition cap.
+///
+/// `add` on a full container returns the action in a [`BoundedOverflow`] instead of
+/// queueing it. Only `add` can fail: the capacity hint of `with_capacity` is clamped
+/// to `N`.
+#[derive(Debug)]
+pub struct BoundedActions<UA, TA: TrackedActionTypes, const N: usize> {
+    actions: Vec<Action<UA, TA>>,
+}
+
+impl<UA, TA: TrackedActionTypes, const N: usize> ActionsContainer<UA, TA>
+    for BoundedActions<UA, TA, N>
+{
+    type Error = BoundedOverflow<UA, TA>;
+
+    fn new() -> Result<Self, Self::Error>
+    where
+        Self: Sized,
+    {
+        Self::with_capacity(N)
+    }
+
+    fn with_capacity(capacity: usize) -> Result<Self, Self::Error>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            actions: Vec::with_capacity(capacity.min(N)),
+        })
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        self.actions.clear();
+        Ok(())
+    }
+
+    fn add(&mut self, action: Action<UA, TA>) -> Result<(), Self::Error> {
+        if self.actions.len() >= N {
+            return Err(BoundedOverflow {
+                action,
+                capacity: N,
+            });
+        }
+        self.actions.push(action);
+        Ok(())
+    }
+
+    fn drain_partition(&mut self) -> Result<Partitioned<UA, TA>, Self::Error>
+    where
+        UA: Clone,
+        TrackedAction<TA>: Clone,
+    {
+        Ok(partition(self.actions.drain(..)))
+    }
+
+    fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a Action<UA, TA>>
+    where
+        UA: 'a,
+        TA: 'a,
+    {
+        self.actions.iter()
+    }
+}
+
+/// Which kind of action a [`PriorityActions`] yields first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ActionOrder {
+    /// Tracked actions, e.g. network calls, before untracked ones such as UI updates.
+    #[default]
+    TrackedFirst,
+    UntrackedFirst,
+}
+
+/// An actions container that yields one kind of action before the other.
+///
+/// STF can add actions in whatever order is natural, and the executor still gets a
+/// consistent dispatch order. Each kind keeps its insertion order, but not its order
+/// relative to the other kind, so don't use it for a machine that relies on an untracked
+/// action being dispatched after a tracked one emitted before it, or the other way
+/// round. `new` and `with_capacity` use [`ActionOrder::TrackedFirst`].
+#[derive(Debug)]
+pub struct PriorityActions<UA, TA: TrackedActionTypes> {
+    order: ActionOrder,
+    tracked: Vec<Action<UA, TA>>,
+    untracked: Vec<Action<UA, TA>>,
+}
+
+impl<UA, TA: TrackedActionTypes> PriorityActions<UA, TA> {
+    pub fn with_order(order: ActionOrder) -> Self {
+        Self {
+            order,
+            tracked: Vec::new(),
+            untracked: Vec::new(),
+        }
+    }
+
+    pub fn order(&self) -> ActionOrder {
+        self.order
+    }
+}
+
+impl<UA, TA: TrackedActionTypes> ActionsContainer<UA, TA> for PriorityActions<UA, TA> {
+    type Error = ();
+
+    fn new() -> Result<Self, Self::Error>
+    where
+        Self: Sized,
+    {
+        Ok(Self::with_order(ActionOrder::default()))
+    }
+
+    fn with_capacity(capacity: usize) -> Result<Self, Self::Error>
+    where
+        Self: Sized,
+    {
+        Ok(Self {
+            order: ActionOrder::default(),
+            tracked: Vec::with_capacity(capacity),
+            untracked: Vec::with_capacity(capacity),
+        })
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        self.tracked.clear();
+        self.untracked.clear();
+        Ok(())
+    }
+
+    fn add(&mut self, action: Action<UA, TA>) -> Result<(), Self::Error> {
+        match action {
+            Action::Tracked(_) => self.tracked.push(action),
+            Action::Untracked(_) => self.untracked.push(action),
+        }
+        Ok(())
+    }
+
+    fn drain_partition(&mut self) -> Result<Partitioned<UA, TA>, Self::Error>
+    where
+        UA: Clone,
+        TrackedAction<TA>: Clone,
+    {
+        Ok(partition(
+            self.tracked.drain(..).chain(self.untracked.drain(..)),
+        ))
+    }
+
+    fn len(&self) -> usize {
+        self.tracked.len() + self.untracked.len()
+    }
+
+    fn has_tracked(&self) -> bool {
+        !self.tracked.is_empty()
+    }
+
+    fn has_untracked(&self) -> bool {
+        !self.untracked.is_empty()
+    }
+
+    /// Iterates over the actions in [`order`](PriorityActions::order), each kind in
+    /// insertion order.
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a Action<UA, TA>>
+    where
+        UA: 'a,
+        TA: 'a,
+    {
+        let (first, second) = match self.order {
+            ActionOrder::TrackedFirst => (&self.tracked, &self.untracked),
+            ActionOrder::UntrackedFirst => (&self.untracked, &self.tracked),
+        };
+        first.iter().chain(second)
+    }
+
+    fn iter_tracked<'a>(&'a self) -> impl Iterator<Item = &'a TrackedAction<TA>>
+    where
+        UA: 'a,
+        TA: 'a,
+    {
+        self.tracked.iter().filter_map(Action::as_tracked)
+    }
+
+    fn iter_untracked<'a>(&'a self) -> impl Iterator<Item = &'a UA>
+    where
+        UA: 'a,
+        TA: 'a,
+    {
+        self.untracked.iter().filter_map(Action::as_untracked)
+    }
+}
+
+/// An actions container that only counts the actions added to it.
+///
+/// For tests that care how state evolves but not what the actions are, such as long
+/// determinism runs: payloads are dropped on `add`, so it never allocates. Nothing is
+/// left to iterate, so `iter` is always empty and an executor given it dispatches
+/// nothing; read the counts instead.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CountingActions {
+    tracked: usize,
+    untracked: usize,
+}
+
+impl CountingActions {
+    /// Tracked actions added since the last `clear`.
+    pub fn tracked(&self) -> usize {
+        self.tracked
+    }
+
+    /// Untracked actions added since the last `clear`.
+    pub fn untracked(&self) -> usize {
+        self.untracked
+    }
+}
+
+impl<UA, TA: TrackedActionTypes> ActionsContainer<UA, TA> for CountingActions {
+    type Error = ();
+
+    fn new() -> Result<Self, Self::Error>
+    where
+        Self: Sized,
+    {
+        Ok(Self::default())
+    }
+
+    fn with_capacity(_capacity: usize) -> Result<Self, Self::Error>
+    where
+        Self: Sized,
+    {
+        Ok(Self::default())
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        *self = Self::default();
+        Ok(())
+    }
+
+    fn add(&mut self, action: Action<UA, TA>) -> Result<(), Self::Error> {
+        match action {
+            Action::Tracked(_) => self.tracked += 1,
+            Action::Untracked(_) => self.untracked += 1,
+        }
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.tracked + self.untracked
+    }
+
+    fn has_tracked(&self) -> bool {
+        self.tracked > 0
+    }
+
+    fn has_untracked(&self) -> bool {
+        self.untracked > 0
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a Action<UA, TA>>
+    where
+        UA: 'a,
+        TA: 'a,
+    {
+        std::iter::empty()
+    }
+}
+
+/// An actions container that stores up to `N` actions inline before spilling to the heap.
+///
+/// Transitions that emit at most `N` actions never allocate, even into a freshly created
+/// container. Beyond `N` it behaves like a `Vec`. Enabled with the `smallvec` feature.
+#[cfg(feature = "smallvec")]
+#[derive(Debug)]
+pub struct SmallActions<UA, TA: TrackedActionTypes, const N: usize>(
+    smallvec::SmallVec<[Action<UA, TA>; N]>,
+);
+
+#[cfg(feature = "smallvec")]
+impl<UA, TA: TrackedActionTypes, const N: usize> SmallActions<UA, TA, N> {
+    /// Whether the actions outgrew the inline storage and moved to the heap.
+    pub fn spilled(&self) -> bool {
+        self.0.spilled()
+    }
+}
+
+#[cfg(feature = "smallvec")]
+impl<UA, TA: TrackedActionTypes, const N: usize> ActionsContainer<UA, TA>
+    for SmallActions<UA, TA, N>
+{
+    type Error = ();
+
+    fn new() -> Result<Self, Self::Error>
+    where
+        Self: Sized,
+    {
+        Ok(Self(smallvec::SmallVec::new()))
+    }
+
+    fn with_capacity(capacity: usize) -> Result<Self, Self::Error>
+    where
+        Self: Sized,
+    {
+        Ok(Self(smallvec::SmallVec::with_capacity(capacity)))
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        self.0.clear();
+        Ok(())
+    }
+
+    fn add(&mut self, action: Action<UA, TA>) -> Result<(), Self::Error> {
+        self.0.push(action);
+        Ok(())
+    }
+
+    fn add_all(
+        &mut self,
+        actions: impl IntoIterator<Item = Action<UA, TA>>,
+    ) -> Result<(), Self::Error> {
+        self.0.extend(actions);
+        Ok(())
+    }
+
+    fn drain_partition(&mut self) -> Result<Partitioned<UA, TA>, Self::Error>
+    where
+        UA: Clone,
+        TrackedAction<TA>: Clone,
+    {
+        Ok(partition(self.0.drain(..)))
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a Action<UA, TA>>
+    where
+        UA: 'a,
+        TA: 'a,
+    {
+        self.0.iter()
+    }
 }
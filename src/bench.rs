@@ -0,0 +1,159 @@
+//! Transition throughput measurement, for catching performance regressions.
+//!
+//! [`bench`] applies a cycle of inputs to a state machine and reports transitions per
+//! second. To also count allocations, install [`CountingAllocator`] as the global
+//! allocator of the benchmark binary:
+//!
+//! ```ignore
+//! #[global_allocator]
+//! static ALLOC: phasm::bench::CountingAllocator = phasm::bench::CountingAllocator;
+//! ```
+//!
+//! Enabled with the `bench` feature.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+    fmt,
+    sync::atomic::{AtomicBool, Ordering},
+    time::{Duration, Instant},
+};
+
+use crate::{Input, StateMachine, actions::ActionsContainer};
+
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static ALLOCATIONS: Cell<u64> = const { Cell::new(0) };
+}
+
+/// A global allocator that counts allocations made by the current thread.
+///
+/// Delegates to [`System`]. Counts are per thread, so allocations by other threads
+/// (e.g. other tests running in parallel) don't leak into a measurement.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        count();
+        unsafe { System.alloc_zeroed(layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+fn count() {
+    INSTALLED.store(true, Ordering::Relaxed);
+    // `try_with` so allocations during thread teardown don't panic
+    let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+}
+
+/// Allocations made by the current thread so far, or `None` if [`CountingAllocator`]
+/// isn't the global allocator.
+pub fn allocations() -> Option<u64> {
+    let n = ALLOCATIONS.with(Cell::get);
+    INSTALLED.load(Ordering::Relaxed).then_some(n)
+}
+
+/// Result of [`bench`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchReport {
+    /// Number of STF calls made, including failed ones.
+    pub transitions: u64,
+    /// STF calls that returned an error.
+    pub errors: u64,
+    /// Time spent inside STF, excluding setup such as cloning inputs.
+    pub elapsed: Duration,
+    /// Allocations made inside STF, or `None` if [`CountingAllocator`] isn't installed.
+    pub allocations: Option<u64>,
+}
+
+impl BenchReport {
+    pub fn transitions_per_sec(&self) -> f64 {
+        self.transitions as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} transitions in {:?} ({:.0}/s)",
+            self.transitions,
+            self.elapsed,
+            self.transitions_per_sec()
+        )?;
+        if self.errors > 0 {
+            write!(f, ", {} errors", self.errors)?;
+        }
+        match self.allocations {
+            Some(n) => write!(
+                f,
+                ", {} allocations ({:.2}/transition)",
+                n,
+                n as f64 / self.transitions as f64
+            ),
+            None => write!(f, ", allocations not counted"),
+        }
+    }
+}
+
+/// Applies `inputs` in order to a clone of `initial`, `iterations` times, and measures
+/// the STF calls.
+///
+/// One actions container is created up front and cleared between calls, which is the
+/// allocation-reuse pattern machines are meant to run under, so a machine that
+/// allocates on every transition shows up in [`BenchReport::allocations`]. Transition
+/// errors are counted, not propagated.
+pub async fn bench<SM: StateMachine>(
+    initial: &SM::State,
+    inputs: &[Input<SM::TrackedAction, SM::Input>],
+    iterations: usize,
+) -> BenchReport
+where
+    SM::State: Clone,
+    Input<SM::TrackedAction, SM::Input>: Clone,
+    <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: fmt::Debug,
+{
+    let mut actions = SM::Actions::new().expect("failed to create actions container");
+    let mut report = BenchReport {
+        transitions: 0,
+        errors: 0,
+        elapsed: Duration::ZERO,
+        allocations: allocations().map(|_| 0),
+    };
+
+    for _ in 0..iterations {
+        let mut state = initial.clone();
+        for input in inputs {
+            let input = input.clone();
+            actions.clear().expect("failed to clear actions container");
+
+            let allocs_before = allocations();
+            let start = Instant::now();
+            let res = SM::stf(&mut state, input, &mut actions).await;
+            report.elapsed += start.elapsed();
+            if let (Some(total), Some(before), Some(after)) =
+                (&mut report.allocations, allocs_before, allocations())
+            {
+                *total += after - before;
+            }
+
+            report.transitions += 1;
+            report.errors += res.is_err() as u64;
+        }
+    }
+    report
+}
@@ -0,0 +1,63 @@
+//! Boxed STF and restore futures, for machines that don't name their future types.
+
+use std::{
+    marker::PhantomData,
+    mem,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// The STF and restore future of an [`AsyncStateMachine`](crate::AsyncStateMachine) or
+/// a `#[state_machine]` impl: the `async fn` body, boxed.
+///
+/// A `dyn Future` can only name one lifetime, but the body borrows both the state and
+/// the actions, and `StateMachine` doesn't relate the two. The box is erased to
+/// `'static` instead, and the lifetimes are kept as markers, so a `BoxFuture` can't
+/// outlive either borrow.
+pub struct BoxFuture<'state, 'actions, T> {
+    future: Pin<Box<dyn Future<Output = T>>>,
+    _borrows: PhantomData<(&'state mut (), &'actions mut ())>,
+}
+
+impl<'state, 'actions, T> BoxFuture<'state, 'actions, T> {
+    /// Pins `future` to the heap.
+    ///
+    /// # Safety
+    ///
+    /// `future` must only borrow data that lives for both `'state` and `'actions`.
+    #[doc(hidden)]
+    pub unsafe fn new(future: impl Future<Output = T>) -> Self {
+        let future: Pin<Box<dyn Future<Output = T> + '_>> = Box::pin(future);
+        Self {
+            // SAFETY: the caller guarantees the borrows outlive `self`
+            future: unsafe {
+                mem::transmute::<
+                    Pin<Box<dyn Future<Output = T> + '_>>,
+                    Pin<Box<dyn Future<Output = T> + 'static>>,
+                >(future)
+            },
+            _borrows: PhantomData,
+        }
+    }
+
+    /// Fixes the output type of an `async` block, so `?` in it knows what to convert
+    /// errors into.
+    #[doc(hidden)]
+    pub fn typed<F: Future<Output = T>>(future: F) -> F {
+        future
+    }
+}
+
+impl<T> Future for BoxFuture<'_, '_, T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        self.future.as_mut().poll(cx)
+    }
+}
+
+/// Makes drop check require the borrows to be live when the future is dropped, since
+/// dropping it drops the body's locals, which may use them.
+impl<T> Drop for BoxFuture<'_, '_, T> {
+    fn drop(&mut self) {}
+}
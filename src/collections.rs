@@ -0,0 +1,108 @@
+//! Collections with deterministic iteration order.
+//!
+//! `HashMap` and `HashSet` iterate in an order that depends on the hasher's seed, so any
+//! state that is iterated to produce actions - in [`StateMachine::stf`] or
+//! [`StateMachine::restore`] - must not use them, or the same state and input can emit
+//! actions in a different order on every run. Use [`OrderedMap`] for that state instead.
+//!
+//! [`StateMachine::stf`]: crate::StateMachine::stf
+//! [`StateMachine::restore`]: crate::StateMachine::restore
+
+use std::{
+    collections::BTreeMap,
+    ops::{Deref, DerefMut},
+};
+
+/// A map that always iterates in ascending key order.
+///
+/// A thin wrapper over [`BTreeMap`] that derefs to it, so it has the full `BTreeMap` API.
+/// The wrapper exists to make the intent explicit: a field typed `OrderedMap` is iterated
+/// somewhere that determinism matters, and must not be "optimized" into a `HashMap`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(
+        transparent,
+        bound(
+            serialize = "K: serde::Serialize, V: serde::Serialize",
+            deserialize = "K: serde::Deserialize<'de> + Ord, V: serde::Deserialize<'de>"
+        )
+    )
+)]
+pub struct OrderedMap<K, V>(BTreeMap<K, V>);
+
+impl<K, V> OrderedMap<K, V> {
+    pub fn new() -> Self {
+        Self(BTreeMap::new())
+    }
+
+    pub fn into_inner(self) -> BTreeMap<K, V> {
+        self.0
+    }
+}
+
+impl<K, V> Default for OrderedMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> Deref for OrderedMap<K, V> {
+    type Target = BTreeMap<K, V>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<K, V> DerefMut for OrderedMap<K, V> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl<K, V> From<BTreeMap<K, V>> for OrderedMap<K, V> {
+    fn from(map: BTreeMap<K, V>) -> Self {
+        Self(map)
+    }
+}
+
+impl<K: Ord, V> FromIterator<(K, V)> for OrderedMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+impl<K: Ord, V> Extend<(K, V)> for OrderedMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        self.0.extend(iter);
+    }
+}
+
+impl<K, V> IntoIterator for OrderedMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = std::collections::btree_map::IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a OrderedMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = std::collections::btree_map::Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+impl<'a, K, V> IntoIterator for &'a mut OrderedMap<K, V> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = std::collections::btree_map::IterMut<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter_mut()
+    }
+}
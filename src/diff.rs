@@ -0,0 +1,107 @@
+//! Field-level "what changed" reports for debugging transitions.
+//!
+//! Implement [`StateDiff`] for a state by listing its top-level fields with
+//! [`FieldChange::value`] and [`FieldChange::entries`], then enable
+//! [`Engine::debug_diff`](crate::engine::Engine::debug_diff) to report the diff of every transition.
+
+use std::{collections::HashMap, fmt, hash::Hash};
+
+/// Reports which top-level fields differ between two versions of a state.
+pub trait StateDiff {
+    /// The changed fields, in field declaration order. Empty if nothing changed.
+    fn diff(before: &Self, after: &Self) -> Vec<FieldChange>;
+}
+
+/// How a single field changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// A plain value changed. Holds the `{:?}` output before and after.
+    Value { before: String, after: String },
+    /// Entries of a keyed collection were added, removed, or modified.
+    Entries {
+        added: usize,
+        removed: usize,
+        modified: usize,
+    },
+}
+
+/// A changed top-level field of a state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub change: Change,
+}
+
+impl FieldChange {
+    /// Compares a plain field, returning a change if `before != after`.
+    pub fn value<T: fmt::Debug + PartialEq>(
+        field: &'static str,
+        before: &T,
+        after: &T,
+    ) -> Option<Self> {
+        (before != after).then(|| Self {
+            field,
+            change: Change::Value {
+                before: format!("{:?}", before),
+                after: format!("{:?}", after),
+            },
+        })
+    }
+
+    /// Compares a keyed collection entry by entry, returning a change if any entry differs.
+    ///
+    /// Entries are matched by key and compared by their `{:?}` output, so values don't
+    /// need `PartialEq`.
+    pub fn entries<K: Eq + Hash, V: fmt::Debug>(
+        field: &'static str,
+        before: impl IntoIterator<Item = (K, V)>,
+        after: impl IntoIterator<Item = (K, V)>,
+    ) -> Option<Self> {
+        let mut before: HashMap<K, String> = before
+            .into_iter()
+            .map(|(k, v)| (k, format!("{:?}", v)))
+            .collect();
+        let (mut added, mut modified) = (0, 0);
+        for (k, v) in after {
+            match before.remove(&k) {
+                Some(old) if old != format!("{:?}", v) => modified += 1,
+                Some(_) => {}
+                None => added += 1,
+            }
+        }
+        let removed = before.len();
+
+        (added + removed + modified > 0).then_some(Self {
+            field,
+            change: Change::Entries {
+                added,
+                removed,
+                modified,
+            },
+        })
+    }
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.change {
+            Change::Value { before, after } => write!(f, "{}: {} -> {}", self.field, before, after),
+            Change::Entries {
+                added,
+                removed,
+                modified,
+            } => {
+                write!(f, "{}:", self.field)?;
+                let mut sep = " ";
+                for (sign, n) in [('+', added), ('-', removed), ('~', modified)] {
+                    if *n > 0 {
+                        let noun = if *n == 1 { "entry" } else { "entries" };
+                        write!(f, "{}{}{} {}", sep, sign, n, noun)?;
+                        sep = ", ";
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,455 @@
+//! Executes a state machine's actions and feeds tracked results back into it.
+//!
+//! A [`Driver`] owns the state and an actions container. [`Driver::submit`] applies an
+//! input, hands every emitted action to an [`ActionExecutor`] in emission order, and
+//! applies the result of each tracked action as [`Input::TrackedActionCompleted`], or
+//! [`Input::TrackedActionExpired`] if it passed its deadline, until there are none left.
+//! Results are applied in ascending tracked id order, not in the order they completed,
+//! so runs that execute actions in a different order converge on the same state. It is
+//! the minimal runnable loop; use an [`Engine`](crate::engine::Engine) when you need
+//! transactions, idempotency or other framework behavior, and execute its actions
+//! yourself.
+//!
+//! A [`RecordingDriver`] also logs every input it applies, and [`replay`] reapplies such
+//! a log to a fresh state to reproduce a run without executing any actions.
+//! [`WalDriver`](crate::wal::WalDriver) logs tracked actions before executing them.
+
+use std::{collections::BTreeMap, future::Future, time::Duration};
+
+use crate::{
+    Input, StateMachine,
+    actions::{Action, ActionsContainer, TrackedAction, TrackedActionTypes},
+};
+
+type TrackedTypes<SM> = <SM as StateMachine>::TrackedAction;
+type TrackedId<SM> = <TrackedTypes<SM> as TrackedActionTypes>::Id;
+type TrackedResult<SM> = <TrackedTypes<SM> as TrackedActionTypes>::Result;
+type Results<SM> = BTreeMap<TrackedId<SM>, Outcome<TrackedResult<SM>>>;
+type SmInput<SM> = Input<<SM as StateMachine>::TrackedAction, <SM as StateMachine>::Input>;
+type ContainerError<SM> = <<SM as StateMachine>::Actions as ActionsContainer<
+    <SM as StateMachine>::UntrackedAction,
+    <SM as StateMachine>::TrackedAction,
+>>::Error;
+
+/// Performs the external operations that actions describe.
+///
+/// Implementations may be written with `async fn`.
+pub trait ActionExecutor<UA, TA: TrackedActionTypes> {
+    /// Performs a fire-and-forget action. Failures are the executor's to log or drop.
+    fn execute_untracked(&mut self, action: &UA) -> impl Future<Output = ()>;
+
+    /// Performs a tracked action and returns its result, which is fed back to the
+    /// state machine. Retry here, or report transient failures with
+    /// [`is_transient`](Self::is_transient) and let the driver's [`RetryPolicy`] retry.
+    fn execute_tracked(
+        &mut self,
+        id: &TA::Id,
+        action: &TA::Action,
+    ) -> impl Future<Output = TA::Result>;
+
+    /// Performs a tracked action like [`execute_tracked`](Self::execute_tracked), giving
+    /// up once `limit` has passed. Returns `None` if it gave up.
+    ///
+    /// Used for actions with a [deadline](TrackedAction::deadline). By default the
+    /// action runs to completion however long it takes; implement it with your runtime's
+    /// timeout so a hung call expires.
+    fn execute_tracked_within(
+        &mut self,
+        id: &TA::Id,
+        action: &TA::Action,
+        _limit: Duration,
+    ) -> impl Future<Output = Option<TA::Result>> {
+        async move { Some(self.execute_tracked(id, action).await) }
+    }
+
+    /// Whether `res` is a transient failure worth executing the action again for.
+    /// `false` by default, so every result is final.
+    fn is_transient(&self, _res: &TA::Result) -> bool {
+        false
+    }
+
+    /// Waits `delay` before a retry. Returns immediately by default; implement it with
+    /// your runtime's timer.
+    fn backoff(&mut self, _delay: Duration) -> impl Future<Output = ()> {
+        async {}
+    }
+}
+
+/// How a [`Driver`] retries tracked actions whose result the executor reports as
+/// [transient](ActionExecutor::is_transient).
+///
+/// The backoff is a fixed schedule, not randomized, so runs are reproducible. Retries
+/// happen entirely in the executor layer: STF only sees the final result, which after
+/// the last attempt may still be the transient failure.
+///
+/// A tracked action with a [deadline](TrackedAction::deadline) expires, whatever the
+/// policy, when an attempt outlasts what is left of the deadline or when the next delay
+/// would use it up; [`Input::TrackedActionExpired`] is applied instead of a result. Each
+/// attempt is given the deadline less the backoff so far, through
+/// [`ActionExecutor::execute_tracked_within`]. Time spent in attempts that return isn't
+/// counted, so which retry a deadline ends at depends only on the schedule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first.
+    pub max_attempts: u32,
+    /// Delay before each retry, in order. The last delay repeats once the schedule runs
+    /// out; an empty schedule retries immediately.
+    pub backoff: Vec<Duration>,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32, backoff: Vec<Duration>) -> Self {
+        Self {
+            max_attempts,
+            backoff,
+        }
+    }
+
+    /// A single attempt, so results are fed back as they are.
+    pub fn none() -> Self {
+        Self::new(1, Vec::new())
+    }
+
+    /// Delay before retry number `retry`, counting from 0.
+    pub fn delay(&self, retry: usize) -> Duration {
+        self.backoff
+            .get(retry)
+            .or(self.backoff.last())
+            .copied()
+            .unwrap_or(Duration::ZERO)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+/// An error from [`Driver::recover`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum RecoverError<R, E> {
+    /// [`StateMachine::restore`] failed. Nothing was executed.
+    Restore(R),
+    /// Applying the result of a restored action failed.
+    Transition(E),
+}
+
+/// An error from [`replay`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReplayError<E, C> {
+    /// The input at `index` failed: the machine or the initial state differ from the
+    /// recorded run.
+    Transition { index: usize, error: E },
+    /// The actions container failed.
+    Actions(C),
+}
+
+/// Observes a [`Driver`] run, for the drivers that wrap it.
+pub(crate) trait RunHooks<SM: StateMachine> {
+    /// Called with every input before it is applied.
+    fn before_apply(&mut self, _input: &SmInput<SM>) {}
+
+    /// Called with the actions of a successful transition, or of a restore, before any
+    /// of them is executed.
+    async fn before_execute(&mut self, _actions: &SM::Actions) {}
+}
+
+impl<SM: StateMachine> RunHooks<SM> for () {}
+
+/// Runs a state machine, executing its actions with an [`ActionExecutor`].
+pub struct Driver<SM: StateMachine, E> {
+    state: SM::State,
+    actions: SM::Actions,
+    executor: E,
+    retry: RetryPolicy,
+}
+
+impl<SM, E> Driver<SM, E>
+where
+    SM: StateMachine,
+    E: ActionExecutor<SM::UntrackedAction, SM::TrackedAction>,
+    TrackedId<SM>: Clone + Ord,
+{
+    pub fn new(state: SM::State, executor: E) -> Result<Self, ContainerError<SM>> {
+        Ok(Self {
+            state,
+            actions: SM::Actions::new()?,
+            executor,
+            retry: RetryPolicy::none(),
+        })
+    }
+
+    /// Retries tracked actions the executor reports as transient, see [`RetryPolicy`].
+    /// By default each action is executed once.
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = policy;
+        self
+    }
+
+    pub fn state(&self) -> &SM::State {
+        &self.state
+    }
+
+    pub fn executor(&self) -> &E {
+        &self.executor
+    }
+
+    pub fn executor_mut(&mut self) -> &mut E {
+        &mut self.executor
+    }
+
+    pub fn into_parts(self) -> (SM::State, E) {
+        (self.state, self.executor)
+    }
+
+    /// Applies `input`, executes the actions it emits, and feeds tracked results back
+    /// until none are left.
+    ///
+    /// Actions of one transition are executed in emission order before any tracked
+    /// result is applied. Completed results are then applied lowest tracked id first,
+    /// and the actions each result emits are executed the same way, so with several
+    /// tracked actions pending the order of applied results depends only on their ids.
+    /// Tracked ids must be unique among the results waiting to be applied: a repeated id
+    /// replaces the earlier result.
+    ///
+    /// If `input` fails nothing is executed. If applying a tracked result fails, the
+    /// error is returned and results still waiting to be applied are dropped: the
+    /// machine's [`restore`](StateMachine::restore) re-emits them after a restart.
+    ///
+    /// # Panics
+    ///
+    /// If the actions container can't be cleared, or, with the `debug-invariants`
+    /// feature, if [`StateMachine::check_invariants`] fails after a transition.
+    pub async fn submit(&mut self, input: SM::Input) -> Result<(), SM::TransitionError> {
+        self.submit_with(input, &mut ()).await
+    }
+
+    /// Runs [`StateMachine::restore`] after a restart, executes the actions it re-emits,
+    /// and feeds tracked results back like [`submit`](Self::submit).
+    ///
+    /// # Panics
+    ///
+    /// Like [`submit`](Self::submit).
+    pub async fn recover(
+        &mut self,
+    ) -> Result<(), RecoverError<SM::RestoreError, SM::TransitionError>> {
+        self.recover_with(&mut ()).await
+    }
+
+    /// [`submit`](Self::submit), reporting the run to `hooks`.
+    pub(crate) async fn submit_with(
+        &mut self,
+        input: SM::Input,
+        hooks: &mut impl RunHooks<SM>,
+    ) -> Result<(), SM::TransitionError> {
+        let mut results = BTreeMap::new();
+        self.apply(Input::Normal(input), &mut results, hooks)
+            .await?;
+        self.settle(results, hooks).await
+    }
+
+    /// [`recover`](Self::recover), reporting the run to `hooks`.
+    pub(crate) async fn recover_with(
+        &mut self,
+        hooks: &mut impl RunHooks<SM>,
+    ) -> Result<(), RecoverError<SM::RestoreError, SM::TransitionError>> {
+        if self.actions.clear().is_err() {
+            panic!("failed to clear actions container");
+        }
+        SM::restore(&self.state, &mut self.actions)
+            .await
+            .map_err(RecoverError::Restore)?;
+        hooks.before_execute(&self.actions).await;
+        let mut results = BTreeMap::new();
+        self.execute(&mut results).await;
+        self.settle(results, hooks)
+            .await
+            .map_err(RecoverError::Transition)
+    }
+
+    /// Applies tracked results lowest id first until none are left.
+    async fn settle(
+        &mut self,
+        mut results: Results<SM>,
+        hooks: &mut impl RunHooks<SM>,
+    ) -> Result<(), SM::TransitionError> {
+        while let Some((id, outcome)) = results.pop_first() {
+            let input = match outcome {
+                Outcome::Completed(res) => Input::TrackedActionCompleted { id, res },
+                Outcome::Expired => Input::TrackedActionExpired { id },
+            };
+            self.apply(input, &mut results, hooks).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs STF on `input` and executes the emitted actions, collecting tracked results.
+    async fn apply(
+        &mut self,
+        input: SmInput<SM>,
+        results: &mut Results<SM>,
+        hooks: &mut impl RunHooks<SM>,
+    ) -> Result<(), SM::TransitionError> {
+        if self.actions.clear().is_err() {
+            panic!("failed to clear actions container");
+        }
+        hooks.before_apply(&input);
+        let res = SM::stf(&mut self.state, input, &mut self.actions).await;
+        #[cfg(feature = "debug-invariants")]
+        crate::assert_invariants::<SM>(&self.state);
+        res?;
+
+        hooks.before_execute(&self.actions).await;
+        self.execute(results).await;
+        Ok(())
+    }
+
+    /// Executes the actions in the container, collecting tracked results.
+    async fn execute(&mut self, results: &mut Results<SM>) {
+        for action in self.actions.iter() {
+            match action {
+                Action::Untracked(action) => self.executor.execute_untracked(action).await,
+                Action::Tracked(tracked) => {
+                    let outcome = execute_tracked(&mut self.executor, &self.retry, tracked).await;
+                    results.insert(tracked.id().clone(), outcome);
+                }
+            }
+        }
+    }
+}
+
+/// How a tracked action executed by a [`Driver`] ended.
+enum Outcome<R> {
+    Completed(R),
+    Expired,
+}
+
+/// Executes `tracked`, retrying transient failures under `retry` until its deadline.
+async fn execute_tracked<UA, TA, E>(
+    executor: &mut E,
+    retry: &RetryPolicy,
+    tracked: &TrackedAction<TA>,
+) -> Outcome<TA::Result>
+where
+    TA: TrackedActionTypes,
+    E: ActionExecutor<UA, TA>,
+{
+    let (id, action) = (tracked.id(), tracked.action());
+    let mut remaining = tracked.deadline();
+    let mut attempts = 0;
+    loop {
+        let res = match remaining {
+            Some(limit) => match executor.execute_tracked_within(id, action, limit).await {
+                Some(res) => res,
+                None => return Outcome::Expired,
+            },
+            None => executor.execute_tracked(id, action).await,
+        };
+        attempts += 1;
+        if attempts >= retry.max_attempts || !executor.is_transient(&res) {
+            return Outcome::Completed(res);
+        }
+        let delay = retry.delay(attempts as usize - 1);
+        if let Some(limit) = &mut remaining {
+            if delay >= *limit {
+                return Outcome::Expired;
+            }
+            *limit -= delay;
+        }
+        executor.backoff(delay).await;
+    }
+}
+
+/// A [`Driver`] that logs every input it applies, for reproducing a run with [`replay`].
+///
+/// [`Input::Normal`] inputs, the [`Input::TrackedActionCompleted`] results fed back and
+/// [`Input::TrackedActionExpired`] expiries are logged, in the order they were applied,
+/// so a replay times out the same actions. Inputs that fail aren't logged: STF leaves
+/// state unchanged on error, so they have no effect to replay.
+pub struct RecordingDriver<SM: StateMachine, E> {
+    driver: Driver<SM, E>,
+    log: Vec<SmInput<SM>>,
+}
+
+impl<SM, E> RecordingDriver<SM, E>
+where
+    SM: StateMachine,
+    E: ActionExecutor<SM::UntrackedAction, SM::TrackedAction>,
+    TrackedId<SM>: Clone + Ord,
+    SmInput<SM>: Clone,
+{
+    pub fn new(state: SM::State, executor: E) -> Result<Self, ContainerError<SM>> {
+        Ok(Self {
+            driver: Driver::new(state, executor)?,
+            log: Vec::new(),
+        })
+    }
+
+    pub fn driver(&self) -> &Driver<SM, E> {
+        &self.driver
+    }
+
+    pub fn state(&self) -> &SM::State {
+        self.driver.state()
+    }
+
+    /// Inputs applied so far.
+    pub fn log(&self) -> &[SmInput<SM>] {
+        &self.log
+    }
+
+    pub fn into_parts(self) -> (SM::State, E, Vec<SmInput<SM>>) {
+        let (state, executor) = self.driver.into_parts();
+        (state, executor, self.log)
+    }
+
+    /// [`Driver::submit`], logging the inputs it applies.
+    ///
+    /// # Panics
+    ///
+    /// Like [`Driver::submit`].
+    pub async fn submit(&mut self, input: SM::Input) -> Result<(), SM::TransitionError> {
+        let res = self.driver.submit_with(input, &mut self.log).await;
+        if res.is_err() {
+            // The input that failed is the last one recorded
+            self.log.pop();
+        }
+        res
+    }
+}
+
+impl<SM: StateMachine> RunHooks<SM> for Vec<SmInput<SM>>
+where
+    SmInput<SM>: Clone,
+{
+    fn before_apply(&mut self, input: &SmInput<SM>) {
+        self.push(input.clone());
+    }
+}
+
+/// Reapplies a log recorded by a [`RecordingDriver`] to `initial` and returns the
+/// resulting state.
+///
+/// Actions are discarded, not executed. Since STF is deterministic, replaying the log
+/// against the state the recording started from reproduces the recorded final state.
+///
+/// # Panics
+///
+/// With the `debug-invariants` feature, if [`StateMachine::check_invariants`] fails
+/// after a transition.
+pub async fn replay<SM: StateMachine>(
+    initial: SM::State,
+    log: impl IntoIterator<Item = SmInput<SM>>,
+) -> Result<SM::State, ReplayError<SM::TransitionError, ContainerError<SM>>> {
+    let mut state = initial;
+    let mut actions = SM::Actions::new().map_err(ReplayError::Actions)?;
+    for (index, input) in log.into_iter().enumerate() {
+        actions.clear().map_err(ReplayError::Actions)?;
+        let res = SM::stf(&mut state, input, &mut actions).await;
+        #[cfg(feature = "debug-invariants")]
+        crate::assert_invariants::<SM>(&state);
+        res.map_err(|error| ReplayError::Transition { index, error })?;
+    }
+    Ok(state)
+}
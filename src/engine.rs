@@ -0,0 +1,1079 @@
+//! A runtime wrapper that owns a state machine's state and actions container.
+//!
+//! [`Engine::step`] runs [`StateMachine::stf`] and layers framework behavior around it
+//! that a single STF call can't provide on its own, such as compensating the rest of a
+//! transaction when one of its tracked actions fails (see [`TrackedAction::new_in_txn`]).
+//!
+//! The engine does not execute actions. After each step the caller executes
+//! [`Engine::actions`] and feeds tracked results back with another `step`.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    hash::{DefaultHasher, Hash, Hasher},
+    time::Duration,
+};
+
+use crate::{
+    Input, StateMachine, StateSize, Versioned,
+    actions::{Action, ActionsContainer, SelfScheduled, TrackedAction, TrackedActionTypes, TxnId},
+    diff::{FieldChange, StateDiff},
+};
+
+type TrackedTypes<SM> = <SM as StateMachine>::TrackedAction;
+type TrackedId<SM> = <TrackedTypes<SM> as TrackedActionTypes>::Id;
+type TrackedOp<SM> = <TrackedTypes<SM> as TrackedActionTypes>::Action;
+type TrackedResult<SM> = <TrackedTypes<SM> as TrackedActionTypes>::Result;
+type ContainerError<SM> = <<SM as StateMachine>::Actions as ActionsContainer<
+    <SM as StateMachine>::UntrackedAction,
+    TrackedTypes<SM>,
+>>::Error;
+/// Compares the state captured before a transition with the state after it.
+type DiffSnapshot<SM> = Box<dyn FnOnce(&<SM as StateMachine>::State) -> Vec<FieldChange>>;
+/// Captures the state before a transition for [`Engine::debug_diff`].
+type Differ<SM> = fn(&<SM as StateMachine>::State) -> DiffSnapshot<SM>;
+/// Receives the changes of each transition, see [`Engine::debug_diff`].
+type DiffSink = Box<dyn FnMut(u64, FieldChange)>;
+/// Receives the state size periodically, see [`Engine::observe_state_size`].
+type SizeObserver = Box<dyn FnMut(u64, StateSize)>;
+/// Takes the follow-up inputs out of the container, see [`Engine::self_inputs`].
+type TakeSelfInputs<SM> = fn(
+    &mut <SM as StateMachine>::Actions,
+) -> Result<Vec<<SM as StateMachine>::Input>, ContainerError<SM>>;
+/// Keeps the first tracked and untracked actions, see [`Engine::self_inputs`].
+type TruncateActions<SM> =
+    fn(&mut <SM as StateMachine>::Actions, usize, usize) -> Result<(), ContainerError<SM>>;
+/// Receives permanently failed tracked actions, see [`Engine::dead_letters`].
+type DeadLetterSink<SM> = Box<dyn FnMut(DeadLetter<TrackedTypes<SM>>)>;
+
+/// An error from an [`Engine`] operation.
+#[derive(Debug, PartialEq, Eq)]
+pub enum EngineError<E, C> {
+    /// The state machine returned an error. State is unchanged and the actions it emitted
+    /// before failing were discarded.
+    Transition(E),
+    /// [`StateMachine::restore`] failed in [`Engine::restore`] or [`Engine::recover`], so
+    /// `E` is the machine's restore error. Nothing was recorded as in flight.
+    Restore(E),
+    /// The actions container failed.
+    Actions(C),
+    /// The input exceeded the [`rate_limit`](Engine::rate_limit) and was rejected before
+    /// STF ran. State is unchanged.
+    RateLimited,
+    /// The transition emitted more tracked actions than the
+    /// [`max_tracked_per_transition`](Engine::max_tracked_per_transition) budget. State was
+    /// rolled back and the transition's actions discarded; when it was a
+    /// [follow-up input](Engine::self_inputs), the transitions before it stay applied.
+    EffectsBudgetExceeded { emitted: usize, max: usize },
+    /// The step kept scheduling [follow-up inputs](Engine::self_inputs) beyond their
+    /// limit. The `applied` follow-ups before it were committed like any transition, and
+    /// their actions are in [`Engine::actions`].
+    SelfInputLimit { applied: usize },
+    /// A [follow-up input](Engine::self_inputs) was about to run on the same state as an
+    /// earlier one of the step with the same input, so the follow-ups would repeat
+    /// forever. The `applied` follow-ups before it were committed like any transition.
+    SelfInputCycle { applied: usize },
+    /// STF returned `error` for a [follow-up input](Engine::self_inputs). Its actions were
+    /// discarded and the follow-ups still queued dropped, but the input and the `applied`
+    /// follow-ups before it were committed like any transition.
+    SelfInputFailed { applied: usize, error: E },
+    /// A tracked action result for an id the machine doesn't know (see
+    /// [`StateMachine::is_known_tracked_id`]), rejected before STF ran. State is unchanged.
+    StaleTrackedResult,
+}
+
+/// An error from [`Engine::settle`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum SettleError<E, C> {
+    /// A step failed while feeding back results.
+    Engine(EngineError<E, C>),
+    /// Tracked actions kept being emitted: either the state after a round repeated an
+    /// earlier one, or `max_rounds` ran out. `digest` is the state digest after the last
+    /// round, which for a cycle is the repeating one.
+    NonConverging { rounds: usize, digest: u64 },
+}
+
+/// How an [`Engine::step_outcome`] went, for API layers that map results to responses
+/// without matching every transition error.
+///
+/// Suggested HTTP mapping:
+///
+/// | Outcome              | Status                                      |
+/// |----------------------|---------------------------------------------|
+/// | `Applied`            | 200, or 202 if it emitted tracked actions   |
+/// | `AppliedNoOp`        | 200, the same response as the original      |
+/// | `RejectedValidation` | 422                                         |
+/// | `RejectedConflict`   | 409                                         |
+/// | `RateLimited`        | 429                                         |
+/// | `Failed`             | 500                                         |
+#[derive(Debug, PartialEq, Eq)]
+pub enum TransitionOutcome<E, C> {
+    /// The input was applied, emitting `actions` actions (including those of follow-up
+    /// inputs and compensations).
+    Applied { actions: usize },
+    /// A duplicate of a keyed input that was already applied successfully (see
+    /// [`Engine::idempotency_keys`]). STF didn't run and nothing was emitted.
+    AppliedNoOp,
+    /// The input is invalid for the current state. Retrying it unchanged won't help.
+    RejectedValidation(E),
+    /// The input conflicts with the current state (see [`StateMachine::is_conflict`]).
+    /// `None` for the result of a tracked action the machine no longer knows about (see
+    /// [`Engine::reject_unknown_results`]).
+    RejectedConflict(Option<E>),
+    /// Rejected by the [`Engine::rate_limit`]. State is unchanged.
+    RateLimited,
+    /// The engine or actions container failed, the step hit one of the engine's limits,
+    /// or one of its follow-up inputs failed after the input was applied.
+    Failed(EngineError<E, C>),
+}
+
+impl<E, C> TransitionOutcome<E, C> {
+    pub fn is_applied(&self) -> bool {
+        matches!(
+            self,
+            TransitionOutcome::Applied { .. } | TransitionOutcome::AppliedNoOp
+        )
+    }
+}
+
+/// Whether [`Engine::step`] ran STF or replayed a cached result.
+enum Stepped {
+    Ran,
+    Replayed,
+}
+
+/// A tracked action that was emitted and whose result hasn't been fed back yet.
+struct InFlight<SM: StateMachine> {
+    id: TrackedId<SM>,
+    action: TrackedOp<SM>,
+    txn_id: Option<TxnId>,
+    deadline: Option<Duration>,
+}
+
+/// Fixed-window limit on the number of transitions, keyed by [`StateMachine::input_time`].
+struct RateLimit {
+    max_per_window: u32,
+    window: u64,
+    window_start: u64,
+    count: u32,
+}
+
+/// Results of keyed inputs, evicting the oldest key once `capacity` is reached.
+struct IdempotencyCache<E> {
+    capacity: usize,
+    results: HashMap<u64, Result<(), E>>,
+    order: VecDeque<u64>,
+    clone_err: fn(&E) -> E,
+}
+
+impl<E> IdempotencyCache<E> {
+    fn get(&self, key: u64) -> Option<Result<(), E>> {
+        self.results
+            .get(&key)
+            .map(|res| res.as_ref().map_err(self.clone_err).copied())
+    }
+
+    fn insert(&mut self, key: u64, res: Result<(), E>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.order.len() == self.capacity
+            && let Some(oldest) = self.order.pop_front()
+        {
+            self.results.remove(&oldest);
+        }
+        self.order.push_back(key);
+        self.results.insert(key, res);
+    }
+}
+
+/// When a tracked action was emitted and when its result was applied.
+///
+/// Times are transition indexes: the value of [`Engine::transitions`] right after the
+/// transition that emitted the action or applied its result. Actions emitted by
+/// [`Engine::restore`] or [`Engine::untracked_failed`] get the current count.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LifecycleRecord<Id> {
+    pub id: Id,
+    pub emitted_at: u64,
+    /// `None` while the action is in flight.
+    pub completed_at: Option<u64>,
+    /// The `{:?}` output of the applied result, once there is one.
+    pub outcome: Option<String>,
+}
+
+/// Lifecycle of every tracked action an [`Engine`] has seen, in emission order.
+///
+/// Enabled with [`Engine::track_lifecycle`]. Records are never evicted, so this is meant
+/// for debugging stuck external operations rather than for long-running production use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackedLifecycle<Id> {
+    records: Vec<LifecycleRecord<Id>>,
+}
+
+impl<Id: PartialEq> TrackedLifecycle<Id> {
+    pub fn get(&self, id: &Id) -> Option<&LifecycleRecord<Id>> {
+        self.records.iter().find(|r| &r.id == id)
+    }
+
+    /// Actions that were emitted and whose result hasn't been applied.
+    pub fn in_flight(&self) -> impl Iterator<Item = &LifecycleRecord<Id>> {
+        self.records.iter().filter(|r| r.completed_at.is_none())
+    }
+
+    /// Actions whose result has been applied.
+    pub fn completed(&self) -> impl Iterator<Item = &LifecycleRecord<Id>> {
+        self.records.iter().filter(|r| r.completed_at.is_some())
+    }
+
+    fn emitted(&mut self, id: &Id, at: u64)
+    where
+        Id: Clone,
+    {
+        match self.records.iter_mut().find(|r| &r.id == id) {
+            // Re-emitted while in flight (e.g. by restore): still the same operation
+            Some(record) if record.completed_at.is_none() => {}
+            // Reusing a completed id starts a new lifecycle
+            Some(record) => {
+                record.emitted_at = at;
+                record.completed_at = None;
+                record.outcome = None;
+            }
+            None => self.records.push(LifecycleRecord {
+                id: id.clone(),
+                emitted_at: at,
+                completed_at: None,
+                outcome: None,
+            }),
+        }
+    }
+
+    fn completed_with(&mut self, id: &Id, at: u64, outcome: String) {
+        if let Some(record) = self.records.iter_mut().find(|r| &r.id == id) {
+            record.completed_at = Some(at);
+            record.outcome = Some(outcome);
+        }
+    }
+}
+
+/// A tracked action that permanently failed, with the context to investigate it.
+#[derive(Debug, PartialEq, Eq)]
+pub struct DeadLetter<TA: TrackedActionTypes> {
+    pub id: TA::Id,
+    pub action: TA::Action,
+    pub txn_id: Option<TxnId>,
+    /// The executor's description of the last failed attempt.
+    pub last_error: String,
+}
+
+/// Tracked actions an [`Engine`] emitted and hasn't seen results for, see [`Engine::outbox`].
+///
+/// Persist it in the same write as the state after every step, so recovery has the raw
+/// emitted actions even if the machine's [`StateMachine::restore`] misses some.
+#[derive(Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "TA::Id: serde::Serialize, TA::Action: serde::Serialize",
+        deserialize = "TA::Id: serde::Deserialize<'de>, TA::Action: serde::Deserialize<'de>"
+    ))
+)]
+pub struct Outbox<TA: TrackedActionTypes> {
+    /// In emission order.
+    pub entries: Vec<TrackedAction<TA>>,
+}
+
+struct DebugDiff<SM: StateMachine> {
+    snapshot: Differ<SM>,
+    sink: DiffSink,
+}
+
+struct SizeReport {
+    every: u64,
+    observer: SizeObserver,
+}
+
+/// Per-transition cap on tracked actions, see [`Engine::max_tracked_per_transition`].
+struct EffectsBudget<S> {
+    max_tracked: usize,
+    clone_state: fn(&S) -> S,
+}
+
+/// How to apply follow-up inputs, see [`Engine::self_inputs`].
+struct SelfInputs<SM: StateMachine> {
+    max: usize,
+    /// In emission order.
+    take: TakeSelfInputs<SM>,
+    /// Discards the actions of an over-budget follow-up.
+    truncate: TruncateActions<SM>,
+    /// Digest of a follow-up input and the state it runs on, for cycle detection.
+    digest: fn(&SM::State, &SM::Input) -> u64,
+}
+
+/// Owns a state machine's state and drives it one input at a time.
+///
+/// The engine keeps an in-memory table of tracked actions that are in flight. It is not
+/// persisted: after a crash, build a new engine from the persisted state and call
+/// [`Engine::restore`] to repopulate it from [`StateMachine::restore`].
+pub struct Engine<SM: StateMachine> {
+    state: SM::State,
+    actions: SM::Actions,
+    in_flight: Vec<InFlight<SM>>,
+    transitions: u64,
+    rate_limit: Option<RateLimit>,
+    differ: Option<DebugDiff<SM>>,
+    idempotency: Option<IdempotencyCache<SM::TransitionError>>,
+    budget: Option<EffectsBudget<SM::State>>,
+    lifecycle: Option<TrackedLifecycle<TrackedId<SM>>>,
+    self_inputs: Option<SelfInputs<SM>>,
+    reject_unknown: bool,
+    dead_letters: Option<DeadLetterSink<SM>>,
+    size_report: Option<SizeReport>,
+}
+
+impl<SM: StateMachine> Engine<SM>
+where
+    TrackedId<SM>: Clone,
+    TrackedOp<SM>: Clone,
+{
+    pub fn new(state: SM::State) -> Result<Self, ContainerError<SM>> {
+        Ok(Self {
+            state,
+            actions: SM::Actions::new()?,
+            in_flight: Vec::new(),
+            transitions: 0,
+            rate_limit: None,
+            differ: None,
+            idempotency: None,
+            budget: None,
+            lifecycle: None,
+            self_inputs: None,
+            reject_unknown: false,
+            dead_letters: None,
+            size_report: None,
+        })
+    }
+
+    /// Applies only the first input for each [`StateMachine::input_key`], replaying its
+    /// result for duplicates without calling STF.
+    ///
+    /// Results of the last `capacity` keys are kept; a key evicted from the cache is
+    /// treated as new. Replays emit no actions, since the original's actions were already
+    /// returned by the first `step`. Inputs rejected by the [`rate_limit`](Self::rate_limit)
+    /// never ran, so they aren't cached.
+    pub fn idempotency_keys(mut self, capacity: usize) -> Self
+    where
+        SM::TransitionError: Clone,
+    {
+        self.idempotency = Some(IdempotencyCache {
+            capacity,
+            results: HashMap::new(),
+            order: VecDeque::new(),
+            clone_err: Clone::clone,
+        });
+        self
+    }
+
+    /// Sends each field a successful transition changed to `sink`, along with the
+    /// transition's number (see [`transitions`](Self::transitions)), e.g. to log it.
+    ///
+    /// The state is cloned before each STF call, so this is meant for debugging only.
+    pub fn debug_diff(mut self, sink: impl FnMut(u64, FieldChange) + 'static) -> Self
+    where
+        SM::State: StateDiff + Clone + 'static,
+    {
+        self.differ = Some(DebugDiff {
+            snapshot: |before| {
+                let before = before.clone();
+                Box::new(move |after| StateDiff::diff(&before, after))
+            },
+            sink: Box::new(sink),
+        });
+        self
+    }
+
+    /// Rejects inputs beyond `max_per_window` per `window` with [`EngineError::RateLimited`].
+    ///
+    /// Windows are measured with [`StateMachine::input_time`], so the limit is as
+    /// deterministic as the inputs. A window opens at the time of the first input it
+    /// admits and lasts `window` units. Only [`Input::Normal`] is limited: results of
+    /// tracked actions are always applied, and inputs without a time are never limited.
+    pub fn rate_limit(mut self, max_per_window: u32, window: u64) -> Self {
+        self.rate_limit = Some(RateLimit {
+            max_per_window,
+            window,
+            window_start: 0,
+            count: 0,
+        });
+        self
+    }
+
+    /// Fails transitions that emit more than `max` tracked actions with
+    /// [`EngineError::EffectsBudgetExceeded`], capping the blast radius of a buggy STF.
+    ///
+    /// The state is cloned before each STF call so an over-budget transition can be rolled
+    /// back, and its actions are discarded. Over-budget results aren't cached by
+    /// [`idempotency_keys`](Self::idempotency_keys), so a retry runs STF again. Untracked
+    /// actions and compensations added by the engine don't count against the budget.
+    pub fn max_tracked_per_transition(mut self, max: usize) -> Self
+    where
+        SM::State: Clone,
+    {
+        self.budget = Some(EffectsBudget {
+            max_tracked: max,
+            clone_state: Clone::clone,
+        });
+        self
+    }
+
+    /// Records when each tracked action is emitted and resolved, see
+    /// [`lifecycle`](Self::lifecycle).
+    pub fn track_lifecycle(mut self) -> Self {
+        self.lifecycle = Some(TrackedLifecycle {
+            records: Vec::new(),
+        });
+        self
+    }
+
+    /// Applies the follow-up inputs STF schedules with [`Action::self_input`] within the
+    /// same [`step`](Self::step), and takes them out of [`actions`](Self::actions).
+    ///
+    /// Follow-ups run breadth first in emission order, each as a transition of its own
+    /// that counts against the [effects budget](Self::max_tracked_per_transition) and
+    /// the [lifecycle](Self::track_lifecycle) like any other. Since STF is deterministic,
+    /// a follow-up about to run on the same state with the same input as an earlier one
+    /// of the step would repeat forever, so the step fails with
+    /// [`EngineError::SelfInputCycle`]. Follow-ups that keep changing the state fail it
+    /// with [`EngineError::SelfInputLimit`] after `max` of them, and a follow-up STF
+    /// rejects fails it with [`EngineError::SelfInputFailed`].
+    ///
+    /// Without this, follow-ups are left in the container like any untracked action.
+    pub fn self_inputs<UA, I>(mut self, max: usize) -> Self
+    where
+        SM: StateMachine<UntrackedAction = SelfScheduled<UA, I>, Input = I>,
+        SM::State: Hash,
+        UA: Clone,
+        I: Clone + Hash,
+    {
+        self.self_inputs = Some(SelfInputs {
+            max,
+            take: take_self_inputs::<SM, UA, I>,
+            truncate: truncate_actions::<SM, UA, I>,
+            digest: |state, input| digest(&(state, input)),
+        });
+        self
+    }
+
+    /// Rejects tracked action results whose id fails [`StateMachine::is_known_tracked_id`]
+    /// with [`EngineError::StaleTrackedResult`], without calling STF.
+    ///
+    /// Stale and duplicate deliveries are then handled the same way for every result,
+    /// instead of by whatever the machine's handler for that result happens to do.
+    pub fn reject_unknown_results(mut self) -> Self {
+        self.reject_unknown = true;
+        self
+    }
+
+    /// Sends [`StateMachine::approx_state_size`] to `observer` every `every` transitions,
+    /// along with the number of transitions so far, so operators can alert on a state
+    /// that keeps growing.
+    pub fn observe_state_size(
+        mut self,
+        every: u64,
+        observer: impl FnMut(u64, StateSize) + 'static,
+    ) -> Self {
+        self.size_report = Some(SizeReport {
+            every: every.max(1),
+            observer: Box::new(observer),
+        });
+        self
+    }
+
+    /// Sends tracked actions reported through [`tracked_exhausted`](Self::tracked_exhausted)
+    /// to `sink`, e.g. to persist them for investigation.
+    pub fn dead_letters(
+        mut self,
+        sink: impl FnMut(DeadLetter<SM::TrackedAction>) + 'static,
+    ) -> Self {
+        self.dead_letters = Some(Box::new(sink));
+        self
+    }
+
+    pub fn state(&self) -> &SM::State {
+        &self.state
+    }
+
+    pub fn into_state(self) -> SM::State {
+        self.state
+    }
+
+    /// Actions emitted by the last [`step`](Self::step) or [`restore`](Self::restore).
+    pub fn actions(&self) -> &SM::Actions {
+        &self.actions
+    }
+
+    /// Whether a tracked action with `id` has been emitted and its result not yet applied.
+    pub fn is_in_flight(&self, id: &TrackedId<SM>) -> bool {
+        self.in_flight.iter().any(|f| &f.id == id)
+    }
+
+    /// Version of the state after the last successful step.
+    pub fn version(&self) -> u64
+    where
+        SM::State: Versioned,
+    {
+        self.state.version()
+    }
+
+    /// The tracked action lifecycle table, if [`track_lifecycle`](Self::track_lifecycle)
+    /// is enabled.
+    pub fn lifecycle(&self) -> Option<&TrackedLifecycle<TrackedId<SM>>> {
+        self.lifecycle.as_ref()
+    }
+
+    /// Number of inputs this engine has successfully applied.
+    pub fn transitions(&self) -> u64 {
+        self.transitions
+    }
+
+    /// Applies one input.
+    ///
+    /// Clears the actions container, runs STF, and records the tracked actions it emits.
+    /// If `input` is the result of a transactional tracked action and
+    /// [`TrackedActionTypes::aborts_txn`] says it failed, or the action
+    /// [expired](Input::TrackedActionExpired), compensating actions for the in-flight
+    /// siblings of that transaction are appended to [`actions`](Self::actions).
+    ///
+    /// With [`self_inputs`](Self::self_inputs), follow-up inputs are then applied as
+    /// further transitions, appending their actions. Each is atomic on its own: if one
+    /// fails, the transitions before it stay applied and the step returns its error,
+    /// [`EngineError::SelfInputFailed`] if STF rejected it.
+    pub async fn step(
+        &mut self,
+        input: Input<SM::TrackedAction, SM::Input>,
+    ) -> Result<(), EngineError<SM::TransitionError, ContainerError<SM>>> {
+        self.step_inner(input).await.map(|_| ())
+    }
+
+    /// Like [`step`](Self::step), but classifies the result as a [`TransitionOutcome`].
+    ///
+    /// Transition errors are split using [`StateMachine::is_conflict`]. A replayed
+    /// duplicate of a failed keyed input is classified like the original failure.
+    pub async fn step_outcome(
+        &mut self,
+        input: Input<SM::TrackedAction, SM::Input>,
+    ) -> TransitionOutcome<SM::TransitionError, ContainerError<SM>> {
+        match self.step_inner(input).await {
+            Ok(Stepped::Ran) => TransitionOutcome::Applied {
+                actions: self.actions.len(),
+            },
+            Ok(Stepped::Replayed) => TransitionOutcome::AppliedNoOp,
+            Err(EngineError::Transition(e)) if SM::is_conflict(&e) => {
+                TransitionOutcome::RejectedConflict(Some(e))
+            }
+            Err(EngineError::Transition(e)) => TransitionOutcome::RejectedValidation(e),
+            Err(EngineError::StaleTrackedResult) => TransitionOutcome::RejectedConflict(None),
+            Err(EngineError::RateLimited) => TransitionOutcome::RateLimited,
+            Err(e) => TransitionOutcome::Failed(e),
+        }
+    }
+
+    async fn step_inner(
+        &mut self,
+        input: Input<SM::TrackedAction, SM::Input>,
+    ) -> Result<Stepped, EngineError<SM::TransitionError, ContainerError<SM>>> {
+        self.actions.clear().map_err(EngineError::Actions)?;
+
+        let key = match &input {
+            Input::Normal(normal) => SM::input_key(normal).filter(|_| self.idempotency.is_some()),
+            Input::TrackedActionCompleted { .. } | Input::TrackedActionExpired { .. } => None,
+        };
+        if let Some(cached) = key.and_then(|key| self.idempotency.as_ref()?.get(key)) {
+            return cached
+                .map(|()| Stepped::Replayed)
+                .map_err(EngineError::Transition);
+        }
+        match &input {
+            Input::Normal(normal) => self.admit(SM::input_time(normal))?,
+            Input::TrackedActionCompleted { id, .. } | Input::TrackedActionExpired { id } => {
+                if self.reject_unknown && !SM::is_known_tracked_id(&self.state, id) {
+                    return Err(EngineError::StaleTrackedResult);
+                }
+            }
+        }
+        let completed = match &input {
+            Input::TrackedActionCompleted { id, res } => {
+                Some((id.clone(), SM::TrackedAction::aborts_txn(res)))
+            }
+            Input::TrackedActionExpired { id } => Some((id.clone(), true)),
+            Input::Normal(_) => None,
+        };
+        let outcome = match &input {
+            Input::TrackedActionCompleted { res, .. } if self.lifecycle.is_some() => {
+                Some(format!("{:?}", res))
+            }
+            Input::TrackedActionExpired { .. } if self.lifecycle.is_some() => {
+                Some("expired".to_string())
+            }
+            _ => None,
+        };
+
+        let res = self.transition(input).await;
+        if let (Some(key), Some(cache)) = (key, &mut self.idempotency) {
+            match &res {
+                Ok(()) => cache.insert(key, Ok(())),
+                Err(EngineError::Transition(e)) => {
+                    let e = (cache.clone_err)(e);
+                    cache.insert(key, Err(e));
+                }
+                // Over budget, so a retry runs STF again
+                Err(_) => {}
+            }
+        }
+        res?;
+
+        if let (Some(lifecycle), Some((id, _)), Some(outcome)) =
+            (&mut self.lifecycle, &completed, outcome)
+        {
+            lifecycle.completed_with(id, self.transitions, outcome);
+        }
+
+        let aborted_txn = completed.and_then(|(id, aborts)| {
+            let pos = self.in_flight.iter().position(|f| f.id == id)?;
+            let done = self.in_flight.remove(pos);
+            done.txn_id.filter(|_| aborts)
+        });
+
+        self.record_emitted(0);
+
+        if let Some(txn_id) = aborted_txn {
+            self.compensate(txn_id).map_err(EngineError::Actions)?;
+        }
+
+        self.run_self_inputs().await?;
+        Ok(Stepped::Ran)
+    }
+
+    /// Runs STF on `input` as one transition, adding to the actions already in the
+    /// container. A failed or over-budget transition has its actions discarded, and an
+    /// over-budget one is rolled back.
+    async fn transition(
+        &mut self,
+        input: Input<SM::TrackedAction, SM::Input>,
+    ) -> Result<(), EngineError<SM::TransitionError, ContainerError<SM>>> {
+        let snapshot = self
+            .differ
+            .as_ref()
+            .map(|differ| (differ.snapshot)(&self.state));
+        let rollback = self
+            .budget
+            .as_ref()
+            .map(|budget| (budget.max_tracked, (budget.clone_state)(&self.state)));
+        let tracked_before = self.actions.iter_tracked().count();
+        let untracked_before = self.actions.iter_untracked().count();
+
+        if let Err(e) = SM::stf(&mut self.state, input, &mut self.actions).await {
+            self.discard_since(tracked_before, untracked_before)
+                .map_err(EngineError::Actions)?;
+            return Err(EngineError::Transition(e));
+        }
+        if let Some((max, before)) = rollback {
+            let emitted = self.actions.iter_tracked().count() - tracked_before;
+            if emitted > max {
+                self.state = before;
+                self.discard_since(tracked_before, untracked_before)
+                    .map_err(EngineError::Actions)?;
+                return Err(EngineError::EffectsBudgetExceeded { emitted, max });
+            }
+        }
+        self.transitions += 1;
+
+        if let (Some(diff), Some(differ)) = (snapshot, &mut self.differ) {
+            for change in diff(&self.state) {
+                (differ.sink)(self.transitions, change);
+            }
+        }
+        if let Some(report) = &mut self.size_report
+            && self.transitions.is_multiple_of(report.every)
+        {
+            (report.observer)(self.transitions, SM::approx_state_size(&self.state));
+        }
+        Ok(())
+    }
+
+    /// Discards the actions added after the first `tracked` tracked and `untracked`
+    /// untracked ones.
+    fn discard_since(
+        &mut self,
+        tracked: usize,
+        untracked: usize,
+    ) -> Result<(), ContainerError<SM>> {
+        match &self.self_inputs {
+            // Only follow-ups run with actions already in the container
+            Some(self_inputs) if tracked + untracked > 0 => {
+                (self_inputs.truncate)(&mut self.actions, tracked, untracked)
+            }
+            _ => self.actions.clear(),
+        }
+    }
+
+    /// Applies the follow-up inputs the step scheduled, breadth first in emission order,
+    /// until none are left. See [`self_inputs`](Self::self_inputs).
+    async fn run_self_inputs(
+        &mut self,
+    ) -> Result<(), EngineError<SM::TransitionError, ContainerError<SM>>> {
+        let Some(self_inputs) = &self.self_inputs else {
+            return Ok(());
+        };
+        let (max, take, digest) = (self_inputs.max, self_inputs.take, self_inputs.digest);
+        let mut queue = VecDeque::from(take(&mut self.actions).map_err(EngineError::Actions)?);
+        let mut seen = Vec::new();
+        let mut applied = 0;
+        while let Some(input) = queue.pop_front() {
+            let run = digest(&self.state, &input);
+            if seen.contains(&run) {
+                return Err(EngineError::SelfInputCycle { applied });
+            }
+            if applied == max {
+                return Err(EngineError::SelfInputLimit { applied });
+            }
+            seen.push(run);
+
+            let tracked_before = self.actions.iter_tracked().count();
+            self.transition(Input::Normal(input))
+                .await
+                .map_err(|e| match e {
+                    EngineError::Transition(error) => {
+                        EngineError::SelfInputFailed { applied, error }
+                    }
+                    e => e,
+                })?;
+            applied += 1;
+            self.record_emitted(tracked_before);
+            queue.extend(take(&mut self.actions).map_err(EngineError::Actions)?);
+        }
+        Ok(())
+    }
+
+    /// Resolves in-flight tracked actions with `resolve` and feeds the results back until
+    /// none are left, returning the number of rounds it took.
+    ///
+    /// Each round resolves every action in flight at its start, in emission order. A
+    /// machine that keeps emitting tracked actions in response to results never settles,
+    /// so this fails with [`SettleError::NonConverging`] when the state after a round
+    /// repeats an earlier one (it would loop forever with a deterministic `resolve`) or
+    /// after `max_rounds`. Untracked actions emitted along the way are dropped.
+    ///
+    /// Meant for tests and recovery tooling; after a crash, call [`restore`](Self::restore)
+    /// first so there is something in flight.
+    pub async fn settle<F>(
+        &mut self,
+        max_rounds: usize,
+        mut resolve: F,
+    ) -> Result<usize, SettleError<SM::TransitionError, ContainerError<SM>>>
+    where
+        SM::State: Hash,
+        F: FnMut(&TrackedId<SM>, &TrackedOp<SM>) -> TrackedResult<SM>,
+    {
+        let mut seen = vec![digest(&self.state)];
+        for round in 0..max_rounds {
+            if self.in_flight.is_empty() {
+                return Ok(round);
+            }
+            let batch: Vec<_> = self
+                .in_flight
+                .iter()
+                .map(|f| (f.id.clone(), f.action.clone()))
+                .collect();
+            for (id, action) in batch {
+                let res = resolve(&id, &action);
+                self.step(Input::TrackedActionCompleted { id, res })
+                    .await
+                    .map_err(SettleError::Engine)?;
+            }
+
+            let digest = digest(&self.state);
+            if !self.in_flight.is_empty() && seen.contains(&digest) {
+                return Err(SettleError::NonConverging {
+                    rounds: round + 1,
+                    digest,
+                });
+            }
+            seen.push(digest);
+        }
+
+        if self.in_flight.is_empty() {
+            return Ok(max_rounds);
+        }
+        Err(SettleError::NonConverging {
+            rounds: max_rounds,
+            digest: digest(&self.state),
+        })
+    }
+
+    /// Reports that executing the untracked `action` failed.
+    ///
+    /// Returns the tracked retry from [`StateMachine::promote_failed_untracked`] for the
+    /// caller to dispatch, recorded as in flight like any emitted tracked action. Returns
+    /// `None` if the machine doesn't promote `action`, in which case the failure is
+    /// dropped as usual. State is not touched.
+    pub fn untracked_failed(
+        &mut self,
+        action: &SM::UntrackedAction,
+    ) -> Option<TrackedAction<SM::TrackedAction>> {
+        let retry = SM::promote_failed_untracked(action)?;
+        let entry = InFlight {
+            id: retry.id().clone(),
+            action: retry.action().clone(),
+            txn_id: retry.txn_id(),
+            deadline: retry.deadline(),
+        };
+        record(&mut self.in_flight, entry);
+        if let Some(lifecycle) = &mut self.lifecycle {
+            lifecycle.emitted(retry.id(), self.transitions);
+        }
+        Some(retry)
+    }
+
+    /// Reports that the in-flight tracked action `id` failed all retries.
+    ///
+    /// The action is handed to the [`dead_letters`](Self::dead_letters) sink with
+    /// `last_error`. If [`TrackedActionTypes::exhausted_result`] provides a failure
+    /// result, it is then applied like any other with [`step`](Self::step), so the
+    /// machine learns the operation failed; otherwise the action just stops being in
+    /// flight. Fails with [`EngineError::StaleTrackedResult`] if `id` isn't in flight.
+    pub async fn tracked_exhausted(
+        &mut self,
+        id: &TrackedId<SM>,
+        last_error: impl Into<String>,
+    ) -> Result<(), EngineError<SM::TransitionError, ContainerError<SM>>> {
+        let pos = self
+            .in_flight
+            .iter()
+            .position(|f| &f.id == id)
+            .ok_or(EngineError::StaleTrackedResult)?;
+        let last_error = last_error.into();
+        let res = SM::TrackedAction::exhausted_result(&self.in_flight[pos].action, &last_error);
+
+        let letter = match res {
+            Some(_) => {
+                let f = &self.in_flight[pos];
+                DeadLetter {
+                    id: f.id.clone(),
+                    action: f.action.clone(),
+                    txn_id: f.txn_id,
+                    last_error,
+                }
+            }
+            None => {
+                let f = self.in_flight.remove(pos);
+                DeadLetter {
+                    id: f.id,
+                    action: f.action,
+                    txn_id: f.txn_id,
+                    last_error,
+                }
+            }
+        };
+        if let Some(sink) = &mut self.dead_letters {
+            sink(letter);
+        }
+
+        match res {
+            Some(res) => {
+                self.step(Input::TrackedActionCompleted {
+                    id: id.clone(),
+                    res,
+                })
+                .await
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Snapshot of the tracked actions in flight, to persist atomically with the state.
+    pub fn outbox(&self) -> Outbox<SM::TrackedAction> {
+        let entries = self
+            .in_flight
+            .iter()
+            .map(|f| {
+                TrackedAction::from_parts(f.id.clone(), f.action.clone(), f.txn_id, f.deadline)
+            })
+            .collect();
+        Outbox { entries }
+    }
+
+    /// Like [`restore`](Self::restore), but also dispatches the entries of an [`Outbox`]
+    /// persisted with the state.
+    ///
+    /// Outbox entries whose id `restore` didn't regenerate are appended to
+    /// [`actions`](Self::actions) in outbox order and recorded as in flight, so an action
+    /// emitted just before a crash is recovered even if the machine failed to persist
+    /// enough state for `restore` to find it. Where both produce an id, `restore` wins,
+    /// since it reflects the machine's current view.
+    pub async fn recover(
+        &mut self,
+        outbox: Outbox<SM::TrackedAction>,
+    ) -> Result<(), EngineError<SM::RestoreError, ContainerError<SM>>> {
+        self.restore().await?;
+        let restored = self.actions.iter_tracked().count();
+        for entry in outbox.entries {
+            if self.actions.iter_tracked().any(|t| t.id() == entry.id()) {
+                continue;
+            }
+            self.actions
+                .add(Action::Tracked(entry))
+                .map_err(EngineError::Actions)?;
+        }
+        self.record_emitted(restored);
+        Ok(())
+    }
+
+    /// Runs [`StateMachine::restore`] and records the restored tracked actions as in flight.
+    pub async fn restore(
+        &mut self,
+    ) -> Result<(), EngineError<SM::RestoreError, ContainerError<SM>>> {
+        self.actions.clear().map_err(EngineError::Actions)?;
+        SM::restore(&self.state, &mut self.actions)
+            .await
+            .map_err(EngineError::Restore)?;
+        self.record_emitted(0);
+        Ok(())
+    }
+
+    /// Counts an input against the rate limit, rejecting it if the window is full.
+    fn admit<E>(&mut self, now: Option<u64>) -> Result<(), EngineError<E, ContainerError<SM>>> {
+        let (Some(limit), Some(now)) = (&mut self.rate_limit, now) else {
+            return Ok(());
+        };
+        if limit.count == 0 || now >= limit.window_start.saturating_add(limit.window) {
+            limit.window_start = now;
+            limit.count = 0;
+        }
+        if limit.count >= limit.max_per_window {
+            return Err(EngineError::RateLimited);
+        }
+        limit.count += 1;
+        Ok(())
+    }
+
+    /// Records the tracked actions in the container, skipping the first `from` of them.
+    ///
+    /// Counts tracked actions rather than positions, since containers such as
+    /// [`PriorityActions`](crate::actions::PriorityActions) only keep the order within
+    /// each kind.
+    fn record_emitted(&mut self, from: usize) {
+        for tracked in self.actions.iter_tracked().skip(from) {
+            let entry = InFlight {
+                id: tracked.id().clone(),
+                action: tracked.action().clone(),
+                txn_id: tracked.txn_id(),
+                deadline: tracked.deadline(),
+            };
+            record(&mut self.in_flight, entry);
+            if let Some(lifecycle) = &mut self.lifecycle {
+                lifecycle.emitted(tracked.id(), self.transitions);
+            }
+        }
+    }
+
+    fn compensate(&mut self, txn_id: TxnId) -> Result<(), ContainerError<SM>> {
+        for sibling in self
+            .in_flight
+            .iter_mut()
+            .filter(|f| f.txn_id == Some(txn_id))
+        {
+            let Some(undo) = SM::TrackedAction::compensate(&sibling.id, &sibling.action) else {
+                continue;
+            };
+            self.actions.add(Action::Tracked(TrackedAction::new(
+                sibling.id.clone(),
+                undo.clone(),
+            )))?;
+            // The compensation is what we now wait on, and it isn't part of the txn
+            sibling.action = undo;
+            sibling.txn_id = None;
+            sibling.deadline = None;
+        }
+        Ok(())
+    }
+}
+
+/// Adds `entry` to the in-flight table.
+fn record<SM: StateMachine>(in_flight: &mut Vec<InFlight<SM>>, entry: InFlight<SM>) {
+    // Re-emitting an id replaces the action we're waiting on
+    match in_flight.iter_mut().find(|f| f.id == entry.id) {
+        Some(existing) => *existing = entry,
+        None => in_flight.push(entry),
+    }
+}
+
+/// Takes the follow-up inputs out of `actions`, keeping the other actions in order.
+fn take_self_inputs<SM, UA, I>(actions: &mut SM::Actions) -> Result<Vec<I>, ContainerError<SM>>
+where
+    SM: StateMachine<UntrackedAction = SelfScheduled<UA, I>, Input = I>,
+    TrackedId<SM>: Clone,
+    TrackedOp<SM>: Clone,
+    UA: Clone,
+    I: Clone,
+{
+    if !actions
+        .iter_untracked()
+        .any(|ua| ua.as_self_input().is_some())
+    {
+        return Ok(Vec::new());
+    }
+    let mut inputs = Vec::new();
+    let mut kept = Vec::new();
+    for action in actions.iter() {
+        match action {
+            Action::Untracked(SelfScheduled::SelfInput(input)) => inputs.push(input.clone()),
+            Action::Untracked(SelfScheduled::Untracked(ua)) => {
+                kept.push(Action::Untracked(SelfScheduled::Untracked(ua.clone())))
+            }
+            Action::Tracked(tracked) => kept.push(Action::Tracked(tracked.clone())),
+        }
+    }
+    actions.clear()?;
+    actions.add_all(kept)?;
+    Ok(inputs)
+}
+
+/// Keeps the first `tracked` tracked and `untracked` untracked actions in `actions`.
+fn truncate_actions<SM, UA, I>(
+    actions: &mut SM::Actions,
+    tracked: usize,
+    untracked: usize,
+) -> Result<(), ContainerError<SM>>
+where
+    SM: StateMachine<UntrackedAction = SelfScheduled<UA, I>, Input = I>,
+    TrackedId<SM>: Clone,
+    TrackedOp<SM>: Clone,
+    UA: Clone,
+    I: Clone,
+{
+    let (mut tracked_left, mut untracked_left) = (tracked, untracked);
+    let mut kept = Vec::new();
+    for action in actions.iter() {
+        let left = match action {
+            Action::Tracked(_) => &mut tracked_left,
+            Action::Untracked(_) => &mut untracked_left,
+        };
+        if *left > 0 {
+            *left -= 1;
+            kept.push(match action {
+                Action::Tracked(tracked) => Action::Tracked(tracked.clone()),
+                Action::Untracked(ua) => Action::Untracked(ua.clone()),
+            });
+        }
+    }
+    actions.clear()?;
+    actions.add_all(kept)
+}
+
+/// A digest of `state`, stable for the lifetime of the binary.
+fn digest<T: Hash>(state: &T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    state.hash(&mut hasher);
+    hasher.finish()
+}
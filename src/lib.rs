@@ -42,8 +42,35 @@
 //! ```
 
 pub mod actions;
+#[cfg(feature = "bench")]
+pub mod bench;
+mod boxed;
+pub mod collections;
+pub mod diff;
+pub mod driver;
+pub mod engine;
+#[cfg(feature = "postcard")]
+pub mod input_log;
+pub mod money;
+pub mod rng;
+#[cfg(feature = "sim")]
+pub mod sim;
+#[cfg(feature = "testing")]
+pub mod testing;
+pub mod time;
+pub mod util;
+#[cfg(feature = "serde")]
+pub mod wal;
 
-use crate::actions::{ActionsContainer, TrackedActionTypes};
+use crate::actions::{ActionsContainer, TrackedAction, TrackedActionTypes};
+pub use crate::boxed::BoxFuture;
+#[cfg(feature = "derive")]
+pub use phasm_derive::state_machine;
+
+type ContainerError<SM> = <<SM as StateMachine>::Actions as ActionsContainer<
+    <SM as StateMachine>::UntrackedAction,
+    <SM as StateMachine>::TrackedAction,
+>>::Error;
 
 /// Input to a state machine's STF.
 ///
@@ -51,6 +78,8 @@ use crate::actions::{ActionsContainer, TrackedActionTypes};
 ///
 /// - [`Input::Normal`]: Regular input from users or external systems
 /// - [`Input::TrackedActionCompleted`]: Result of a tracked action that was previously emitted
+/// - [`Input::TrackedActionExpired`]: A tracked action that didn't resolve by its
+///   [deadline](crate::actions::TrackedAction::deadline)
 ///
 /// # Important
 ///
@@ -63,17 +92,100 @@ use crate::actions::{ActionsContainer, TrackedActionTypes};
 ///     let now = SystemTime::now(); // Non-deterministic!
 /// }
 ///
-/// // ✅ CORRECT
-/// fn stf(state: &mut State, input: Input<_, (UserRequest, SystemTime)>) {
+/// // ✅ CORRECT: the caller reads the clock, see `time::Timed`
+/// fn stf(state: &mut State, input: Input<_, Timed<UserRequest>>) {
 ///     let (request, timestamp) = match input {
-///         Input::Normal((req, ts)) => (req, ts),
+///         Input::Normal(Timed { at, inner }) => (inner, at),
 ///         ...
 ///     };
 /// }
 /// ```
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "T: serde::Serialize, TA::Id: serde::Serialize, TA::Result: serde::Serialize",
+        deserialize = "T: serde::Deserialize<'de>, TA::Id: serde::Deserialize<'de>, TA::Result: serde::Deserialize<'de>"
+    ))
+)]
 pub enum Input<TA: TrackedActionTypes, T> {
     Normal(T),
-    TrackedActionCompleted { id: TA::Id, res: TA::Result },
+    TrackedActionCompleted {
+        id: TA::Id,
+        res: TA::Result,
+    },
+    /// The tracked action `id` passed its deadline without a result. Its result will
+    /// never be applied, so STF should fail or retry the operation.
+    TrackedActionExpired {
+        id: TA::Id,
+    },
+}
+
+// Manual impls: a derive would require `TA: Clone` or `TA: Debug`, but only the id and
+// result are stored.
+impl<TA: TrackedActionTypes, T: Clone> Clone for Input<TA, T>
+where
+    TA::Id: Clone,
+    TA::Result: Clone,
+{
+    fn clone(&self) -> Self {
+        match self {
+            Input::Normal(input) => Input::Normal(input.clone()),
+            Input::TrackedActionCompleted { id, res } => Input::TrackedActionCompleted {
+                id: id.clone(),
+                res: res.clone(),
+            },
+            Input::TrackedActionExpired { id } => Input::TrackedActionExpired { id: id.clone() },
+        }
+    }
+}
+
+impl<TA: TrackedActionTypes, T: std::fmt::Debug> std::fmt::Debug for Input<TA, T>
+where
+    TA::Id: std::fmt::Debug,
+    TA::Result: std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Input::Normal(input) => f.debug_tuple("Normal").field(input).finish(),
+            Input::TrackedActionCompleted { id, res } => f
+                .debug_struct("TrackedActionCompleted")
+                .field("id", id)
+                .field("res", res)
+                .finish(),
+            Input::TrackedActionExpired { id } => f
+                .debug_struct("TrackedActionExpired")
+                .field("id", id)
+                .finish(),
+        }
+    }
+}
+
+/// The result of a tracked action, stored apart from other inputs.
+///
+/// To replay completions after a crash, persist them as `{id, res}` records when they
+/// come back from external systems, and turn each back into
+/// [`Input::TrackedActionCompleted`] with `Input::from`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(
+        serialize = "TA::Id: serde::Serialize, TA::Result: serde::Serialize",
+        deserialize = "TA::Id: serde::Deserialize<'de>, TA::Result: serde::Deserialize<'de>"
+    ))
+)]
+pub struct Completion<TA: TrackedActionTypes> {
+    pub id: TA::Id,
+    pub res: TA::Result,
+}
+
+impl<TA: TrackedActionTypes, T> From<Completion<TA>> for Input<TA, T> {
+    fn from(completion: Completion<TA>) -> Self {
+        Input::TrackedActionCompleted {
+            id: completion.id,
+            res: completion.res,
+        }
+    }
 }
 
 /// A trait for describing a fallible, asynchronous state machine.
@@ -114,16 +226,18 @@ pub enum Input<TA: TrackedActionTypes, T> {
 ///
 /// ## 3. State Always Valid
 ///
-/// After every STF (success or failure), invariants must hold:
+/// After every STF (success or failure), invariants must hold. Express them with
+/// [`StateMachine::check_invariants`]:
 ///
 /// ```ignore
-/// impl MyState {
-///     fn check_invariants(&self) -> Result<(), String> {
-///         // Verify no overlaps, consistency, etc.
-///     }
+/// fn check_invariants(state: &Self::State) -> Result<(), String> {
+///     // Verify no overlaps, consistency, etc.
 /// }
 /// ```
 ///
+/// With the `debug-invariants` feature, [`atomic_stf`] and [`driver::Driver`] check them
+/// after every transition.
+///
 /// ## 4. Tracked Actions Must Be Stored in State
 ///
 /// Before emitting a tracked action, store enough data in state to recreate it:
@@ -282,6 +396,13 @@ pub trait StateMachine {
     ///             };
     ///             Ok(())
     ///         }
+    ///         Input::TrackedActionExpired { id } => {
+    ///             // No result will come, so give up on the request
+    ///             let pending = state.pending.get_mut(&id)
+    ///                 .ok_or(MyError::UnknownRequest)?;
+    ///             pending.status = Status::Failed;
+    ///             Ok(())
+    ///         }
     ///     }
     /// }
     /// ```
@@ -314,8 +435,12 @@ pub trait StateMachine {
     ///
     /// 1. **Only use state**: Cannot open new database connections or query external APIs.
     ///    Reading from a database through `state` is fine - it's opening new connections that's forbidden.
-    /// 2. **Must be deterministic**: Same state always produces same actions
-    /// 3. **Clear before use**: The actions container should be cleared before adding
+    /// 2. **Must be deterministic**: Same state always produces same actions, in the same
+    ///    order. Iterate state kept in an [`OrderedMap`](crate::collections::OrderedMap),
+    ///    never a `HashMap`
+    /// 3. **Clear before use**: The actions container should be cleared before adding.
+    ///    Callers that don't reuse a container can use
+    ///    [`restore_owned`](Self::restore_owned) instead
     ///
     /// # Example
     ///
@@ -374,4 +499,618 @@ pub trait StateMachine {
         state: &'state Self::State,
         actions: &'actions mut Self::Actions,
     ) -> Self::RestoreFuture<'state, 'actions>;
+
+    /// [`restore`](Self::restore) into a fresh actions container, returned by value.
+    ///
+    /// Convenient where there is no container to reuse, such as at startup. Use
+    /// `restore` to reuse an allocation.
+    fn restore_owned(
+        state: &Self::State,
+    ) -> impl Future<
+        Output = Result<Self::Actions, RestoreOwnedError<Self::RestoreError, ContainerError<Self>>>,
+    > {
+        async move {
+            let mut actions = Self::Actions::new().map_err(RestoreOwnedError::Actions)?;
+            Self::restore(state, &mut actions)
+                .await
+                .map_err(RestoreOwnedError::Restore)?;
+            Ok(actions)
+        }
+    }
+
+    /// The time carried by `input`, if any, in whatever unit the input uses.
+    ///
+    /// Used by the [`Engine`](engine::Engine) for time-based accounting such as
+    /// [`rate_limit`](engine::Engine::rate_limit), which must read time from input
+    /// rather than the wall clock to stay deterministic. Inputs returning `None`
+    /// are not subject to time-based limits.
+    fn input_time(_input: &Self::Input) -> Option<u64> {
+        None
+    }
+
+    /// The idempotency key of `input`, if any, e.g. a client-supplied request token.
+    ///
+    /// With [`idempotency_keys`](engine::Engine::idempotency_keys) enabled, the
+    /// [`Engine`](engine::Engine) applies only the first input with a given key and
+    /// replays its result for duplicates without calling STF.
+    fn input_key(_input: &Self::Input) -> Option<u64> {
+        None
+    }
+
+    /// A tracked retry for an untracked action whose execution failed, if it needs one.
+    ///
+    /// Untracked actions are fire-and-forget: normally a failure is dropped. Returning
+    /// `Some` for critical ones (e.g. a billing ledger write) makes
+    /// [`Engine::untracked_failed`](engine::Engine::untracked_failed) hand back a tracked
+    /// action to dispatch instead, whose result the machine then receives in STF.
+    ///
+    /// This deliberately blurs the line between the two kinds: the machine gets a result
+    /// for a tracked id it never emitted and isn't persisted in state, so it won't be
+    /// regenerated by `restore`. Derive the id from the untracked action so it can't
+    /// collide with ids from state, and handle its result like any other. If an action
+    /// always needs a result, emit it tracked in the first place.
+    fn promote_failed_untracked(
+        _action: &Self::UntrackedAction,
+    ) -> Option<TrackedAction<Self::TrackedAction>> {
+        None
+    }
+
+    /// Whether `error` means the input conflicted with the current state (e.g. a stale
+    /// version or a slot taken by someone else) rather than being invalid.
+    ///
+    /// Used by [`Engine::step_outcome`](engine::Engine::step_outcome) to tell
+    /// [`RejectedConflict`](engine::TransitionOutcome::RejectedConflict) from
+    /// [`RejectedValidation`](engine::TransitionOutcome::RejectedValidation). Conflicts
+    /// may succeed if the client refreshes and retries; validation errors won't. `false`
+    /// by default.
+    fn is_conflict(_error: &Self::TransitionError) -> bool {
+        false
+    }
+
+    /// Whether `input` only reads state, such as an availability query.
+    ///
+    /// A read-only input must leave state unchanged and emit no tracked actions, so in a
+    /// replicated deployment a router can serve it from a replica instead of the leader.
+    /// `false` by default, which routes every input as a write.
+    fn is_read_only(_input: &Self::Input) -> bool {
+        false
+    }
+
+    /// Checks that `state` is valid, describing the first violation found.
+    ///
+    /// Must hold after every transition, successful or not. Simulation tests call it
+    /// after every input, and with the `debug-invariants` feature [`atomic_stf`] and
+    /// [`Driver`](driver::Driver) call it after every transition and panic on a
+    /// violation. Always `Ok` by default.
+    fn check_invariants(_state: &Self::State) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Health of `state`, for readiness probes and other ops tooling.
+    ///
+    /// Runs [`check_invariants`](Self::check_invariants) by default. Override it to add
+    /// softer checks, such as drift between records that should agree, as extra issues
+    /// without failing the invariants. Like
+    /// [`approx_state_size`](Self::approx_state_size), it's never called on the
+    /// transition path, so it may take linear time.
+    fn health(state: &Self::State) -> HealthReport {
+        HealthReport::from_invariants(Self::check_invariants(state))
+    }
+
+    /// Whether `id` belongs to a tracked action the machine emitted and still tracks in
+    /// state.
+    ///
+    /// Stale or duplicate deliveries feed results for ids the machine no longer knows, and
+    /// handlers tend to treat those inconsistently. With
+    /// [`reject_unknown_results`](engine::Engine::reject_unknown_results) enabled, the
+    /// [`Engine`](engine::Engine) rejects such results before STF runs. Ids handed out by
+    /// [`promote_failed_untracked`](Self::promote_failed_untracked) aren't in state, so
+    /// account for them here if the machine uses both.
+    fn is_known_tracked_id(
+        _state: &Self::State,
+        _id: &<Self::TrackedAction as TrackedActionTypes>::Id,
+    ) -> bool {
+        true
+    }
+
+    /// Approximate size of `state`, for monitoring long-lived machines for leaks.
+    ///
+    /// Report the entry count of each collection that grows with use (pending requests,
+    /// caches, ...). Only called by tooling such as
+    /// [`Engine::observe_state_size`](engine::Engine::observe_state_size), never by the
+    /// engine's transition path, so counting may take linear time. Empty by default.
+    fn approx_state_size(_state: &Self::State) -> StateSize {
+        StateSize::default()
+    }
+
+    /// Names of the inputs [`parse_input`](Self::parse_input) can construct.
+    ///
+    /// Lets generic tooling (e.g. an admin CLI) list what it can submit to a machine
+    /// without knowing its input type. Empty unless the machine opts in.
+    fn input_variants() -> &'static [&'static str] {
+        &[]
+    }
+
+    /// Constructs the input named `name` from JSON `args`.
+    ///
+    /// `name` is one of [`input_variants`](Self::input_variants). Inputs built here go
+    /// through STF like any other, so this only needs to check the arguments are well
+    /// formed, not that the input will succeed.
+    #[cfg(feature = "serde")]
+    fn parse_input(name: &str, _args: &serde_json::Value) -> Result<Self::Input, InputParseError> {
+        Err(InputParseError::UnknownVariant(name.to_string()))
+    }
 }
+
+/// [`StateMachine`] with `stf` and `restore` written as `async fn`s.
+///
+/// Every `AsyncStateMachine` that is `'static` is a [`StateMachine`], with the futures
+/// boxed as [`BoxFuture`]s, so implement this instead when naming the future types
+/// isn't worth it:
+///
+/// ```ignore
+/// impl AsyncStateMachine for Counter {
+///     // The same associated types as `StateMachine`, minus the futures
+///
+///     async fn stf(
+///         state: &mut Self::State,
+///         input: Input<Self::TrackedAction, Self::Input>,
+///         actions: &mut Self::Actions,
+///     ) -> Result<(), Self::TransitionError> {
+///         state.count = state.count.checked_add(1).ok_or(CounterError::Overflow)?;
+///         Ok(())
+///     }
+///
+///     async fn restore(
+///         _state: &Self::State,
+///         _actions: &mut Self::Actions,
+///     ) -> Result<(), Self::RestoreError> {
+///         Ok(())
+///     }
+/// }
+/// ```
+///
+/// The hooks mirror [`StateMachine`]'s and are forwarded to it. Boxing costs an
+/// allocation per call; implement [`StateMachine`] directly where that matters, or to
+/// poll by hand.
+pub trait AsyncStateMachine {
+    /// See [`StateMachine::TrackedAction`].
+    type TrackedAction: TrackedActionTypes;
+    /// See [`StateMachine::UntrackedAction`].
+    type UntrackedAction;
+    /// See [`StateMachine::Actions`].
+    type Actions: ActionsContainer<Self::UntrackedAction, Self::TrackedAction>;
+    /// See [`StateMachine::State`].
+    type State;
+    /// See [`StateMachine::Input`].
+    type Input;
+    /// See [`StateMachine::TransitionError`].
+    type TransitionError;
+    /// See [`StateMachine::RestoreError`].
+    type RestoreError;
+
+    /// See [`StateMachine::stf`].
+    fn stf(
+        state: &mut Self::State,
+        input: Input<Self::TrackedAction, Self::Input>,
+        actions: &mut Self::Actions,
+    ) -> impl Future<Output = Result<(), Self::TransitionError>>;
+
+    /// See [`StateMachine::restore`].
+    fn restore(
+        state: &Self::State,
+        actions: &mut Self::Actions,
+    ) -> impl Future<Output = Result<(), Self::RestoreError>>;
+
+    /// See [`StateMachine::input_time`].
+    fn input_time(_input: &Self::Input) -> Option<u64> {
+        None
+    }
+
+    /// See [`StateMachine::input_key`].
+    fn input_key(_input: &Self::Input) -> Option<u64> {
+        None
+    }
+
+    /// See [`StateMachine::promote_failed_untracked`].
+    fn promote_failed_untracked(
+        _action: &Self::UntrackedAction,
+    ) -> Option<TrackedAction<Self::TrackedAction>> {
+        None
+    }
+
+    /// See [`StateMachine::is_conflict`].
+    fn is_conflict(_error: &Self::TransitionError) -> bool {
+        false
+    }
+
+    /// See [`StateMachine::is_read_only`].
+    fn is_read_only(_input: &Self::Input) -> bool {
+        false
+    }
+
+    /// See [`StateMachine::check_invariants`].
+    fn check_invariants(_state: &Self::State) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// See [`StateMachine::health`].
+    fn health(state: &Self::State) -> HealthReport {
+        HealthReport::from_invariants(Self::check_invariants(state))
+    }
+
+    /// See [`StateMachine::is_known_tracked_id`].
+    fn is_known_tracked_id(
+        _state: &Self::State,
+        _id: &<Self::TrackedAction as TrackedActionTypes>::Id,
+    ) -> bool {
+        true
+    }
+
+    /// See [`StateMachine::approx_state_size`].
+    fn approx_state_size(_state: &Self::State) -> StateSize {
+        StateSize::default()
+    }
+
+    /// See [`StateMachine::input_variants`].
+    fn input_variants() -> &'static [&'static str] {
+        &[]
+    }
+
+    /// See [`StateMachine::parse_input`].
+    #[cfg(feature = "serde")]
+    fn parse_input(name: &str, _args: &serde_json::Value) -> Result<Self::Input, InputParseError> {
+        Err(InputParseError::UnknownVariant(name.to_string()))
+    }
+}
+
+/// `'static` rules out borrows through anything but `state` and `actions`: an impl
+/// parameter used in an associated type must appear in `SM`, so every associated type
+/// is `'static` too.
+impl<SM: AsyncStateMachine + 'static> StateMachine for SM {
+    type TrackedAction = SM::TrackedAction;
+    type UntrackedAction = SM::UntrackedAction;
+    type Actions = SM::Actions;
+    type State = SM::State;
+    type Input = SM::Input;
+    type TransitionError = SM::TransitionError;
+    type RestoreError = SM::RestoreError;
+
+    type StfFuture<'state, 'actions> = BoxFuture<'state, 'actions, Result<(), SM::TransitionError>>;
+    type RestoreFuture<'state, 'actions> =
+        BoxFuture<'state, 'actions, Result<(), SM::RestoreError>>;
+
+    fn stf<'state, 'actions>(
+        state: &'state mut Self::State,
+        input: Input<Self::TrackedAction, Self::Input>,
+        actions: &'actions mut Self::Actions,
+    ) -> Self::StfFuture<'state, 'actions> {
+        // SAFETY: the future borrows only `state` and `actions`, see above
+        unsafe { BoxFuture::new(SM::stf(state, input, actions)) }
+    }
+
+    fn restore<'state, 'actions>(
+        state: &'state Self::State,
+        actions: &'actions mut Self::Actions,
+    ) -> Self::RestoreFuture<'state, 'actions> {
+        // SAFETY: the future borrows only `state` and `actions`, see above
+        unsafe { BoxFuture::new(SM::restore(state, actions)) }
+    }
+
+    fn input_time(input: &Self::Input) -> Option<u64> {
+        SM::input_time(input)
+    }
+
+    fn input_key(input: &Self::Input) -> Option<u64> {
+        SM::input_key(input)
+    }
+
+    fn promote_failed_untracked(
+        action: &Self::UntrackedAction,
+    ) -> Option<TrackedAction<Self::TrackedAction>> {
+        SM::promote_failed_untracked(action)
+    }
+
+    fn is_conflict(error: &Self::TransitionError) -> bool {
+        SM::is_conflict(error)
+    }
+
+    fn is_read_only(input: &Self::Input) -> bool {
+        SM::is_read_only(input)
+    }
+
+    fn check_invariants(state: &Self::State) -> Result<(), String> {
+        SM::check_invariants(state)
+    }
+
+    fn health(state: &Self::State) -> HealthReport {
+        SM::health(state)
+    }
+
+    fn is_known_tracked_id(
+        state: &Self::State,
+        id: &<Self::TrackedAction as TrackedActionTypes>::Id,
+    ) -> bool {
+        SM::is_known_tracked_id(state, id)
+    }
+
+    fn approx_state_size(state: &Self::State) -> StateSize {
+        SM::approx_state_size(state)
+    }
+
+    fn input_variants() -> &'static [&'static str] {
+        SM::input_variants()
+    }
+
+    #[cfg(feature = "serde")]
+    fn parse_input(name: &str, args: &serde_json::Value) -> Result<Self::Input, InputParseError> {
+        SM::parse_input(name, args)
+    }
+}
+
+/// Runs [`SM::stf`](StateMachine::stf), restoring `state` and clearing `actions` if it
+/// fails.
+///
+/// Enforces STF atomicity for machines whose state is an in-memory `Clone` value: the
+/// state is cloned before the transition, and on `Err` the clone is put back, so a
+/// transition that mutates before it validates can't leave partial changes behind. The
+/// clone costs a full copy of the state per transition, so prefer fixing the STF where
+/// that matters; this is a safety net, not a substitute.
+///
+/// # Panics
+///
+/// If the actions container can't be cleared after a failed transition, or, with the
+/// `debug-invariants` feature, if [`StateMachine::check_invariants`] fails afterwards.
+pub async fn atomic_stf<SM: StateMachine>(
+    state: &mut SM::State,
+    input: Input<SM::TrackedAction, SM::Input>,
+    actions: &mut SM::Actions,
+) -> Result<(), SM::TransitionError>
+where
+    SM::State: Clone,
+{
+    let snapshot = state.clone();
+    let res = SM::stf(state, input, actions).await;
+    if res.is_err() {
+        *state = snapshot;
+        // Actions of a failed transition are discarded
+        if actions.clear().is_err() {
+            panic!("failed to clear actions container");
+        }
+    }
+    #[cfg(feature = "debug-invariants")]
+    assert_invariants::<SM>(state);
+    res
+}
+
+/// Applies `inputs` in order as one batch: either all of them succeed, or `state` is
+/// restored to what it was before the first.
+///
+/// Actions of every transition are added to `actions` in order. If the input at index
+/// `i` fails, the batch is rolled back, `actions` is cleared and `(i, error)` is
+/// returned; the remaining inputs aren't applied, nor pulled from `inputs`, which may be
+/// any iterator. Like [`atomic_stf`], this clones the state once up front.
+///
+/// # Panics
+///
+/// If the actions container can't be cleared after a failed batch, or, with the
+/// `debug-invariants` feature, if [`StateMachine::check_invariants`] fails after a
+/// transition.
+pub async fn apply_all_atomic<SM: StateMachine>(
+    state: &mut SM::State,
+    inputs: impl IntoIterator<Item = Input<SM::TrackedAction, SM::Input>>,
+    actions: &mut SM::Actions,
+) -> Result<(), (usize, SM::TransitionError)>
+where
+    SM::State: Clone,
+{
+    let snapshot = state.clone();
+    for (i, input) in inputs.into_iter().enumerate() {
+        let res = SM::stf(state, input, actions).await;
+        #[cfg(feature = "debug-invariants")]
+        assert_invariants::<SM>(state);
+        if let Err(e) = res {
+            *state = snapshot;
+            if actions.clear().is_err() {
+                panic!("failed to clear actions container");
+            }
+            return Err((i, e));
+        }
+    }
+    Ok(())
+}
+
+/// Applies `inputs` in order until one fails, for feeding a batch of events through STF.
+///
+/// Unlike [`apply_all_atomic`], inputs applied before a failure stay applied. Returns
+/// how many inputs were applied, or `(i, error)` if the input at index `i` failed, in
+/// which case `i` inputs were applied and the rest aren't pulled from `inputs`. Actions
+/// accumulate in `actions` in order; on failure they end with whatever the failed
+/// transition added before returning its error, which STF is allowed to do.
+///
+/// # Panics
+///
+/// With the `debug-invariants` feature, if [`StateMachine::check_invariants`] fails
+/// after a transition.
+pub async fn process_all<SM: StateMachine>(
+    state: &mut SM::State,
+    inputs: impl IntoIterator<Item = Input<SM::TrackedAction, SM::Input>>,
+    actions: &mut SM::Actions,
+) -> Result<usize, (usize, SM::TransitionError)> {
+    let mut applied = 0;
+    for input in inputs {
+        let res = SM::stf(state, input, actions).await;
+        #[cfg(feature = "debug-invariants")]
+        assert_invariants::<SM>(state);
+        res.map_err(|e| (applied, e))?;
+        applied += 1;
+    }
+    Ok(applied)
+}
+
+/// Panics if `state` violates [`StateMachine::check_invariants`].
+#[cfg(feature = "debug-invariants")]
+pub(crate) fn assert_invariants<SM: StateMachine>(state: &SM::State) {
+    if let Err(e) = SM::check_invariants(state) {
+        panic!("state invariant violated after transition: {}", e);
+    }
+}
+
+/// State that carries a version bumped by every successful transition.
+///
+/// Clients doing optimistic concurrency echo the version they last saw with their next
+/// input, and the STF rejects the input before mutating anything if the state has moved
+/// on since. Failed transitions must leave the version unchanged, like the rest of state.
+pub trait Versioned {
+    fn version(&self) -> u64;
+}
+
+/// Entry counts of a state's collections, see [`StateMachine::approx_state_size`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateSize {
+    /// Total entries across all collections.
+    pub entries: usize,
+    /// Entries per named collection, in the order reported.
+    pub collections: Vec<(&'static str, usize)>,
+}
+
+impl StateSize {
+    pub fn new(collections: impl IntoIterator<Item = (&'static str, usize)>) -> Self {
+        let collections: Vec<_> = collections.into_iter().collect();
+        Self {
+            entries: collections.iter().map(|(_, n)| n).sum(),
+            collections,
+        }
+    }
+
+    /// Entries in the collection called `name`, if reported.
+    pub fn get(&self, name: &str) -> Option<usize> {
+        self.collections
+            .iter()
+            .find(|(collection, _)| *collection == name)
+            .map(|(_, n)| *n)
+    }
+}
+
+impl std::fmt::Display for StateSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} entries", self.entries)?;
+        for (i, (name, n)) in self.collections.iter().enumerate() {
+            let sep = if i == 0 { " (" } else { ", " };
+            write!(f, "{}{}: {}", sep, name, n)?;
+        }
+        if !self.collections.is_empty() {
+            write!(f, ")")?;
+        }
+        Ok(())
+    }
+}
+
+/// Result of [`StateMachine::health`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthReport {
+    /// Whether [`StateMachine::check_invariants`] passed.
+    pub invariants_ok: bool,
+    /// Everything wrong with the state: the invariant violation first, if any, then any
+    /// other issues the machine reports.
+    pub issues: Vec<String>,
+}
+
+impl HealthReport {
+    pub fn from_invariants(invariants: Result<(), String>) -> Self {
+        match invariants {
+            Ok(()) => Self {
+                invariants_ok: true,
+                issues: Vec::new(),
+            },
+            Err(e) => Self {
+                invariants_ok: false,
+                issues: vec![e],
+            },
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// State that can be checkpointed to bytes and loaded back.
+///
+/// A checkpoint plus the inputs applied after it must reproduce the state exactly, so
+/// `decode(encode(state))` has to equal `state` - any field `encode` drops silently
+/// diverges on recovery. `verify_checkpoint` in the `testing` module checks this
+/// against a replay from genesis.
+pub trait Snapshot: Sized {
+    type Error;
+
+    fn encode(&self) -> Vec<u8>;
+    fn decode(bytes: &[u8]) -> Result<Self, Self::Error>;
+}
+
+/// An error from [`StateMachine::restore_owned`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum RestoreOwnedError<R, C> {
+    /// [`StateMachine::restore`] failed.
+    Restore(R),
+    /// The actions container couldn't be created.
+    Actions(C),
+}
+
+/// An error from [`load_and_restore`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum LoadError<D, R, C> {
+    /// [`Snapshot::decode`] failed.
+    Decode(D),
+    /// [`StateMachine::restore`] failed on the decoded state.
+    Restore(R),
+    /// The actions container couldn't be created.
+    Actions(C),
+}
+
+/// Recovers after a crash: decodes the persisted state and runs
+/// [`restore`](StateMachine::restore) on it.
+///
+/// Returns the state and the actions to execute to resume what was in flight, e.g.
+/// with a [`Driver`](driver::Driver) built from the state.
+pub async fn load_and_restore<SM: StateMachine>(
+    bytes: &[u8],
+) -> Result<
+    (SM::State, SM::Actions),
+    LoadError<<SM::State as Snapshot>::Error, SM::RestoreError, ContainerError<SM>>,
+>
+where
+    SM::State: Snapshot,
+{
+    let state = SM::State::decode(bytes).map_err(LoadError::Decode)?;
+    let actions = SM::restore_owned(&state).await.map_err(|e| match e {
+        RestoreOwnedError::Restore(e) => LoadError::Restore(e),
+        RestoreOwnedError::Actions(e) => LoadError::Actions(e),
+    })?;
+    Ok((state, actions))
+}
+
+/// Error from [`StateMachine::parse_input`].
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InputParseError {
+    /// The machine has no input with this name.
+    UnknownVariant(String),
+    /// The arguments don't form a valid input of this variant.
+    InvalidArgs { variant: String, reason: String },
+}
+
+#[cfg(feature = "serde")]
+impl std::fmt::Display for InputParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            InputParseError::UnknownVariant(name) => write!(f, "unknown input {:?}", name),
+            InputParseError::InvalidArgs { variant, reason } => {
+                write!(f, "invalid arguments for {}: {}", variant, reason)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl std::error::Error for InputParseError {}
@@ -0,0 +1,47 @@
+//! Integer money amounts.
+//!
+//! Float arithmetic can round differently across platforms and compilers, which breaks
+//! the determinism STF relies on, and casting a float amount to cents truncates. Keep
+//! amounts in state and inputs as [`Cents`], and convert floats once at the boundary
+//! with [`Cents::from_dollars_f32`].
+
+use std::fmt;
+
+use crate::util::f32_to_cents;
+
+/// A non-negative amount in cents.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Cents(pub u64);
+
+impl Cents {
+    pub const ZERO: Self = Self(0);
+
+    /// Rounds a dollar amount to the nearest cent, see [`f32_to_cents`].
+    ///
+    /// `None` for NaN, negative and out of range amounts.
+    pub fn from_dollars_f32(dollars: f32) -> Option<Self> {
+        f32_to_cents(dollars).map(|cents| Self(cents.into()))
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    /// `None` if `other` is larger.
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    /// `self - other`, or zero if `other` is larger.
+    pub fn saturating_sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+}
+
+impl fmt::Display for Cents {
+    /// Formats as `$X.YY`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "${}.{:02}", self.0 / 100, self.0 % 100)
+    }
+}
@@ -0,0 +1,55 @@
+//! A minimal deterministic PRNG for generating simulation inputs.
+//!
+//! Uses only `core`, so it is available without the `testing` feature and without the
+//! `rand` stack, e.g. for input generation on embedded targets. It is not
+//! cryptographically secure. [`testing`](crate::testing) re-exports it alongside the
+//! `ChaCha8Rng`-based helpers, for simulations that need more than this surface.
+
+use core::ops::Range;
+
+/// The SplitMix64 generator: 64 bits of state, one add and three xor-shift-multiplies
+/// per output.
+///
+/// The same seed always produces the same sequence, on every platform.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A uniformly distributed value in `range`, without modulo bias.
+    ///
+    /// # Panics
+    ///
+    /// If `range` is empty.
+    pub fn gen_range(&mut self, range: Range<u64>) -> u64 {
+        assert!(range.start < range.end, "cannot sample empty range");
+        let span = range.end - range.start;
+        // Lemire's multiply-shift, rejecting the low products that would bias the result
+        let threshold = span.wrapping_neg() % span;
+        loop {
+            let m = self.next_u64() as u128 * span as u128;
+            if m as u64 >= threshold {
+                return range.start + (m >> 64) as u64;
+            }
+        }
+    }
+
+    /// `true` with probability `p`.
+    pub fn gen_bool(&mut self, p: f64) -> bool {
+        // The top 53 bits give a uniform float in [0, 1)
+        ((self.next_u64() >> 11) as f64 / (1u64 << 53) as f64) < p
+    }
+}
@@ -0,0 +1,289 @@
+//! A seeded simulation runner for state machines.
+//!
+//! [`Simulator`] applies generated inputs to a state machine and checks
+//! [`StateMachine::check_invariants`] after every transition. Inputs come from a
+//! generator seeded from a single `u64`, so a failing run is reproduced exactly by
+//! running the same seed again, and [`Simulator::shrink`] reduces it to a short
+//! reproducer. Enabled with the `sim` feature.
+//!
+//! ```ignore
+//! let sim = Simulator::<Shop, _>::new(|rng, shop| match shop.pending_order() {
+//!     Some(id) if rng.gen_bool(0.5) => Input::TrackedActionCompleted { id, res: true },
+//!     _ => Input::Normal(rng.gen_range(1..100)),
+//! })
+//! .steps(10_000);
+//! for seed in 0..16 {
+//!     sim.run(seed, &mut Shop::default()).await.assert_ok();
+//! }
+//! ```
+
+use std::{
+    fmt::{self, Debug},
+    marker::PhantomData,
+};
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::{Input, StateMachine, actions::ActionsContainer};
+
+type SmInput<SM> = Input<<SM as StateMachine>::TrackedAction, <SM as StateMachine>::Input>;
+
+/// Transitions applied by [`Simulator::run`] when [`Simulator::steps`] isn't called.
+pub const DEFAULT_STEPS: usize = 1000;
+
+/// Upper bound on the replays [`Simulator::shrink`] makes, so it always terminates
+/// promptly even for long failing runs.
+pub const MAX_SHRINK_REPLAYS: usize = 10_000;
+
+/// Runs seeded random inputs through a state machine, checking invariants after each.
+pub struct Simulator<SM: StateMachine, G> {
+    generate: G,
+    steps: usize,
+    _sm: PhantomData<fn() -> SM>,
+}
+
+impl<SM, G> Simulator<SM, G>
+where
+    SM: StateMachine,
+    G: Fn(&mut ChaCha8Rng, &SM::State) -> Input<SM::TrackedAction, SM::Input>,
+{
+    /// A simulator that draws each input from `generate`, given the seeded RNG and the
+    /// current state.
+    ///
+    /// The generator sees the state so it can complete tracked actions the machine is
+    /// waiting on. It must be deterministic given its arguments.
+    pub fn new(generate: G) -> Self {
+        Self {
+            generate,
+            steps: DEFAULT_STEPS,
+            _sm: PhantomData,
+        }
+    }
+
+    /// Sets the number of transitions per run.
+    pub fn steps(mut self, steps: usize) -> Self {
+        self.steps = steps;
+        self
+    }
+
+    /// Applies the inputs generated from `seed` to `state`.
+    ///
+    /// Transition errors are counted, not fatal: a generator is expected to produce
+    /// inputs the machine rejects. The run stops at the first invariant violation, which
+    /// is recorded in the report along with the seed and step.
+    ///
+    /// # Panics
+    ///
+    /// If the actions container can't be created or cleared.
+    pub async fn run(&self, seed: u64, state: &mut SM::State) -> SimReport
+    where
+        <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+    {
+        self.run_recording(seed, state, |_| {}).await
+    }
+
+    /// [`run`](Self::run), passing every generated input to `record` before applying it.
+    async fn run_recording(
+        &self,
+        seed: u64,
+        state: &mut SM::State,
+        mut record: impl FnMut(&SmInput<SM>),
+    ) -> SimReport
+    where
+        <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+    {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let mut actions = SM::Actions::new().expect("failed to create actions container");
+        let mut report = SimReport {
+            seed,
+            steps: 0,
+            applied: 0,
+            rejected: 0,
+            actions: 0,
+            failure: None,
+        };
+
+        for step in 0..self.steps {
+            let input = (self.generate)(&mut rng, state);
+            record(&input);
+            actions.clear().expect("failed to clear actions container");
+            let res = SM::stf(state, input, &mut actions).await;
+            report.steps += 1;
+            match res {
+                Ok(()) => {
+                    report.applied += 1;
+                    report.actions += actions.len();
+                }
+                Err(_) => report.rejected += 1,
+            }
+
+            if let Err(error) = SM::check_invariants(state) {
+                report.failure = Some(SimFailure { step, error });
+                break;
+            }
+        }
+        report
+    }
+}
+
+impl<SM, G> Simulator<SM, G>
+where
+    SM: StateMachine,
+    SM::State: Clone,
+    SmInput<SM>: Clone,
+    G: Fn(&mut ChaCha8Rng, &SM::State) -> Input<SM::TrackedAction, SM::Input>,
+    <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+{
+    /// Reduces the failing run in `report` to a short input sequence that still violates
+    /// an invariant.
+    ///
+    /// `initial` must be the state the run started from. The inputs are regenerated from
+    /// the report's seed, then removed greedily, in halving chunks down to single inputs,
+    /// keeping every removal after which replaying from `initial` still violates some
+    /// invariant (not necessarily with the same message). Inputs after the violation are
+    /// dropped as well. The result is deterministic, and unless
+    /// [`MAX_SHRINK_REPLAYS`] is hit no single input can be removed from it.
+    ///
+    /// Returns `None` if the report has no failure, or if regenerating the run doesn't
+    /// fail the same way, i.e. the generator isn't deterministic.
+    pub async fn shrink(&self, report: &SimReport, initial: &SM::State) -> Option<Shrunk<SM>> {
+        let failure = report.failure.as_ref()?;
+        let mut inputs = Vec::new();
+        let rerun = self
+            .run_recording(report.seed, &mut initial.clone(), |input| {
+                inputs.push(input.clone())
+            })
+            .await;
+        if rerun.failure.as_ref() != Some(failure) {
+            return None;
+        }
+
+        let mut actions = SM::Actions::new().expect("failed to create actions container");
+        let mut error = failure.error.clone();
+        let mut replays = 0;
+        let mut chunk = (inputs.len() / 2).max(1);
+        loop {
+            let mut start = 0;
+            while start < inputs.len() && replays < MAX_SHRINK_REPLAYS {
+                let end = (start + chunk).min(inputs.len());
+                let candidate: Vec<_> = inputs[..start]
+                    .iter()
+                    .chain(&inputs[end..])
+                    .cloned()
+                    .collect();
+                replays += 1;
+                match replay::<SM>(initial, &candidate, &mut actions).await {
+                    Some((step, e)) => {
+                        inputs = candidate;
+                        inputs.truncate(step + 1);
+                        error = e;
+                    }
+                    None => start = end,
+                }
+            }
+            if chunk == 1 || replays >= MAX_SHRINK_REPLAYS {
+                break;
+            }
+            chunk /= 2;
+        }
+        Some(Shrunk {
+            inputs,
+            error,
+            replays,
+        })
+    }
+}
+
+/// Applies `inputs` to a clone of `initial`, returning the step and error of the first
+/// invariant violation.
+async fn replay<SM: StateMachine>(
+    initial: &SM::State,
+    inputs: &[SmInput<SM>],
+    actions: &mut SM::Actions,
+) -> Option<(usize, String)>
+where
+    SM::State: Clone,
+    SmInput<SM>: Clone,
+    <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+{
+    let mut state = initial.clone();
+    for (step, input) in inputs.iter().enumerate() {
+        actions.clear().expect("failed to clear actions container");
+        let _ = SM::stf(&mut state, input.clone(), actions).await;
+        if let Err(e) = SM::check_invariants(&state) {
+            return Some((step, e));
+        }
+    }
+    None
+}
+
+/// A minimal reproducer found by [`Simulator::shrink`].
+pub struct Shrunk<SM: StateMachine> {
+    /// Inputs that, applied in order to the run's initial state, violate an invariant
+    /// after the last one.
+    pub inputs: Vec<SmInput<SM>>,
+    /// What [`StateMachine::check_invariants`] reported after the last input.
+    pub error: String,
+    /// Replays it took to get here.
+    pub replays: usize,
+}
+
+/// Result of [`Simulator::run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimReport {
+    /// The seed the run was generated from.
+    pub seed: u64,
+    /// Transitions attempted, including the one that violated an invariant.
+    pub steps: usize,
+    /// Transitions that succeeded.
+    pub applied: usize,
+    /// Transitions that returned an error.
+    pub rejected: usize,
+    /// Actions emitted by successful transitions.
+    pub actions: usize,
+    /// The first invariant violation, if any.
+    pub failure: Option<SimFailure>,
+}
+
+/// An invariant violation found by [`Simulator::run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimFailure {
+    /// Zero-based index of the transition after which the check failed.
+    pub step: usize,
+    /// What [`StateMachine::check_invariants`] reported.
+    pub error: String,
+}
+
+impl SimReport {
+    pub fn is_ok(&self) -> bool {
+        self.failure.is_none()
+    }
+
+    /// # Panics
+    ///
+    /// If the run found an invariant violation, with the seed and step to reproduce it.
+    #[track_caller]
+    pub fn assert_ok(&self) {
+        if !self.is_ok() {
+            panic!("{}", self);
+        }
+    }
+}
+
+impl fmt::Display for SimReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.failure {
+            Some(failure) => write!(
+                f,
+                "seed {} violated an invariant at step {}: {}",
+                self.seed, failure.step, failure.error
+            ),
+            None => write!(
+                f,
+                "seed {}: {} steps, {} applied, {} rejected, {} actions",
+                self.seed, self.steps, self.applied, self.rejected, self.actions
+            ),
+        }
+    }
+}
@@ -0,0 +1,839 @@
+//! Helpers for deterministic simulation testing.
+//!
+//! Everything in this module is driven by a seeded [`ChaCha8Rng`] so that a failing
+//! simulation can be reproduced exactly from its seed. Enabled with the `testing` feature.
+//! [`SplitMix64`] is re-exported for input generation that doesn't need `rand`.
+
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    fmt::Debug,
+    future::{Future, poll_fn},
+    pin::pin,
+    rc::Rc,
+};
+
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    Input, Snapshot, StateMachine,
+    actions::{Action, ActionsContainer, TrackedAction, TrackedActionTypes},
+};
+
+pub use crate::rng::SplitMix64;
+
+type TrackedId<SM> = <<SM as StateMachine>::TrackedAction as TrackedActionTypes>::Id;
+type SmInput<SM> = Input<<SM as StateMachine>::TrackedAction, <SM as StateMachine>::Input>;
+type Invariants<SM> = fn(&<SM as StateMachine>::State) -> Result<(), String>;
+type EffectFold<SM, A> = fn(&mut A, &TrackedAction<<SM as StateMachine>::TrackedAction>);
+
+/// Shuffles `items` in place using a Fisher–Yates shuffle driven by `rng`.
+///
+/// This is the approved reordering primitive for simulations: use it whenever a
+/// harness needs to apply a fixed set of inputs in a seed-determined order (e.g. to
+/// deliver tracked action results out of order). Never reach for a shuffle backed by
+/// `thread_rng` or any other unseeded source - the same seed must always produce the
+/// same ordering, or failing seeds stop being reproducible.
+///
+/// ```ignore
+/// let mut rng = ChaCha8Rng::seed_from_u64(seed);
+/// let mut completions = vec![req_a, req_b, req_c];
+/// deterministic_shuffle(&mut completions, &mut rng);
+/// for req_id in completions {
+///     complete_preauth(&mut system, req_id).await?;
+/// }
+/// ```
+pub fn deterministic_shuffle<T>(items: &mut [T], rng: &mut ChaCha8Rng) {
+    for i in (1..items.len()).rev() {
+        let j = rng.gen_range(0..=i);
+        items.swap(i, j);
+    }
+}
+
+/// Asserts that every tracked action in `emitted` would be regenerated by
+/// [`StateMachine::restore`] from `state_after`.
+///
+/// Call it right after an STF with the state and actions it produced. A tracked id that
+/// `restore` doesn't regenerate means the machine didn't persist enough to recover that
+/// action after a crash. Restore may also regenerate ids emitted by earlier transitions
+/// that are still in flight, so only the emitted ids are required to match.
+///
+/// # Panics
+///
+/// If `restore` fails, or if any emitted tracked id is missing from its output.
+pub async fn assert_emit_matches_restore<SM: StateMachine>(
+    state_after: &SM::State,
+    emitted_actions: &SM::Actions,
+) where
+    SM::RestoreError: Debug,
+    <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+{
+    let restored = SM::restore_owned(state_after)
+        .await
+        .expect("restore failed");
+
+    let restored_ids: Vec<&TrackedId<SM>> = tracked_ids::<SM>(&restored).collect();
+    let missing: Vec<&TrackedId<SM>> = tracked_ids::<SM>(emitted_actions)
+        .filter(|id| !restored_ids.contains(id))
+        .collect();
+    assert!(
+        missing.is_empty(),
+        "tracked actions {:?} were emitted but restore doesn't regenerate them (restored: {:?})",
+        missing,
+        restored_ids
+    );
+}
+
+/// Asserts that the actions emitted by STF are a function of state and input alone.
+///
+/// Runs STF twice, each time on a fresh clone of `state` with a clone of `input`, and
+/// compares only the emitted action lists. Narrower than full determinism checking, it
+/// pinpoints nondeterministic emission such as building actions by iterating a
+/// `HashMap` or `HashSet` created during the transition.
+///
+/// # Panics
+///
+/// If the two action lists differ. Transition errors are not compared.
+pub async fn assert_actions_deterministic<SM: StateMachine>(
+    state: &SM::State,
+    input: Input<SM::TrackedAction, SM::Input>,
+) where
+    SM::State: Clone,
+    SM::Actions: PartialEq + Debug,
+    Input<SM::TrackedAction, SM::Input>: Clone,
+    <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+{
+    let mut runs = Vec::with_capacity(2);
+    for input in [input.clone(), input] {
+        let mut state = state.clone();
+        let mut actions = SM::Actions::new().expect("failed to create actions container");
+        let _ = SM::stf(&mut state, input, &mut actions).await;
+        runs.push(actions);
+    }
+    assert_eq!(
+        runs[0], runs[1],
+        "actions differ between two STF runs on the same state and input"
+    );
+}
+
+/// Asserts that STF is a function of state and input alone: invariant #2.
+///
+/// Like [`assert_actions_deterministic`], but also compares the resulting states and
+/// whether the transitions succeeded, so it catches state built from a `HashMap`'s
+/// iteration order or a hidden clock read as well as nondeterministic emission.
+///
+/// # Panics
+///
+/// If the two runs differ in success, resulting state, or emitted actions.
+pub async fn assert_deterministic<SM: StateMachine>(
+    state: &SM::State,
+    input: Input<SM::TrackedAction, SM::Input>,
+) where
+    SM::State: Clone + PartialEq + Debug,
+    SM::Actions: PartialEq + Debug,
+    Input<SM::TrackedAction, SM::Input>: Clone,
+    <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+{
+    let mut runs = Vec::with_capacity(2);
+    for input in [input.clone(), input] {
+        let mut state = state.clone();
+        let mut actions = SM::Actions::new().expect("failed to create actions container");
+        let ok = SM::stf(&mut state, input, &mut actions).await.is_ok();
+        runs.push((ok, state, actions));
+    }
+    let [(ok1, state1, actions1), (ok2, state2, actions2)] = &runs[..] else {
+        unreachable!();
+    };
+    assert_eq!(
+        ok1, ok2,
+        "STF succeeded in one of two runs on the same state and input"
+    );
+    assert_eq!(
+        state1, state2,
+        "state differs between two STF runs on the same state and input"
+    );
+    assert_eq!(
+        actions1, actions2,
+        "actions differ between two STF runs on the same state and input"
+    );
+}
+
+/// Applies `input` to `state` and asserts it succeeds with exactly `expected_actions`.
+///
+/// Replaces the usual "run STF, unwrap, compare the action list" block in integration
+/// tests. On mismatch the panic lists which expected actions are missing and which
+/// emitted actions were unexpected, so a long list doesn't have to be compared by eye.
+/// The state is left as the transition produced it, so calls can be chained.
+///
+/// ```ignore
+/// assert_transition::<CoffeeShopApp>(
+///     &mut app,
+///     Input::Normal(UserAction::RedeemPoints { points: 100 }),
+///     &[
+///         Action::Tracked(TrackedAction::new(RedemptionId(0), redeem(100))),
+///         Action::Untracked(UntrackedAction::ShowStampAnimation),
+///     ],
+/// )
+/// .await;
+/// ```
+///
+/// # Panics
+///
+/// If STF returns an error, or if the emitted actions differ from `expected_actions`
+/// (including only in order).
+pub async fn assert_transition<SM: StateMachine>(
+    state: &mut SM::State,
+    input: Input<SM::TrackedAction, SM::Input>,
+    expected_actions: &[Action<SM::UntrackedAction, SM::TrackedAction>],
+) where
+    SM::TransitionError: Debug,
+    Action<SM::UntrackedAction, SM::TrackedAction>: PartialEq + Debug,
+    <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+{
+    let mut actions = SM::Actions::new().expect("failed to create actions container");
+    if let Err(e) = SM::stf(state, input, &mut actions).await {
+        panic!("transition failed: {:?}", e);
+    }
+
+    let emitted: Vec<_> = actions.iter().collect();
+    if emitted.iter().copied().eq(expected_actions) {
+        return;
+    }
+
+    // Multiset difference, so duplicated actions are counted
+    let mut unexpected = emitted.clone();
+    let mut missing = Vec::new();
+    for action in expected_actions {
+        match unexpected.iter().position(|a| *a == action) {
+            Some(i) => {
+                unexpected.remove(i);
+            }
+            None => missing.push(action),
+        }
+    }
+
+    let mut report = String::from("transition emitted unexpected actions\n");
+    for action in &missing {
+        report.push_str(&format!("  - {:?}\n", action));
+    }
+    for action in &unexpected {
+        report.push_str(&format!("  + {:?}\n", action));
+    }
+    if missing.is_empty() && unexpected.is_empty() {
+        report.push_str("  (same actions, different order)\n");
+    }
+    panic!(
+        "{}expected: {:#?}\nemitted: {:#?}",
+        report, expected_actions, emitted
+    );
+}
+
+/// Applies `input` to `state` and, if the transition fails, asserts it left `state`
+/// unchanged.
+///
+/// Catches STF that mutates before it validates, without snapshotting fields by hand:
+/// the whole state is cloned before the transition and compared after it. Actions are
+/// not checked, since STF may emit them before returning an error. The state is left
+/// as the transition produced it, and its result is returned so the error can be
+/// checked too.
+///
+/// ```ignore
+/// let result = assert_atomic::<CoffeeShopApp>(
+///     &mut app,
+///     Input::Normal(UserAction::RedeemPoints { points: 200 }),
+/// )
+/// .await;
+/// assert!(matches!(result, Err(CoffeeError::InsufficientPoints { .. })));
+/// ```
+///
+/// # Panics
+///
+/// If the transition fails and `state` differs from before it, showing both.
+pub async fn assert_atomic<SM: StateMachine>(
+    state: &mut SM::State,
+    input: Input<SM::TrackedAction, SM::Input>,
+) -> Result<(), SM::TransitionError>
+where
+    SM::State: Clone + PartialEq + Debug,
+    SM::TransitionError: Debug,
+    <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+{
+    let before = state.clone();
+    let mut actions = SM::Actions::new().expect("failed to create actions container");
+    let result = SM::stf(state, input, &mut actions).await;
+    if let Err(e) = &result
+        && *state != before
+    {
+        panic!(
+            "transition failed with {:?} but changed state\nbefore: {:#?}\nafter: {:#?}",
+            e, before, state
+        );
+    }
+    result
+}
+
+/// Applies `input` to `state` and asserts the transition created no silent pending work.
+///
+/// Every tracked id that [`StateMachine::restore`] regenerates from the state after the
+/// transition must either have been regenerated from the state before it, or have been
+/// emitted by the transition itself. Otherwise the transition recorded a pending
+/// operation (e.g. a request awaiting preauthorization) without emitting the action that
+/// drives it, and nothing will happen until the next restart.
+///
+/// The state is left as the transition produced it. The check runs whether or not the
+/// transition succeeds, since a failed transition must not leave silent work behind
+/// either.
+///
+/// # Panics
+///
+/// If `restore` fails on either state, or if a newly restorable id wasn't emitted.
+pub async fn assert_no_silent_pending<SM: StateMachine>(
+    state: &mut SM::State,
+    input: Input<SM::TrackedAction, SM::Input>,
+) where
+    TrackedId<SM>: Clone,
+    SM::RestoreError: Debug,
+    <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+{
+    let mut restored = SM::Actions::new().expect("failed to create actions container");
+    SM::restore(state, &mut restored)
+        .await
+        .expect("restore failed before the transition");
+    let before: Vec<TrackedId<SM>> = tracked_ids::<SM>(&restored).cloned().collect();
+
+    let mut emitted = SM::Actions::new().expect("failed to create actions container");
+    let _ = SM::stf(state, input, &mut emitted).await;
+
+    restored.clear().expect("failed to clear actions container");
+    SM::restore(state, &mut restored)
+        .await
+        .expect("restore failed after the transition");
+    let emitted_ids: Vec<&TrackedId<SM>> = tracked_ids::<SM>(&emitted).collect();
+    let silent: Vec<&TrackedId<SM>> = tracked_ids::<SM>(&restored)
+        .filter(|id| !before.contains(id) && !emitted_ids.contains(id))
+        .collect();
+    assert!(
+        silent.is_empty(),
+        "transition created pending work for tracked actions {:?} without emitting them \
+         (emitted: {:?})",
+        silent,
+        emitted_ids
+    );
+}
+
+/// Asserts that calling [`StateMachine::restore`] twice on `state` emits the same actions.
+///
+/// Restore takes the state by shared reference, so a difference means it depends on
+/// something outside the state, such as a counter in a `Cell` or iteration order of a
+/// hash map. Pair with [`assert_restore_settles`], which checks the other half of
+/// idempotence: that executing the restored actions stops them being restored.
+///
+/// # Panics
+///
+/// If `restore` fails, or if the two action lists differ.
+pub async fn assert_restore_idempotent<SM: StateMachine>(state: &SM::State)
+where
+    SM::Actions: PartialEq + Debug,
+    SM::RestoreError: Debug,
+    <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+{
+    let mut runs = Vec::with_capacity(2);
+    for _ in 0..2 {
+        let mut actions = SM::Actions::new().expect("failed to create actions container");
+        SM::restore(state, &mut actions)
+            .await
+            .expect("restore failed");
+        runs.push(actions);
+    }
+    assert_eq!(
+        runs[0], runs[1],
+        "actions differ between two restores of the same state"
+    );
+}
+
+/// Restores `state`, applies a result from `result_for` to every restored tracked
+/// action, and asserts that restoring again doesn't regenerate any of them.
+///
+/// Models a restart followed by the executor completing everything it was handed. A
+/// settled action that restore emits again would be executed twice after the next
+/// restart, e.g. charging a card twice. Restore may emit new work caused by the results
+/// (including a different action under a reused id), so only the same id with the same
+/// action counts as a repeat.
+///
+/// The state is left with the results applied.
+///
+/// # Panics
+///
+/// If either restore fails, if applying a result fails, or if a settled action is
+/// restored again.
+pub async fn assert_restore_settles<SM: StateMachine>(
+    state: &mut SM::State,
+    mut result_for: impl FnMut(
+        &TrackedAction<SM::TrackedAction>,
+    ) -> <SM::TrackedAction as TrackedActionTypes>::Result,
+) where
+    TrackedId<SM>: Clone,
+    SM::TransitionError: Debug,
+    SM::RestoreError: Debug,
+    <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+{
+    let mut restored = SM::Actions::new().expect("failed to create actions container");
+    SM::restore(state, &mut restored)
+        .await
+        .expect("restore failed");
+
+    let mut actions = SM::Actions::new().expect("failed to create actions container");
+    for tracked in restored.iter_tracked() {
+        let input = Input::TrackedActionCompleted {
+            id: tracked.id().clone(),
+            res: result_for(tracked),
+        };
+        actions.clear().expect("failed to clear actions container");
+        if let Err(e) = SM::stf(state, input, &mut actions).await {
+            panic!(
+                "applying the result of restored tracked action {:?} failed: {:?}",
+                tracked.id(),
+                e
+            );
+        }
+    }
+
+    let mut again = SM::Actions::new().expect("failed to create actions container");
+    SM::restore(state, &mut again)
+        .await
+        .expect("restore failed after applying results");
+    let repeated: Vec<&TrackedId<SM>> = again
+        .iter_tracked()
+        .filter(|t| {
+            restored
+                .iter_tracked()
+                .any(|r| r.id() == t.id() && r.action() == t.action())
+        })
+        .map(TrackedAction::id)
+        .collect();
+    assert!(
+        repeated.is_empty(),
+        "restore regenerated tracked actions {:?} after their results were applied",
+        repeated
+    );
+}
+
+/// Asserts that recovering from a checkpoint taken after `snapshot_at` inputs reaches
+/// the same state as replaying every input from `genesis`.
+///
+/// Replays `all_inputs` from a clone of `genesis` once in full, and once up to
+/// `snapshot_at`, then round-trips that state through [`Snapshot::encode`] and
+/// [`Snapshot::decode`] and replays the remaining inputs on top. Transition errors are
+/// ignored on both paths, as a replayed input log also contains rejected inputs.
+///
+/// # Panics
+///
+/// If `snapshot_at` is past the end of `all_inputs`, if decoding fails, or if the two
+/// final states differ.
+pub async fn verify_checkpoint<SM: StateMachine>(
+    genesis: &SM::State,
+    all_inputs: &[Input<SM::TrackedAction, SM::Input>],
+    snapshot_at: usize,
+) where
+    SM::State: Clone + Snapshot + PartialEq + Debug,
+    <SM::State as Snapshot>::Error: Debug,
+    Input<SM::TrackedAction, SM::Input>: Clone,
+    <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+{
+    assert!(
+        snapshot_at <= all_inputs.len(),
+        "snapshot_at {} is past the end of {} inputs",
+        snapshot_at,
+        all_inputs.len()
+    );
+    let (head, tail) = all_inputs.split_at(snapshot_at);
+    let mut actions = SM::Actions::new().expect("failed to create actions container");
+
+    let mut full = genesis.clone();
+    replay::<SM>(&mut full, all_inputs, &mut actions).await;
+
+    let mut checkpointed = genesis.clone();
+    replay::<SM>(&mut checkpointed, head, &mut actions).await;
+    let mut recovered =
+        SM::State::decode(&checkpointed.encode()).expect("failed to decode snapshot");
+    replay::<SM>(&mut recovered, tail, &mut actions).await;
+
+    assert_eq!(
+        recovered, full,
+        "recovering from the snapshot after input {} diverges from replaying from genesis",
+        snapshot_at
+    );
+}
+
+async fn replay<SM: StateMachine>(
+    state: &mut SM::State,
+    inputs: &[Input<SM::TrackedAction, SM::Input>],
+    actions: &mut SM::Actions,
+) where
+    Input<SM::TrackedAction, SM::Input>: Clone,
+    <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+{
+    for input in inputs {
+        actions.clear().expect("failed to clear actions container");
+        let _ = SM::stf(state, input.clone(), actions).await;
+    }
+}
+
+/// Applies `inputs` in order to a clone of `initial` and returns the state after each
+/// one, e.g. to render a run as a timeline.
+///
+/// If an input fails, `(i, error)` is returned for its index.
+///
+/// # Panics
+///
+/// If the actions container can't be created or cleared.
+pub async fn trace_states<SM: StateMachine>(
+    initial: &SM::State,
+    inputs: &[Input<SM::TrackedAction, SM::Input>],
+) -> Result<Vec<SM::State>, (usize, SM::TransitionError)>
+where
+    SM::State: Clone,
+    Input<SM::TrackedAction, SM::Input>: Clone,
+    <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+{
+    let mut actions = SM::Actions::new().expect("failed to create actions container");
+    let mut state = initial.clone();
+    let mut states = Vec::with_capacity(inputs.len());
+    for (i, input) in inputs.iter().enumerate() {
+        actions.clear().expect("failed to clear actions container");
+        SM::stf(&mut state, input.clone(), &mut actions)
+            .await
+            .map_err(|e| (i, e))?;
+        states.push(state.clone());
+    }
+    Ok(states)
+}
+
+/// A logical clock for scripting time-dependent tests, see [`Timeline`].
+///
+/// Machines take the current time from their inputs, so tests drive time by choosing
+/// what `now` to put in each input. The clock only moves forward.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LogicalClock {
+    now: u64,
+}
+
+impl LogicalClock {
+    pub fn new(start: u64) -> Self {
+        Self { now: start }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    /// # Panics
+    ///
+    /// If `time` is before [`now`](Self::now).
+    pub fn advance_to(&mut self, time: u64) {
+        assert!(
+            time >= self.now,
+            "logical time can't go backwards ({} -> {})",
+            self.now,
+            time
+        );
+        self.now = time;
+    }
+
+    /// Advances by `duration` and returns the new time.
+    pub fn advance_by(&mut self, duration: u64) -> u64 {
+        self.now += duration;
+        self.now
+    }
+}
+
+/// A scripted sequence of inputs at logical times, for exercising TTLs, expiry and lead
+/// times deterministically.
+///
+/// Inputs are applied in time order (insertion order for equal times), advancing a
+/// [`LogicalClock`] to each input's time first, and the invariant check set with
+/// [`check`](Self::check) runs after every one. The time isn't passed to the machine:
+/// put it in the input the same way production callers do.
+///
+/// ```ignore
+/// let mut timeline = Timeline::<Desk>::new(0)
+///     .at(10, Input::Normal(DeskInput::Place { now: 10 }))
+///     .at(40, Input::Normal(DeskInput::ExpireStale { now: 40 }))
+///     .check(Desk::check_invariants);
+/// timeline.run_until(&mut desk, 39).await; // the hold is still there
+/// let steps = timeline.run(&mut desk).await; // expired at 40
+/// ```
+pub struct Timeline<SM: StateMachine> {
+    clock: LogicalClock,
+    events: VecDeque<(u64, SmInput<SM>)>,
+    invariants: Option<Invariants<SM>>,
+}
+
+/// One input applied by a [`Timeline`].
+pub struct TimelineStep<SM: StateMachine> {
+    pub time: u64,
+    pub result: Result<(), SM::TransitionError>,
+    pub actions: SM::Actions,
+}
+
+impl<SM: StateMachine> Timeline<SM> {
+    /// An empty timeline whose clock starts at `start`.
+    pub fn new(start: u64) -> Self {
+        Self {
+            clock: LogicalClock::new(start),
+            events: VecDeque::new(),
+            invariants: None,
+        }
+    }
+
+    /// Schedules `input` at logical `time`.
+    ///
+    /// # Panics
+    ///
+    /// If `time` is already in the past.
+    pub fn at(mut self, time: u64, input: Input<SM::TrackedAction, SM::Input>) -> Self {
+        assert!(
+            time >= self.clock.now(),
+            "can't schedule at {}, the timeline is already at {}",
+            time,
+            self.clock.now()
+        );
+        let pos = self.events.partition_point(|(t, _)| *t <= time);
+        self.events.insert(pos, (time, input));
+        self
+    }
+
+    /// Checks `invariants` after every applied input, panicking if it fails.
+    pub fn check(mut self, invariants: Invariants<SM>) -> Self {
+        self.invariants = Some(invariants);
+        self
+    }
+
+    pub fn now(&self) -> u64 {
+        self.clock.now()
+    }
+
+    /// Applies every remaining input, ending at the time of the last one.
+    pub async fn run(&mut self, state: &mut SM::State) -> Vec<TimelineStep<SM>>
+    where
+        <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+    {
+        let end = self.events.back().map_or(self.clock.now(), |(t, _)| *t);
+        self.run_until(state, end).await
+    }
+
+    /// Applies the inputs scheduled at or before `until`, then advances the clock to it.
+    ///
+    /// Transition errors are returned in the steps rather than propagated, since a
+    /// script may deliberately include inputs that should be rejected.
+    ///
+    /// # Panics
+    ///
+    /// If `until` is in the past, or if the invariant check fails after an input.
+    pub async fn run_until(&mut self, state: &mut SM::State, until: u64) -> Vec<TimelineStep<SM>>
+    where
+        <SM::Actions as ActionsContainer<SM::UntrackedAction, SM::TrackedAction>>::Error: Debug,
+    {
+        let mut steps = Vec::new();
+        while self.events.front().is_some_and(|(t, _)| *t <= until) {
+            let (time, input) = self.events.pop_front().unwrap();
+            self.clock.advance_to(time);
+
+            let mut actions = SM::Actions::new().expect("failed to create actions container");
+            let result = SM::stf(state, input, &mut actions).await;
+            if let Some(check) = self.invariants
+                && let Err(e) = check(state)
+            {
+                panic!(
+                    "invariant violated at logical time {} (step {}): {}",
+                    time,
+                    steps.len(),
+                    e
+                );
+            }
+            steps.push(TimelineStep {
+                time,
+                result,
+                actions,
+            });
+        }
+        self.clock.advance_to(until);
+        steps
+    }
+}
+
+/// Folds the tracked actions emitted over a run into a user-defined aggregate, for
+/// asserting properties of a simulation's total effects.
+///
+/// Per-transition invariants only see state. Some properties are about the side effects
+/// instead, e.g. "money preauthorized equals money booked plus money released". Pass
+/// every container STF or [`restore`](StateMachine::restore) fills to
+/// [`record`](Self::record) and assert on [`total`](Self::total) during or after the run.
+///
+/// ```ignore
+/// let mut held = EffectsLedger::<Shop, u64>::new(0, |cents, tracked| {
+///     if let Charge::Hold { amount_cents } = tracked.action() {
+///         *cents += amount_cents;
+///     }
+/// });
+/// Shop::stf(&mut shop, input, &mut actions).await?;
+/// held.record(&actions);
+/// ```
+pub struct EffectsLedger<SM: StateMachine, A> {
+    total: A,
+    fold: EffectFold<SM, A>,
+    recorded: usize,
+}
+
+impl<SM: StateMachine, A> EffectsLedger<SM, A> {
+    /// A ledger starting from `init` that applies `fold` to each recorded tracked action.
+    pub fn new(init: A, fold: EffectFold<SM, A>) -> Self {
+        Self {
+            total: init,
+            fold,
+            recorded: 0,
+        }
+    }
+
+    /// Folds the tracked actions in `actions`, in emission order. Untracked actions are
+    /// skipped.
+    pub fn record(&mut self, actions: &SM::Actions) {
+        for tracked in actions.iter_tracked() {
+            (self.fold)(&mut self.total, tracked);
+            self.recorded += 1;
+        }
+    }
+
+    pub fn total(&self) -> &A {
+        &self.total
+    }
+
+    /// Tracked actions folded so far.
+    pub fn recorded(&self) -> usize {
+        self.recorded
+    }
+
+    pub fn into_total(self) -> A {
+        self.total
+    }
+}
+
+/// An actions container that catches STFs misusing it, for test builds.
+///
+/// STF must only add to its actions container, never read from it, and shouldn't await
+/// anything external mid-transition. Use this as a machine's `Actions` in tests and run
+/// transitions with [`guarded_stf`]:
+///
+/// - reading the container (`iter` and friends) during a transition panics;
+/// - adding an action after the STF future returned `Pending` is counted in
+///   [`adds_after_yield`](Self::adds_after_yield), since a pure STF has nothing to wait
+///   for and interleaving with other work is a sign of external I/O.
+///
+/// Outside [`guarded_stf`] it behaves like a `Vec`.
+#[derive(Debug)]
+pub struct GuardedActions<UA, TA: TrackedActionTypes> {
+    actions: Vec<Action<UA, TA>>,
+    guard: Rc<Guard>,
+}
+
+#[derive(Debug, Default)]
+struct Guard {
+    in_transition: Cell<bool>,
+    yielded: Cell<bool>,
+    adds_after_yield: Cell<usize>,
+}
+
+impl<UA, TA: TrackedActionTypes> GuardedActions<UA, TA> {
+    /// Actions added after the STF future yielded, across all guarded transitions.
+    pub fn adds_after_yield(&self) -> usize {
+        self.guard.adds_after_yield.get()
+    }
+
+    pub fn into_inner(self) -> Vec<Action<UA, TA>> {
+        self.actions
+    }
+}
+
+impl<UA, TA: TrackedActionTypes> ActionsContainer<UA, TA> for GuardedActions<UA, TA> {
+    type Error = ();
+
+    fn new() -> Result<Self, Self::Error> {
+        Self::with_capacity(0)
+    }
+
+    fn with_capacity(capacity: usize) -> Result<Self, Self::Error> {
+        Ok(Self {
+            actions: Vec::with_capacity(capacity),
+            guard: Rc::default(),
+        })
+    }
+
+    fn clear(&mut self) -> Result<(), Self::Error> {
+        self.actions.clear();
+        Ok(())
+    }
+
+    fn add(&mut self, action: Action<UA, TA>) -> Result<(), Self::Error> {
+        if self.guard.in_transition.get() && self.guard.yielded.get() {
+            let n = &self.guard.adds_after_yield;
+            n.set(n.get() + 1);
+        }
+        self.actions.push(action);
+        Ok(())
+    }
+
+    fn iter<'a>(&'a self) -> impl Iterator<Item = &'a Action<UA, TA>>
+    where
+        UA: 'a,
+        TA: 'a,
+    {
+        assert!(
+            !self.guard.in_transition.get(),
+            "actions container read during a transition; STF must only add to it"
+        );
+        self.actions.iter()
+    }
+}
+
+/// Runs [`StateMachine::stf`] with [`GuardedActions`] checking how it uses the container.
+///
+/// # Panics
+///
+/// If STF reads the container.
+pub async fn guarded_stf<SM, UA, TA>(
+    state: &mut SM::State,
+    input: Input<TA, SM::Input>,
+    actions: &mut GuardedActions<UA, TA>,
+) -> Result<(), SM::TransitionError>
+where
+    SM: StateMachine<UntrackedAction = UA, TrackedAction = TA, Actions = GuardedActions<UA, TA>>,
+    TA: TrackedActionTypes,
+{
+    let guard = actions.guard.clone();
+    guard.in_transition.set(true);
+    guard.yielded.set(false);
+
+    let mut stf = pin!(SM::stf(state, input, actions));
+    let res = poll_fn(|cx| {
+        let poll = stf.as_mut().poll(cx);
+        if poll.is_pending() {
+            guard.yielded.set(true);
+        }
+        poll
+    })
+    .await;
+
+    guard.in_transition.set(false);
+    res
+}
+
+fn tracked_ids<'a, SM: StateMachine>(
+    actions: &'a SM::Actions,
+) -> impl Iterator<Item = &'a TrackedId<SM>>
+where
+    SM::UntrackedAction: 'a,
+    SM::TrackedAction: 'a,
+{
+    actions.iter_tracked().map(|tracked| tracked.id())
+}
@@ -0,0 +1,129 @@
+//! Deterministic time carried by inputs.
+//!
+//! STF must not read the clock, so the caller reads it once and passes it in with the
+//! input. [`LogicalTime`] is a plain millisecond count to do that with, and [`Timed`]
+//! pairs an input with the time it was taken at:
+//!
+//! ```ignore
+//! type Input = Timed<BookingRequest>;
+//!
+//! // Outside STF, where reading the clock is fine
+//! let now = LogicalTime::from_millis(clock.unix_millis());
+//! engine.step(Input::Normal(Timed::new(now, request))).await?;
+//!
+//! // Inside STF
+//! let expires = input.at + Duration::from_secs(15 * 60);
+//! ```
+//!
+//! Simulations and tests choose the time instead, so replays see exactly the same
+//! values.
+
+use std::{
+    fmt,
+    ops::{Add, Sub},
+    time::Duration,
+};
+
+/// Milliseconds since the Unix epoch, or since any origin the machine agrees on.
+///
+/// Arithmetic is in whole milliseconds: sub-millisecond parts of a [`Duration`] are
+/// truncated.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LogicalTime(pub u64);
+
+impl LogicalTime {
+    pub const ZERO: Self = Self(0);
+
+    pub fn from_millis(millis: u64) -> Self {
+        Self(millis)
+    }
+
+    pub fn as_millis(self) -> u64 {
+        self.0
+    }
+
+    /// `self + duration`, or `None` on overflow.
+    pub fn checked_add(self, duration: Duration) -> Option<Self> {
+        let millis = u64::try_from(duration.as_millis()).ok()?;
+        self.0.checked_add(millis).map(Self)
+    }
+
+    /// `self - duration`, or `None` if that is before the origin.
+    pub fn checked_sub(self, duration: Duration) -> Option<Self> {
+        let millis = u64::try_from(duration.as_millis()).ok()?;
+        self.0.checked_sub(millis).map(Self)
+    }
+
+    /// Time elapsed from `earlier` to `self`, or zero if `earlier` is later.
+    pub fn saturating_duration_since(self, earlier: Self) -> Duration {
+        Duration::from_millis(self.0.saturating_sub(earlier.0))
+    }
+}
+
+impl Add<Duration> for LogicalTime {
+    type Output = Self;
+
+    /// # Panics
+    ///
+    /// On overflow, see [`checked_add`](LogicalTime::checked_add).
+    fn add(self, duration: Duration) -> Self {
+        self.checked_add(duration)
+            .expect("overflow when adding duration to logical time")
+    }
+}
+
+impl Sub<Duration> for LogicalTime {
+    type Output = Self;
+
+    /// # Panics
+    ///
+    /// If the result is before the origin, see [`checked_sub`](LogicalTime::checked_sub).
+    fn sub(self, duration: Duration) -> Self {
+        self.checked_sub(duration)
+            .expect("overflow when subtracting duration from logical time")
+    }
+}
+
+/// Time elapsed between two logical times, saturating at zero like [`Instant`] does.
+///
+/// [`Instant`]: std::time::Instant
+impl Sub for LogicalTime {
+    type Output = Duration;
+
+    fn sub(self, earlier: Self) -> Duration {
+        self.saturating_duration_since(earlier)
+    }
+}
+
+impl fmt::Display for LogicalTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}ms", self.0)
+    }
+}
+
+/// An input together with the time it was taken at.
+///
+/// Making the timestamp part of the input type means a caller can't forget it, and
+/// [`StateMachine::input_time`](crate::StateMachine::input_time) is one line:
+/// `Some(input.at.as_millis())`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Timed<T> {
+    pub at: LogicalTime,
+    pub inner: T,
+}
+
+impl<T> Timed<T> {
+    pub fn new(at: LogicalTime, inner: T) -> Self {
+        Self { at, inner }
+    }
+
+    /// Applies `f` to the input, keeping the time.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Timed<U> {
+        Timed {
+            at: self.at,
+            inner: f(self.inner),
+        }
+    }
+}
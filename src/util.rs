@@ -0,0 +1,13 @@
+//! Small conversions that are easy to get subtly wrong in state machine code.
+
+/// Converts a currency amount to whole cents, rounding to the nearest cent.
+///
+/// `None` for NaN, negative amounts and amounts over `u32::MAX` cents, where a plain
+/// `(amount * 100.0) as u32` would silently truncate or saturate. Prefer integer cents in
+/// state and inputs; this is for the boundary where amounts arrive as floats.
+pub fn f32_to_cents(amount: f32) -> Option<u32> {
+    let cents = (f64::from(amount) * 100.0).round();
+    (0.0..=f64::from(u32::MAX))
+        .contains(&cents)
+        .then_some(cents as u32)
+}
@@ -0,0 +1,159 @@
+//! Write-ahead logging of the tracked actions a [`Driver`] run emits.
+//!
+//! Restore can only re-emit what the machine recorded in state. A [`WalDriver`] also
+//! appends every tracked action a transition emits to an [`ActionSink`] before
+//! executing any of them, so a crash between STF and execution can't lose an action
+//! without a durable record of it. After a restart, [`WalDriver::recover`] runs the
+//! machine's [`restore`](StateMachine::restore), logs what it re-emits and executes it.
+//!
+//! Each record is one JSON-encoded [`TrackedAction`]. Truncating the log once a state
+//! snapshot covering it is persisted is up to the sink.
+//!
+//! Enabled with the `serde` feature.
+
+use std::future::Future;
+
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{
+    StateMachine,
+    actions::{ActionsContainer, TrackedAction, TrackedActionTypes},
+    driver::{ActionExecutor, Driver, RecoverError, RunHooks},
+};
+
+type TrackedTypes<SM> = <SM as StateMachine>::TrackedAction;
+type TrackedId<SM> = <TrackedTypes<SM> as TrackedActionTypes>::Id;
+type TrackedRequest<SM> = <TrackedTypes<SM> as TrackedActionTypes>::Action;
+type ContainerError<SM> = <<SM as StateMachine>::Actions as ActionsContainer<
+    <SM as StateMachine>::UntrackedAction,
+    <SM as StateMachine>::TrackedAction,
+>>::Error;
+
+/// Durable storage for write-ahead records.
+///
+/// Implementations may be written with `async fn`.
+pub trait ActionSink {
+    /// Appends one record. Return once it is durable: the action is executed as soon as
+    /// this returns, so retries belong here.
+    fn append(&mut self, bytes: &[u8]) -> impl Future<Output = ()>;
+}
+
+/// An [`ActionSink`] that keeps records in memory, for tests.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct MemorySink {
+    records: Vec<Vec<u8>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn records(&self) -> &[Vec<u8>] {
+        &self.records
+    }
+
+    /// Decodes the records, oldest first.
+    pub fn tracked<TA>(&self) -> Result<Vec<TrackedAction<TA>>, serde_json::Error>
+    where
+        TA: TrackedActionTypes,
+        TA::Id: DeserializeOwned,
+        TA::Action: DeserializeOwned,
+    {
+        self.records
+            .iter()
+            .map(|record| serde_json::from_slice(record))
+            .collect()
+    }
+}
+
+impl ActionSink for MemorySink {
+    async fn append(&mut self, bytes: &[u8]) {
+        self.records.push(bytes.to_vec());
+    }
+}
+
+/// Appends the tracked actions of every transition to the sink.
+struct WalHooks<'a, W>(&'a mut W);
+
+impl<SM, W> RunHooks<SM> for WalHooks<'_, W>
+where
+    SM: StateMachine,
+    W: ActionSink,
+    TrackedId<SM>: Serialize,
+    TrackedRequest<SM>: Serialize,
+{
+    async fn before_execute(&mut self, actions: &SM::Actions) {
+        for tracked in actions.iter_tracked() {
+            let Ok(record) = serde_json::to_vec(tracked) else {
+                panic!("failed to encode tracked action");
+            };
+            self.0.append(&record).await;
+        }
+    }
+}
+
+/// A [`Driver`] that logs tracked actions to an [`ActionSink`] before executing them.
+pub struct WalDriver<SM: StateMachine, E, W> {
+    driver: Driver<SM, E>,
+    sink: W,
+}
+
+impl<SM, E, W> WalDriver<SM, E, W>
+where
+    SM: StateMachine,
+    E: ActionExecutor<SM::UntrackedAction, SM::TrackedAction>,
+    W: ActionSink,
+    TrackedId<SM>: Clone + Ord + Serialize,
+    TrackedRequest<SM>: Serialize,
+{
+    pub fn new(state: SM::State, executor: E, sink: W) -> Result<Self, ContainerError<SM>> {
+        Ok(Self {
+            driver: Driver::new(state, executor)?,
+            sink,
+        })
+    }
+
+    pub fn driver(&self) -> &Driver<SM, E> {
+        &self.driver
+    }
+
+    pub fn state(&self) -> &SM::State {
+        self.driver.state()
+    }
+
+    pub fn sink(&self) -> &W {
+        &self.sink
+    }
+
+    pub fn into_parts(self) -> (SM::State, E, W) {
+        let (state, executor) = self.driver.into_parts();
+        (state, executor, self.sink)
+    }
+
+    /// [`Driver::submit`], appending the tracked actions of each transition to the sink
+    /// before executing any of them.
+    ///
+    /// # Panics
+    ///
+    /// Like [`Driver::submit`], or if a tracked action can't be encoded.
+    pub async fn submit(&mut self, input: SM::Input) -> Result<(), SM::TransitionError> {
+        self.driver
+            .submit_with(input, &mut WalHooks(&mut self.sink))
+            .await
+    }
+
+    /// [`Driver::recover`], appending the restored tracked actions to the sink before
+    /// executing them.
+    ///
+    /// # Panics
+    ///
+    /// Like [`submit`](Self::submit).
+    pub async fn recover(
+        &mut self,
+    ) -> Result<(), RecoverError<SM::RestoreError, SM::TransitionError>> {
+        self.driver
+            .recover_with(&mut WalHooks(&mut self.sink))
+            .await
+    }
+}
@@ -0,0 +1,302 @@
+use phasm::{
+    Input,
+    actions::{
+        Action, ActionOrder, ActionsContainer, ArenaActions, ArenaFull, BoundedActions,
+        CountingActions, PriorityActions, SmallActions, TrackedAction, TrackedActionTypes,
+    },
+};
+
+#[derive(Debug)]
+struct Payments;
+
+impl TrackedActionTypes for Payments {
+    type Id = u64;
+    type Action = &'static str;
+    type Result = ();
+}
+
+#[test]
+fn test_iter_projections_split_mixed_container() {
+    let mut actions: Vec<Action<&'static str, Payments>> = ActionsContainer::new().unwrap();
+    actions.add(Action::Untracked("log:start")).unwrap();
+    actions
+        .add(Action::Tracked(TrackedAction::new(1, "preauth")))
+        .unwrap();
+    actions.add(Action::Untracked("notify")).unwrap();
+    actions
+        .add(Action::Tracked(TrackedAction::new(2, "capture")))
+        .unwrap();
+
+    let tracked: Vec<(u64, &str)> = actions
+        .iter_tracked()
+        .map(|t| (*t.id(), *t.action()))
+        .collect();
+    assert_eq!(tracked, vec![(1, "preauth"), (2, "capture")]);
+
+    let untracked: Vec<&str> = actions.iter_untracked().copied().collect();
+    assert_eq!(untracked, vec!["log:start", "notify"]);
+}
+#[test]
+fn test_arena_actions_is_fixed_capacity() {
+    let mut actions: ArenaActions<&'static str, Payments> =
+        ActionsContainer::with_capacity(2).unwrap();
+    actions.add(Action::Untracked("notify")).unwrap();
+    actions
+        .add(Action::Tracked(TrackedAction::new(1, "preauth")))
+        .unwrap();
+    assert_eq!(
+        actions.add(Action::Untracked("overflow")),
+        Err(ArenaFull { capacity: 2 })
+    );
+    assert_eq!(
+        actions.iter_untracked().copied().collect::<Vec<_>>(),
+        ["notify"]
+    );
+
+    actions.clear().unwrap();
+    assert!(actions.is_empty());
+    assert_eq!(actions.capacity(), 2);
+    actions.add(Action::Untracked("again")).unwrap();
+    assert_eq!(actions.len(), 1);
+}
+
+#[test]
+fn test_arena_actions_recycles_cleared_payloads() {
+    let mut actions: ArenaActions<String, Payments> = ActionsContainer::with_capacity(2).unwrap();
+    actions
+        .add(Action::Untracked(String::with_capacity(32)))
+        .unwrap();
+    actions
+        .add(Action::Tracked(TrackedAction::new(1, "preauth")))
+        .unwrap();
+    assert!(actions.recycle().is_none(), "Nothing has been cleared yet");
+
+    actions.clear().unwrap();
+    let Some(Action::Untracked(mut label)) = actions.recycle() else {
+        panic!("Cleared actions come back in the order they were added");
+    };
+    assert_eq!(label.capacity(), 32, "The payload's buffer is kept");
+    label.push_str("notify");
+    actions.add(Action::Untracked(label)).unwrap();
+
+    actions.clear().unwrap();
+    assert!(matches!(actions.recycle(), Some(Action::Untracked(label)) if label == "notify"));
+    assert!(
+        matches!(actions.recycle(), Some(Action::Tracked(_))),
+        "Older cleared actions are kept while there's room"
+    );
+    assert!(actions.recycle().is_none());
+}
+
+#[test]
+fn test_len_counts_through_the_trait() {
+    fn summary<C: ActionsContainer<&'static str, Payments>>(actions: &C) -> (usize, bool) {
+        (actions.len(), actions.is_empty())
+    }
+
+    let mut actions: Vec<Action<&'static str, Payments>> = ActionsContainer::new().unwrap();
+    assert_eq!(summary(&actions), (0, true));
+    actions.add(Action::Untracked("notify")).unwrap();
+    actions
+        .add(Action::Tracked(TrackedAction::new(1, "preauth")))
+        .unwrap();
+    assert_eq!(summary(&actions), (2, false));
+}
+#[test]
+fn test_bounded_actions_returns_overflowing_action() {
+    let mut actions: BoundedActions<&'static str, Payments, 2> = ActionsContainer::new().unwrap();
+    actions.add(Action::Untracked("notify")).unwrap();
+    actions
+        .add(Action::Tracked(TrackedAction::new(1, "preauth")))
+        .unwrap();
+
+    let overflow = actions
+        .add(Action::Tracked(TrackedAction::new(2, "capture")))
+        .unwrap_err();
+    assert_eq!(overflow.capacity, 2);
+    assert_eq!(
+        overflow.to_string(),
+        "actions container is full (2 actions per transition)"
+    );
+    assert!(matches!(
+        overflow.into_action(),
+        Action::Tracked(t) if *t.id() == 2 && *t.action() == "capture"
+    ));
+    assert_eq!(actions.len(), 2, "The rejected action isn't queued");
+
+    actions.clear().unwrap();
+    actions.add(Action::Untracked("again")).unwrap();
+    assert_eq!(
+        actions.iter_untracked().copied().collect::<Vec<_>>(),
+        ["again"]
+    );
+}
+#[test]
+fn test_small_actions_spills_past_inline_capacity() {
+    let mut actions: SmallActions<&'static str, Payments, 2> = ActionsContainer::new().unwrap();
+    actions.add(Action::Untracked("notify")).unwrap();
+    actions
+        .add(Action::Tracked(TrackedAction::new(1, "preauth")))
+        .unwrap();
+    assert!(!actions.spilled());
+
+    actions.add(Action::Untracked("log")).unwrap();
+    assert!(actions.spilled());
+    assert_eq!(actions.len(), 3);
+    assert_eq!(
+        actions.iter_untracked().copied().collect::<Vec<_>>(),
+        ["notify", "log"]
+    );
+}
+
+#[test]
+fn test_add_all_stops_at_first_failure() {
+    let batch = || (1..=3).map(|id| Action::Tracked(TrackedAction::new(id, "refund")));
+
+    let mut actions: Vec<Action<&'static str, Payments>> = ActionsContainer::new().unwrap();
+    actions.add(Action::Untracked("log")).unwrap();
+    actions.add_all(batch()).unwrap();
+    let ids: Vec<u64> = actions.iter_tracked().map(|t| *t.id()).collect();
+    assert_eq!(ids, [1, 2, 3]);
+    assert_eq!(actions.len(), 4);
+
+    let mut bounded: BoundedActions<&'static str, Payments, 2> = ActionsContainer::new().unwrap();
+    let overflow = bounded.add_all(batch()).unwrap_err();
+    assert!(matches!(overflow.into_action(), Action::Tracked(t) if *t.id() == 3));
+    assert_eq!(bounded.len(), 2);
+}
+/// Adds one tracked and two untracked actions, returning the container's length.
+fn emit_three<C: ActionsContainer<&'static str, Payments>>(actions: &mut C) -> usize
+where
+    C::Error: std::fmt::Debug,
+{
+    actions.add(Action::Untracked("notify")).unwrap();
+    actions
+        .add(Action::Tracked(TrackedAction::new(1, "preauth")))
+        .unwrap();
+    actions.add(Action::Untracked("log")).unwrap();
+    assert_eq!(actions.iter().count(), 0, "Payloads aren't kept");
+    actions.len()
+}
+
+#[test]
+fn test_counting_actions_keeps_only_counts() {
+    let mut actions = CountingActions::default();
+    assert_eq!(emit_three(&mut actions), 3);
+    assert_eq!((actions.tracked(), actions.untracked()), (1, 2));
+}
+#[test]
+fn test_has_tracked_and_untracked() {
+    let mut actions: Vec<Action<&'static str, Payments>> = ActionsContainer::new().unwrap();
+    assert!(!actions.has_tracked() && !actions.has_untracked());
+    actions.add(Action::Untracked("log")).unwrap();
+    assert!(!actions.has_tracked() && actions.has_untracked());
+
+    let mut counting = CountingActions::default();
+    emit_three(&mut counting);
+    assert!(
+        ActionsContainer::<&'static str, Payments>::has_tracked(&counting),
+        "Overridden where iterating sees nothing"
+    );
+
+    let mut priority: PriorityActions<&'static str, Payments> = ActionsContainer::new().unwrap();
+    priority
+        .add(Action::Tracked(TrackedAction::new(1, "preauth")))
+        .unwrap();
+    assert!(priority.has_tracked() && !priority.has_untracked());
+}
+#[test]
+fn test_drain_partition_empties_the_container() {
+    let mut actions: Vec<Action<&'static str, Payments>> = ActionsContainer::new().unwrap();
+    actions.add(Action::Untracked("notify")).unwrap();
+    actions
+        .add(Action::Tracked(TrackedAction::new(1, "preauth")))
+        .unwrap();
+    actions.add(Action::Untracked("log")).unwrap();
+    actions
+        .add(Action::Tracked(TrackedAction::new(2, "capture")))
+        .unwrap();
+
+    let (untracked, tracked) = actions.drain_partition().unwrap();
+    assert_eq!(untracked, ["notify", "log"]);
+    let tracked: Vec<(u64, &str)> = tracked.iter().map(|t| (*t.id(), *t.action())).collect();
+    assert_eq!(tracked, [(1, "preauth"), (2, "capture")]);
+    assert!(actions.is_empty());
+
+    let mut priority: PriorityActions<&'static str, Payments> = ActionsContainer::new().unwrap();
+    priority.add(Action::Untracked("log")).unwrap();
+    priority
+        .add(Action::Tracked(TrackedAction::new(3, "refund")))
+        .unwrap();
+    let (untracked, tracked) = priority.drain_partition().unwrap();
+    assert_eq!((untracked.len(), tracked.len()), (1, 1));
+    assert!(priority.is_empty() && !priority.has_tracked());
+}
+#[test]
+fn test_priority_actions_yields_tracked_first() {
+    let mut actions: PriorityActions<&'static str, Payments> = ActionsContainer::new().unwrap();
+    actions.add(Action::Untracked("show:pending")).unwrap();
+    actions
+        .add(Action::Tracked(TrackedAction::new(1, "preauth")))
+        .unwrap();
+    actions.add(Action::Untracked("log")).unwrap();
+    actions
+        .add(Action::Tracked(TrackedAction::new(2, "capture")))
+        .unwrap();
+
+    let order: Vec<String> = actions
+        .iter()
+        .map(|action| match action {
+            Action::Tracked(t) => t.action().to_string(),
+            Action::Untracked(u) => u.to_string(),
+        })
+        .collect();
+    assert_eq!(order, ["preauth", "capture", "show:pending", "log"]);
+    assert_eq!(actions.len(), 4);
+    assert_eq!(
+        actions.iter_untracked().copied().collect::<Vec<_>>(),
+        ["show:pending", "log"]
+    );
+
+    let mut actions =
+        PriorityActions::<&'static str, Payments>::with_order(ActionOrder::UntrackedFirst);
+    actions
+        .add(Action::Tracked(TrackedAction::new(1, "preauth")))
+        .unwrap();
+    actions.add(Action::Untracked("log")).unwrap();
+    assert!(matches!(
+        actions.iter().next(),
+        Some(Action::Untracked("log"))
+    ));
+
+    actions.clear().unwrap();
+    assert!(actions.is_empty());
+    assert_eq!(
+        actions.order(),
+        ActionOrder::UntrackedFirst,
+        "Clearing keeps the order"
+    );
+}
+
+#[test]
+fn test_input_clone_and_debug_need_only_payload_bounds() {
+    /// Neither `Clone` nor `Debug`.
+    struct Refunds;
+
+    impl TrackedActionTypes for Refunds {
+        type Id = u64;
+        type Action = ();
+        type Result = Result<(), String>;
+    }
+
+    let normal: Input<Refunds, &str> = Input::Normal("refund 7");
+    let completed: Input<Refunds, &str> = Input::TrackedActionCompleted {
+        id: 7,
+        res: Err("card expired".to_string()),
+    };
+    assert_eq!(format!("{:?}", normal.clone()), r#"Normal("refund 7")"#);
+    assert_eq!(
+        format!("{:?}", completed.clone()),
+        r#"TrackedActionCompleted { id: 7, res: Err("card expired") }"#
+    );
+}
@@ -0,0 +1,114 @@
+use std::future;
+
+use phasm::{
+    AsyncStateMachine, Input, StateMachine,
+    actions::{Action, ActionsContainer, TrackedAction, TrackedActionTypes},
+    atomic_stf,
+};
+
+/// Reserves seats, each held by a tracked payment until it completes.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Seats {
+    free: u32,
+    held: Vec<u32>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum SeatError {
+    SoldOut,
+    NotHeld,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Payment;
+
+impl TrackedActionTypes for Payment {
+    /// The seat paid for.
+    type Id = u32;
+    type Action = ();
+    type Result = ();
+}
+
+impl AsyncStateMachine for Seats {
+    type TrackedAction = Payment;
+    type UntrackedAction = ();
+    type Actions = Vec<Action<(), Payment>>;
+    type State = Self;
+    type Input = ();
+    type TransitionError = SeatError;
+    type RestoreError = ();
+
+    async fn stf(
+        state: &mut Self::State,
+        input: Input<Self::TrackedAction, Self::Input>,
+        actions: &mut Self::Actions,
+    ) -> Result<(), Self::TransitionError> {
+        future::ready(()).await;
+        match input {
+            Input::Normal(()) => {
+                state.free = state.free.checked_sub(1).ok_or(SeatError::SoldOut)?;
+                state.held.push(state.free);
+                let _ = actions.add(Action::Tracked(TrackedAction::new(state.free, ())));
+            }
+            Input::TrackedActionCompleted { id, .. } | Input::TrackedActionExpired { id } => {
+                let i = state
+                    .held
+                    .iter()
+                    .position(|seat| *seat == id)
+                    .ok_or(SeatError::NotHeld)?;
+                state.held.remove(i);
+            }
+        }
+        Ok(())
+    }
+
+    async fn restore(
+        state: &Self::State,
+        actions: &mut Self::Actions,
+    ) -> Result<(), Self::RestoreError> {
+        actions.clear();
+        for seat in &state.held {
+            let _ = actions.add(Action::Tracked(TrackedAction::new(*seat, ())));
+        }
+        Ok(())
+    }
+
+    fn is_conflict(error: &Self::TransitionError) -> bool {
+        *error == SeatError::SoldOut
+    }
+}
+
+#[monoio::test]
+async fn test_async_state_machine_is_a_state_machine() {
+    let mut state = Seats {
+        free: 1,
+        held: Vec::new(),
+    };
+    let mut actions = Vec::new();
+
+    atomic_stf::<Seats>(&mut state, Input::Normal(()), &mut actions)
+        .await
+        .unwrap();
+    assert_eq!(actions, [Action::Tracked(TrackedAction::new(0, ()))]);
+    assert_eq!(
+        <Seats as StateMachine>::restore_owned(&state)
+            .await
+            .unwrap(),
+        actions
+    );
+
+    let err = <Seats as StateMachine>::stf(&mut state, Input::Normal(()), &mut actions)
+        .await
+        .unwrap_err();
+    assert!(
+        <Seats as StateMachine>::is_conflict(&err),
+        "Hooks are forwarded"
+    );
+    assert!(!<Seats as StateMachine>::is_read_only(&()));
+
+    let completed = Input::TrackedActionCompleted { id: 0, res: () };
+    <Seats as StateMachine>::stf(&mut state, completed, &mut actions)
+        .await
+        .unwrap();
+    assert!(state.held.is_empty());
+}
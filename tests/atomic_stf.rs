@@ -0,0 +1,176 @@
+use std::future;
+
+use phasm::{
+    Input, StateMachine,
+    actions::{Action, TrackedActionTypes},
+    apply_all_atomic, atomic_stf,
+};
+
+/// The counter from `examples/csm.rs`, but it increments before checking for overflow
+/// and announces the increment before the check, violating STF atomicity. It also never
+/// checks `max`.
+#[derive(Debug, Clone, PartialEq)]
+struct SloppyCounter {
+    counter: u64,
+    max: u64,
+}
+
+#[derive(Debug, PartialEq)]
+enum CounterError {
+    Overflowed,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum CounterAction {
+    Incremented { to: u64 },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct NoTracked;
+
+impl TrackedActionTypes for NoTracked {
+    type Id = ();
+    type Action = ();
+    type Result = ();
+}
+
+impl StateMachine for SloppyCounter {
+    type TrackedAction = NoTracked;
+    type UntrackedAction = CounterAction;
+    type Actions = Vec<Action<CounterAction, NoTracked>>;
+    type State = Self;
+    type Input = ();
+    type TransitionError = CounterError;
+    type RestoreError = ();
+    type StfFuture<'state, 'actions> = future::Ready<Result<(), CounterError>>;
+    type RestoreFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+
+    fn stf<'state, 'actions>(
+        state: &'state mut Self::State,
+        _input: Input<Self::TrackedAction, Self::Input>,
+        actions: &'actions mut Self::Actions,
+    ) -> Self::StfFuture<'state, 'actions> {
+        state.counter = state.counter.wrapping_add(1);
+        actions.push(Action::Untracked(CounterAction::Incremented {
+            to: state.counter,
+        }));
+        if state.counter == 0 {
+            return future::ready(Err(CounterError::Overflowed));
+        }
+        future::ready(Ok(()))
+    }
+
+    fn check_invariants(state: &Self) -> Result<(), String> {
+        if state.counter > state.max {
+            return Err(format!("counter {} is over {}", state.counter, state.max));
+        }
+        Ok(())
+    }
+
+    fn restore<'state, 'actions>(
+        _state: &'state Self::State,
+        _actions: &'actions mut Self::Actions,
+    ) -> Self::RestoreFuture<'state, 'actions> {
+        future::ready(Ok(()))
+    }
+}
+
+#[monoio::test]
+async fn test_atomic_stf_rolls_back_failed_transition() {
+    // Without the wrapper the overflow leaves a wrapped counter and a stale action
+    let mut state = SloppyCounter {
+        counter: u64::MAX,
+        max: u64::MAX,
+    };
+    let mut actions = Vec::new();
+    let res = SloppyCounter::stf(&mut state, Input::Normal(()), &mut actions).await;
+    assert_eq!(res, Err(CounterError::Overflowed));
+    assert_eq!(state.counter, 0);
+    assert_eq!(actions.len(), 1);
+
+    let mut state = SloppyCounter {
+        counter: u64::MAX,
+        max: u64::MAX,
+    };
+    let mut actions = Vec::new();
+    let res = atomic_stf::<SloppyCounter>(&mut state, Input::Normal(()), &mut actions).await;
+    assert_eq!(res, Err(CounterError::Overflowed));
+    assert_eq!(
+        state,
+        SloppyCounter {
+            counter: u64::MAX,
+            max: u64::MAX,
+        }
+    );
+    assert!(
+        actions.is_empty(),
+        "Actions of a failed transition are cleared"
+    );
+}
+
+#[monoio::test]
+async fn test_atomic_stf_keeps_successful_transition() {
+    let mut state = SloppyCounter {
+        counter: 41,
+        max: u64::MAX,
+    };
+    let mut actions = Vec::new();
+    atomic_stf::<SloppyCounter>(&mut state, Input::Normal(()), &mut actions)
+        .await
+        .unwrap();
+    assert_eq!(state.counter, 42);
+    assert_eq!(
+        actions,
+        [Action::Untracked(CounterAction::Incremented { to: 42 })]
+    );
+}
+
+#[monoio::test]
+#[should_panic(expected = "state invariant violated after transition: counter 11 is over 10")]
+async fn test_atomic_stf_checks_invariants() {
+    let mut state = SloppyCounter {
+        counter: 9,
+        max: 10,
+    };
+    let mut actions = Vec::new();
+    for _ in 0..2 {
+        atomic_stf::<SloppyCounter>(&mut state, Input::Normal(()), &mut actions)
+            .await
+            .unwrap();
+    }
+}
+
+#[monoio::test]
+async fn test_apply_all_atomic_rolls_back_iterator_batch() {
+    let mut state = SloppyCounter {
+        counter: u64::MAX - 2,
+        max: u64::MAX,
+    };
+    let mut actions = Vec::new();
+    let mut pulled = 0;
+    let inputs = std::iter::repeat_with(|| {
+        pulled += 1;
+        Input::Normal(())
+    })
+    .take(5);
+    let res = apply_all_atomic::<SloppyCounter>(&mut state, inputs, &mut actions).await;
+
+    assert_eq!(res, Err((2, CounterError::Overflowed)));
+    assert_eq!(pulled, 3, "Inputs after the failure aren't pulled");
+    assert_eq!(state.counter, u64::MAX - 2);
+    assert!(actions.is_empty());
+
+    let inputs = std::iter::repeat_with(|| Input::Normal(())).take(2);
+    apply_all_atomic::<SloppyCounter>(&mut state, inputs, &mut actions)
+        .await
+        .unwrap();
+    assert_eq!(state.counter, u64::MAX);
+    assert_eq!(
+        actions,
+        [
+            Action::Untracked(CounterAction::Incremented { to: u64::MAX - 1 }),
+            Action::Untracked(CounterAction::Incremented { to: u64::MAX })
+        ],
+        "Actions of every input accumulate"
+    );
+}
@@ -0,0 +1,154 @@
+use std::{
+    fmt::{self, Write},
+    future,
+};
+
+use phasm::{
+    Input, StateMachine,
+    actions::{Action, ActionsContainer, ArenaActions, ArenaFull, TrackedActionTypes},
+    bench::{CountingAllocator, allocations, bench},
+};
+
+#[global_allocator]
+static ALLOC: CountingAllocator = CountingAllocator;
+
+/// Sums inputs, and when asked to, emits an action with a freshly allocated label.
+#[derive(Debug, Clone, Default)]
+struct Summer {
+    total: u64,
+}
+
+#[derive(Debug)]
+struct SummerTracked;
+
+impl TrackedActionTypes for SummerTracked {
+    type Id = ();
+    type Action = ();
+    type Result = ();
+}
+
+impl StateMachine for Summer {
+    type TrackedAction = SummerTracked;
+    type UntrackedAction = Option<String>;
+    type Actions = Vec<Action<Option<String>, SummerTracked>>;
+    type State = Self;
+    /// Amount to add, and whether to label the action.
+    type Input = (u64, bool);
+    type TransitionError = ();
+    type RestoreError = ();
+    type StfFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+    type RestoreFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+
+    fn stf<'state, 'actions>(
+        state: &'state mut Self::State,
+        input: Input<Self::TrackedAction, Self::Input>,
+        actions: &'actions mut Self::Actions,
+    ) -> Self::StfFuture<'state, 'actions> {
+        let Input::Normal((amount, label)) = input else {
+            return future::ready(Err(()));
+        };
+        state.total += amount;
+        actions.push(Action::Untracked(label.then(|| format!("+{}", amount))));
+        future::ready(Ok(()))
+    }
+
+    fn restore<'state, 'actions>(
+        _state: &'state Self::State,
+        _actions: &'actions mut Self::Actions,
+    ) -> Self::RestoreFuture<'state, 'actions> {
+        future::ready(Ok(()))
+    }
+}
+
+#[monoio::test]
+async fn test_bench_counts_transitions_and_errors() {
+    let inputs = vec![
+        Input::Normal((1, false)),
+        Input::Normal((2, false)),
+        Input::TrackedActionCompleted { id: (), res: () },
+    ];
+    let report = bench::<Summer>(&Summer::default(), &inputs, 10).await;
+    assert_eq!(report.transitions, 30);
+    assert_eq!(report.errors, 10);
+    assert!(report.transitions_per_sec() > 0.0);
+}
+
+#[monoio::test]
+async fn test_bench_counts_allocations() {
+    assert!(allocations().is_some(), "CountingAllocator is installed");
+
+    // The reused actions container allocates once, on the first push
+    let quiet = vec![Input::Normal((1, false))];
+    let report = bench::<Summer>(&Summer::default(), &quiet, 100).await;
+    assert_eq!(report.allocations, Some(1));
+
+    let labelled = vec![Input::Normal((1, true))];
+    let report = bench::<Summer>(&Summer::default(), &labelled, 100).await;
+    assert_eq!(report.allocations, Some(101), "One label per transition");
+}
+/// Like `Summer`, but labels every action, reusing labels recycled by an arena.
+#[derive(Debug, Clone, Default)]
+struct ArenaSummer {
+    total: u64,
+}
+
+impl StateMachine for ArenaSummer {
+    type TrackedAction = SummerTracked;
+    type UntrackedAction = String;
+    type Actions = ArenaActions<String, SummerTracked>;
+    type State = Self;
+    type Input = u64;
+    type TransitionError = ();
+    type RestoreError = ();
+    type StfFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+    type RestoreFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+
+    fn stf<'state, 'actions>(
+        state: &'state mut Self::State,
+        input: Input<Self::TrackedAction, Self::Input>,
+        actions: &'actions mut Self::Actions,
+    ) -> Self::StfFuture<'state, 'actions> {
+        let Input::Normal(amount) = input else {
+            return future::ready(Err(()));
+        };
+        state.total += amount;
+        let res = label(actions, format_args!("+{}", amount))
+            .and_then(|()| label(actions, format_args!("={}", state.total)));
+        future::ready(res.map_err(|_| ()))
+    }
+
+    fn restore<'state, 'actions>(
+        _state: &'state Self::State,
+        _actions: &'actions mut Self::Actions,
+    ) -> Self::RestoreFuture<'state, 'actions> {
+        future::ready(Ok(()))
+    }
+}
+
+fn label(
+    actions: &mut ArenaActions<String, SummerTracked>,
+    text: fmt::Arguments<'_>,
+) -> Result<(), ArenaFull> {
+    let mut label = match actions.recycle() {
+        Some(Action::Untracked(label)) => label,
+        _ => String::with_capacity(16),
+    };
+    label.clear();
+    label.write_fmt(text).unwrap();
+    actions.add(Action::Untracked(label))
+}
+
+#[monoio::test]
+async fn test_arena_actions_steady_state_does_not_allocate() {
+    assert!(allocations().is_some(), "CountingAllocator is installed");
+
+    let inputs: Vec<_> = (1..=8).map(Input::Normal).collect();
+    let report = bench::<ArenaSummer>(&ArenaSummer::default(), &inputs, 100).await;
+    assert_eq!(report.transitions, 800);
+    assert_eq!(report.errors, 0);
+    assert_eq!(
+        report.allocations,
+        Some(2),
+        "Only the first transition's labels are allocated"
+    );
+}
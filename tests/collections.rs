@@ -0,0 +1,29 @@
+use phasm::collections::OrderedMap;
+
+#[test]
+fn test_ordered_map_iterates_by_key() {
+    let mut map = OrderedMap::new();
+    for id in [42u64, 7, 19, 3] {
+        map.insert(id, id * 10);
+    }
+    let keys: Vec<u64> = map.keys().copied().collect();
+    assert_eq!(keys, vec![3, 7, 19, 42]);
+
+    let collected: OrderedMap<u64, u64> = [(42, 420), (3, 30), (19, 190), (7, 70)]
+        .into_iter()
+        .collect();
+    assert_eq!(collected, map, "Insertion order must not matter");
+}
+
+#[test]
+fn test_ordered_map_serializes_as_plain_map() {
+    let map: OrderedMap<String, u32> = [("b".to_string(), 2), ("a".to_string(), 1)]
+        .into_iter()
+        .collect();
+    let json = serde_json::to_string(&map).unwrap();
+    assert_eq!(json, r#"{"a":1,"b":2}"#);
+    assert_eq!(
+        serde_json::from_str::<OrderedMap<String, u32>>(&json).unwrap(),
+        map
+    );
+}
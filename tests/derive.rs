@@ -0,0 +1,137 @@
+use std::{cell::Cell, collections::BTreeMap, future};
+
+use phasm::{
+    Input, StateMachine,
+    actions::{Action, ActionsContainer, TrackedAction, TrackedActionTypes},
+};
+
+/// A key-value store behind an async API, like a database transaction.
+#[derive(Debug, Default)]
+struct Store {
+    values: BTreeMap<u64, u64>,
+    reads: Cell<u32>,
+}
+
+impl Store {
+    async fn get(&self, key: u64) -> Option<u64> {
+        future::ready(()).await;
+        self.reads.set(self.reads.get() + 1);
+        self.values.get(&key).copied()
+    }
+
+    async fn set(&mut self, key: u64, value: u64) {
+        future::ready(()).await;
+        self.values.insert(key, value);
+    }
+}
+
+/// Sends funds and records them as in flight until the transfer completes.
+#[derive(Debug, Default)]
+struct Transfers {
+    store: Store,
+    next_id: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum TransferError {
+    InsufficientFunds,
+    UnknownTransfer,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Transfer;
+
+impl TrackedActionTypes for Transfer {
+    type Id = u64;
+    type Action = u64;
+    type Result = ();
+}
+
+const BALANCE: u64 = 0;
+const IN_FLIGHT: u64 = 1_000;
+
+#[phasm::state_machine]
+impl StateMachine for Transfers {
+    type TrackedAction = Transfer;
+    type UntrackedAction = ();
+    type Actions = Vec<Action<(), Transfer>>;
+    type State = Self;
+    type Input = u64;
+    type TransitionError = TransferError;
+    type RestoreError = ();
+
+    async fn stf(
+        state: &mut Self::State,
+        input: Input<Self::TrackedAction, Self::Input>,
+        actions: &mut Self::Actions,
+    ) -> Result<(), Self::TransitionError> {
+        match input {
+            Input::Normal(amount) => {
+                let balance = state.store.get(BALANCE).await.unwrap_or(0);
+                let rest = balance
+                    .checked_sub(amount)
+                    .ok_or(TransferError::InsufficientFunds)?;
+                let id = state.next_id;
+                state.next_id += 1;
+                state.store.set(BALANCE, rest).await;
+                state.store.set(IN_FLIGHT + id, amount).await;
+                let _ = actions.add(Action::Tracked(TrackedAction::new(id, amount)));
+            }
+            Input::TrackedActionCompleted { id, .. } | Input::TrackedActionExpired { id } => {
+                state
+                    .store
+                    .values
+                    .remove(&(IN_FLIGHT + id))
+                    .ok_or(TransferError::UnknownTransfer)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn restore(
+        state: &Self::State,
+        actions: &mut Self::Actions,
+    ) -> Result<(), Self::RestoreError> {
+        actions.clear();
+        for (key, amount) in state.store.values.range(IN_FLIGHT..) {
+            let _ = actions.add(Action::Tracked(TrackedAction::new(
+                key - IN_FLIGHT,
+                *amount,
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[monoio::test]
+async fn test_state_machine_attribute_runs_async_bodies() {
+    let mut state = Transfers::default();
+    state.store.values.insert(BALANCE, 100);
+    let mut actions = Vec::new();
+
+    Transfers::stf(&mut state, Input::Normal(30), &mut actions)
+        .await
+        .unwrap();
+    assert_eq!(
+        actions,
+        [Action::Tracked(TrackedAction::new(0, 30))],
+        "Actions are emitted through the borrowed container"
+    );
+    assert_eq!(state.store.reads.get(), 1);
+
+    let result = Transfers::stf(&mut state, Input::Normal(80), &mut actions).await;
+    assert_eq!(result, Err(TransferError::InsufficientFunds));
+    assert_eq!(
+        state.store.values[&BALANCE], 70,
+        "`?` returns before mutating"
+    );
+
+    let restored = Transfers::restore_owned(&state).await.unwrap();
+    assert_eq!(restored, [Action::Tracked(TrackedAction::new(0, 30))]);
+
+    let completed = Input::TrackedActionCompleted { id: 0, res: () };
+    Transfers::stf(&mut state, completed, &mut actions)
+        .await
+        .unwrap();
+    assert!(Transfers::restore_owned(&state).await.unwrap().is_empty());
+}
@@ -0,0 +1,436 @@
+use std::{collections::BTreeMap, future, time::Duration};
+
+use phasm::{
+    Input, StateMachine,
+    actions::{Action, TrackedAction, TrackedActionTypes},
+    driver::{ActionExecutor, Driver, RecordingDriver, RetryPolicy, replay},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+enum OrderStatus {
+    AwaitingCharge,
+    Paid,
+    Declined,
+}
+
+/// Orders that are charged, and get a receipt once the charge succeeds.
+#[derive(Debug, Default, serde::Serialize)]
+struct Shop {
+    orders: BTreeMap<u64, OrderStatus>,
+    next_id: u64,
+}
+
+#[derive(Debug)]
+struct ChargeTracked;
+
+impl TrackedActionTypes for ChargeTracked {
+    type Id = u64;
+    /// Amount to charge.
+    type Action = u64;
+    /// Whether the charge went through.
+    type Result = bool;
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Notice {
+    Placed(u64),
+    Receipt(u64),
+}
+
+impl StateMachine for Shop {
+    type TrackedAction = ChargeTracked;
+    type UntrackedAction = Notice;
+    type Actions = Vec<Action<Notice, ChargeTracked>>;
+    type State = Self;
+    /// Order amount; zero is rejected.
+    type Input = u64;
+    type TransitionError = ();
+    type RestoreError = ();
+    type StfFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+    type RestoreFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+
+    fn stf<'state, 'actions>(
+        state: &'state mut Self::State,
+        input: Input<Self::TrackedAction, Self::Input>,
+        actions: &'actions mut Self::Actions,
+    ) -> Self::StfFuture<'state, 'actions> {
+        match input {
+            Input::Normal(0) => return future::ready(Err(())),
+            Input::Normal(amount) => {
+                let id = state.next_id;
+                state.next_id += 1;
+                state.orders.insert(id, OrderStatus::AwaitingCharge);
+                actions.push(Action::Untracked(Notice::Placed(id)));
+                actions.push(Action::Tracked(TrackedAction::new(id, amount)));
+            }
+            Input::TrackedActionCompleted { id, res } => {
+                let Some(status) = state.orders.get_mut(&id) else {
+                    return future::ready(Err(()));
+                };
+                if res {
+                    *status = OrderStatus::Paid;
+                    actions.push(Action::Untracked(Notice::Receipt(id)));
+                } else {
+                    *status = OrderStatus::Declined;
+                }
+            }
+            Input::TrackedActionExpired { id } => {
+                let Some(status) = state.orders.get_mut(&id) else {
+                    return future::ready(Err(()));
+                };
+                *status = OrderStatus::Declined;
+            }
+        }
+        future::ready(Ok(()))
+    }
+
+    fn restore<'state, 'actions>(
+        _state: &'state Self::State,
+        _actions: &'actions mut Self::Actions,
+    ) -> Self::RestoreFuture<'state, 'actions> {
+        future::ready(Ok(()))
+    }
+}
+
+/// Records what it executed, declining charges over `limit`.
+struct Gateway {
+    limit: u64,
+    log: Vec<String>,
+}
+
+impl ActionExecutor<Notice, ChargeTracked> for Gateway {
+    async fn execute_untracked(&mut self, action: &Notice) {
+        self.log.push(format!("{:?}", action));
+    }
+
+    async fn execute_tracked(&mut self, id: &u64, amount: &u64) -> bool {
+        self.log.push(format!("Charge({}, {})", id, amount));
+        *amount <= self.limit
+    }
+}
+
+fn driver() -> Driver<Shop, Gateway> {
+    let gateway = Gateway {
+        limit: 100,
+        log: Vec::new(),
+    };
+    Driver::new(Shop::default(), gateway).unwrap()
+}
+
+#[monoio::test]
+async fn test_submit_executes_actions_and_feeds_results_back() {
+    let mut driver = driver();
+    driver.submit(40).await.unwrap();
+    driver.submit(500).await.unwrap();
+
+    assert_eq!(
+        driver.state().orders,
+        BTreeMap::from([(0, OrderStatus::Paid), (1, OrderStatus::Declined)])
+    );
+    assert_eq!(
+        driver.executor().log,
+        [
+            "Placed(0)",
+            "Charge(0, 40)",
+            "Receipt(0)",
+            "Placed(1)",
+            "Charge(1, 500)"
+        ]
+    );
+}
+
+#[monoio::test]
+async fn test_failed_input_executes_nothing() {
+    let mut driver = driver();
+    assert_eq!(driver.submit(0).await, Err(()));
+    assert!(driver.state().orders.is_empty());
+
+    let (_, gateway) = driver.into_parts();
+    assert!(gateway.log.is_empty());
+}
+/// Starts one job per id in the input, in the given order, and records the order their
+/// results are applied in.
+#[derive(Debug, Default, PartialEq)]
+struct Jobs {
+    applied: Vec<u64>,
+    expired: Vec<u64>,
+    /// Deadline given to every job started.
+    deadline: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct JobTracked;
+
+/// Outcome of a job; `Busy` is a transient failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Run {
+    Done,
+    Busy,
+}
+
+impl TrackedActionTypes for JobTracked {
+    type Id = u64;
+    type Action = ();
+    type Result = Run;
+}
+
+impl StateMachine for Jobs {
+    type TrackedAction = JobTracked;
+    type UntrackedAction = ();
+    type Actions = Vec<Action<(), JobTracked>>;
+    type State = Self;
+    type Input = Vec<u64>;
+    type TransitionError = ();
+    type RestoreError = ();
+    type StfFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+    type RestoreFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+
+    fn stf<'state, 'actions>(
+        state: &'state mut Self::State,
+        input: Input<Self::TrackedAction, Self::Input>,
+        actions: &'actions mut Self::Actions,
+    ) -> Self::StfFuture<'state, 'actions> {
+        match input {
+            Input::Normal(ids) => {
+                for id in ids {
+                    let job = match state.deadline {
+                        Some(deadline) => TrackedAction::with_deadline(id, (), deadline),
+                        None => TrackedAction::new(id, ()),
+                    };
+                    actions.push(Action::Tracked(job));
+                }
+            }
+            Input::TrackedActionCompleted { id, .. } => state.applied.push(id),
+            Input::TrackedActionExpired { id } => state.expired.push(id),
+        }
+        future::ready(Ok(()))
+    }
+
+    fn restore<'state, 'actions>(
+        _state: &'state Self::State,
+        _actions: &'actions mut Self::Actions,
+    ) -> Self::RestoreFuture<'state, 'actions> {
+        future::ready(Ok(()))
+    }
+}
+
+#[derive(Default)]
+struct Worker {
+    executed: Vec<u64>,
+}
+
+impl ActionExecutor<(), JobTracked> for Worker {
+    async fn execute_untracked(&mut self, _action: &()) {}
+
+    async fn execute_tracked(&mut self, id: &u64, _action: &()) -> Run {
+        self.executed.push(*id);
+        Run::Done
+    }
+}
+
+#[monoio::test]
+async fn test_results_applied_in_id_order() {
+    let mut states = Vec::new();
+    for ids in [vec![3, 1, 2], vec![2, 3, 1]] {
+        let mut driver = Driver::<Jobs, _>::new(Jobs::default(), Worker::default()).unwrap();
+        driver.submit(ids.clone()).await.unwrap();
+
+        assert_eq!(driver.executor().executed, ids);
+        let (state, _) = driver.into_parts();
+        assert_eq!(state.applied, [1, 2, 3]);
+        states.push(state);
+    }
+    assert_eq!(states[0], states[1]);
+}
+
+#[monoio::test]
+async fn test_replayed_log_reproduces_state() {
+    let gateway = Gateway {
+        limit: 100,
+        log: Vec::new(),
+    };
+    let mut driver = RecordingDriver::<Shop, _>::new(Shop::default(), gateway).unwrap();
+    driver.submit(40).await.unwrap();
+    assert_eq!(driver.submit(0).await, Err(()));
+    driver.submit(500).await.unwrap();
+
+    let (state, _, log) = driver.into_parts();
+    let entries: Vec<_> = log
+        .iter()
+        .map(|input| match input {
+            Input::Normal(amount) => format!("Normal({})", amount),
+            Input::TrackedActionCompleted { id, res } => format!("Completed({}, {})", id, res),
+            Input::TrackedActionExpired { id } => format!("Expired({})", id),
+        })
+        .collect();
+    assert_eq!(
+        entries,
+        [
+            "Normal(40)",
+            "Completed(0, true)",
+            "Normal(500)",
+            "Completed(1, false)"
+        ],
+        "Failed inputs aren't logged"
+    );
+
+    let replayed = replay::<Shop>(Shop::default(), log).await.unwrap();
+    assert_eq!(
+        serde_json::to_vec(&replayed).unwrap(),
+        serde_json::to_vec(&state).unwrap()
+    );
+}
+
+/// Fails every attempt until `failures` attempts have failed, then succeeds, or hangs
+/// past any limit if `hangs`.
+#[derive(Default)]
+struct FlakyWorker {
+    failures: u32,
+    hangs: bool,
+    attempts: u32,
+    /// Time limit of each attempt given one.
+    limits: Vec<Duration>,
+    waited: Vec<Duration>,
+}
+
+impl ActionExecutor<(), JobTracked> for FlakyWorker {
+    async fn execute_untracked(&mut self, _action: &()) {}
+
+    async fn execute_tracked(&mut self, _id: &u64, _action: &()) -> Run {
+        self.attempts += 1;
+        if self.attempts > self.failures {
+            Run::Done
+        } else {
+            Run::Busy
+        }
+    }
+
+    async fn execute_tracked_within(
+        &mut self,
+        id: &u64,
+        action: &(),
+        limit: Duration,
+    ) -> Option<Run> {
+        self.limits.push(limit);
+        let run = self.execute_tracked(id, action).await;
+        (run == Run::Busy || !self.hangs).then_some(run)
+    }
+
+    fn is_transient(&self, run: &Run) -> bool {
+        *run == Run::Busy
+    }
+
+    async fn backoff(&mut self, delay: Duration) {
+        self.waited.push(delay);
+    }
+}
+
+#[monoio::test]
+async fn test_transient_failures_are_retried() {
+    let ms = Duration::from_millis;
+    let worker = FlakyWorker {
+        failures: 2,
+        ..Default::default()
+    };
+    let mut driver = Driver::<Jobs, _>::new(Jobs::default(), worker)
+        .unwrap()
+        .retry(RetryPolicy::new(3, vec![ms(10), ms(20)]));
+    driver.submit(vec![7]).await.unwrap();
+
+    assert_eq!(driver.executor().attempts, 3);
+    assert_eq!(driver.executor().waited, [ms(10), ms(20)]);
+    assert_eq!(driver.state().applied, [7], "STF sees one completion");
+
+    // Out of attempts: the last failure is fed back, and the schedule's last delay repeats
+    let worker = FlakyWorker {
+        failures: 10,
+        ..Default::default()
+    };
+    let mut driver = Driver::<Jobs, _>::new(Jobs::default(), worker)
+        .unwrap()
+        .retry(RetryPolicy::new(4, vec![ms(10)]));
+    driver.submit(vec![7]).await.unwrap();
+
+    assert_eq!(driver.executor().attempts, 4);
+    assert_eq!(driver.executor().waited, [ms(10); 3]);
+    assert_eq!(driver.state().applied, [7]);
+}
+
+#[monoio::test]
+async fn test_retries_stop_at_deadline() {
+    let ms = Duration::from_millis;
+    let jobs = Jobs {
+        deadline: Some(ms(25)),
+        ..Default::default()
+    };
+    let worker = FlakyWorker {
+        failures: 10,
+        ..Default::default()
+    };
+    let mut driver = Driver::<Jobs, _>::new(jobs, worker)
+        .unwrap()
+        .retry(RetryPolicy::new(5, vec![ms(10)]));
+    driver.submit(vec![7]).await.unwrap();
+
+    // A third retry would wait until 30ms
+    assert_eq!(driver.executor().attempts, 3);
+    assert_eq!(driver.executor().waited, [ms(10), ms(10)]);
+    assert_eq!(driver.executor().limits, [ms(25), ms(15), ms(5)]);
+    assert_eq!(driver.state().expired, [7]);
+    assert!(driver.state().applied.is_empty());
+
+    // Succeeding within the deadline is an ordinary result
+    let jobs = Jobs {
+        deadline: Some(ms(25)),
+        ..Default::default()
+    };
+    let worker = FlakyWorker {
+        failures: 2,
+        ..Default::default()
+    };
+    let mut driver = Driver::<Jobs, _>::new(jobs, worker)
+        .unwrap()
+        .retry(RetryPolicy::new(5, vec![ms(10)]));
+    driver.submit(vec![7]).await.unwrap();
+
+    assert_eq!(driver.state().applied, [7]);
+    assert!(driver.state().expired.is_empty());
+}
+
+#[monoio::test]
+async fn test_hung_attempt_expires() {
+    let ms = Duration::from_millis;
+    let jobs = Jobs {
+        deadline: Some(ms(25)),
+        ..Default::default()
+    };
+    let worker = FlakyWorker {
+        hangs: true,
+        ..Default::default()
+    };
+    let mut driver = Driver::<Jobs, _>::new(jobs, worker).unwrap();
+    driver.submit(vec![7]).await.unwrap();
+
+    // Enforced without any retry policy
+    assert_eq!(driver.executor().limits, [ms(25)]);
+    assert_eq!(driver.state().expired, [7]);
+
+    // A retry only gets what the backoff left of the deadline
+    let jobs = Jobs {
+        deadline: Some(ms(25)),
+        ..Default::default()
+    };
+    let worker = FlakyWorker {
+        failures: 1,
+        hangs: true,
+        ..Default::default()
+    };
+    let mut driver = Driver::<Jobs, _>::new(jobs, worker)
+        .unwrap()
+        .retry(RetryPolicy::new(5, vec![ms(10)]));
+    driver.submit(vec![7]).await.unwrap();
+
+    assert_eq!(driver.executor().limits, [ms(25), ms(15)]);
+    assert_eq!(driver.state().expired, [7]);
+    assert!(driver.state().applied.is_empty());
+}
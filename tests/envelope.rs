@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use phasm::actions::{
+    ACTION_ENVELOPE_VERSION, Action, ActionEnvelope, TrackedAction, TrackedActionTypes, TxnId,
+};
+
+#[derive(Debug, PartialEq, Eq)]
+struct Outbox;
+
+impl TrackedActionTypes for Outbox {
+    type Id = u64;
+    type Action = String;
+    type Result = ();
+}
+
+type Envelope = ActionEnvelope<String, Outbox>;
+
+#[test]
+fn test_current_envelope_round_trips() {
+    let envelope = Envelope::new(Action::Tracked(TrackedAction::new_in_txn(
+        7,
+        "refund".into(),
+        TxnId(3),
+    )));
+    assert_eq!(envelope.version, ACTION_ENVELOPE_VERSION);
+
+    let json = serde_json::to_string(&envelope).unwrap();
+    let decoded: Envelope = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded, envelope);
+}
+
+#[test]
+fn test_mixed_actions_round_trip() {
+    let actions: Vec<Action<String, Outbox>> = vec![
+        Action::Untracked("log".into()),
+        Action::Tracked(TrackedAction::new(1, "charge".into())),
+        Action::Tracked(TrackedAction::new_in_txn(2, "refund".into(), TxnId(3))),
+        Action::Tracked(TrackedAction::with_deadline(
+            4,
+            "capture".into(),
+            Duration::from_secs(30),
+        )),
+        Action::Untracked("notify".into()),
+    ];
+
+    let json = serde_json::to_string(&actions).unwrap();
+    let decoded: Vec<Action<String, Outbox>> = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded, actions);
+
+    let bytes = postcard::to_allocvec(&actions).unwrap();
+    let decoded: Vec<Action<String, Outbox>> = postcard::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded, actions);
+}
+
+#[test]
+fn test_v1_0_envelope_is_migrated() {
+    // Written by a binary that predates transactions
+    let json = r#"{"version":256,"action":{"Tracked":{"action_id":7,"action":"refund"}}}"#;
+
+    let decoded: Envelope = serde_json::from_str(json).expect("1.0 should still decode");
+    assert_eq!(decoded.version, 0x0100, "Original version is preserved");
+    assert_eq!(
+        decoded.into_action(),
+        Action::Tracked(TrackedAction::new(7, "refund".into())),
+        "Missing txn_id should migrate to None"
+    );
+
+    let json = r#"{"version":256,"action":{"Untracked":"log"}}"#;
+    let decoded: Envelope = serde_json::from_str(json).unwrap();
+    assert_eq!(decoded.into_action(), Action::Untracked("log".into()));
+}
+
+#[test]
+fn test_v1_1_envelope_is_migrated() {
+    // Written by a binary that predates deadlines
+    let json =
+        r#"{"version":257,"action":{"Tracked":{"action_id":7,"action":"refund","txn_id":3}}}"#;
+
+    let decoded: Envelope = serde_json::from_str(json).expect("1.1 should still decode");
+    assert_eq!(decoded.version, 0x0101);
+    assert_eq!(
+        decoded.into_action(),
+        Action::Tracked(TrackedAction::new_in_txn(7, "refund".into(), TxnId(3))),
+        "Missing deadline should migrate to None"
+    );
+}
+
+#[test]
+fn test_unknown_versions_are_rejected() {
+    // Major 2 with a payload that would otherwise decode fine
+    let json = r#"{"version":512,"action":{"Untracked":"log"}}"#;
+    let err = serde_json::from_str::<Envelope>(json).expect_err("Unknown major must be rejected");
+    assert!(
+        err.to_string()
+            .contains("unsupported action envelope version 2.0"),
+        "Unexpected error: {}",
+        err
+    );
+
+    let json = r#"{"version":259,"action":{"Untracked":"log"}}"#;
+    let err = serde_json::from_str::<Envelope>(json).expect_err("Newer minor must be rejected");
+    assert!(err.to_string().contains("1.3"), "Unexpected error: {}", err);
+}
@@ -0,0 +1,135 @@
+use std::future;
+
+use phasm::{
+    Input, StateMachine,
+    actions::{Action, TrackedActionTypes},
+    input_log::{FORMAT_VERSION, InputLogError, InputLogWriter, MAGIC, read_input_log},
+};
+
+#[derive(Debug, Default, PartialEq)]
+struct Tally {
+    total: u64,
+    acks: u64,
+}
+
+#[derive(Debug)]
+struct TallyTracked;
+
+impl TrackedActionTypes for TallyTracked {
+    type Id = u64;
+    type Action = ();
+    type Result = bool;
+}
+
+impl StateMachine for Tally {
+    type TrackedAction = TallyTracked;
+    type UntrackedAction = ();
+    type Actions = Vec<Action<(), TallyTracked>>;
+    type State = Self;
+    type Input = u64;
+    type TransitionError = ();
+    type RestoreError = ();
+    type StfFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+    type RestoreFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+
+    fn stf<'state, 'actions>(
+        state: &'state mut Self::State,
+        input: Input<Self::TrackedAction, Self::Input>,
+        _actions: &'actions mut Self::Actions,
+    ) -> Self::StfFuture<'state, 'actions> {
+        match input {
+            Input::Normal(amount) => state.total += amount,
+            Input::TrackedActionCompleted { res, .. } => state.acks += res as u64,
+            Input::TrackedActionExpired { .. } => {}
+        }
+        future::ready(Ok(()))
+    }
+
+    fn restore<'state, 'actions>(
+        _state: &'state Self::State,
+        _actions: &'actions mut Self::Actions,
+    ) -> Self::RestoreFuture<'state, 'actions> {
+        future::ready(Ok(()))
+    }
+}
+
+type TallyInput = Input<TallyTracked, u64>;
+
+fn inputs() -> Vec<TallyInput> {
+    vec![
+        Input::Normal(5),
+        Input::TrackedActionCompleted { id: 1, res: true },
+        Input::Normal(1_000_000),
+    ]
+}
+
+async fn replay(inputs: Vec<TallyInput>) -> Tally {
+    let mut state = Tally::default();
+    let mut actions = Vec::new();
+    for input in inputs {
+        Tally::stf(&mut state, input, &mut actions).await.unwrap();
+        actions.clear();
+    }
+    state
+}
+
+#[monoio::test]
+async fn test_write_and_replay_log() {
+    let mut writer = InputLogWriter::new(Vec::new()).unwrap();
+    for input in inputs() {
+        writer.append(&input).unwrap();
+    }
+    let bytes = writer.into_inner();
+    assert_eq!(bytes[..4], MAGIC);
+    assert_eq!(bytes[4], FORMAT_VERSION);
+
+    let log = read_input_log::<TallyInput>(&bytes).unwrap();
+    assert_eq!(log.entries.len(), 3);
+    assert_eq!(log.valid_len, bytes.len());
+    assert_eq!(log.truncated_bytes, 0);
+    assert_eq!(replay(log.entries).await, replay(inputs()).await);
+}
+
+#[monoio::test]
+async fn test_truncated_tail_is_ignored() {
+    let mut writer = InputLogWriter::new(Vec::new()).unwrap();
+    for input in inputs() {
+        writer.append(&input).unwrap();
+    }
+    let mut bytes = writer.into_inner();
+    let complete_len = bytes.len();
+
+    // Crash while appending a fourth record: prefix written, payload torn
+    InputLogWriter::resume(&mut bytes)
+        .append(&TallyInput::Normal(u64::MAX))
+        .unwrap();
+    let torn_len = bytes.len() - 2 - complete_len;
+    bytes.truncate(complete_len + torn_len);
+
+    let log = read_input_log::<TallyInput>(&bytes).expect("Torn tail should not be an error");
+    assert_eq!(log.entries.len(), 3, "Only complete records are replayed");
+    assert_eq!(log.valid_len, complete_len);
+    assert_eq!(log.truncated_bytes, torn_len);
+    assert_eq!(replay(log.entries).await, replay(inputs()).await);
+
+    // A torn length prefix is ignored too
+    bytes.truncate(complete_len + 2);
+    let log = read_input_log::<TallyInput>(&bytes).unwrap();
+    assert_eq!(log.entries.len(), 3);
+    assert_eq!(log.truncated_bytes, 2);
+}
+
+#[test]
+fn test_foreign_data_is_rejected() {
+    assert!(matches!(
+        read_input_log::<TallyInput>(b"{\"json\": true}"),
+        Err(InputLogError::BadMagic)
+    ));
+
+    let mut bytes = InputLogWriter::new(Vec::new()).unwrap().into_inner();
+    bytes[4] = FORMAT_VERSION + 1;
+    assert!(matches!(
+        read_input_log::<TallyInput>(&bytes),
+        Err(InputLogError::UnsupportedVersion(v)) if v == FORMAT_VERSION + 1
+    ));
+}
@@ -0,0 +1,17 @@
+use phasm::money::Cents;
+
+#[test]
+fn test_cents_arithmetic_and_display() {
+    let price = Cents::from_dollars_f32(19.99).unwrap();
+    assert_eq!(price, Cents(1_999));
+    assert_eq!(price.to_string(), "$19.99");
+    assert_eq!(Cents(5).to_string(), "$0.05");
+
+    assert_eq!(price.checked_add(Cents(1)), Some(Cents(2_000)));
+    assert_eq!(Cents(u64::MAX).checked_add(Cents(1)), None);
+    assert_eq!(price.checked_sub(Cents(2_000)), None);
+    assert_eq!(price.saturating_sub(Cents(2_000)), Cents::ZERO);
+
+    assert_eq!(Cents::from_dollars_f32(f32::NAN), None);
+    assert_eq!(Cents::from_dollars_f32(-1.0), None);
+}
@@ -0,0 +1,169 @@
+use std::future;
+
+use phasm::{
+    Input, StateMachine,
+    actions::{Action, TrackedActionTypes},
+    sim::{SimReport, Simulator},
+};
+use rand::Rng;
+use rand_chacha::ChaCha8Rng;
+
+/// Sells seats up to a capacity. With `lax` set, single seats skip the capacity check.
+#[derive(Debug, Clone)]
+struct Venue {
+    capacity: u32,
+    sold: u32,
+    lax: bool,
+}
+
+#[derive(Debug)]
+struct NoTracked;
+
+impl TrackedActionTypes for NoTracked {
+    type Id = ();
+    type Action = ();
+    type Result = ();
+}
+
+impl StateMachine for Venue {
+    type TrackedAction = NoTracked;
+    type UntrackedAction = u32;
+    type Actions = Vec<Action<u32, NoTracked>>;
+    type State = Self;
+    /// Seats to sell.
+    type Input = u32;
+    type TransitionError = ();
+    type RestoreError = ();
+    type StfFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+    type RestoreFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+
+    fn stf<'state, 'actions>(
+        state: &'state mut Self::State,
+        input: Input<Self::TrackedAction, Self::Input>,
+        actions: &'actions mut Self::Actions,
+    ) -> Self::StfFuture<'state, 'actions> {
+        let Input::Normal(seats) = input else {
+            return future::ready(Err(()));
+        };
+        let checked = !(state.lax && seats == 1);
+        if checked && state.sold + seats > state.capacity {
+            return future::ready(Err(()));
+        }
+        state.sold += seats;
+        actions.push(Action::Untracked(seats));
+        future::ready(Ok(()))
+    }
+
+    fn restore<'state, 'actions>(
+        _state: &'state Self::State,
+        _actions: &'actions mut Self::Actions,
+    ) -> Self::RestoreFuture<'state, 'actions> {
+        future::ready(Ok(()))
+    }
+
+    fn check_invariants(state: &Self) -> Result<(), String> {
+        if state.sold > state.capacity {
+            return Err(format!("sold {} of {} seats", state.sold, state.capacity));
+        }
+        Ok(())
+    }
+}
+
+fn venue(lax: bool) -> Venue {
+    Venue {
+        capacity: 100,
+        sold: 0,
+        lax,
+    }
+}
+
+fn simulator() -> Simulator<Venue, impl Fn(&mut ChaCha8Rng, &Venue) -> Input<NoTracked, u32>> {
+    Simulator::new(|rng: &mut ChaCha8Rng, _: &Venue| Input::Normal(rng.gen_range(1..=4))).steps(200)
+}
+
+#[monoio::test]
+async fn test_simulator_is_reproducible() {
+    let mut reports: Vec<SimReport> = Vec::new();
+    for _ in 0..2 {
+        reports.push(simulator().run(7, &mut venue(false)).await);
+    }
+    assert_eq!(reports[0], reports[1]);
+
+    let report = &reports[0];
+    report.assert_ok();
+    assert_eq!(report.steps, 200);
+    assert_eq!(report.applied + report.rejected, 200);
+    assert_eq!(report.actions, report.applied);
+    assert!(report.rejected > 0, "The venue fills up");
+}
+
+#[monoio::test]
+async fn test_simulator_reports_invariant_violation() {
+    let report = simulator().run(7, &mut venue(true)).await;
+    let failure = report.failure.clone().expect("Lax venue oversells");
+    assert!(failure.error.starts_with("sold 101 of 100"));
+    assert_eq!(
+        report.steps,
+        failure.step + 1,
+        "The run stops at the violation"
+    );
+    assert_eq!(
+        report.to_string(),
+        format!(
+            "seed 7 violated an invariant at step {}: {}",
+            failure.step, failure.error
+        )
+    );
+
+    // The seed reproduces it
+    assert_eq!(simulator().run(7, &mut venue(true)).await, report);
+}
+
+fn seats(inputs: &[Input<NoTracked, u32>]) -> Vec<u32> {
+    inputs
+        .iter()
+        .map(|input| match input {
+            Input::Normal(seats) => *seats,
+            Input::TrackedActionCompleted { .. } | Input::TrackedActionExpired { .. } => {
+                unreachable!()
+            }
+        })
+        .collect()
+}
+
+#[monoio::test]
+async fn test_shrink_finds_minimal_reproducer() {
+    let initial = Venue {
+        capacity: 10,
+        sold: 0,
+        lax: true,
+    };
+    let sim = simulator();
+    let report = sim.run(3, &mut initial.clone()).await;
+    assert!(!report.is_ok());
+
+    let shrunk = sim.shrink(&report, &initial).await.unwrap();
+    let reproducer = seats(&shrunk.inputs);
+    assert!(reproducer.len() < report.steps);
+    assert_eq!(reproducer.last(), Some(&1), "Only a lax sale oversells");
+    assert_eq!(shrunk.error, "sold 11 of 10 seats");
+
+    // No single input can be dropped
+    for skip in 0..shrunk.inputs.len() {
+        let mut state = initial.clone();
+        let mut actions = Vec::new();
+        for (i, input) in shrunk.inputs.iter().enumerate() {
+            if i != skip {
+                let _ = Venue::stf(&mut state, input.clone(), &mut actions).await;
+            }
+        }
+        assert!(Venue::check_invariants(&state).is_ok(), "{:?}", reproducer);
+    }
+
+    // Deterministic
+    let again = sim.shrink(&report, &initial).await.unwrap();
+    assert_eq!(seats(&again.inputs), reproducer);
+
+    let passing = sim.run(3, &mut venue(false)).await;
+    assert!(sim.shrink(&passing, &venue(false)).await.is_none());
+}
@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use phasm::time::{LogicalTime, Timed};
+
+#[test]
+fn test_logical_time_arithmetic() {
+    let start = LogicalTime::from_millis(1_000);
+    let later = start + Duration::from_secs(90);
+    assert_eq!(later, LogicalTime(91_000));
+    assert!(later > start);
+    assert_eq!(later - start, Duration::from_secs(90));
+    assert_eq!(start - later, Duration::ZERO, "Elapsed time saturates");
+    assert_eq!(later - Duration::from_secs(90), start);
+    assert_eq!(
+        start + Duration::from_micros(1_999),
+        LogicalTime(1_001),
+        "Sub-millisecond parts are truncated"
+    );
+
+    assert_eq!(start.checked_sub(Duration::from_secs(2)), None);
+    assert_eq!(
+        LogicalTime(u64::MAX).checked_add(Duration::from_millis(1)),
+        None
+    );
+}
+
+#[test]
+#[should_panic(expected = "overflow when adding duration to logical time")]
+fn test_logical_time_overflow_panics() {
+    let _ = LogicalTime(u64::MAX) + Duration::from_millis(1);
+}
+
+#[test]
+fn test_timed_orders_by_time_first() {
+    let mut inputs = [
+        Timed::new(LogicalTime(20), "b"),
+        Timed::new(LogicalTime(10), "z"),
+        Timed::new(LogicalTime(20), "a"),
+    ];
+    inputs.sort();
+    let order: Vec<_> = inputs.iter().map(|t| (t.at.as_millis(), t.inner)).collect();
+    assert_eq!(order, [(10, "z"), (20, "a"), (20, "b")]);
+
+    let mapped = inputs[0].map(str::len);
+    assert_eq!(mapped, Timed::new(LogicalTime(10), 1));
+}
@@ -0,0 +1,14 @@
+use phasm::util::f32_to_cents;
+
+#[test]
+fn test_f32_to_cents_rounds_and_rejects_invalid() {
+    assert_eq!(f32_to_cents(75.0), Some(7_500));
+    assert_eq!(f32_to_cents(19.99), Some(1_999), "0.99 isn't exact in f32");
+    assert_eq!(f32_to_cents(0.125), Some(13), "Halves round away from zero");
+    assert_eq!(f32_to_cents(-0.001), Some(0), "Rounds to zero");
+
+    assert_eq!(f32_to_cents(f32::NAN), None);
+    assert_eq!(f32_to_cents(-1.0), None);
+    assert_eq!(f32_to_cents(f32::INFINITY), None);
+    assert_eq!(f32_to_cents(5e7), None, "Over u32::MAX cents");
+}
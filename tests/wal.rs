@@ -0,0 +1,134 @@
+use std::{cell::RefCell, collections::BTreeMap, future, rc::Rc};
+
+use phasm::{
+    Input, StateMachine,
+    actions::{Action, TrackedAction, TrackedActionTypes},
+    driver::ActionExecutor,
+    wal::{ActionSink, MemorySink, WalDriver},
+};
+
+/// Payouts that are sent, and stay pending until the bank confirms them.
+#[derive(Debug, Default)]
+struct Payouts {
+    pending: BTreeMap<u64, u64>,
+    paid: u64,
+    next_id: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct PayoutTracked;
+
+impl TrackedActionTypes for PayoutTracked {
+    type Id = u64;
+    /// Amount to pay out.
+    type Action = u64;
+    type Result = ();
+}
+
+impl StateMachine for Payouts {
+    type TrackedAction = PayoutTracked;
+    type UntrackedAction = ();
+    type Actions = Vec<Action<(), PayoutTracked>>;
+    type State = Self;
+    type Input = u64;
+    type TransitionError = ();
+    type RestoreError = ();
+    type StfFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+    type RestoreFuture<'state, 'actions> = future::Ready<Result<(), ()>>;
+
+    fn stf<'state, 'actions>(
+        state: &'state mut Self::State,
+        input: Input<Self::TrackedAction, Self::Input>,
+        actions: &'actions mut Self::Actions,
+    ) -> Self::StfFuture<'state, 'actions> {
+        match input {
+            Input::Normal(amount) => {
+                let id = state.next_id;
+                state.next_id += 1;
+                state.pending.insert(id, amount);
+                actions.push(Action::Tracked(TrackedAction::new(id, amount)));
+            }
+            Input::TrackedActionCompleted { id, .. } | Input::TrackedActionExpired { id } => {
+                match state.pending.remove(&id) {
+                    Some(amount) => state.paid += amount,
+                    None => return future::ready(Err(())),
+                }
+            }
+        }
+        future::ready(Ok(()))
+    }
+
+    fn restore<'state, 'actions>(
+        state: &'state Self::State,
+        actions: &'actions mut Self::Actions,
+    ) -> Self::RestoreFuture<'state, 'actions> {
+        for (id, amount) in &state.pending {
+            actions.push(Action::Tracked(TrackedAction::new(*id, *amount)));
+        }
+        future::ready(Ok(()))
+    }
+}
+
+type Events = Rc<RefCell<Vec<String>>>;
+
+/// Pays out, noting each payout in the shared events.
+struct Bank(Events);
+
+impl ActionExecutor<(), PayoutTracked> for Bank {
+    async fn execute_untracked(&mut self, _action: &()) {}
+
+    async fn execute_tracked(&mut self, id: &u64, amount: &u64) {
+        self.0.borrow_mut().push(format!("pay {} {}", id, amount));
+    }
+}
+
+/// Appends to a `MemorySink`, noting each record in the shared events.
+struct NotingSink(Events, MemorySink);
+
+impl ActionSink for NotingSink {
+    async fn append(&mut self, bytes: &[u8]) {
+        self.0
+            .borrow_mut()
+            .push(format!("log {}", self.1.records().len()));
+        self.1.append(bytes).await;
+    }
+}
+
+#[monoio::test]
+async fn test_tracked_actions_logged_before_execution() {
+    let events = Events::default();
+    let sink = NotingSink(events.clone(), MemorySink::new());
+    let mut driver =
+        WalDriver::<Payouts, _, _>::new(Payouts::default(), Bank(events.clone()), sink).unwrap();
+    driver.submit(30).await.unwrap();
+    driver.submit(12).await.unwrap();
+
+    assert_eq!(*events.borrow(), ["log 0", "pay 0 30", "log 1", "pay 1 12"]);
+    assert_eq!(driver.state().paid, 42);
+    assert_eq!(
+        driver.sink().1.tracked::<PayoutTracked>().unwrap(),
+        [TrackedAction::new(0, 30), TrackedAction::new(1, 12)]
+    );
+}
+
+#[monoio::test]
+async fn test_recover_logs_restored_actions() {
+    // Crashed after logging payout 0 but before it was executed
+    let state = Payouts {
+        pending: BTreeMap::from([(0, 30)]),
+        paid: 0,
+        next_id: 1,
+    };
+    let events = Events::default();
+    let mut driver =
+        WalDriver::<Payouts, _, _>::new(state, Bank(events.clone()), MemorySink::new()).unwrap();
+    driver.recover().await.unwrap();
+
+    assert_eq!(*events.borrow(), ["pay 0 30"]);
+    assert_eq!(driver.state().paid, 30);
+    assert!(driver.state().pending.is_empty());
+    assert_eq!(
+        driver.sink().tracked::<PayoutTracked>().unwrap(),
+        [TrackedAction::new(0, 30)]
+    );
+}